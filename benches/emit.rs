@@ -0,0 +1,112 @@
+//! Measures the time and peak heap allocation of emitting a large Mach-O object, whose
+//! definitions all have relocations with implicit addends, to memory and streamed to a sink.
+//! Streaming should allocate far less than the object, even for its one very large table.
+//!
+//! Run with `cargo bench --bench emit`.
+
+extern crate faerie;
+#[macro_use]
+extern crate target_lexicon;
+
+use faerie::{Artifact, Decl, Link, Reloc};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::hash_map::DefaultHasher;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The number of functions, and of tables referring to them
+const DEFINITIONS: usize = 2_000;
+/// The size of each function and table
+const SIZE: usize = 16 * 1024;
+/// The size of the very large table
+const LARGE_SIZE: usize = 32 * 1024 * 1024;
+
+/// The system allocator, recording the bytes allocated and the most allocated at once
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn artifact() -> Artifact {
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "bench.o".into());
+    for i in 0..DEFINITIONS {
+        let (f, table) = (format!("f{}", i), format!("table{}", i));
+        obj.declare_with(&f, Decl::function().global(), vec![0xc3; SIZE])
+            .expect("can declare and define function");
+        obj.declare_with(&table, Decl::data().global(), vec![0; SIZE])
+            .expect("can declare and define table");
+        obj.link_with(
+            Link {
+                from: &table,
+                to: &f,
+                at: 0,
+            },
+            Reloc::Absolute { size: 8, addend: 8 },
+        )
+        .expect("can link table to function");
+    }
+    obj.declare_with("large", Decl::data().global(), vec![0; LARGE_SIZE])
+        .expect("can declare and define large");
+    obj.link_with(
+        Link {
+            from: "large",
+            to: "f0",
+            at: 8,
+        },
+        Reloc::Absolute { size: 8, addend: 8 },
+    )
+    .expect("can link large to f0");
+    obj
+}
+
+/// Run `emit`, and report how long it took and the most it had allocated at once, beyond what
+/// was already allocated
+fn measure<F: FnOnce()>(name: &str, emit: F) {
+    let baseline = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    let start = Instant::now();
+    emit();
+    let elapsed = start.elapsed();
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+    println!(
+        "{:<12} {:>10.2?} {:>8} KiB peak allocation",
+        name,
+        elapsed,
+        peak / 1024
+    );
+}
+
+fn main() {
+    let obj = artifact();
+    println!(
+        "{} definitions of {} KiB and one of {} KiB, {} KiB in all",
+        2 * DEFINITIONS,
+        SIZE / 1024,
+        LARGE_SIZE / 1024,
+        (2 * DEFINITIONS * SIZE + LARGE_SIZE) / 1024
+    );
+    measure("emit", || {
+        obj.emit().expect("can emit object");
+    });
+    measure("write_hashed", || {
+        obj.write_hashed(&mut io::sink(), &mut DefaultHasher::new())
+            .expect("can write object");
+    });
+}
@@ -0,0 +1,192 @@
+//! Builds an artifact from arbitrary declarations, definitions and links, and emits it in every
+//! binary format; faerie must return an error for anything it cannot emit, rather than panic.
+
+#![no_main]
+use faerie::*;
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use target_lexicon::{BinaryFormat, Triple};
+
+const TRIPLES: &[&str] = &[
+    "x86_64-unknown-linux-gnu",
+    "x86_64-apple-darwin",
+    "aarch64-unknown-linux-gnu",
+    "aarch64-apple-darwin",
+    "i686-unknown-linux-gnu",
+    "i686-apple-darwin",
+    "arm-unknown-linux-gnueabi",
+    "x86_64-pc-windows-msvc",
+];
+
+const NAMES: &[&str] = &[
+    "",
+    "a",
+    "b",
+    "c",
+    ".text.a",
+    ".debug_info",
+    ".debug_str",
+    ".eh_frame",
+    "__text",
+    "__data",
+    "__compact_unwind",
+    "custom",
+];
+
+/// The fuzzer's input, read a byte at a time; once it runs out, every byte read is 0
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn byte(&mut self) -> u8 {
+        match self.0.split_first() {
+            Some((&byte, rest)) => {
+                self.0 = rest;
+                byte
+            }
+            None => 0,
+        }
+    }
+    fn bool(&mut self) -> bool {
+        self.byte() & 1 == 1
+    }
+    fn pick<'b, T>(&mut self, items: &'b [T]) -> &'b T {
+        &items[self.byte() as usize % items.len()]
+    }
+    fn u64(&mut self) -> u64 {
+        (0..8).fold(0, |value, _| value << 8 | u64::from(self.byte()))
+    }
+    fn bytes(&mut self) -> Vec<u8> {
+        let len = self.byte() as usize % 64;
+        (0..len).map(|_| self.byte()).collect()
+    }
+    fn align(&mut self) -> Option<u64> {
+        // huge alignments are valid, but pad the output with gigabytes of zeros
+        match self.byte() % 4 {
+            0 => None,
+            _ => Some(1 << (self.byte() % 20)),
+        }
+    }
+}
+
+fn decl(input: &mut Input) -> Decl {
+    let scope = *input.pick(&[Scope::Local, Scope::Global, Scope::Weak]);
+    match input.byte() % 6 {
+        0 => Decl::function()
+            .with_scope(scope)
+            .with_align(input.align())
+            .with_ifunc(input.bool())
+            .with_coalesced(input.bool())
+            .with_compact_unwind(if input.bool() { Some(0) } else { None })
+            .into(),
+        1 => Decl::data()
+            .with_scope(scope)
+            .with_align(input.align())
+            .with_writable(input.bool())
+            .with_tls(input.bool())
+            .with_coalesced(input.bool())
+            .with_datatype(*input.pick(&[
+                DataType::Bytes,
+                DataType::String,
+                DataType::Literal4,
+                DataType::Literal8,
+            ]))
+            .into(),
+        2 => Decl::cstring().with_scope(scope).into(),
+        3 => {
+            Decl::section(*input.pick(&[SectionKind::Data, SectionKind::Debug, SectionKind::Text]))
+                .with_align(input.align())
+                .with_raw(input.bool())
+                .with_retain(input.bool())
+                .with_mach_segname(*input.pick(&[None, Some(""), Some("__LD")]))
+                .into()
+        }
+        4 => Decl::function_import().into(),
+        _ => Decl::data_import().into(),
+    }
+}
+
+fn data(input: &mut Input) -> Data {
+    match input.byte() % 3 {
+        0 => Data::Blob(input.bytes()),
+        1 => Data::ZeroInit(input.byte() as usize),
+        _ => {
+            let size = input.byte() as usize;
+            let chunks = (0..input.byte() % 4)
+                .map(|_| (input.byte() as usize, input.bytes()))
+                .collect();
+            Data::Sparse {
+                size,
+                fill: 0,
+                chunks,
+            }
+        }
+    }
+}
+
+fn reloc(input: &mut Input) -> Reloc {
+    let size = *input.pick(&[0, 1, 2, 4, 8, 16]);
+    let addend = input.u64() as i32;
+    match input.byte() % 9 {
+        0 => Reloc::Auto,
+        1 => Reloc::Raw {
+            reloc: u32::from(input.byte()),
+            addend,
+        },
+        2 => Reloc::Call,
+        3 => Reloc::GotLoad,
+        4 => Reloc::RelaxableGotLoad { rex: input.bool() },
+        5 => Reloc::PcRelative {
+            trailing: input.byte(),
+        },
+        6 => Reloc::Absolute { size, addend },
+        7 => Reloc::Debug { size, addend },
+        _ => Reloc::Scattered {
+            reloc: u32::from(input.byte()),
+            addend,
+        },
+    }
+}
+
+fuzz_target!(|bytes: &[u8]| {
+    let mut input = Input(bytes);
+    let target = Triple::from_str(*input.pick(TRIPLES)).unwrap();
+    let mut obj = ArtifactBuilder::new(target)
+        .relocation_model(*input.pick(&[RelocationModel::Pic, RelocationModel::Static]))
+        .finish();
+    while !input.0.is_empty() {
+        let name = *input.pick(NAMES);
+        let _ = match input.byte() % 6 {
+            0 => {
+                let decl = decl(&mut input);
+                obj.declare(name, decl).map_err(Into::into)
+            }
+            1 => {
+                let mut symbols = BTreeMap::new();
+                if input.bool() {
+                    symbols.insert(input.pick(NAMES).to_string(), u64::from(input.byte()));
+                }
+                let data = data(&mut input);
+                obj.define_with_symbols(name, data, symbols)
+                    .map_err(Into::into)
+            }
+            2 => obj.import(name, *input.pick(&[ImportKind::Function, ImportKind::Data])),
+            // calls to it, e.g. from a custom text section, go through a stub on Mach-O
+            3 => obj.import_with_stub(name),
+            _ => {
+                let link = Link {
+                    from: name,
+                    to: *input.pick(NAMES),
+                    at: input.u64() % 256,
+                };
+                let reloc = reloc(&mut input);
+                obj.link_with(link, reloc)
+            }
+        };
+    }
+    for &format in &[BinaryFormat::Elf, BinaryFormat::Macho] {
+        let _ = obj.emit_as(format);
+        let _ = obj.layout_as(format);
+        let _ = obj.section_contents_as(format);
+    }
+});
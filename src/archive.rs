@@ -0,0 +1,256 @@
+//! A writer for static libraries: `ar` archives of emitted objects, with a symbol index so that a
+//! linker can find the member defining a symbol without reading every member.
+
+use crate::artifact::Artifact;
+use crate::describe::describe;
+use failure::Error;
+use std::path::Path;
+use target_lexicon::{Endianness, OperatingSystem};
+
+/// The magic string every archive starts with
+const MAGIC: &[u8] = b"!<arch>\n";
+
+/// The size of a member header
+const SIZEOF_HEADER: usize = 60;
+
+/// The name of the BSD symbol index, whose entries are sorted by symbol name
+const BSD_SYMDEF_NAME: &str = "__.SYMDEF SORTED";
+
+/// The flavour of the `ar` archive format, which differ in their symbol index and in how they
+/// store member names which do not fit in a member header
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// The GNU or System V format: the symbol index is a `/` member with big-endian offsets, and
+    /// long member names are stored in a `//` member, which member headers refer to as
+    /// `/<offset>`
+    Gnu,
+    /// The BSD format, as used by Darwin: the symbol index is a `__.SYMDEF SORTED` member with
+    /// offsets in the target's byte order, and long member names follow their member header,
+    /// which names them `#1/<length>`
+    Bsd,
+}
+
+impl ArchiveFormat {
+    /// The format the system archiver of `os` produces: BSD on Darwin, and GNU otherwise
+    pub fn for_os(os: OperatingSystem) -> Self {
+        match os {
+            OperatingSystem::Darwin | OperatingSystem::Ios | OperatingSystem::MacOSX { .. } => {
+                ArchiveFormat::Bsd
+            }
+            _ => ArchiveFormat::Gnu,
+        }
+    }
+}
+
+/// A member of an archive being written
+struct Member {
+    name: String,
+    bytes: Vec<u8>,
+    symbols: Vec<String>,
+}
+
+/// Emit each artifact as an object file in its target format, and bundle the objects into a
+/// static library, in the format the system archiver of the first artifact's target produces
+pub fn to_archive_bytes(artifacts: &[&Artifact]) -> Result<Vec<u8>, Error> {
+    let format = match artifacts.first() {
+        Some(artifact) => ArchiveFormat::for_os(artifact.target.operating_system),
+        None => return Err(format_err!("an archive needs at least one artifact")),
+    };
+    to_archive_bytes_as(artifacts, format)
+}
+
+/// Emit each artifact as an object file in its target format, and bundle the objects into a
+/// static library in the given format. Each member is named after the final component of its
+/// artifact's name, as `ar` names members after files, e.g. `foo.o` for `out/foo.o`, and the
+/// symbol index lists the global symbols each object defines.
+pub fn to_archive_bytes_as(
+    artifacts: &[&Artifact],
+    format: ArchiveFormat,
+) -> Result<Vec<u8>, Error> {
+    let mut members = Vec::with_capacity(artifacts.len());
+    for artifact in artifacts {
+        // a `/` terminates the name in a GNU member header
+        let name = Path::new(&artifact.name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format_err!("artifact {:?} has no file name", artifact.name))?
+            .to_string();
+        let bytes = artifact.emit()?;
+        let symbols = describe(&bytes)?
+            .symbols
+            .into_iter()
+            .filter(|symbol| symbol.global && symbol.section.is_some())
+            .map(|symbol| symbol.name)
+            .collect();
+        members.push(Member {
+            name,
+            bytes,
+            symbols,
+        });
+    }
+    // the symbol index is in the byte order of the objects on BSD
+    let little = artifacts
+        .first()
+        .map(|artifact| artifact.target.endianness())
+        != Some(Ok(Endianness::Big));
+    match format {
+        ArchiveFormat::Gnu => Ok(gnu(&members)),
+        ArchiveFormat::Bsd => Ok(bsd(&members, little)),
+    }
+}
+
+/// Write a member header, whose fields are space-padded decimal ASCII; the timestamp and owner
+/// are 0, so that archiving the same objects produces the same bytes
+fn write_header(archive: &mut Vec<u8>, name: &str, size: usize) {
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8o}{:<10}`\n",
+        name, 0, 0, 0, 0o644, size
+    );
+    debug_assert_eq!(header.len(), SIZEOF_HEADER);
+    archive.extend_from_slice(header.as_bytes());
+}
+
+/// Pad the archive with a newline, so that the next member header is 2-byte aligned
+fn pad(archive: &mut Vec<u8>) {
+    if archive.len() % 2 == 1 {
+        archive.push(b'\n');
+    }
+}
+
+fn write_u32(archive: &mut Vec<u8>, value: usize, little: bool) {
+    let value = value as u32;
+    if little {
+        archive.extend_from_slice(&value.to_le_bytes());
+    } else {
+        archive.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn gnu(members: &[Member]) -> Vec<u8> {
+    let symbols = members
+        .iter()
+        .map(|member| member.symbols.len())
+        .sum::<usize>();
+    let strings = members
+        .iter()
+        .flat_map(|member| member.symbols.iter())
+        .map(|symbol| symbol.len() + 1)
+        .sum::<usize>();
+    let index_size = 4 + 4 * symbols + strings;
+
+    // names which, along with their terminating `/`, do not fit in the header are in the `//`
+    // member, each terminated by `/\n`
+    let mut names = Vec::new();
+    let header_names = members
+        .iter()
+        .map(|member| {
+            if member.name.len() < 16 {
+                format!("{}/", member.name)
+            } else {
+                let offset = names.len();
+                names.extend_from_slice(member.name.as_bytes());
+                names.extend_from_slice(b"/\n");
+                format!("/{}", offset)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut offset = MAGIC.len() + SIZEOF_HEADER + index_size + index_size % 2;
+    if !names.is_empty() {
+        offset += SIZEOF_HEADER + names.len() + names.len() % 2;
+    }
+    let mut offsets = Vec::with_capacity(members.len());
+    for member in members {
+        offsets.push(offset);
+        offset += SIZEOF_HEADER + member.bytes.len() + member.bytes.len() % 2;
+    }
+
+    let mut archive = Vec::with_capacity(offset);
+    archive.extend_from_slice(MAGIC);
+    write_header(&mut archive, "/", index_size);
+    write_u32(&mut archive, symbols, false);
+    for (member, &offset) in members.iter().zip(&offsets) {
+        for _ in &member.symbols {
+            write_u32(&mut archive, offset, false);
+        }
+    }
+    for symbol in members.iter().flat_map(|member| member.symbols.iter()) {
+        archive.extend_from_slice(symbol.as_bytes());
+        archive.push(0);
+    }
+    pad(&mut archive);
+    if !names.is_empty() {
+        write_header(&mut archive, "//", names.len());
+        archive.extend_from_slice(&names);
+        pad(&mut archive);
+    }
+    for (member, name) in members.iter().zip(&header_names) {
+        write_header(&mut archive, name, member.bytes.len());
+        archive.extend_from_slice(&member.bytes);
+        pad(&mut archive);
+    }
+    archive
+}
+
+/// The name field of a BSD member header for `name`, and the name following the header, if it
+/// does not fit in the header; the name is padded with NULs so that the member's contents are
+/// 8-byte aligned, as `ld64` expects, when the header is at `offset`
+fn bsd_name(name: &str, offset: usize) -> (String, Vec<u8>) {
+    if name.len() <= 16 && !name.contains(' ') {
+        return (name.to_string(), Vec::new());
+    }
+    let mut extended = name.as_bytes().to_vec();
+    let end = offset + SIZEOF_HEADER + extended.len();
+    extended.resize(extended.len() + (8 - end % 8) % 8, 0);
+    (format!("#1/{}", extended.len()), extended)
+}
+
+fn bsd(members: &[Member], little: bool) -> Vec<u8> {
+    // every symbol, along with its member, sorted by name so that the linker can binary search
+    // for it
+    let mut symbols = members
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, member)| member.symbols.iter().map(move |symbol| (symbol, idx)))
+        .collect::<Vec<_>>();
+    symbols.sort_by_key(|&(symbol, _)| symbol);
+    let mut strings = Vec::new();
+    let mut string_offsets = Vec::with_capacity(symbols.len());
+    for (symbol, _) in &symbols {
+        string_offsets.push(strings.len());
+        strings.extend_from_slice(symbol.as_bytes());
+        strings.push(0);
+    }
+    strings.resize((strings.len() + 3) & !3, 0);
+
+    let (index_name, index_extended) = bsd_name(BSD_SYMDEF_NAME, MAGIC.len());
+    let index_size = index_extended.len() + 4 + 8 * symbols.len() + 4 + strings.len();
+    let mut offset = MAGIC.len() + SIZEOF_HEADER + index_size + index_size % 2;
+    let mut headers = Vec::with_capacity(members.len());
+    for member in members {
+        let (name, extended) = bsd_name(&member.name, offset);
+        let size = extended.len() + member.bytes.len();
+        headers.push((offset, name, extended));
+        offset += SIZEOF_HEADER + size + size % 2;
+    }
+
+    let mut archive = Vec::with_capacity(offset);
+    archive.extend_from_slice(MAGIC);
+    write_header(&mut archive, &index_name, index_size);
+    archive.extend_from_slice(&index_extended);
+    write_u32(&mut archive, 8 * symbols.len(), little);
+    for (&(_, idx), &string_offset) in symbols.iter().zip(&string_offsets) {
+        write_u32(&mut archive, string_offset, little);
+        write_u32(&mut archive, headers[idx].0, little);
+    }
+    write_u32(&mut archive, strings.len(), little);
+    archive.extend_from_slice(&strings);
+    pad(&mut archive);
+    for (member, (_, name, extended)) in members.iter().zip(&headers) {
+        write_header(&mut archive, name, extended.len() + member.bytes.len());
+        archive.extend_from_slice(extended);
+        archive.extend_from_slice(&member.bytes);
+        pad(&mut archive);
+    }
+    archive
+}
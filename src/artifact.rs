@@ -3,17 +3,23 @@
 use failure::Error;
 use indexmap::IndexMap;
 use string_interner::StringInterner;
-use target_lexicon::{BinaryFormat, Triple};
+use target_lexicon::{Architecture, BinaryFormat, Triple};
 
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet};
-use std::fs::File;
-use std::io::Write;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 use crate::{elf, mach};
 
 pub(crate) mod decl;
 pub use crate::artifact::decl::{
-    DataType, Decl, DefinedDecl, ImportKind, Scope, SectionKind, Visibility,
+    DataDecl, DataType, Decl, DefinedDecl, ImportKind, Scope, SectionKind, SymbolKind, Visibility,
 };
 
 // we need Ord so that `InternalDefinition` can go in a BTreeSet
@@ -24,6 +30,126 @@ pub enum Data {
     Blob(Vec<u8>),
     /// Zero-initialized data with a given size. This is implemented as a .bss section.
     ZeroInit(usize),
+    /// Bytes which are only produced when the artifact is emitted, e.g. a function body which is
+    /// generated while the preceding definitions are written. Its size is known up front.
+    Lazy(Arc<dyn LazyData>),
+    /// `size` bytes which are all `fill`, except for `chunks` of bytes at their offsets, e.g. a
+    /// sparse table. The chunks must be in order of offset, must not overlap, and must lie within
+    /// `size` bytes.
+    Sparse {
+        /// The number of bytes of the data
+        size: usize,
+        /// The byte the data is filled with outside of its chunks
+        fill: u8,
+        /// The chunks of bytes, as `(offset, bytes)`
+        chunks: Vec<(usize, Vec<u8>)>,
+    },
+}
+
+/// The contents of a [`Data::Lazy`](enum.Data.html#variant.Lazy) definition, which are produced
+/// while emitting the artifact rather than held in memory until then.
+///
+/// The contents are produced every time the artifact is emitted, and once per emit; computing
+/// the layout of the artifact only uses their `size`.
+pub trait LazyData: fmt::Debug + Send + Sync {
+    /// The number of bytes `write_to` writes
+    fn size(&self) -> usize;
+    /// Write the contents to `sink`
+    fn write_to(&self, sink: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Lazily produced contents are only equal to themselves; they are ordered by address so that
+/// definitions of them can be kept in sets.
+impl PartialEq for dyn LazyData {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for dyn LazyData {}
+
+impl PartialOrd for dyn LazyData {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for dyn LazyData {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let address = |data: &Self| data as *const Self as *const u8 as usize;
+        address(self).cmp(&address(other))
+    }
+}
+
+/// `LazyData` produced by a closure
+struct LazyFn<F> {
+    size: usize,
+    produce: F,
+}
+
+impl<F> fmt::Debug for LazyFn<F> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "LazyFn({} bytes)", self.size)
+    }
+}
+
+impl<F: Fn() -> Vec<u8> + Send + Sync> LazyData for LazyFn<F> {
+    fn size(&self) -> usize {
+        self.size
+    }
+    fn write_to(&self, sink: &mut dyn Write) -> io::Result<()> {
+        sink.write_all(&(self.produce)())
+    }
+}
+
+/// Forwards writes to `inner`, counting the bytes written
+struct CountingWriter<'a, W: ?Sized> {
+    inner: &'a mut W,
+    count: usize,
+}
+
+impl<'a, W: Write + ?Sized> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Forwards writes to `inner`, hashing the bytes written with `hasher`
+struct HashingWriter<'a, W: ?Sized, H: ?Sized> {
+    inner: &'a mut W,
+    hasher: &'a mut H,
+    position: u64,
+}
+
+impl<'a, W: Write + ?Sized, H: Hasher + ?Sized> Write for HashingWriter<'a, W, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.write(&buf[..written]);
+        self.position += written as u64;
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The bytes are hashed in the order they are written, so the writer can only report where it is,
+/// not move
+impl<'a, W: Write + ?Sized, H: Hasher + ?Sized> Seek for HashingWriter<'a, W, H> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.position),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a hashed object is written sequentially",
+            )),
+        }
+    }
 }
 
 /// The kind of relocation for a link.
@@ -39,13 +165,157 @@ pub enum Reloc {
         /// Raw addend, significance depends on the raw relocation used
         addend: i32,
     },
+    /// A PC-relative call or branch to the target, through the PLT or a stub if necessary.
+    /// This is what `Auto` selects for links from a function to a function or function import.
+    Call,
+    /// A PC-relative load of the target's address from the GOT.
+    /// This is what `Auto` selects for links from a function to a data import.
+    GotLoad,
+    /// A PC-relative load of the target's address from the GOT, which the linker may relax into
+    /// computing the address directly, e.g. `mov foo@GOTPCREL(%rip), %rax` into
+    /// `lea foo(%rip), %rax`, when the target turns out to be defined in the linked image.
+    /// On ELF, this is `R_X86_64_REX_GOTPCRELX` when the instruction has a REX prefix, as 64-bit
+    /// loads do, and `R_X86_64_GOTPCRELX` otherwise; on Mach-O, it is the same as `GotLoad`, which
+    /// ld64 already relaxes.
+    RelaxableGotLoad {
+        /// Whether the instruction has a REX prefix
+        rex: bool,
+    },
+    /// A PC-relative 4-byte reference to the target, as in a RIP-relative data access, which is
+    /// followed by `trailing` more bytes of the instruction, e.g. the 4-byte immediate of
+    /// `movl $imm, sym(%rip)`. The displacement is relative to the end of the instruction, so
+    /// they are accounted for in the addend on ELF, and by `X86_64_RELOC_SIGNED_1`, `_2` or `_4`
+    /// on Mach-O, which only supports 0, 1, 2 or 4 trailing bytes.
+    /// This is what `Auto` selects, with no trailing bytes, for links from a function to data.
+    PcRelative {
+        /// The number of bytes of the instruction after the displacement
+        trailing: u8,
+    },
+    /// An absolute reference to the address of the target plus the addend, in a field of `size`
+    /// bytes, e.g. a 4-byte `.long foo` where `Auto` would select a pointer-sized relocation.
+    /// On Mach-O, the addend is written into the relocated contents, and on ELF, it is only
+    /// supported for x86_64. The addend is signed, e.g. -4 for the address just below the
+    /// target; written into the contents, it is sign-extended to the size of the field.
+    Absolute {
+        /// Size (in bytes) of the relocated field, 4 or 8
+        size: u8,
+        /// Addend for the relocation
+        addend: i32,
+    },
     /// A relocation in a debug section.
+    /// On ELF, like any relocation to a definition or section, it targets the section symbol of
+    /// the target's section, with the addend. On Mach-O, a relocation to another debug section
+    /// refers to it by its ordinal rather than by a symbol.
     Debug {
         /// Size (in bytes) of the pointer to be relocated
         size: u8,
         /// Addend for the relocation
         addend: i32,
     },
+    /// A Mach-O scattered relocation of the 4-byte absolute address of the target plus the
+    /// addend. It refers to the target by address rather than by symbol, so that the linker can
+    /// tell which part of a section it refers to even when the addend takes it past the target,
+    /// e.g. into another local symbol. Classic 32-bit Mach-O requires it for such references.
+    /// **NB**: only 32-bit Mach-O supports scattered relocations, and the target must be defined.
+    Scattered {
+        /// Raw relocation, as an integer value to be encoded by the backend
+        reloc: u32,
+        /// Addend for the relocation, written into the relocated contents
+        addend: i32,
+    },
+}
+
+/// The Mach-O file type to emit; ignored by the other backends.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MachFileType {
+    /// A relocatable object file (`MH_OBJECT`), the default
+    Object,
+    /// A dynamically bound bundle (`MH_BUNDLE`).
+    /// **NB**: faerie does not emit the dyld information a bundle requires, so emitting will return
+    /// an error unless an `LC_DYLD_INFO` or `LC_DYLD_INFO_ONLY` load command is
+    /// [added](struct.ArtifactBuilder.html#method.mach_load_command).
+    Bundle,
+    /// A dynamically bound shared library (`MH_DYLIB`).
+    /// **NB**: as for a bundle, emitting will return an error unless an `LC_DYLD_INFO` or
+    /// `LC_DYLD_INFO_ONLY` load command, and an `LC_ID_DYLIB` load command naming the library, are
+    /// [added](struct.ArtifactBuilder.html#method.mach_load_command).
+    Dylib,
+}
+
+/// The kind of section a defined symbol is placed in, see
+/// [`section_kind_of`](struct.Artifact.html#method.section_kind_of)
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SymbolSection {
+    /// Code, from a function
+    Text,
+    /// Initialized data
+    Data,
+    /// String data
+    CString,
+    /// Mergeable 4-byte constants
+    Literal4,
+    /// Mergeable 8-byte constants
+    Literal8,
+    /// Thread-local variables
+    ThreadLocal,
+    /// Zero-initialized data
+    Bss,
+    /// Common data, which the linker allocates
+    Common,
+    /// A custom section of the given kind
+    Custom(SectionKind),
+}
+
+/// How the maximum and initial protections of the Mach-O segment are chosen; ignored by the
+/// other backends.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MachProtection {
+    /// Readable, writable and executable, whatever the segment contains, the default
+    ReadWriteExecute,
+    /// Readable, writable only if there is writable data, and executable only if there is code
+    Computed,
+}
+
+/// The order of the symbols in the Mach-O symbol table, and so the symbol numbers relocations
+/// refer to them by; ignored by the other backends. The dynamic symbol table requires the local
+/// symbols, then the defined external symbols, then the undefined ones, so every order arranges
+/// the symbols within each of those groups.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MachSymbolOrder {
+    /// The order the symbols are first laid out in, section by section, then the imports; the
+    /// default
+    InsertionOrder,
+    /// Sorted by name
+    Alphabetical,
+    /// The local symbols, then the defined external symbols, then the undefined ones, each
+    /// sorted by name, as ld64 lays out linked images
+    LocalsThenGlobals,
+    /// The defined symbols sorted by address, then the undefined ones sorted by name
+    Address,
+}
+
+/// The UUID of a Mach-O object, emitted in its `LC_UUID` command, which debuggers and symbol
+/// servers match it to its dSYM by; ignored by the other backends.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MachUuid {
+    /// This UUID
+    Fixed([u8; 16]),
+    /// A name-based UUID derived from a hash of the rest of the object file, so emitting the same
+    /// object always gives it the same UUID
+    Hashed,
+}
+
+/// How the code of an artifact addresses data, which determines the relocations `Reloc::Auto`
+/// infers for references from functions to data.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RelocationModel {
+    /// Position-independent code, which addresses data relative to the instruction pointer, and
+    /// imported data through the GOT; the default
+    Pic,
+    /// Code at a fixed address, which addresses data without the GOT: by its absolute, 32-bit
+    /// sign-extended address when the output is ELF, and relative to the instruction pointer
+    /// when the output is Mach-O, which has no 32-bit absolute relocations for x86_64
+    Static,
 }
 
 type StringID = usize;
@@ -60,6 +330,9 @@ pub enum ArtifactError {
     #[fail(display = "Attempt to define an undefined import: {}", _0)]
     /// Attempt to define an undefined import
     ImportDefined(String),
+    #[fail(display = "Declared but undefined symbol: {}", _0)]
+    /// A non-import declaration without a definition
+    Undefined(String),
     #[fail(display = "Attempt to add a relocation to an import: {}", _0)]
     /// Attempt to use a relocation inside an import
     RelocateImport(String),
@@ -84,13 +357,199 @@ pub enum ArtifactError {
     )]
     /// ZeroInit is only allowed for data
     InvalidZeroInit(DefinedDecl),
+    #[fail(
+        display = "Common symbol {} must be zero-initialized data, without custom symbols or links",
+        _0
+    )]
+    /// Only zero-initialized data, which is not thread-local and has no custom symbols, can be
+    /// common, and links cannot be from a common symbol, which has no contents
+    InvalidCommon(String),
+
+    /// Attempt to undeclare a symbol which is still defined or referenced by a link, or to remove
+    /// a symbol which is still referenced by a link from another symbol, or by a re-export.
+    #[fail(
+        display = "Attempt to remove a symbol which is still defined or linked: {}",
+        _0
+    )]
+    ReferencedDeclaration(String),
+
+    /// Attempt to rename a symbol to a name which is already declared.
+    #[fail(
+        display = "Attempt to rename a symbol to an already declared name: {}",
+        _0
+    )]
+    AlreadyDeclared(String),
+
+    /// Attempt to declare a symbol with an alignment which is not a power of two.
+    #[fail(
+        display = "Alignment of {} must be a nonzero power of two, got {}",
+        name, align
+    )]
+    InvalidAlignment {
+        /// The symbol being declared
+        name: String,
+        /// The requested alignment, in bytes
+        align: u64,
+    },
+
+    /// Attempt to re-export a re-export, or to re-export a symbol which another re-export refers to.
+    #[fail(display = "Re-exports cannot refer to other re-exports: {}", _0)]
+    ChainedReexport(String),
+
+    /// Attempt to add symbols or relocations to a raw section.
+    #[fail(display = "Raw sections cannot have symbols or relocations: {}", _0)]
+    RawSection(String),
+
+    /// Attempt to define a symbol with data of a size other than its declared size.
+    #[fail(
+        display = "Definition of {} has {} bytes, but it was declared with {}",
+        name, defined, declared
+    )]
+    SizeMismatch {
+        /// The symbol being defined
+        name: String,
+        /// The declared size, in bytes
+        declared: u64,
+        /// The size of the definition, in bytes
+        defined: u64,
+    },
+
+    /// Attempt to define sparse data whose chunks are out of order, overlap, or lie beyond its
+    /// size.
+    #[fail(display = "Invalid chunks of sparse data for symbol: {}", _0)]
+    InvalidSparseData(String),
+
+    /// A section boundary import whose name does not follow the convention of the binary format.
+    #[fail(display = "Invalid name for a section boundary: {}", _0)]
+    InvalidSectionBoundary(String),
+
+    /// Attempt to declare a symbol, or define a custom symbol, with an empty name.
+    #[fail(display = "Symbol names cannot be empty")]
+    EmptyName,
+
+    /// A custom symbol of a definition has the name of a declaration, or of another custom symbol.
+    #[fail(display = "Custom symbol is already declared or defined: {}", _0)]
+    DuplicateSymbol(String),
+
+    /// A link is at an offset past the end of the definition it is from.
+    #[fail(
+        display = "Link from {} at {:#x} is past the end of its {} bytes",
+        from, at, size
+    )]
+    LinkOutOfBounds {
+        /// The symbol the link is from
+        from: String,
+        /// The offset of the link
+        at: u64,
+        /// The size of the definition the link is from
+        size: u64,
+    },
+
+    /// Attempt to emit an object file in a binary format which has no backend.
+    #[fail(display = "Unsupported binary format: {}", _0)]
+    UnsupportedFormat(BinaryFormat),
+
+    /// Attempt to emit an object file for an architecture its binary format cannot express.
+    #[fail(display = "Unsupported architecture for the binary format: {}", _0)]
+    UnsupportedArchitecture(Architecture),
+
+    /// A link with a relocation which the binary format or architecture cannot express.
+    #[fail(display = "Unsupported relocation from {} to {}: {}", from, to, reason)]
+    UnsupportedRelocation {
+        /// The symbol the relocation is from
+        from: String,
+        /// The symbol the relocation is to
+        to: String,
+        /// Why the relocation cannot be expressed
+        reason: String,
+    },
+
+    /// A value which is too large for the field of the object file it is written to.
+    #[fail(display = "Value {:#x} overflows {}", value, field)]
+    RelocationOverflow {
+        /// The overflowing field
+        field: &'static str,
+        /// The value that does not fit
+        value: u64,
+    },
 
-    /// A non section declaration got custom symbols during definition.
+    /// Attempt to refer to a link which was never added.
+    #[fail(display = "No link from {} to {} at {:#x}", from, to, at)]
+    NoSuchLink {
+        /// The symbol the link would be from
+        from: String,
+        /// The symbol the link would be to
+        to: String,
+        /// The offset the link would be at
+        at: u64,
+    },
+
+    /// A symbol which the binary format, or the kind of object file being emitted, cannot express.
+    #[fail(display = "Unsupported symbol {}: {}", name, reason)]
+    UnsupportedSymbol {
+        /// The unsupported symbol
+        name: String,
+        /// Why the symbol cannot be expressed
+        reason: String,
+    },
+
+    /// Attempt to emit a Mach-O file type without a load command it requires, which faerie does
+    /// not emit itself.
     #[fail(
-        display = "Attempt to add custom symbols {:?} to non section declaration {:?}",
-        _1, _0
+        display = "Mach-O file type {:?} requires the load command {}",
+        filetype, command
     )]
-    NonSectionCustomSymbols(DefinedDecl, BTreeMap<String, u64>),
+    MissingLoadCommand {
+        /// The file type being emitted
+        filetype: MachFileType,
+        /// The name of the missing load command
+        command: &'static str,
+    },
+
+    /// A Mach-O segment name longer than 16 bytes, or an empty segment name or one containing a NUL
+    /// byte for a custom section.
+    #[fail(display = "Invalid Mach-O segment name: {:?}", _0)]
+    InvalidSegmentName(String),
+
+    /// Attempt to add a Mach-O load command which faerie emits itself.
+    #[fail(
+        display = "Load command {} is emitted by faerie, and cannot be added",
+        _0
+    )]
+    ReservedLoadCommand(&'static str),
+
+    /// Mach-O header flags which describe a linked image, rather than a relocatable object.
+    #[fail(display = "Header flags {:#x} are not valid for MH_OBJECT", _0)]
+    InvalidHeaderFlags(u32),
+
+    /// Attempt to emit a fat object without any artifacts.
+    #[fail(display = "A fat object needs at least one artifact")]
+    EmptyFatObject,
+
+    /// An artifact which does not target the architecture it is paired with in a fat object.
+    #[fail(display = "Artifact {} targets {}, not {}", name, target, architecture)]
+    ArchitectureMismatch {
+        /// The name of the artifact
+        name: String,
+        /// The architecture the artifact targets
+        target: Architecture,
+        /// The architecture the artifact is paired with
+        architecture: Architecture,
+    },
+
+    /// Attempt to put more than one object for an architecture in a fat object.
+    #[fail(display = "A fat object can only have one {} object", _0)]
+    DuplicateArchitecture(Architecture),
+
+    /// A custom section has the name of the section faerie puts another definition in, or of
+    /// another custom section.
+    #[fail(display = "Section {} is the section of more than one definition", _0)]
+    SectionNameCollision(String),
+
+    /// Attempt to emit an artifact which is not valid, with every problem
+    /// [`validate`](struct.Artifact.html#method.validate) found.
+    #[fail(display = "The artifact is not valid and cannot be emitted: {:?}", _0)]
+    Invalid(Vec<ArtifactError>),
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -115,6 +574,10 @@ impl Into<Data> for Vec<u8> {
 }
 
 impl Data {
+    /// Create data of `size` bytes which are produced by `produce` when the artifact is emitted.
+    pub fn lazy<F: Fn() -> Vec<u8> + Send + Sync + 'static>(size: usize, produce: F) -> Data {
+        Data::Lazy(Arc::new(LazyFn { size, produce }))
+    }
     /// Return the number of bytes of _disk_ this data will use.
     ///
     /// This is different from the bytes of _memory_ for the `ZeroInit` variant,
@@ -123,24 +586,171 @@ impl Data {
         match self {
             Data::Blob(blob) => blob.len(),
             Data::ZeroInit(_) => 0,
+            Data::Lazy(lazy) => lazy.size(),
+            Data::Sparse { size, .. } => *size,
         }
     }
-    /// Return whether the data has at least one byte defined
-    pub fn is_empty(&self) -> bool {
+    /// Return the number of bytes of _memory_ this data will use once loaded.
+    pub fn size(&self) -> usize {
         match self {
-            Data::Blob(blob) => blob.is_empty(),
-            Data::ZeroInit(size) => *size == 0,
+            Data::Blob(blob) => blob.len(),
+            Data::ZeroInit(size) => *size,
+            Data::Lazy(lazy) => lazy.size(),
+            Data::Sparse { size, .. } => *size,
         }
     }
+    /// Return whether the data has at least one byte defined
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
     /// Return whether this data is a ZeroInit variant
     pub fn is_zero_init(&self) -> bool {
         match self {
             Data::ZeroInit(_) => true,
-            Data::Blob(_) => false,
+            Data::Blob(_) | Data::Lazy(_) | Data::Sparse { .. } => false,
+        }
+    }
+    /// Whether the chunks of sparse data are in order, do not overlap, and lie within its size;
+    /// other data is always valid
+    fn is_valid(&self) -> bool {
+        match self {
+            Data::Sparse { size, chunks, .. } => {
+                let mut end = 0;
+                for (offset, bytes) in chunks {
+                    if *offset < end {
+                        return false;
+                    }
+                    end = offset + bytes.len();
+                }
+                end <= *size
+            }
+            Data::Blob(_) | Data::ZeroInit(_) | Data::Lazy(_) => true,
+        }
+    }
+    /// The datatype of this data when it is declared with `datatype`: a `String` which is not a
+    /// single NUL-terminated C string, e.g. one with embedded NULs, or lazy or sparse data which
+    /// is not checked, is `Bytes`, since the linker splits sections of C strings at every NUL
+    pub(crate) fn datatype(&self, datatype: DataType) -> DataType {
+        match (datatype, self) {
+            (DataType::String, Data::Blob(bytes))
+                if bytes.iter().position(|&byte| byte == 0).map(|nul| nul + 1)
+                    == Some(bytes.len()) =>
+            {
+                DataType::String
+            }
+            (DataType::String, Data::Blob(_))
+            | (DataType::String, Data::Lazy(_))
+            | (DataType::String, Data::Sparse { .. }) => DataType::Bytes,
+            (datatype, _) => datatype,
+        }
+    }
+    /// Write the `file_size` bytes of this data in the file to `sink`, producing them if they are
+    /// lazy
+    pub(crate) fn write_to<W: Write>(&self, sink: &mut W) -> io::Result<()> {
+        match self {
+            Data::Blob(blob) => sink.write_all(blob),
+            Data::ZeroInit(_) => Ok(()),
+            Data::Lazy(lazy) => {
+                let mut sink = CountingWriter {
+                    inner: sink,
+                    count: 0,
+                };
+                lazy.write_to(&mut sink)?;
+                if sink.count != lazy.size() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "lazy data produced {} bytes, but its size is {}",
+                            sink.count,
+                            lazy.size()
+                        ),
+                    ));
+                }
+                Ok(())
+            }
+            Data::Sparse { size, fill, chunks } => {
+                let mut offset = 0;
+                for (chunk_offset, bytes) in chunks {
+                    write_fill(sink, *fill, chunk_offset - offset)?;
+                    sink.write_all(bytes)?;
+                    offset = chunk_offset + bytes.len();
+                }
+                write_fill(sink, *fill, size - offset)
+            }
         }
     }
 }
 
+/// Write `count` bytes of `fill` to `sink`, a block at a time
+fn write_fill<W: Write>(sink: &mut W, fill: u8, mut count: usize) -> io::Result<()> {
+    let block = [fill; 4096];
+    while count > 0 {
+        let len = std::cmp::min(count, block.len());
+        sink.write_all(&block[..len])?;
+        count -= len;
+    }
+    Ok(())
+}
+
+/// Whether `name` is a symbol the linker defines at a boundary of a section, in `format`; when the
+/// format is unknown, either convention will do
+fn is_section_boundary(name: &str, format: BinaryFormat) -> bool {
+    let elf = || {
+        let section = match name
+            .strip_prefix("__start_")
+            .or_else(|| name.strip_prefix("__stop_"))
+        {
+            Some(section) => section,
+            None => return false,
+        };
+        // the linker only defines the boundaries of sections whose names are C identifiers
+        match section.chars().next() {
+            Some(c) if !c.is_ascii_digit() => section
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            _ => false,
+        }
+    };
+    let mach = || {
+        let names = match name
+            .strip_prefix("section$start$")
+            .or_else(|| name.strip_prefix("section$end$"))
+        {
+            Some(names) => names,
+            None => return false,
+        };
+        let mut names = names.splitn(2, '$');
+        match (names.next(), names.next()) {
+            (Some(segment), Some(section)) => [segment, section]
+                .iter()
+                .all(|name| !name.is_empty() && name.len() <= 16 && !name.contains('$')),
+            _ => false,
+        }
+    };
+    match format {
+        BinaryFormat::Elf => elf(),
+        BinaryFormat::Macho => mach(),
+        _ => elf() || mach(),
+    }
+}
+
+/// Whether `name` is a pseudo-symbol the linker defines in every image it links, like the Mach
+/// header or ELF's GOT base, which may be linked to without being declared; as for other symbols,
+/// the names are those of C, before Mach-O prefixes them with an underscore
+fn is_linker_defined(name: &str, format: BinaryFormat) -> bool {
+    match format {
+        BinaryFormat::Elf => name == elf::GLOBAL_OFFSET_TABLE,
+        BinaryFormat::Macho => [
+            "_mh_execute_header",
+            "_mh_dylib_header",
+            "_mh_bundle_header",
+            "__dso_handle",
+        ]
+        .contains(&name),
+        _ => false,
+    }
+}
+
 impl InternalDecl {
     /// Wrap up a declaration. Initially marked as not defined.
     pub fn new(decl: Decl) -> Self {
@@ -184,7 +794,7 @@ pub(crate) struct Definition<'a> {
     pub name: &'a str,
     /// Contents of definition
     pub data: &'a Data,
-    /// Custom symbols referencing this section, or none for other definition types.
+    /// Custom symbols at offsets into this definition, in addition to the definition's own symbol.
     pub symbols: &'a BTreeMap<String, u64>,
     /// Declaration of symbol
     pub decl: &'a DefinedDecl,
@@ -213,11 +823,84 @@ pub struct Link<'a> {
     pub at: u64,
 }
 
+/// The placement of a section in an emitted object file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedSection {
+    /// The section's name, as written in the object file
+    pub name: String,
+    /// The byte offset of the section's contents in the object file
+    pub offset: u64,
+    /// The size of the section in bytes, as recorded in its section header
+    pub size: u64,
+}
+
+/// A relocation in an emitted object file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedReloc {
+    /// The name of the section the relocation is applied to
+    pub section: String,
+    /// The byte offset of the relocation, relative to the start of `section`
+    pub offset: u64,
+    /// The name of the symbol being relocated against, i.e., the `to` of the link
+    pub symbol: String,
+    /// The backend-specific relocation type
+    pub reloc: u32,
+    /// The size of the relocated field in bytes, if the backend knows it; e.g. ELF relocations
+    /// which patch an instruction's encoding have no size of their own
+    pub size: Option<u8>,
+    /// The explicit addend of the relocation; always 0 for formats with implicit addends, like
+    /// Mach-O, or ELF on architectures with `SHT_REL` relocations
+    pub addend: i64,
+}
+
+/// A defined symbol in an emitted object file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedSymbol {
+    /// The symbol's name, without any prefix added by the backend
+    pub name: String,
+    /// The name of the section the symbol is defined in
+    pub section: String,
+    /// The byte offset of the symbol, relative to the start of `section`
+    pub offset: u64,
+}
+
+/// The layout an object file would be emitted with: where every section, defined symbol and
+/// relocation ends up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// The placement of every section
+    pub sections: Vec<ResolvedSection>,
+    /// The placement of every defined symbol
+    pub symbols: Vec<ResolvedSymbol>,
+    /// Every relocation
+    pub relocations: Vec<ResolvedReloc>,
+}
+
+/// An emitted object file, along with the placement of every defined section and every relocation
+/// in it
+pub type ResolvedObject = (Vec<u8>, Vec<ResolvedSection>, Vec<ResolvedReloc>);
+
 /// Builder for creating an artifact
 pub struct ArtifactBuilder {
     target: Triple,
     name: Option<String>,
     library: bool,
+    mach_filetype: MachFileType,
+    mach_function_starts: bool,
+    mach_protection: MachProtection,
+    mach_section_alignments: BTreeMap<String, u64>,
+    mach_section_fills: BTreeMap<String, u8>,
+    mach_header_flags: Option<u32>,
+    mach_symbol_order: MachSymbolOrder,
+    mach_section_order: Vec<String>,
+    mach_segment_name: Option<String>,
+    mach_load_commands: Vec<(u32, Vec<u8>)>,
+    mach_uuid: Option<MachUuid>,
+    relocation_model: RelocationModel,
+    prune_imports: bool,
+    elf_osabi: Option<u8>,
+    elf_abiversion: u8,
+    elf_flags: Option<u32>,
 }
 
 impl ArtifactBuilder {
@@ -227,6 +910,22 @@ impl ArtifactBuilder {
             target,
             name: None,
             library: false,
+            mach_filetype: MachFileType::Object,
+            mach_function_starts: false,
+            mach_protection: MachProtection::ReadWriteExecute,
+            mach_section_alignments: BTreeMap::new(),
+            mach_section_fills: BTreeMap::new(),
+            mach_header_flags: None,
+            mach_symbol_order: MachSymbolOrder::InsertionOrder,
+            mach_section_order: Vec::new(),
+            mach_segment_name: None,
+            mach_load_commands: Vec::new(),
+            mach_uuid: None,
+            relocation_model: RelocationModel::Pic,
+            prune_imports: false,
+            elf_osabi: None,
+            elf_abiversion: 0,
+            elf_flags: None,
         }
     }
     /// Set this artifacts name
@@ -239,11 +938,116 @@ impl ArtifactBuilder {
         self.library = is_library;
         self
     }
+    /// Set the file type to emit when the output is Mach-O
+    pub fn mach_filetype(mut self, filetype: MachFileType) -> Self {
+        self.mach_filetype = filetype;
+        self
+    }
+    /// Set whether to emit an `LC_FUNCTION_STARTS` table when the output is Mach-O
+    pub fn mach_function_starts(mut self, function_starts: bool) -> Self {
+        self.mach_function_starts = function_starts;
+        self
+    }
+    /// Set how the segment protections are chosen when the output is Mach-O
+    pub fn mach_protection(mut self, protection: MachProtection) -> Self {
+        self.mach_protection = protection;
+        self
+    }
+    /// Set the minimum alignment, in bytes, of the Mach-O section `sectname` (one of `__text`,
+    /// `__data`, `__cstring` or `__bss`), even if none of its members are as aligned
+    pub fn mach_section_align(mut self, sectname: &str, align: u64) -> Self {
+        self.mach_section_alignments
+            .insert(sectname.to_string(), align);
+        self
+    }
+    /// Set the byte the padding between the members of the Mach-O section `sectname` is filled
+    /// with, e.g. 0 to match other toolchains' output, instead of `0xcc` for code and `0xaa`
+    /// otherwise
+    pub fn mach_section_fill(mut self, sectname: &str, fill: u8) -> Self {
+        self.mach_section_fills.insert(sectname.to_string(), fill);
+        self
+    }
+    /// Set the raw Mach-O header flags, instead of `MH_SUBSECTIONS_VIA_SYMBOLS`
+    pub fn mach_header_flags(mut self, flags: u32) -> Self {
+        self.mach_header_flags = Some(flags);
+        self
+    }
+    /// Set the order of the symbol table when the output is Mach-O
+    pub fn mach_symbol_order(mut self, order: MachSymbolOrder) -> Self {
+        self.mach_symbol_order = order;
+        self
+    }
+    /// Set the names of the Mach-O sections, e.g. `__data`, which are laid out first, in this
+    /// order, when the output is Mach-O
+    pub fn mach_section_order(mut self, sectnames: Vec<String>) -> Self {
+        self.mach_section_order = sectnames;
+        self
+    }
+    /// Set the name of the segment when the output is Mach-O, instead of the empty name
+    pub fn mach_segment_name(mut self, segname: String) -> Self {
+        self.mach_segment_name = Some(segname);
+        self
+    }
+    /// Add a load command of type `cmd` with the contents `payload`, which follow its `cmd` and
+    /// `cmdsize` fields, when the output is Mach-O, e.g. an `LC_LINKER_OPTION` requesting `-lm`
+    pub fn mach_load_command(mut self, cmd: u32, payload: Vec<u8>) -> Self {
+        self.mach_load_commands.push((cmd, payload));
+        self
+    }
+    /// Set the UUID of the `LC_UUID` command emitted when the output is Mach-O, which is not
+    /// emitted otherwise
+    pub fn mach_uuid(mut self, uuid: MachUuid) -> Self {
+        self.mach_uuid = Some(uuid);
+        self
+    }
+    /// Set how the artifact's code addresses data, which determines the relocations
+    /// `Reloc::Auto` infers for references from functions to data
+    pub fn relocation_model(mut self, model: RelocationModel) -> Self {
+        self.relocation_model = model;
+        self
+    }
+    /// Set whether imports which no link refers to are left out of the emitted symbol table
+    pub fn prune_imports(mut self, prune: bool) -> Self {
+        self.prune_imports = prune;
+        self
+    }
+    /// Set the `EI_OSABI` byte of the ident when the output is ELF, instead of choosing it
+    /// automatically
+    pub fn elf_osabi(mut self, osabi: u8) -> Self {
+        self.elf_osabi = Some(osabi);
+        self
+    }
+    /// Set the `EI_ABIVERSION` byte of the ident when the output is ELF
+    pub fn elf_abiversion(mut self, abiversion: u8) -> Self {
+        self.elf_abiversion = abiversion;
+        self
+    }
+    /// Set the processor specific `e_flags` of the header when the output is ELF
+    pub fn elf_flags(mut self, flags: u32) -> Self {
+        self.elf_flags = Some(flags);
+        self
+    }
     /// Build into an Artifact
     pub fn finish(self) -> Artifact {
         let name = self.name.unwrap_or_else(|| "faerie.o".to_owned());
         let mut artifact = Artifact::new(self.target, name);
         artifact.is_library = self.library;
+        artifact.mach_filetype = self.mach_filetype;
+        artifact.mach_function_starts = self.mach_function_starts;
+        artifact.mach_protection = self.mach_protection;
+        artifact.mach_section_alignments = self.mach_section_alignments;
+        artifact.mach_section_fills = self.mach_section_fills;
+        artifact.mach_header_flags = self.mach_header_flags;
+        artifact.mach_symbol_order = self.mach_symbol_order;
+        artifact.mach_section_order = self.mach_section_order;
+        artifact.mach_segment_name = self.mach_segment_name;
+        artifact.mach_load_commands = self.mach_load_commands;
+        artifact.mach_uuid = self.mach_uuid;
+        artifact.relocation_model = self.relocation_model;
+        artifact.prune_imports = self.prune_imports;
+        artifact.elf_osabi = self.elf_osabi;
+        artifact.elf_abiversion = self.elf_abiversion;
+        artifact.elf_flags = self.elf_flags;
         artifact
     }
 }
@@ -257,12 +1061,73 @@ pub struct Artifact {
     pub target: Triple,
     /// Whether this is a static library or not
     pub is_library: bool,
+    /// The file type to emit when the output is Mach-O
+    pub mach_filetype: MachFileType,
+    /// Whether to emit an `LC_FUNCTION_STARTS` table, listing the file offset of every function,
+    /// when the output is Mach-O
+    pub mach_function_starts: bool,
+    /// How the segment protections are chosen when the output is Mach-O
+    pub mach_protection: MachProtection,
+    /// The minimum alignment, in bytes, of the Mach-O sections `__text`, `__data`, `__cstring`
+    /// and `__bss`, keyed by section name, when the output is Mach-O. Members of these sections
+    /// are still only padded to their own alignment
+    pub mach_section_alignments: BTreeMap<String, u64>,
+    /// The byte the padding between the members of a Mach-O section is filled with, keyed by
+    /// section name, when the output is Mach-O. Sections without one are padded with `0xcc`, a
+    /// debug interrupt on x86, if they contain code, and `0xaa` otherwise
+    pub mach_section_fills: BTreeMap<String, u8>,
+    /// The flags of the header when the output is Mach-O. If `None`, this is
+    /// `MH_SUBSECTIONS_VIA_SYMBOLS`; flags only meaningful for linked images, like `MH_PIE`, are
+    /// rejected when emitting an `MH_OBJECT`
+    pub mach_header_flags: Option<u32>,
+    /// The order of the symbol table when the output is Mach-O
+    pub mach_symbol_order: MachSymbolOrder,
+    /// The names of the Mach-O sections, e.g. `__data`, which are laid out first, in this order,
+    /// when the output is Mach-O. The other sections follow in the default order: `__text`,
+    /// `__data`, `__cstring`, `__bss`, the literal and coalesced sections, the thread-local
+    /// sections, the custom sections in the order they were declared, then `__compact_unwind`.
+    /// Zero-filled sections are still placed at the end of the segment's memory
+    pub mach_section_order: Vec<String>,
+    /// The name of the segment containing every section when the output is Mach-O. If `None`,
+    /// this is empty, as is conventional for `MH_OBJECT`; names longer than 16 bytes are rejected
+    pub mach_segment_name: Option<String>,
+    /// Additional load commands, each a type and the contents following its `cmd` and `cmdsize`
+    /// fields, which follow the load commands faerie emits when the output is Mach-O. The
+    /// contents are padded to a pointer size; the segment, symbol table and dynamic symbol table
+    /// commands are rejected, since faerie emits those itself
+    pub mach_load_commands: Vec<(u32, Vec<u8>)>,
+    /// The UUID of the `LC_UUID` command when the output is Mach-O. If `None`, there is no
+    /// `LC_UUID` command
+    pub mach_uuid: Option<MachUuid>,
+    /// How the artifact's code addresses data, which determines the relocations `Reloc::Auto`
+    /// infers for references from functions to data
+    pub relocation_model: RelocationModel,
+    /// Whether imports which no link refers to, e.g. those a frontend declared speculatively,
+    /// are left out of the emitted symbol table. Re-exports, and the imports they refer to, are
+    /// always emitted
+    pub prune_imports: bool,
+    /// The `EI_OSABI` byte of the ident when the output is ELF. If `None`, this is
+    /// `ELFOSABI_GNU` (3) when GNU extensions such as retained sections are used, and
+    /// `ELFOSABI_NONE` (System V) otherwise
+    pub elf_osabi: Option<u8>,
+    /// The `EI_ABIVERSION` byte of the ident when the output is ELF
+    pub elf_abiversion: u8,
+    /// The processor specific `e_flags` of the header when the output is ELF, such as the
+    /// RISC-V float ABI. If `None`, this is derived from the target, e.g. `riscv64gc` objects use
+    /// compressed instructions and the double-precision float ABI
+    pub elf_flags: Option<u32>,
+    source_file: Option<String>,
     // will keep this for now; may be useful to pre-partition code and data vectors, not sure
     imports: Vec<(StringID, ImportKind)>,
+    import_libraries: BTreeMap<StringID, StringID>,
+    weak_imports: BTreeSet<StringID>,
+    stub_imports: BTreeSet<StringID>,
+    reexports: BTreeMap<StringID, StringID>,
     links: Vec<Relocation>,
     declarations: IndexMap<StringID, InternalDecl>,
     local_definitions: BTreeSet<InternalDefinition>,
     nonlocal_definitions: BTreeSet<InternalDefinition>,
+    anonymous_definitions: usize,
     strings: StringInterner<StringID>,
 }
 
@@ -272,24 +1137,177 @@ impl Artifact {
     pub fn new(target: Triple, name: String) -> Self {
         Artifact {
             imports: Vec::new(),
+            import_libraries: BTreeMap::new(),
+            weak_imports: BTreeSet::new(),
+            stub_imports: BTreeSet::new(),
+            reexports: BTreeMap::new(),
             links: Vec::new(),
             name,
             target,
             is_library: false,
+            mach_filetype: MachFileType::Object,
+            mach_function_starts: false,
+            mach_protection: MachProtection::ReadWriteExecute,
+            mach_section_alignments: BTreeMap::new(),
+            mach_section_fills: BTreeMap::new(),
+            mach_header_flags: None,
+            mach_symbol_order: MachSymbolOrder::InsertionOrder,
+            mach_section_order: Vec::new(),
+            mach_segment_name: None,
+            mach_load_commands: Vec::new(),
+            mach_uuid: None,
+            relocation_model: RelocationModel::Pic,
+            prune_imports: false,
+            elf_osabi: None,
+            elf_abiversion: 0,
+            elf_flags: None,
+            source_file: None,
             declarations: IndexMap::new(),
             local_definitions: BTreeSet::new(),
             nonlocal_definitions: BTreeSet::new(),
+            anonymous_definitions: 0,
             strings: StringInterner::new(),
         }
     }
-    /// Get an iterator over this artifact's imports
-    pub fn imports<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a str, &'a ImportKind)> + 'a> {
+    /// Set the path of the source file this artifact was compiled from, which names the leading
+    /// `STT_FILE` symbol when the output is ELF, instead of the artifact's name. Mach-O objects
+    /// have no such symbol; the linker builds the debug map `dsymutil` reads from their DWARF
+    pub fn set_source_file<T: AsRef<str>>(&mut self, path: T) {
+        self.source_file = Some(path.as_ref().to_string());
+    }
+    /// Get the path of the source file this artifact was compiled from, if it was set
+    pub fn source_file(&self) -> Option<&str> {
+        self.source_file.as_deref()
+    }
+    /// Remove all declarations, definitions, links, and imports, so this artifact can be reused to
+    /// build another object without reallocating. The name, target, and other options are kept.
+    ///
+    /// **NB**: Symbol names stay interned, so names repeated across objects are not reallocated.
+    pub fn clear(&mut self) {
+        self.imports.clear();
+        self.import_libraries.clear();
+        self.weak_imports.clear();
+        self.stub_imports.clear();
+        self.reexports.clear();
+        self.links.clear();
+        self.declarations.clear();
+        self.local_definitions.clear();
+        self.nonlocal_definitions.clear();
+        self.anonymous_definitions = 0;
+    }
+    /// Get an iterator over this artifact's imports, and whether each is a function or data import
+    pub fn imports<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a str, ImportKind)> + 'a> {
+        Box::new(
+            self.imports
+                .iter()
+                .map(move |&(id, kind)| (self.strings.resolve(id).unwrap(), kind)),
+        )
+    }
+    /// Get an iterator over the imports which are the `to` of a link, or are re-exported, and so
+    /// are needed by the object file; the others can be
+    /// [undeclared](struct.Artifact.html#method.undeclare), or pruned with
+    /// [`prune_imports`](struct.ArtifactBuilder.html#method.prune_imports), to leave their
+    /// undefined symbols, and on Mach-O possibly their `LC_LOAD_DYLIB` commands, out of it
+    pub fn referenced_imports<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (&'a str, ImportKind)> + 'a> {
+        Box::new(
+            self.imports
+                .iter()
+                .filter(move |&&(id, _)| self.is_referenced_import(id))
+                .map(move |&(id, kind)| (self.strings.resolve(id).unwrap(), kind)),
+        )
+    }
+    /// Whether some link, or re-export, refers to the import `id`
+    fn is_referenced_import(&self, id: StringID) -> bool {
+        self.links.iter().any(|&(_, to, _, _)| to == id)
+            || self.reexports.values().any(|&import| import == id)
+    }
+    /// The imports to emit: every import, or only those which are referenced or re-exported
+    /// when `prune_imports` is set
+    pub(crate) fn emitted_imports<'a>(
+        &'a self,
+    ) -> Box<dyn Iterator<Item = (&'a str, ImportKind)> + 'a> {
+        if !self.prune_imports {
+            return self.imports();
+        }
         Box::new(
             self.imports
                 .iter()
-                .map(move |&(id, ref kind)| (self.strings.resolve(id).unwrap(), kind)),
+                .filter(move |&&(id, _)| {
+                    self.is_referenced_import(id) || self.reexports.contains_key(&id)
+                })
+                .map(move |&(id, kind)| (self.strings.resolve(id).unwrap(), kind)),
         )
     }
+    /// Get the kind of section the defined symbol `name` is placed in, which is the section of
+    /// the definition containing it for custom symbols. Returns `None` if `name` is not defined.
+    pub fn section_kind_of<T: AsRef<str>>(&self, name: T) -> Option<SymbolSection> {
+        let name = name.as_ref();
+        let id = self.strings.get(name);
+        let def = self
+            .local_definitions
+            .iter()
+            .chain(self.nonlocal_definitions.iter())
+            .find(|def| Some(def.name) == id || def.symbols.contains_key(name))?;
+        Some(match def.decl {
+            DefinedDecl::Function(_) => SymbolSection::Text,
+            DefinedDecl::Data(d) if d.is_tls() => SymbolSection::ThreadLocal,
+            DefinedDecl::Data(_) if def.decl.is_common() => SymbolSection::Common,
+            DefinedDecl::Data(_) if def.data.is_zero_init() => SymbolSection::Bss,
+            DefinedDecl::Data(d) => match def.data.datatype(d.get_datatype()) {
+                DataType::Bytes => SymbolSection::Data,
+                DataType::String => SymbolSection::CString,
+                DataType::Literal4 => SymbolSection::Literal4,
+                DataType::Literal8 => SymbolSection::Literal8,
+            },
+            DefinedDecl::Section(s) => SymbolSection::Custom(s.kind()),
+        })
+    }
+    /// Get the alignment `name` was declared with, whether or not it is defined yet. Returns
+    /// `None` if `name` is an import, is not declared, or was declared without an alignment.
+    pub fn alignment_of<T: AsRef<str>>(&self, name: T) -> Option<u64> {
+        let id = self.strings.get(name.as_ref())?;
+        match self.declarations.get(&id)?.decl {
+            Decl::Defined(decl) => decl.get_align(),
+            Decl::Import(_) => None,
+        }
+    }
+    /// Get the dynamic library `import` was [imported from](struct.Artifact.html#method.import_from), if any
+    pub fn import_library<T: AsRef<str>>(&self, import: T) -> Option<&str> {
+        let import = self.strings.get(import.as_ref())?;
+        self.import_libraries
+            .get(&import)
+            .map(|&library| self.strings.resolve(library).unwrap())
+    }
+    /// Whether `import` was [imported weakly](struct.Artifact.html#method.import_weak)
+    pub fn is_weak_import<T: AsRef<str>>(&self, import: T) -> bool {
+        match self.strings.get(import.as_ref()) {
+            Some(import) => self.weak_imports.contains(&import),
+            None => false,
+        }
+    }
+    /// Whether `import` was [imported with a stub](struct.Artifact.html#method.import_with_stub)
+    pub fn is_stubbed_import<T: AsRef<str>>(&self, import: T) -> bool {
+        match self.strings.get(import.as_ref()) {
+            Some(import) => self.stub_imports.contains(&import),
+            None => false,
+        }
+    }
+    /// Get the import `name` is a [re-export](struct.Artifact.html#method.reexport) of, if any
+    pub fn reexported_import<T: AsRef<str>>(&self, name: T) -> Option<&str> {
+        let name = self.strings.get(name.as_ref())?;
+        self.reexports
+            .get(&name)
+            .map(|&import| self.strings.resolve(import).unwrap())
+    }
+    /// Whether `name` is declared, as an import or a definition
+    pub(crate) fn is_declared(&self, name: &str) -> bool {
+        match self.strings.get(name) {
+            Some(id) => self.declarations.contains_key(&id),
+            None => false,
+        }
+    }
     pub(crate) fn definitions<'a>(&'a self) -> Box<dyn Iterator<Item = Definition<'a>> + 'a> {
         Box::new(
             self.local_definitions
@@ -339,6 +1357,27 @@ impl Artifact {
         self.define(name, definition)?;
         Ok(())
     }
+    /// Declare and define local data without a name of its own, such as a constant pool entry
+    /// which is only referenced by links from code, and return the name it was given, e.g.
+    /// `anon.0`, to link to it with. The data is always local, so its symbol is not visible
+    /// outside of the object file.
+    pub fn declare_anonymous<D: Into<Data>>(
+        &mut self,
+        decl: DataDecl,
+        definition: D,
+    ) -> Result<String, ArtifactError> {
+        let name = loop {
+            let name = format!("anon.{}", self.anonymous_definitions);
+            self.anonymous_definitions += 1;
+            match self.strings.get(&name) {
+                Some(id) if self.declarations.contains_key(&id) => {}
+                _ => break name,
+            }
+        };
+        self.declare(&name, decl.local())?;
+        self.define_with_symbols(&name, definition, BTreeMap::new())?;
+        Ok(name)
+    }
     /// Declare a new symbolic reference, with the given `decl`.
     /// **Note**: All declarations _must_ precede their definitions.
     pub fn declare<T: AsRef<str>, D: Into<Decl>>(
@@ -347,6 +1386,27 @@ impl Artifact {
         decl: D,
     ) -> Result<(), ArtifactError> {
         let decl = decl.into();
+        if name.as_ref().is_empty() {
+            return Err(ArtifactError::EmptyName);
+        }
+        if decl == Decl::Import(ImportKind::SectionBoundary)
+            && !is_section_boundary(name.as_ref(), self.target.binary_format)
+        {
+            return Err(ArtifactError::InvalidSectionBoundary(
+                name.as_ref().to_string(),
+            ));
+        }
+        if let Decl::Defined(defined) = decl {
+            match defined.get_align() {
+                Some(align) if !align.is_power_of_two() => {
+                    return Err(ArtifactError::InvalidAlignment {
+                        name: name.as_ref().to_string(),
+                        align,
+                    });
+                }
+                _ => {}
+            }
+        }
         let decl_name = self.strings.get_or_intern(name.as_ref());
         let previous_was_import;
         let new_idecl = {
@@ -387,6 +1447,9 @@ impl Artifact {
                 let _ = self
                     .imports
                     .swap_remove(index.expect("previous import was not in the imports array"));
+                self.import_libraries.remove(&decl_name);
+                self.weak_imports.remove(&decl_name);
+                self.stub_imports.remove(&decl_name);
                 Ok(())
             }
             _ => Ok(()),
@@ -405,6 +1468,8 @@ impl Artifact {
     /// Defines a _previously declared_ program object with the given data.
     /// **NB**: If you attempt to define an import, this will return an error.
     /// If you attempt to define something which has not been declared, this will return an error.
+    /// Defining something which is already defined is also an error; use
+    /// [redefine](struct.Artifact.html#method.redefine) to replace a definition.
     ///
     /// See the documentation for [`Data`](type.Data.html) for the difference
     /// from `define_zero_init`.
@@ -416,6 +1481,9 @@ impl Artifact {
     /// Defines a _previously declared_ program object with all zeros.
     /// **NB**: If you attempt to define an import, this will return an error.
     /// If you attempt to define something which has not been declared, this will return an error.
+    ///
+    /// Like C tentative definitions, defining an object which is already defined with all zeros
+    /// is not an error: the definitions merge, keeping the larger size.
     #[inline]
     pub fn define_zero_init<T: AsRef<str>>(
         &mut self,
@@ -425,7 +1493,11 @@ impl Artifact {
         self.define_with_symbols(name, Data::ZeroInit(size), BTreeMap::new())
     }
 
-    /// Same as `define` but also allows to add custom symbols referencing a section decl.
+    /// Same as `define` but also allows to add custom symbols at offsets into the definition, e.g.
+    /// the fields of a struct or the entry points of a function.
+    ///
+    /// Custom symbols of functions and data have the same scope and visibility as the definition;
+    /// custom symbols of sections are global.
     ///
     /// # Examples
     ///
@@ -460,7 +1532,59 @@ impl Artifact {
         let data = data.into();
         match self.declarations.get_mut(&decl_name) {
             Some(ref mut stype) => {
+                if let Decl::Defined(decl) = stype.decl {
+                    match decl.get_size() {
+                        Some(declared) if declared != data.size() as u64 => {
+                            return Err(ArtifactError::SizeMismatch {
+                                name: name.as_ref().to_string(),
+                                declared,
+                                defined: data.size() as u64,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                if !data.is_valid() {
+                    return Err(ArtifactError::InvalidSparseData(name.as_ref().to_string()));
+                }
+                if symbols.keys().any(String::is_empty) {
+                    return Err(ArtifactError::EmptyName);
+                }
+                if let Decl::Defined(decl) = stype.decl {
+                    let valid = match decl {
+                        DefinedDecl::Data(d) => {
+                            !d.is_tls() && data.is_zero_init() && symbols.is_empty()
+                        }
+                        _ => false,
+                    };
+                    if decl.is_common() && !valid {
+                        return Err(ArtifactError::InvalidCommon(name.as_ref().to_string()));
+                    }
+                }
                 if stype.defined {
+                    if let Data::ZeroInit(size) = data {
+                        // like C tentative definitions, zero-initialized definitions of the
+                        // same symbol merge into the largest of them
+                        let definitions = match stype.decl {
+                            Decl::Defined(decl) if decl.is_global() => {
+                                &mut self.nonlocal_definitions
+                            }
+                            _ => &mut self.local_definitions,
+                        };
+                        let previous = definitions
+                            .iter()
+                            .find(|def| def.name == decl_name)
+                            .cloned()
+                            .expect("defined symbol has a definition");
+                        if let Data::ZeroInit(previous_size) = previous.data {
+                            let mut merged = previous.clone();
+                            merged.data = Data::ZeroInit(previous_size.max(size));
+                            merged.symbols.extend(symbols);
+                            definitions.remove(&previous);
+                            definitions.insert(merged);
+                            return Ok(());
+                        }
+                    }
                     Err(ArtifactError::DuplicateDefinition(
                         name.as_ref().to_string(),
                     ))?;
@@ -472,14 +1596,6 @@ impl Artifact {
                     }
                 };
 
-                match decl {
-                    DefinedDecl::Section(_) => {}
-                    _ => {
-                        if !symbols.is_empty() {
-                            return Err(ArtifactError::NonSectionCustomSymbols(decl, symbols));
-                        }
-                    }
-                }
                 match decl {
                     DefinedDecl::Data(_) => {}
                     _ => {
@@ -488,7 +1604,6 @@ impl Artifact {
                         }
                     }
                 }
-
                 if decl.is_global() {
                     self.nonlocal_definitions.insert(InternalDefinition {
                         name: decl_name,
@@ -510,12 +1625,123 @@ impl Artifact {
         }
         Ok(())
     }
+    /// Replace the definition of the _previously defined_ program object `name` with `data`,
+    /// keeping its declaration, custom symbols and links, e.g. to patch a function body which was
+    /// defined before its final contents were known. Defining it again with `define` is an error,
+    /// so codegen which defines a symbol twice by mistake is caught.
+    /// **NB**: If `name` is undeclared, or declared but not yet defined, this will return an
+    /// error.
+    pub fn redefine<T: AsRef<str>, D: Into<Data>>(
+        &mut self,
+        name: T,
+        data: D,
+    ) -> Result<(), ArtifactError> {
+        let name = name.as_ref();
+        let decl_name = match self.strings.get(name) {
+            Some(decl_name) if self.declarations.contains_key(&decl_name) => decl_name,
+            _ => return Err(ArtifactError::Undeclared(name.to_string())),
+        };
+        if !self.declarations[&decl_name].defined {
+            return Err(ArtifactError::Undefined(name.to_string()));
+        }
+        let previous = self
+            .local_definitions
+            .iter()
+            .chain(self.nonlocal_definitions.iter())
+            .find(|def| def.name == decl_name)
+            .cloned()
+            .expect("defined symbol has a definition");
+        self.local_definitions.remove(&previous);
+        self.nonlocal_definitions.remove(&previous);
+        self.declarations[&decl_name].defined = false;
+        let result = self.define_with_symbols(name, data, previous.symbols.clone());
+        if result.is_err() {
+            // the definition is unchanged if the new one is invalid
+            self.declarations[&decl_name].defined = true;
+            if previous.decl.is_global() {
+                self.nonlocal_definitions.insert(previous);
+            } else {
+                self.local_definitions.insert(previous);
+            }
+        }
+        result
+    }
     /// Declare `import` to be an import with `kind`.
     /// This is just sugar for `declare("name", Decl::FunctionImport)` or `declare("data", Decl::DataImport)`
     pub fn import<T: AsRef<str>>(&mut self, import: T, kind: ImportKind) -> Result<(), Error> {
         self.declare(import.as_ref(), Decl::Import(kind))?;
         Ok(())
     }
+    /// Declare `import` to be an import with `kind`, which is expected to be found in the dynamic
+    /// library `library` (e.g. `/usr/lib/libSystem.B.dylib`).
+    ///
+    /// Mach-O records the library with an `LC_LOAD_DYLIB` command and references it from the
+    /// symbol's library ordinal, so the symbol is resolved with a two-level namespace. Other
+    /// formats ignore the library.
+    pub fn import_from<T: AsRef<str>, L: AsRef<str>>(
+        &mut self,
+        import: T,
+        kind: ImportKind,
+        library: L,
+    ) -> Result<(), Error> {
+        self.import(import.as_ref(), kind)?;
+        let import = self.strings.get_or_intern(import.as_ref());
+        let library = self.strings.get_or_intern(library.as_ref());
+        self.import_libraries.insert(import, library);
+        Ok(())
+    }
+    /// Declare `import` to be a weak import with `kind`, which the linker may leave unresolved,
+    /// in which case its address is 0 at runtime, e.g. for an API which is only available in
+    /// newer versions of a library.
+    ///
+    /// Mach-O marks the symbol with `N_WEAK_REF`, and ELF makes it an undefined `STB_WEAK` symbol.
+    pub fn import_weak<T: AsRef<str>>(&mut self, import: T, kind: ImportKind) -> Result<(), Error> {
+        self.import(import.as_ref(), kind)?;
+        let import = self.strings.get_or_intern(import.as_ref());
+        self.weak_imports.insert(import);
+        Ok(())
+    }
+    /// Declare `import` to be a function import which is called through a symbol stub, e.g. for
+    /// position-dependent code which is far from the function.
+    ///
+    /// Mach-O emits a `jmp *ptr(%rip)` stub for it in a `__TEXT,__stubs` section, with a lazy
+    /// pointer in a `__DATA,__la_symbol_ptr` section, both of which refer to the import through
+    /// the indirect symbol table; calls to the import go to its stub. Only x86_64 has stubs. Other
+    /// formats ignore the stub, since the linker builds the PLT.
+    pub fn import_with_stub<T: AsRef<str>>(&mut self, import: T) -> Result<(), Error> {
+        self.import(import.as_ref(), ImportKind::Function)?;
+        let import = self.strings.get_or_intern(import.as_ref());
+        self.stub_imports.insert(import);
+        Ok(())
+    }
+    /// Declare `name` to be a re-export of `import`, an indirect symbol which the linker resolves
+    /// to whatever `import` resolves to, e.g. for an umbrella framework forwarding a symbol of one
+    /// of the libraries it wraps. Both are imported with `kind`, if they are not already imported.
+    ///
+    /// Mach-O emits `name` as an `N_INDR` symbol whose value is the string table offset of
+    /// `import`. ELF has no indirect symbols, so it cannot emit re-exports.
+    /// **NB**: If `import` is itself a re-export, or `name` is the import of another re-export,
+    /// this will return an error.
+    pub fn reexport<T: AsRef<str>, U: AsRef<str>>(
+        &mut self,
+        name: T,
+        import: U,
+        kind: ImportKind,
+    ) -> Result<(), Error> {
+        let (name, import) = (name.as_ref(), import.as_ref());
+        if self.reexported_import(import).is_some() {
+            return Err(ArtifactError::ChainedReexport(import.to_string()).into());
+        }
+        let name_id = self.strings.get_or_intern(name);
+        if self.reexports.values().any(|&import| import == name_id) {
+            return Err(ArtifactError::ChainedReexport(name.to_string()).into());
+        }
+        self.import(import, kind)?;
+        self.import(name, kind)?;
+        let import = self.strings.get_or_intern(import);
+        self.reexports.insert(name_id, import);
+        Ok(())
+    }
     /// Link a relocation at `link.at` from `link.from` to `link.to`
     /// **NB**: If either `link.from` or `link.to` is undeclared, then this will return an error.
     /// If `link.from` is an import you previously declared, this will also return an error.
@@ -523,28 +1749,165 @@ impl Artifact {
         self.link_with(link, Reloc::Auto)
     }
     /// A variant of `link` with a `Reloc` provided. Has all of the same invariants as
-    /// `link`.
+    /// `link`, except that `link.to` may be a pseudo-symbol the linker defines, like the Mach header
+    /// `_mh_execute_header` on Mach-O, or the GOT base `_GLOBAL_OFFSET_TABLE_` on ELF, which is
+    /// imported as data when it is first linked to. `Auto` links from a function to the GOT base
+    /// are `R_X86_64_GOTPC32`, relative to the instruction pointer.
     pub fn link_with<'a>(&mut self, link: Link<'a>, reloc: Reloc) -> Result<(), Error> {
+        let link_from = self.strings.get_or_intern(link.from);
+        match self.declarations.get(&link_from) {
+            Some(from_type) if from_type.decl.is_import() => {
+                return Err(ArtifactError::RelocateImport(link.from.to_string()).into());
+            }
+            Some(from_type) if from_type.decl.is_common() => {
+                return Err(ArtifactError::InvalidCommon(link.from.to_string()).into());
+            }
+            Some(_) => {}
+            None => return Err(ArtifactError::Undeclared(link.from.to_string()).into()),
+        }
+        // only import a linker defined symbol once the link is known to be valid
+        let link_to = self.strings.get_or_intern(link.to);
+        if !self.declarations.contains_key(&link_to) {
+            if !is_linker_defined(link.to, self.target.binary_format) {
+                return Err(ArtifactError::Undeclared(link.to.to_string()).into());
+            }
+            self.import(link.to, ImportKind::Data)?;
+        }
+        self.links.push((link_from, link_to, link.at, reloc));
+        Ok(())
+    }
+
+    /// Remove every link at `link.at` from `link.from` to `link.to`, regardless of its `Reloc`.
+    /// **NB**: If either `link.from` or `link.to` is undeclared, then this will return an error.
+    pub fn unlink<'a>(&mut self, link: Link<'a>) -> Result<(), ArtifactError> {
         let (link_from, link_to) = (
             self.strings.get_or_intern(link.from),
             self.strings.get_or_intern(link.to),
         );
-        match (
-            self.declarations.get(&link_from),
-            self.declarations.get(&link_to),
-        ) {
-            (Some(ref from_type), Some(_)) => {
-                if from_type.decl.is_import() {
-                    return Err(ArtifactError::RelocateImport(link.from.to_string()).into());
-                }
-                let link = (link_from, link_to, link.at, reloc);
-                self.links.push(link);
-            }
-            (None, _) => {
-                return Err(ArtifactError::Undeclared(link.from.to_string()).into());
-            }
-            (_, None) => {
-                return Err(ArtifactError::Undeclared(link.to.to_string()).into());
+        if !self.declarations.contains_key(&link_from) {
+            return Err(ArtifactError::Undeclared(link.from.to_string()));
+        }
+        if !self.declarations.contains_key(&link_to) {
+            return Err(ArtifactError::Undeclared(link.to.to_string()));
+        }
+        self.links
+            .retain(|&(from, to, at, _)| (from, to, at) != (link_from, link_to, link.at));
+        Ok(())
+    }
+
+    /// Remove a previously declared symbol, as if it had never been declared.
+    /// **NB**: If `name` is undeclared, this will return an error.
+    /// If `name` has been defined, is the `from` or `to` of any link, or is the import of a
+    /// re-export, this will also return an error; use
+    /// [unlink](struct.Artifact.html#method.unlink) to drop the links first.
+    pub fn undeclare<T: AsRef<str>>(&mut self, name: T) -> Result<(), ArtifactError> {
+        let name = name.as_ref();
+        let decl_name = self.strings.get_or_intern(name);
+        let defined = match self.declarations.get(&decl_name) {
+            Some(idecl) => idecl.defined,
+            None => return Err(ArtifactError::Undeclared(name.to_string())),
+        };
+        if defined
+            || self
+                .links
+                .iter()
+                .any(|&(from, to, _, _)| from == decl_name || to == decl_name)
+            || self.reexports.values().any(|&import| import == decl_name)
+        {
+            return Err(ArtifactError::ReferencedDeclaration(name.to_string()));
+        }
+        self.declarations.shift_remove(&decl_name);
+        self.imports.retain(|&(import, _)| import != decl_name);
+        self.import_libraries.remove(&decl_name);
+        self.weak_imports.remove(&decl_name);
+        self.stub_imports.remove(&decl_name);
+        self.reexports.remove(&decl_name);
+        Ok(())
+    }
+
+    /// Remove a previously declared symbol along with its definition, if any, and every link from
+    /// it, as if it had never been declared.
+    /// **NB**: If `name` is undeclared, this will return an error.
+    /// If `name` is the `to` of a link from another symbol, or the import of a re-export, this will
+    /// also return an error.
+    pub fn remove<T: AsRef<str>>(&mut self, name: T) -> Result<(), ArtifactError> {
+        let name = name.as_ref();
+        let decl_name = self.strings.get_or_intern(name);
+        if !self.declarations.contains_key(&decl_name) {
+            return Err(ArtifactError::Undeclared(name.to_string()));
+        }
+        if self
+            .links
+            .iter()
+            .any(|&(from, to, _, _)| to == decl_name && from != decl_name)
+            || self.reexports.values().any(|&import| import == decl_name)
+        {
+            return Err(ArtifactError::ReferencedDeclaration(name.to_string()));
+        }
+        self.links.retain(|&(from, _, _, _)| from != decl_name);
+        self.local_definitions.retain(|def| def.name != decl_name);
+        self.nonlocal_definitions
+            .retain(|def| def.name != decl_name);
+        self.declarations.shift_remove(&decl_name);
+        self.imports.retain(|&(import, _)| import != decl_name);
+        self.import_libraries.remove(&decl_name);
+        self.weak_imports.remove(&decl_name);
+        self.stub_imports.remove(&decl_name);
+        self.reexports.remove(&decl_name);
+        Ok(())
+    }
+
+    /// Rename the previously declared symbol `old` to `new`, updating its declaration, definition,
+    /// and every link from or to it.
+    /// **NB**: If `old` is undeclared, this will return an error.
+    /// If `new` is already declared, this will also return an error.
+    pub fn rename<T: AsRef<str>, U: AsRef<str>>(
+        &mut self,
+        old: T,
+        new: U,
+    ) -> Result<(), ArtifactError> {
+        let old_name = self.strings.get_or_intern(old.as_ref());
+        let new_name = self.strings.get_or_intern(new.as_ref());
+        if !self.declarations.contains_key(&old_name) {
+            return Err(ArtifactError::Undeclared(old.as_ref().to_string()));
+        }
+        if self.declarations.contains_key(&new_name) {
+            return Err(ArtifactError::AlreadyDeclared(new.as_ref().to_string()));
+        }
+        let rename = |id: StringID| if id == old_name { new_name } else { id };
+        // rebuild rather than remove + insert, so the declaration order is preserved
+        self.declarations = self
+            .declarations
+            .drain(..)
+            .map(|(name, decl)| (rename(name), decl))
+            .collect();
+        for import in self.imports.iter_mut() {
+            import.0 = rename(import.0);
+        }
+        if let Some(library) = self.import_libraries.remove(&old_name) {
+            self.import_libraries.insert(new_name, library);
+        }
+        if self.weak_imports.remove(&old_name) {
+            self.weak_imports.insert(new_name);
+        }
+        if self.stub_imports.remove(&old_name) {
+            self.stub_imports.insert(new_name);
+        }
+        self.reexports = self
+            .reexports
+            .iter()
+            .map(|(&name, &import)| (rename(name), rename(import)))
+            .collect();
+        for link in self.links.iter_mut() {
+            link.0 = rename(link.0);
+            link.1 = rename(link.1);
+        }
+        for definitions in &mut [&mut self.local_definitions, &mut self.nonlocal_definitions] {
+            let renamed = definitions.iter().find(|def| def.name == old_name).cloned();
+            if let Some(mut def) = renamed {
+                definitions.remove(&def);
+                def.name = new_name;
+                definitions.insert(def);
             }
         }
         Ok(())
@@ -566,30 +1929,521 @@ impl Artifact {
         syms
     }
 
+    /// Check that this artifact is consistent and ready to `emit`, returning every problem found.
+    ///
+    /// Every link must be from and to a declared symbol, every definition must have a matching
+    /// non-import declaration, every import must be declared as an import and left undefined,
+    /// every other declaration must be defined, every link must be within the definition it is
+    /// from, and every custom symbol must have a name of its own.
+    pub fn validate(&self) -> Result<(), Vec<ArtifactError>> {
+        let mut errors = Vec::new();
+        let name = |id: StringID| {
+            self.strings
+                .resolve(id)
+                .expect("symbol has a name")
+                .to_string()
+        };
+        let sizes = self
+            .local_definitions
+            .iter()
+            .chain(self.nonlocal_definitions.iter())
+            .map(|def| (def.name, def.data.size() as u64))
+            .collect::<BTreeMap<_, _>>();
+        for &(from, to, at, _) in self.links.iter() {
+            for &id in &[from, to] {
+                if !self.declarations.contains_key(&id) {
+                    errors.push(ArtifactError::Undeclared(name(id)));
+                }
+            }
+            match sizes.get(&from) {
+                Some(&size) if at >= size => errors.push(ArtifactError::LinkOutOfBounds {
+                    from: name(from),
+                    at,
+                    size,
+                }),
+                _ => {}
+            }
+            if let Some(from_decl) = self.declarations.get(&from) {
+                if from_decl.decl.is_import() {
+                    errors.push(ArtifactError::RelocateImport(name(from)));
+                }
+                if from_decl.decl.is_common() {
+                    errors.push(ArtifactError::InvalidCommon(name(from)));
+                }
+            }
+            for &id in &[from, to] {
+                if self.is_raw_section(id) {
+                    errors.push(ArtifactError::RawSection(name(id)));
+                }
+            }
+        }
+        for def in self
+            .local_definitions
+            .iter()
+            .chain(self.nonlocal_definitions.iter())
+        {
+            match self.declarations.get(&def.name) {
+                Some(idecl) if idecl.decl.is_import() => {
+                    errors.push(ArtifactError::ImportDefined(name(def.name)))
+                }
+                Some(_) => {}
+                None => errors.push(ArtifactError::Undeclared(name(def.name))),
+            }
+            if !def.symbols.is_empty() && self.is_raw_section(def.name) {
+                errors.push(ArtifactError::RawSection(name(def.name)));
+            }
+        }
+        let mut custom_symbols = BTreeSet::new();
+        for symbol in self
+            .local_definitions
+            .iter()
+            .chain(self.nonlocal_definitions.iter())
+            .flat_map(|def| def.symbols.keys())
+        {
+            let declared = match self.strings.get(symbol) {
+                Some(id) => self.declarations.contains_key(&id),
+                None => false,
+            };
+            if declared || !custom_symbols.insert(symbol) {
+                errors.push(ArtifactError::DuplicateSymbol(symbol.clone()));
+            }
+        }
+        for &(import, _) in self.imports.iter() {
+            match self.declarations.get(&import) {
+                Some(idecl) if !idecl.decl.is_import() || idecl.defined => {
+                    errors.push(ArtifactError::ImportDefined(name(import)))
+                }
+                Some(_) => {}
+                None => errors.push(ArtifactError::Undeclared(name(import))),
+            }
+        }
+        for name in self.undefined_symbols() {
+            errors.push(ArtifactError::Undefined(name));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Emit a blob of bytes representing the object file in the format specified in the target the
-    /// `Artifact` was constructed with.
+    /// `Artifact` was constructed with, e.g. Mach-O for `x86_64-apple-darwin` and ELF for
+    /// `x86_64-unknown-linux-gnu`. Only ELF and Mach-O are supported; other formats, including
+    /// COFF and the unknown format of a bare architecture, are an error.
     pub fn emit(&self) -> Result<Vec<u8>, Error> {
         self.emit_as(self.target.binary_format)
     }
 
     /// Emit a blob of bytes representing an object file in the given format.
     pub fn emit_as(&self, format: BinaryFormat) -> Result<Vec<u8>, Error> {
-        let undef = self.undefined_symbols();
-        if undef.is_empty() {
-            match format {
-                BinaryFormat::Elf => elf::to_bytes(self),
-                BinaryFormat::Macho => mach::to_bytes(self),
-                _ => Err(format_err!(
-                    "binary format {} is not supported",
-                    self.target.binary_format
-                )),
+        self.emit_with_relocations_as(format)
+            .map(|(bytes, _sections, _relocations)| bytes)
+    }
+
+    /// Emit a blob of bytes representing an ELF shared library (`ET_DYN`), which exports every
+    /// global, non-hidden definition in its dynamic symbol table, so it can be loaded with
+    /// `dlopen`.
+    ///
+    /// **NB**: The definitions must be self-contained: imports and links need dynamic relocations,
+    /// which are not supported yet, so they are an error, as are custom sections and
+    /// thread-local variables.
+    pub fn emit_shared_library(&self) -> Result<Vec<u8>, Error> {
+        self.check_valid()?;
+        match self.target.binary_format {
+            BinaryFormat::Elf => elf::shared::to_bytes(self),
+            format => Err(ArtifactError::UnsupportedFormat(format).into()),
+        }
+    }
+
+    /// Emit a blob of bytes representing an object file, in the format specified in the target the
+    /// `Artifact` was constructed with, containing only the debug sections, their relocations and
+    /// their custom symbols, e.g. to split the debug info off into a file of its own. The code and
+    /// data are omitted, so the definitions the debug sections refer to are undefined symbols in
+    /// it, which name the definitions of the object the debug info describes.
+    ///
+    /// **NB**: A debug section referring to a section which is not a debug section is an error.
+    pub fn emit_debug_only(&self) -> Result<Vec<u8>, Error> {
+        self.check_valid()?;
+        self.debug_only()?.emit()
+    }
+
+    /// An artifact with the same options, and only the debug sections of this one
+    fn debug_only(&self) -> Result<Artifact, Error> {
+        let is_debug = |decl: &Decl| match decl {
+            Decl::Defined(DefinedDecl::Section(s)) => s.kind() == SectionKind::Debug,
+            _ => false,
+        };
+        let mut debug = Artifact {
+            is_library: self.is_library,
+            mach_filetype: self.mach_filetype,
+            mach_function_starts: self.mach_function_starts,
+            mach_protection: self.mach_protection,
+            mach_section_alignments: self.mach_section_alignments.clone(),
+            mach_section_fills: self.mach_section_fills.clone(),
+            mach_header_flags: self.mach_header_flags,
+            mach_symbol_order: self.mach_symbol_order,
+            mach_section_order: self.mach_section_order.clone(),
+            mach_segment_name: self.mach_segment_name.clone(),
+            mach_load_commands: self.mach_load_commands.clone(),
+            mach_uuid: self.mach_uuid,
+            relocation_model: self.relocation_model,
+            prune_imports: self.prune_imports,
+            elf_osabi: self.elf_osabi,
+            elf_abiversion: self.elf_abiversion,
+            elf_flags: self.elf_flags,
+            source_file: self.source_file.clone(),
+            ..Artifact::new(self.target.clone(), self.name.clone())
+        };
+        for def in self.definitions() {
+            if let DefinedDecl::Section(s) = def.decl {
+                if s.kind() == SectionKind::Debug {
+                    debug.declare(def.name, Decl::Defined(*def.decl))?;
+                    debug.define_with_symbols(def.name, def.data.clone(), def.symbols.clone())?;
+                }
             }
-        } else {
-            Err(format_err!(
-                "the following symbols are declared but not defined: {:?}",
-                undef
-            ))
         }
+        for link in self.links() {
+            if !is_debug(link.from.decl) {
+                continue;
+            }
+            match *link.to.decl {
+                _ if is_debug(link.to.decl) => {}
+                Decl::Defined(DefinedDecl::Function(_)) => {
+                    debug.import(link.to.name, ImportKind::Function)?
+                }
+                Decl::Defined(DefinedDecl::Data(_)) => {
+                    debug.import(link.to.name, ImportKind::Data)?
+                }
+                Decl::Import(kind) => debug.import(link.to.name, kind)?,
+                Decl::Defined(DefinedDecl::Section(_)) => {
+                    return Err(ArtifactError::UnsupportedRelocation {
+                        from: link.from.name.to_string(),
+                        to: link.to.name.to_string(),
+                        reason: "a debug section can only refer to symbols and debug sections"
+                            .to_string(),
+                    }
+                    .into());
+                }
+            }
+            let to_link = Link {
+                from: link.from.name,
+                to: link.to.name,
+                at: link.at,
+            };
+            debug.link_with(to_link, link.reloc)?;
+        }
+        Ok(debug)
+    }
+
+    fn is_raw_section(&self, id: StringID) -> bool {
+        match self.declarations.get(&id) {
+            Some(InternalDecl {
+                decl: Decl::Defined(DefinedDecl::Section(s)),
+                ..
+            }) => s.is_raw(),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn check_valid(&self) -> Result<(), Error> {
+        self.validate()
+            .map_err(|errors| ArtifactError::Invalid(errors).into())
+    }
+
+    /// Emit a blob of bytes representing the object file in the format specified in the target the
+    /// `Artifact` was constructed with, along with the placement of every defined section and every
+    /// relocation in it, e.g. for a JIT which loads and relocates the object itself.
+    pub fn emit_with_relocations(&self) -> Result<ResolvedObject, Error> {
+        self.emit_with_relocations_as(self.target.binary_format)
+    }
+
+    /// Emit a blob of bytes representing an object file in the given format, along with the
+    /// placement of every defined section and every relocation in it.
+    pub fn emit_with_relocations_as(&self, format: BinaryFormat) -> Result<ResolvedObject, Error> {
+        self.check_valid()?;
+        match format {
+            BinaryFormat::Elf => elf::to_bytes(self),
+            BinaryFormat::Macho => mach::to_bytes(self),
+            _ => Err(ArtifactError::UnsupportedFormat(format).into()),
+        }
+    }
+
+    /// Get the name and contents of every section of the object file in the format specified in
+    /// the target the `Artifact` was constructed with, in the order they are laid out, including
+    /// the padding between their members, e.g. to compare generated code against the expected
+    /// machine code. Zero-filled sections, like `.bss`, are as many zeros as they are long.
+    pub fn section_contents(&self) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.section_contents_as(self.target.binary_format)
+    }
+
+    /// Get the name and contents of every section of an object file in the given format.
+    pub fn section_contents_as(
+        &self,
+        format: BinaryFormat,
+    ) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.check_valid()?;
+        match format {
+            BinaryFormat::Elf => elf::section_contents(self),
+            BinaryFormat::Macho => mach::section_contents(self),
+            _ => Err(ArtifactError::UnsupportedFormat(format).into()),
+        }
+    }
+
+    /// Compute the layout of the object file in the format specified in the target the `Artifact`
+    /// was constructed with, without emitting it, e.g. to inspect its structure while debugging.
+    pub fn layout(&self) -> Result<Layout, Error> {
+        self.layout_as(self.target.binary_format)
+    }
+
+    /// Compute the layout of an object file in the given format, without emitting it.
+    pub fn layout_as(&self, format: BinaryFormat) -> Result<Layout, Error> {
+        self.check_valid()?;
+        match format {
+            BinaryFormat::Elf => elf::layout(self),
+            BinaryFormat::Macho => mach::layout(self),
+            _ => Err(ArtifactError::UnsupportedFormat(format).into()),
+        }
+    }
+
+    /// Get every relocation of the object file in the format specified in the target the
+    /// `Artifact` was constructed with, grouped by the name of the section it applies to, in the
+    /// order the sections are laid out, e.g. to verify or report on them. Sections without
+    /// relocations are omitted.
+    pub fn relocations(&self) -> Result<Vec<(String, Vec<ResolvedReloc>)>, Error> {
+        self.relocations_as(self.target.binary_format)
+    }
+
+    /// Get every relocation of an object file in the given format, grouped by section.
+    pub fn relocations_as(
+        &self,
+        format: BinaryFormat,
+    ) -> Result<Vec<(String, Vec<ResolvedReloc>)>, Error> {
+        let Layout {
+            sections,
+            relocations,
+            ..
+        } = self.layout_as(format)?;
+        Ok(sections
+            .into_iter()
+            .map(|section| {
+                let relocations = relocations
+                    .iter()
+                    .filter(|reloc| reloc.section == section.name)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (section.name, relocations)
+            })
+            .filter(|(_, relocations)| !relocations.is_empty())
+            .collect())
+    }
+
+    /// Compute the size of the object file in the format specified in the target the `Artifact`
+    /// was constructed with, without emitting it, e.g. to report progress while emitting it.
+    pub fn estimated_size(&self) -> Result<u64, Error> {
+        self.estimated_size_as(self.target.binary_format)
+    }
+
+    /// Compute the size of an object file in the given format, without emitting it.
+    pub fn estimated_size_as(&self, format: BinaryFormat) -> Result<u64, Error> {
+        self.check_valid()?;
+        match format {
+            BinaryFormat::Elf => elf::estimated_size(self),
+            BinaryFormat::Macho => mach::estimated_size(self),
+            _ => Err(ArtifactError::UnsupportedFormat(format).into()),
+        }
+    }
+    /// Write `value`, the address `to` resolved to, into the relocated field of the link from
+    /// `from` to `to` at `at` in `buffer`, which holds an object file emitted from this artifact
+    /// in its target format and is loaded at `base`; e.g. to bind a reference lazily once the
+    /// object has been loaded. The addend of the relocation is added to `value`.
+    ///
+    /// Absolute relocations are written as `value` plus the addend: `Auto` relocations from data,
+    /// and `Absolute` and `Debug` relocations. On x86_64, 4-byte PC-relative fields are written
+    /// as `value` plus the addend minus their own address: `Call` and `PcRelative` relocations,
+    /// and `Auto` relocations from functions, which are absolute only from code at a fixed
+    /// address in ELF. References through the GOT cannot be patched this way.
+    pub fn patch_relocation(
+        &self,
+        buffer: &mut [u8],
+        base: u64,
+        from: &str,
+        to: &str,
+        at: u64,
+        value: u64,
+    ) -> Result<(), Error> {
+        use std::convert::TryFrom;
+        use target_lexicon::Endianness;
+
+        let unsupported = |reason: &str| ArtifactError::UnsupportedRelocation {
+            from: from.to_string(),
+            to: to.to_string(),
+            reason: reason.to_string(),
+        };
+        let no_such_link = || ArtifactError::NoSuchLink {
+            from: from.to_string(),
+            to: to.to_string(),
+            at,
+        };
+        let (from_id, to_id) = match (self.strings.get(from), self.strings.get(to)) {
+            (Some(from_id), Some(to_id)) => (from_id, to_id),
+            _ => return Err(no_such_link().into()),
+        };
+        let reloc = self
+            .links
+            .iter()
+            .find(|&&(link_from, link_to, link_at, _)| {
+                link_from == from_id && link_to == to_id && link_at == at
+            })
+            .map(|&(_, _, _, reloc)| reloc)
+            .ok_or_else(no_such_link)?;
+        let x86_64 = self.target.architecture == Architecture::X86_64;
+        let fixed_elf_code = self.target.binary_format == BinaryFormat::Elf
+            && self.relocation_model == RelocationModel::Static;
+        // the size of the field, its addend, and whether it is PC-relative
+        let (size, addend, pc_relative) = match reloc {
+            Reloc::Auto => match self.declarations[&from_id].decl {
+                Decl::Defined(DefinedDecl::Data(_)) => {
+                    let pointer_size = self
+                        .target
+                        .pointer_width()
+                        .map_err(|()| unsupported("the target has no pointer width"))?
+                        .bytes();
+                    (pointer_size, 0, false)
+                }
+                Decl::Defined(DefinedDecl::Function(_)) if !x86_64 => {
+                    return Err(
+                        unsupported("PC-relative fields can only be patched on x86_64").into(),
+                    )
+                }
+                Decl::Defined(DefinedDecl::Function(_)) => match self.declarations[&to_id].decl {
+                    Decl::Defined(DefinedDecl::Data(d)) if d.is_tls() => {
+                        return Err(unsupported(
+                            "thread-local variables are accessed through their descriptors",
+                        )
+                        .into())
+                    }
+                    Decl::Defined(DefinedDecl::Section(_)) => {
+                        return Err(unsupported(
+                            "Auto relocations from a function to a section are not supported",
+                        )
+                        .into())
+                    }
+                    Decl::Import(ImportKind::Data)
+                        if self.relocation_model != RelocationModel::Static =>
+                    {
+                        return Err(
+                            unsupported("references to imported data are through the GOT").into(),
+                        )
+                    }
+                    Decl::Defined(DefinedDecl::Data(_))
+                    | Decl::Import(ImportKind::Data)
+                    | Decl::Import(ImportKind::SectionBoundary)
+                        if fixed_elf_code =>
+                    {
+                        (4, 0, false)
+                    }
+                    _ => (4, -4, true),
+                },
+                _ => {
+                    return Err(
+                        unsupported("Auto relocations from a section are not supported").into(),
+                    )
+                }
+            },
+            Reloc::Call | Reloc::PcRelative { .. } if !x86_64 => {
+                return Err(unsupported("PC-relative fields can only be patched on x86_64").into())
+            }
+            Reloc::Call => (4, -4, true),
+            Reloc::PcRelative { trailing } => (4, -4 - i32::from(trailing), true),
+            Reloc::Absolute { size, addend } | Reloc::Debug { size, addend } => {
+                (size, addend, false)
+            }
+            _ => {
+                return Err(
+                    unsupported("only absolute and PC-relative relocations can be patched").into(),
+                )
+            }
+        };
+
+        // the relocated field is at `at` in `from`, which is either a symbol or a section
+        let layout = self.layout()?;
+        let (section_name, offset) = layout
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == from)
+            .map_or((from, 0), |symbol| (symbol.section.as_str(), symbol.offset));
+        let section = layout
+            .sections
+            .iter()
+            .find(|section| section.name == section_name)
+            .ok_or_else(|| format_err!("{} is not in a section of the object file", from))?;
+        let start = section.offset + offset + at;
+
+        let mut value = value.wrapping_add(addend as i64 as u64);
+        if pc_relative {
+            value = value.wrapping_sub(base.wrapping_add(start));
+        }
+        let little = self.target.endianness() != Ok(Endianness::Big);
+        let bytes = match size {
+            4 => {
+                // a 4-byte field holds either an unsigned or a sign-extended value, and a
+                // PC-relative displacement is always sign-extended
+                let fits = i32::try_from(value as i64).is_ok()
+                    || (!pc_relative && u32::try_from(value).is_ok());
+                if !fits {
+                    return Err(ArtifactError::RelocationOverflow {
+                        field: "a 4-byte relocated field",
+                        value,
+                    }
+                    .into());
+                }
+                let value = value as u32;
+                if little {
+                    value.to_le_bytes().to_vec()
+                } else {
+                    value.to_be_bytes().to_vec()
+                }
+            }
+            8 => {
+                if little {
+                    value.to_le_bytes().to_vec()
+                } else {
+                    value.to_be_bytes().to_vec()
+                }
+            }
+            size => {
+                return Err(unsupported(&format!("unsupported relocation size {}", size)).into())
+            }
+        };
+        let start = start as usize;
+        buffer
+            .get_mut(start..start + bytes.len())
+            .ok_or_else(|| format_err!("the relocated field is beyond the end of the buffer"))?
+            .copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// Compute the address every defined symbol would have if the object file, in the format
+    /// specified in the target the `Artifact` was constructed with, were loaded at `base`, e.g.
+    /// for a JIT which maps the emitted object itself. Common symbols have no address until the
+    /// linker allocates them.
+    ///
+    /// On Mach-O, this is `base` plus the symbol's offset in the segment, its `n_value`, for a
+    /// loader which maps the segment at `base`. On ELF, it is `base` plus the symbol's offset in
+    /// the file, for a loader which maps the file as is at `base`, followed by the zero-fill
+    /// sections, each aligned, which take up no space in the file.
+    pub fn symbol_addresses(&self, base: u64) -> Result<BTreeMap<String, u64>, Error> {
+        self.check_valid()?;
+        let offsets = match self.target.binary_format {
+            BinaryFormat::Elf => elf::symbol_offsets(self)?,
+            BinaryFormat::Macho => mach::symbol_offsets(self)?,
+            format => return Err(ArtifactError::UnsupportedFormat(format).into()),
+        };
+        Ok(offsets
+            .into_iter()
+            .map(|(name, offset)| (name, base + offset))
+            .collect())
     }
 
     /// Emit and write to disk a blob of bytes representing the object file in the format specified
@@ -604,4 +2458,91 @@ impl Artifact {
         sink.write_all(&bytes)?;
         Ok(())
     }
+
+    /// Emit and write to `sink` a blob of bytes representing an object file in the format
+    /// specified in the target the `Artifact` was constructed with, feeding the bytes to `hasher`
+    /// as they are written, and return its digest, e.g. as a key for caching the object.
+    ///
+    /// Mach-O objects are streamed through `hasher` as they are laid out. ELF objects, and Mach-O
+    /// objects with a [`MachUuid::Hashed`] UUID, are patched after they are laid out, so they are
+    /// emitted in full before they are written.
+    pub fn write_hashed<W: Write + ?Sized, H: Hasher + ?Sized>(
+        &self,
+        sink: &mut W,
+        hasher: &mut H,
+    ) -> Result<u64, Error> {
+        let mut sink = HashingWriter {
+            inner: sink,
+            hasher,
+            position: 0,
+        };
+        match self.target.binary_format {
+            BinaryFormat::Macho if self.mach_uuid != Some(MachUuid::Hashed) => {
+                self.check_valid()?;
+                mach::write_sequentially(self, &mut sink)?;
+            }
+            _ => {
+                let bytes = self.emit()?;
+                sink.write_all(&bytes)?;
+            }
+        }
+        Ok(sink.hasher.finish())
+    }
+
+    /// Emit the object file in the format specified in the target the `Artifact` was constructed
+    /// with, and write it to `path`.
+    ///
+    /// The object is written to a new temporary file in the same directory, which is synced and
+    /// then renamed over `path`, so `path` never contains a partially written object, even when
+    /// several threads or processes write it at once. On Unix, the directory is synced too, so the
+    /// replacement survives a crash.
+    pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        /// Distinguishes the temporary files of the writes in this process
+        static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+        let path = path.as_ref();
+        let bytes = self.emit()?;
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| format_err!("{} is not a path to a file", path.display()))?;
+        let (temp_path, mut temp) = loop {
+            let mut temp_name = std::ffi::OsString::from(".");
+            temp_name.push(file_name);
+            temp_name.push(format!(
+                ".{}.{}.tmp",
+                std::process::id(),
+                TEMP_FILES.fetch_add(1, AtomicOrdering::Relaxed)
+            ));
+            let temp_path = path.with_file_name(temp_name);
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp_path)
+            {
+                Ok(temp) => break (temp_path, temp),
+                // left behind by a process which had the same id
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let mut write_temp = || -> Result<(), Error> {
+            temp.write_all(&bytes)?;
+            temp.sync_all()?;
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        };
+        if let Err(e) = write_temp() {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        #[cfg(unix)]
+        {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
 }
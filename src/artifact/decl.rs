@@ -9,13 +9,19 @@ pub enum Decl {
     Defined(DefinedDecl),
 }
 
-/// The kind of import this is - either a function, or a copy relocation of data from a shared library
+/// The kind of import this is - either a function, a copy relocation of data from a shared library,
+/// or a symbol the linker defines at a boundary of a section
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ImportKind {
     /// A function
     Function,
     /// An imported piece of data
     Data,
+    /// The start or end of a section of the linked image, which the linker defines. Its name
+    /// must follow the convention of the binary format: `__start_<section>` or `__stop_<section>`
+    /// on ELF, where the section name is a C identifier, and `section$start$<segment>$<section>` or
+    /// `section$end$<segment>$<section>` on Mach-O, which is not prefixed with an underscore.
+    SectionBoundary,
 }
 
 impl ImportKind {
@@ -39,6 +45,10 @@ pub enum Scope {
     /// definition is not found. No conflict if there are multiple
     /// weak symbols.
     Weak,
+    /// Available to all components, like C tentative definitions: the linker allocates the
+    /// largest of the common definitions of the symbol, unless a Global definition is found.
+    /// Only zero-initialized data can be common.
+    Common,
 }
 
 macro_rules! scope_methods {
@@ -68,9 +78,9 @@ macro_rules! scope_methods {
     pub fn set_scope(&mut self, scope: Scope) {
         self.scope = scope;
     }
-    /// Check if scope is `Scope::Global`. False if set to Local or Weak.
+    /// Check if scope is `Scope::Global` or `Scope::Common`. False if set to Local or Weak.
     pub fn is_global(&self) -> bool {
-        self.scope == Scope::Global
+        self.scope == Scope::Global || self.scope == Scope::Common
     }
 }}
 
@@ -116,13 +126,66 @@ macro_rules! visibility_methods {
     }
 }}
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// Type of a definition's symbol, which ELF records in its `st_info`; Mach-O symbols have no type
+pub enum SymbolKind {
+    /// A function (`STT_FUNC`)
+    Function,
+    /// A data object (`STT_OBJECT`)
+    Object,
+    /// A thread-local variable (`STT_TLS`)
+    ThreadLocal,
+    /// An indirect function (`STT_GNU_IFUNC`), whose definition is a resolver returning the
+    /// address of the implementation to use
+    IndirectFunction,
+    /// No particular type (`STT_NOTYPE`)
+    NoType,
+}
+
+macro_rules! symbol_kind_methods {
+    () => {
+    /// Build the type of the symbol, overriding the type inferred from the declaration, e.g. to
+    /// give a hand-written stub defined as data a function symbol. If None, it is inferred.
+    pub fn with_symbol_kind(mut self, kind: Option<SymbolKind>) -> Self {
+        self.symbol_kind = kind;
+        self
+    }
+    /// Set the type of the symbol
+    pub fn set_symbol_kind(&mut self, kind: Option<SymbolKind>) {
+        self.symbol_kind = kind;
+    }
+    /// Get the type of the symbol, if it overrides the type inferred from the declaration
+    pub fn get_symbol_kind(&self) -> Option<SymbolKind> {
+        self.symbol_kind
+    }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 /// Type of data declared
 pub enum DataType {
     /// Ordinary raw bytes
     Bytes,
-    /// 0-terminated C-style string.
+    /// 0-terminated C-style string. Data which is not a single C string, e.g. because it has
+    /// embedded NULs, is placed with ordinary bytes instead.
     String,
+    /// A read-only 4-byte constant, such as a single-precision float, which the linker may merge
+    /// with identical constants
+    Literal4,
+    /// A read-only 8-byte constant, such as a double-precision float, which the linker may merge
+    /// with identical constants
+    Literal8,
+}
+
+impl DataType {
+    /// The size in bytes of each constant of a literal datatype, or `None` otherwise
+    pub fn literal_size(self) -> Option<u64> {
+        match self {
+            DataType::Literal4 => Some(4),
+            DataType::Literal8 => Some(8),
+            DataType::Bytes | DataType::String => None,
+        }
+    }
 }
 
 macro_rules! datatype_methods {
@@ -143,19 +206,35 @@ macro_rules! datatype_methods {
     }
 }
 
+macro_rules! size_methods {
+    () => {
+    /// Build the size of the definition in bytes, which defining the symbol with data of any
+    /// other size is an error. If None, the definition may have any size.
+    pub fn with_size(mut self, size: Option<u64>) -> Self {
+        self.size = size;
+        self
+    }
+    /// Set the size of the definition
+    pub fn set_size(&mut self, size: Option<u64>) {
+        self.size = size;
+    }
+    /// Get the size of the definition, if it is declared
+    pub fn get_size(&self) -> Option<u64> {
+        self.size
+    }
+    }
+}
+
 macro_rules! align_methods {
     () => {
     /// Build alignment. Size is in bytes. If None, a default is chosen
-    /// in the backend.
+    /// in the backend. Declaring an alignment which is not a power of two is an error.
     pub fn with_align(mut self, align: Option<u64>) -> Self {
         self.set_align(align);
         self
     }
     /// Set alignment
     pub fn set_align(&mut self, align: Option<u64>) {
-        if let Some(align) = align {
-            debug_assert_eq!(align.checked_next_power_of_two(), Some(align));
-        }
         self.align = align;
     }
     /// Get alignment
@@ -210,6 +289,57 @@ impl DefinedDecl {
         }
     }
 
+    /// Accessor to determine whether scope is common
+    pub fn is_common(&self) -> bool {
+        self.get_scope() == Scope::Common
+    }
+
+    /// Accessor to determine the linker binding scope. Sections are always local.
+    pub fn get_scope(&self) -> Scope {
+        match self {
+            DefinedDecl::Function(a) => a.get_scope(),
+            DefinedDecl::Data(a) => a.get_scope(),
+            DefinedDecl::Section(_) => Scope::Local,
+        }
+    }
+
+    /// Accessor to determine the linker visibility. Sections always have default visibility.
+    pub fn get_visibility(&self) -> Visibility {
+        match self {
+            DefinedDecl::Function(a) => a.get_visibility(),
+            DefinedDecl::Data(a) => a.get_visibility(),
+            DefinedDecl::Section(_) => Visibility::Default,
+        }
+    }
+
+    /// Accessor to determine the type of the symbol: the explicit symbol kind if there is one,
+    /// and otherwise the type inferred from the declaration. Sections have no symbol kind.
+    pub fn get_symbol_kind(&self) -> Option<SymbolKind> {
+        match self {
+            DefinedDecl::Function(a) => Some(a.get_symbol_kind().unwrap_or(if a.is_ifunc() {
+                SymbolKind::IndirectFunction
+            } else {
+                SymbolKind::Function
+            })),
+            DefinedDecl::Data(a) => Some(a.get_symbol_kind().unwrap_or(if a.is_tls() {
+                SymbolKind::ThreadLocal
+            } else {
+                SymbolKind::Object
+            })),
+            DefinedDecl::Section(_) => None,
+        }
+    }
+
+    /// Accessor to determine the declared size of the definition, if any. Sections have no
+    /// declared size.
+    pub fn get_size(&self) -> Option<u64> {
+        match self {
+            DefinedDecl::Function(a) => a.get_size(),
+            DefinedDecl::Data(a) => a.get_size(),
+            DefinedDecl::Section(_) => None,
+        }
+    }
+
     /// Accessor to determine whether contents are writable
     pub fn is_writable(&self) -> bool {
         match self {
@@ -242,6 +372,10 @@ impl Decl {
     pub fn function() -> FunctionDecl {
         FunctionDecl::default()
     }
+    /// An indirect function (a GNU ifunc) defined in this artifact by its resolver
+    pub fn ifunc() -> FunctionDecl {
+        FunctionDecl::default().ifunc()
+    }
     /// A data object defined in this artifact
     pub fn data() -> DataDecl {
         DataDecl::default()
@@ -250,7 +384,11 @@ impl Decl {
     pub fn cstring() -> DataDecl {
         DataDecl::default().with_datatype(DataType::String)
     }
-    /// A section defined in this artifact
+    /// A section defined in this artifact.
+    ///
+    /// On ELF, a section named `.gnu.linkonce.<kind>.<name>`, e.g. `.gnu.linkonce.t.foo`, is
+    /// allocated like the section `kind` abbreviates (`t` for text, `d` and `b` for writable data,
+    /// `r` for read-only data), so a legacy linker without COMDAT groups keeps only one copy of it
     pub fn section(kind: SectionKind) -> SectionDecl {
         SectionDecl::new(kind)
     }
@@ -263,9 +401,12 @@ impl Decl {
     /// 2. **If** the previous declaration was an [FunctionImport](enum.Decl.html#variant.FunctionImport) or [DataImport](enum.Decl.html#variant.DataImport),
     ///    **then** if the subsequent declaration is a corresponding matching [Function](enum.Decl.html#variant.Function) or [Data](enum.Decl.html#variant.Data)
     ///    declaration, it is said to be "upgraded", and forever after is considered a declaration in need of a definition.
-    /// 3. **If** the previous declaration was a `Function` or `Data` declaration,
+    /// 3. **If** the previous declaration was a `FunctionImport` or `DataImport`,
+    ///    **then** a subsequent function or data import is a no-op, and the original kind is kept.
+    ///    The same name can then be both called and address-taken, by choosing the [Reloc](enum.Reloc.html) at each link site.
+    /// 4. **If** the previous declaration was a `Function` or `Data` declaration,
     ///    **then** a subsequent corresponding `FunctionImport` or `DataImport` is a no-op.
-    /// 4. Anything else is a [IncompatibleDeclaration](enum.ArtifactError.html#variant.IncompatibleDeclaration) error!
+    /// 5. Anything else is a [IncompatibleDeclaration](enum.ArtifactError.html#variant.IncompatibleDeclaration) error!
     // ref https://github.com/m4b/faerie/issues/24
     // ref https://github.com/m4b/faerie/issues/18
     pub fn absorb(&mut self, other: Self) -> Result<(), ArtifactError> {
@@ -278,7 +419,7 @@ impl Decl {
                         *self = other;
                         Ok(())
                     }
-                    Decl::Import(ImportKind::Data) => Ok(()),
+                    Decl::Import(ImportKind::Function) | Decl::Import(ImportKind::Data) => Ok(()),
                     _ => Err(ArtifactError::IncompatibleDeclaration {
                         old: *self,
                         new: other,
@@ -293,7 +434,7 @@ impl Decl {
                         *self = other;
                         Ok(())
                     }
-                    Decl::Import(ImportKind::Function) => Ok(()),
+                    Decl::Import(ImportKind::Function) | Decl::Import(ImportKind::Data) => Ok(()),
                     _ => Err(ArtifactError::IncompatibleDeclaration {
                         old: *self,
                         new: other,
@@ -360,6 +501,13 @@ impl Decl {
             _ => false,
         }
     }
+    /// Is this a common definition, which the linker allocates?
+    pub fn is_common(&self) -> bool {
+        match *self {
+            Decl::Defined(decl) => decl.is_common(),
+            Decl::Import(_) => false,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -400,6 +548,11 @@ pub struct FunctionDecl {
     scope: Scope,
     visibility: Visibility,
     align: Option<u64>,
+    ifunc: bool,
+    coalesced: bool,
+    symbol_kind: Option<SymbolKind>,
+    compact_unwind: Option<u32>,
+    size: Option<u64>,
 }
 
 impl Default for FunctionDecl {
@@ -408,6 +561,11 @@ impl Default for FunctionDecl {
             scope: Scope::Local,
             visibility: Visibility::Default,
             align: None,
+            ifunc: false,
+            coalesced: false,
+            symbol_kind: None,
+            compact_unwind: None,
+            size: None,
         }
     }
 }
@@ -416,6 +574,60 @@ impl FunctionDecl {
     scope_methods!();
     visibility_methods!();
     align_methods!();
+    symbol_kind_methods!();
+    size_methods!();
+
+    /// Builder for making this an indirect function (a GNU ifunc): its definition is a resolver,
+    /// which the dynamic linker calls to get the address of the implementation to use
+    pub fn with_ifunc(mut self, ifunc: bool) -> Self {
+        self.ifunc = ifunc;
+        self
+    }
+    /// Make this an indirect function
+    pub fn ifunc(self) -> Self {
+        self.with_ifunc(true)
+    }
+    /// Setter for making this an indirect function
+    pub fn set_ifunc(&mut self, ifunc: bool) {
+        self.ifunc = ifunc;
+    }
+    /// Accessor for whether this is an indirect function
+    pub fn is_ifunc(&self) -> bool {
+        self.ifunc
+    }
+    /// Builder for making this a coalesced function: several objects may define it, and the linker
+    /// keeps one of them. On Mach-O it is placed in a coalesced section
+    pub fn with_coalesced(mut self, coalesced: bool) -> Self {
+        self.coalesced = coalesced;
+        self
+    }
+    /// Make this a coalesced function, which also makes it weak
+    pub fn coalesced(self) -> Self {
+        self.weak().with_coalesced(true)
+    }
+    /// Setter for making this a coalesced function
+    pub fn set_coalesced(&mut self, coalesced: bool) {
+        self.coalesced = coalesced;
+    }
+    /// Accessor for whether this is a coalesced function
+    pub fn is_coalesced(&self) -> bool {
+        self.coalesced
+    }
+    /// Builder for the compact unwind encoding of this function, e.g. `0x0100_0000` for a
+    /// function with a standard `rbp` frame. On Mach-O, it is recorded in an entry of the
+    /// `__LD,__compact_unwind` section; ignored by the other backends
+    pub fn with_compact_unwind(mut self, encoding: Option<u32>) -> Self {
+        self.compact_unwind = encoding;
+        self
+    }
+    /// Setter for the compact unwind encoding of this function
+    pub fn set_compact_unwind(&mut self, encoding: Option<u32>) {
+        self.compact_unwind = encoding;
+    }
+    /// Accessor for the compact unwind encoding of this function
+    pub fn get_compact_unwind(&self) -> Option<u32> {
+        self.compact_unwind
+    }
 }
 
 impl Into<Decl> for FunctionDecl {
@@ -432,6 +644,10 @@ pub struct DataDecl {
     writable: bool,
     datatype: DataType,
     align: Option<u64>,
+    tls: bool,
+    coalesced: bool,
+    symbol_kind: Option<SymbolKind>,
+    size: Option<u64>,
 }
 
 impl Default for DataDecl {
@@ -442,6 +658,10 @@ impl Default for DataDecl {
             writable: false,
             datatype: DataType::Bytes,
             align: None,
+            tls: false,
+            coalesced: false,
+            symbol_kind: None,
+            size: None,
         }
     }
 }
@@ -451,7 +671,14 @@ impl DataDecl {
     visibility_methods!();
     datatype_methods!();
     align_methods!();
+    symbol_kind_methods!();
+    size_methods!();
 
+    /// Set scope to common, for zero-initialized data the linker allocates; it is aligned as
+    /// declared, or to 8 bytes
+    pub fn common(self) -> Self {
+        self.with_scope(Scope::Common)
+    }
     /// Builder for writability
     pub fn with_writable(mut self, writable: bool) -> Self {
         self.writable = writable;
@@ -473,6 +700,42 @@ impl DataDecl {
     pub fn is_writable(&self) -> bool {
         self.writable
     }
+    /// Builder for making this a thread-local variable, whose definition is the initial value
+    /// of every thread's copy
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+    /// Make this a thread-local variable
+    pub fn tls(self) -> Self {
+        self.with_tls(true)
+    }
+    /// Setter for making this a thread-local variable
+    pub fn set_tls(&mut self, tls: bool) {
+        self.tls = tls;
+    }
+    /// Accessor for whether this is a thread-local variable
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+    /// Builder for making this a coalesced definition: several objects may define it, and the linker
+    /// keeps one of them. On Mach-O it is placed in a coalesced section
+    pub fn with_coalesced(mut self, coalesced: bool) -> Self {
+        self.coalesced = coalesced;
+        self
+    }
+    /// Make this a coalesced definition, which also makes it weak
+    pub fn coalesced(self) -> Self {
+        self.weak().with_coalesced(true)
+    }
+    /// Setter for making this a coalesced definition
+    pub fn set_coalesced(&mut self, coalesced: bool) {
+        self.coalesced = coalesced;
+    }
+    /// Accessor for whether this is a coalesced definition
+    pub fn is_coalesced(&self) -> bool {
+        self.coalesced
+    }
 }
 
 impl Into<Decl> for DataDecl {
@@ -500,6 +763,11 @@ pub struct SectionDecl {
     kind: SectionKind,
     datatype: DataType,
     align: Option<u64>,
+    retain: bool,
+    raw: bool,
+    elf_flags: u64,
+    mach_flags: u32,
+    mach_segname: Option<[u8; 16]>,
 }
 
 impl SectionDecl {
@@ -512,9 +780,117 @@ impl SectionDecl {
             kind,
             datatype: DataType::Bytes,
             align: None,
+            retain: false,
+            raw: false,
+            elf_flags: 0,
+            mach_flags: 0,
+            mach_segname: None,
         }
     }
 
+    /// Builder for retaining this section, i.e. preventing the linker from garbage collecting it
+    /// even if nothing references it
+    pub fn with_retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+    /// Retain this section during linker garbage collection
+    pub fn retain(self) -> Self {
+        self.with_retain(true)
+    }
+    /// Setter for retaining this section
+    pub fn set_retain(&mut self, retain: bool) {
+        self.retain = retain;
+    }
+    /// Accessor for whether this section is retained
+    pub fn is_retained(&self) -> bool {
+        self.retain
+    }
+
+    /// Builder for making this a raw section, whose bytes are emitted verbatim with an alignment
+    /// of 1, and which can have no symbols or relocations
+    pub fn with_raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+    /// Make this a raw section
+    pub fn raw(self) -> Self {
+        self.with_raw(true)
+    }
+    /// Setter for making this a raw section
+    pub fn set_raw(&mut self, raw: bool) {
+        self.raw = raw;
+    }
+    /// Accessor for whether this is a raw section
+    pub fn is_raw(&self) -> bool {
+        self.raw
+    }
+
+    /// Builder for raw ELF section flags, e.g. `SHF_ALLOC | SHF_EXECINSTR`, which are ORed into
+    /// the `sh_flags` the section would otherwise have; ignored by the other backends
+    pub fn with_elf_flags(mut self, flags: u64) -> Self {
+        self.elf_flags = flags;
+        self
+    }
+    /// Setter for raw ELF section flags
+    pub fn set_elf_flags(&mut self, flags: u64) {
+        self.elf_flags = flags;
+    }
+    /// Accessor for the raw ELF section flags
+    pub fn get_elf_flags(&self) -> u64 {
+        self.elf_flags
+    }
+
+    /// Builder for raw Mach-O section flags, e.g. a section type like `S_COALESCED` or
+    /// attributes like `S_ATTR_NO_TOC`, which are ORed into the flags the section would otherwise
+    /// have; ignored by the other backends
+    pub fn with_mach_flags(mut self, flags: u32) -> Self {
+        self.mach_flags = flags;
+        self
+    }
+    /// Setter for raw Mach-O section flags
+    pub fn set_mach_flags(&mut self, flags: u32) {
+        self.mach_flags = flags;
+    }
+    /// Accessor for the raw Mach-O section flags
+    pub fn get_mach_flags(&self) -> u32 {
+        self.mach_flags
+    }
+
+    /// Builder for the Mach-O segment name of this section, e.g. `__LD`, instead of the one its
+    /// kind implies; ignored by the other backends. Returns an error if the name is empty, longer
+    /// than 16 bytes, or contains a NUL byte
+    pub fn with_mach_segname(mut self, segname: Option<&str>) -> Result<Self, ArtifactError> {
+        self.set_mach_segname(segname)?;
+        Ok(self)
+    }
+    /// Setter for the Mach-O segment name of this section
+    pub fn set_mach_segname(&mut self, segname: Option<&str>) -> Result<(), ArtifactError> {
+        self.mach_segname = match segname {
+            Some(name) if name.is_empty() || name.len() > 16 || name.contains('\0') => {
+                return Err(ArtifactError::InvalidSegmentName(name.to_string()));
+            }
+            Some(name) => {
+                let mut segname = [0; 16];
+                segname[..name.len()].copy_from_slice(name.as_bytes());
+                Some(segname)
+            }
+            None => None,
+        };
+        Ok(())
+    }
+    /// Accessor for the Mach-O segment name of this section, if it overrides the one its kind
+    /// implies
+    pub fn get_mach_segname(&self) -> Option<&str> {
+        self.mach_segname.as_ref().and_then(|segname| {
+            let len = segname
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(segname.len());
+            std::str::from_utf8(&segname[..len]).ok()
+        })
+    }
+
     /// Sections are never global, but we have an accessor
     /// for symmetry with other section declarations
     pub fn is_global(&self) -> bool {
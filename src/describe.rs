@@ -0,0 +1,366 @@
+//! A normalized description of the structure of an object file, parsed from its bytes, so that
+//! objects emitted by faerie can be compared with objects produced by other tools, e.g. an
+//! assembler, independently of the details of how each lays out its file.
+
+use failure::Error;
+use goblin::container::{Container, Ctx, Endian};
+use goblin::elf::Elf;
+use goblin::mach::{Mach, MachO};
+use goblin::Object;
+
+/// A section of an object file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionDescription {
+    /// The section's name; on Mach-O, this is the segment and section name, e.g. `__TEXT,__text`
+    pub name: String,
+    /// The section's type: `sh_type` on ELF, and the `SECTION_TYPE` bits of the flags on Mach-O
+    pub kind: u32,
+    /// The section's flags: `sh_flags` on ELF, and the attribute bits of the flags on Mach-O
+    pub flags: u64,
+    /// The section's alignment, in bytes
+    pub align: u64,
+    /// The size of the section in bytes
+    pub size: u64,
+}
+
+/// The section name an absolute symbol is described with
+pub const ABSOLUTE: &str = "*ABS*";
+/// The section name a common symbol is described with
+pub const COMMON: &str = "*COM*";
+
+/// A symbol of an object file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolDescription {
+    /// The symbol's name, as written in the object file, e.g. with Mach-O's `_` prefix
+    pub name: String,
+    /// The symbol's type: `st_type` on ELF, and the `N_TYPE` bits of `n_type` on Mach-O
+    pub kind: u8,
+    /// Whether the symbol is visible outside of the object file
+    pub global: bool,
+    /// Whether the symbol is a weak definition or reference
+    pub weak: bool,
+    /// The name of the section the symbol is defined in, [`ABSOLUTE`](constant.ABSOLUTE.html) or
+    /// [`COMMON`](constant.COMMON.html) for absolute and common symbols, or `None` if it is
+    /// undefined
+    pub section: Option<String>,
+    /// The byte offset of the symbol, relative to the start of its section
+    pub value: u64,
+}
+
+/// A relocation of an object file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationDescription {
+    /// The name of the section the relocation is applied to
+    pub section: String,
+    /// The byte offset of the relocation, relative to the start of `section`
+    pub offset: u64,
+    /// The backend-specific relocation type
+    pub reloc: u32,
+    /// The name of the symbol the relocation refers to, or of the section for relocations which
+    /// refer to a section rather than a symbol
+    pub target: String,
+    /// The explicit addend of the relocation; always 0 for formats with implicit addends
+    pub addend: i64,
+}
+
+/// The sections, symbols and relocations of an object file, in the order they appear in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectDescription {
+    /// Every section, except for ELF's null section, symbol, string and relocation tables
+    pub sections: Vec<SectionDescription>,
+    /// Every symbol, except for ELF's null, file and section symbols, and Mach-O's debugging
+    /// symbols
+    pub symbols: Vec<SymbolDescription>,
+    /// Every relocation, grouped by section
+    pub relocations: Vec<RelocationDescription>,
+}
+
+/// Describe the structure of the ELF or Mach-O object file `bytes`, e.g. one emitted by
+/// [`Artifact::emit`](struct.Artifact.html#method.emit)
+pub fn describe(bytes: &[u8]) -> Result<ObjectDescription, Error> {
+    match Object::parse(bytes)? {
+        Object::Elf(elf) => describe_elf(&elf, bytes),
+        Object::Mach(Mach::Binary(mach)) => describe_mach(&mach, bytes),
+        _ => Err(format_err!(
+            "only ELF and Mach-O object files can be described"
+        )),
+    }
+}
+
+fn describe_elf(elf: &Elf, bytes: &[u8]) -> Result<ObjectDescription, Error> {
+    use goblin::elf::section_header::{
+        SHN_ABS, SHN_COMMON, SHN_LORESERVE, SHN_UNDEF, SHN_XINDEX, SHT_NULL, SHT_REL, SHT_RELA,
+        SHT_STRTAB, SHT_SYMTAB, SHT_SYMTAB_SHNDX,
+    };
+    use goblin::elf::sym::{Sym, STB_LOCAL, STB_WEAK, STT_FILE, STT_SECTION};
+
+    let shdr_name = |sh_name: usize| -> Result<String, Error> {
+        match elf.shdr_strtab.get(sh_name) {
+            Some(name) => Ok(name?.to_string()),
+            None => Err(format_err!("section name {:#x} is out of bounds", sh_name)),
+        }
+    };
+    let section_name = |shndx: usize| -> Result<String, Error> {
+        let shdr = elf
+            .section_headers
+            .get(shndx)
+            .ok_or_else(|| format_err!("section index {} is out of bounds", shndx))?;
+        shdr_name(shdr.sh_name)
+    };
+    let symbol_name = |sym: &Sym| -> Result<String, Error> {
+        match elf.strtab.get(sym.st_name) {
+            Some(name) => Ok(name?.to_string()),
+            None => Err(format_err!(
+                "symbol name {:#x} is out of bounds",
+                sym.st_name
+            )),
+        }
+    };
+    // the extended section indices of the symbols whose `st_shndx` is `SHN_XINDEX`
+    let shndx_table = match elf
+        .section_headers
+        .iter()
+        .find(|shdr| shdr.sh_type == SHT_SYMTAB_SHNDX)
+    {
+        Some(shdr) => {
+            let start = shdr.sh_offset as usize;
+            bytes
+                .get(start..start.saturating_add(shdr.sh_size as usize))
+                .ok_or_else(|| format_err!("the extended section index table is out of bounds"))?
+        }
+        None => &[],
+    };
+    // the section index of the `index`th symbol
+    let shndx = |index: usize, sym: &Sym| -> Result<usize, Error> {
+        if sym.st_shndx != SHN_XINDEX as usize {
+            return Ok(sym.st_shndx);
+        }
+        let entry = shndx_table
+            .get(index * 4..index * 4 + 4)
+            .ok_or_else(|| format_err!("symbol {} has no extended section index", index))?;
+        let entry = [entry[0], entry[1], entry[2], entry[3]];
+        Ok(if elf.little_endian {
+            u32::from_le_bytes(entry)
+        } else {
+            u32::from_be_bytes(entry)
+        } as usize)
+    };
+
+    let sections = elf
+        .section_headers
+        .iter()
+        .filter(|shdr| {
+            ![
+                SHT_NULL,
+                SHT_SYMTAB,
+                SHT_STRTAB,
+                SHT_REL,
+                SHT_RELA,
+                SHT_SYMTAB_SHNDX,
+            ]
+            .contains(&shdr.sh_type)
+        })
+        .map(|shdr| {
+            Ok(SectionDescription {
+                name: shdr_name(shdr.sh_name)?,
+                kind: shdr.sh_type,
+                flags: shdr.sh_flags,
+                align: shdr.sh_addralign,
+                size: shdr.sh_size,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    let mut symbols = Vec::new();
+    for (index, sym) in elf.syms.iter().enumerate().skip(1) {
+        if sym.st_type() == STT_FILE || sym.st_type() == STT_SECTION {
+            continue;
+        }
+        let section = match sym.st_shndx as u32 {
+            SHN_UNDEF => None,
+            SHN_ABS => Some(ABSOLUTE.to_string()),
+            SHN_COMMON => Some(COMMON.to_string()),
+            SHN_XINDEX => Some(section_name(shndx(index, &sym)?)?),
+            st_shndx if st_shndx >= SHN_LORESERVE => {
+                return Err(format_err!(
+                    "symbol {} has the reserved section index {:#x}",
+                    index,
+                    st_shndx
+                ));
+            }
+            _ => Some(section_name(sym.st_shndx)?),
+        };
+        symbols.push(SymbolDescription {
+            name: symbol_name(&sym)?,
+            kind: sym.st_type(),
+            global: sym.st_bind() != STB_LOCAL,
+            weak: sym.st_bind() == STB_WEAK,
+            section,
+            value: sym.st_value,
+        });
+    }
+    let mut relocations = Vec::new();
+    for (shndx_rel, relocs) in &elf.shdr_relocs {
+        let shdr = elf
+            .section_headers
+            .get(*shndx_rel)
+            .ok_or_else(|| format_err!("section index {} is out of bounds", shndx_rel))?;
+        let section = section_name(shdr.sh_info as usize)?;
+        for reloc in relocs.iter() {
+            let sym = elf.syms.get(reloc.r_sym).unwrap_or_default();
+            let target = if sym.st_type() == STT_SECTION {
+                section_name(shndx(reloc.r_sym, &sym)?)?
+            } else {
+                symbol_name(&sym)?
+            };
+            relocations.push(RelocationDescription {
+                section: section.clone(),
+                offset: reloc.r_offset,
+                reloc: reloc.r_type,
+                target,
+                addend: reloc.r_addend.unwrap_or(0),
+            });
+        }
+    }
+    Ok(ObjectDescription {
+        sections,
+        symbols,
+        relocations,
+    })
+}
+
+fn describe_mach(mach: &MachO, bytes: &[u8]) -> Result<ObjectDescription, Error> {
+    use goblin::mach::constants::SECTION_TYPE;
+    use goblin::mach::symbols::{
+        NO_SECT, N_ABS, N_EXT, N_STAB, N_TYPE, N_UNDF, N_WEAK_DEF, N_WEAK_REF,
+    };
+
+    /// The bit set in the first word of a scattered relocation
+    const R_SCATTERED: u32 = 0x8000_0000;
+
+    let mut sections = Vec::new();
+    for section in mach.segments.sections().flatten() {
+        let (section, _) = section?;
+        sections.push(section);
+    }
+    let section_name = |section: &goblin::mach::segment::Section| -> Result<String, Error> {
+        Ok(format!("{},{}", section.segname()?, section.name()?))
+    };
+    // the name of the section containing the address `addr`
+    let section_at = |addr: u64| -> Result<String, Error> {
+        let section = sections
+            .iter()
+            .find(|section| section.addr <= addr && addr - section.addr < section.size)
+            .ok_or_else(|| format_err!("no section contains the address {:#x}", addr))?;
+        section_name(section)
+    };
+
+    let mut symbol_names = Vec::new();
+    let mut symbols = Vec::new();
+    for symbol in mach.symbols() {
+        let (name, nlist) = symbol?;
+        symbol_names.push(name.to_string());
+        if nlist.n_type & N_STAB != 0 {
+            continue;
+        }
+        let (section, value) = if nlist.n_sect == NO_SECT as usize {
+            let section = match nlist.n_type & N_TYPE {
+                N_ABS => Some(ABSOLUTE.to_string()),
+                // an external undefined symbol with a value is a common symbol of that size
+                N_UNDF if nlist.n_type & N_EXT != 0 && nlist.n_value != 0 => {
+                    Some(COMMON.to_string())
+                }
+                _ => None,
+            };
+            (section, nlist.n_value)
+        } else {
+            let section = sections
+                .get(nlist.n_sect - 1)
+                .ok_or_else(|| format_err!("symbol {} has an invalid section", name))?;
+            let value = nlist
+                .n_value
+                .checked_sub(section.addr)
+                .ok_or_else(|| format_err!("symbol {} precedes its section", name))?;
+            (Some(section_name(section)?), value)
+        };
+        symbols.push(SymbolDescription {
+            name: name.to_string(),
+            kind: nlist.n_type & N_TYPE,
+            global: nlist.n_type & N_EXT != 0,
+            weak: nlist.n_desc & (N_WEAK_DEF | N_WEAK_REF) != 0,
+            section,
+            value,
+        });
+    }
+
+    let container = if mach.is_64 {
+        Container::Big
+    } else {
+        Container::Little
+    };
+    let endian = if mach.little_endian {
+        Endian::Little
+    } else {
+        Endian::Big
+    };
+    let ctx = Ctx::new(container, endian);
+    let mut relocations = Vec::new();
+    for section in &sections {
+        for reloc in section.iter_relocations(bytes, ctx) {
+            let reloc = reloc?;
+            let description = if reloc.r_address as u32 & R_SCATTERED != 0 {
+                // scattered relocations refer to their target by address
+                let word = reloc.r_address as u32;
+                RelocationDescription {
+                    section: section_name(section)?,
+                    offset: u64::from(word & 0x00ff_ffff),
+                    reloc: (word >> 24) & 0xf,
+                    target: section_at(u64::from(reloc.r_info))?,
+                    addend: 0,
+                }
+            } else {
+                let target = if reloc.r_extern() != 0 {
+                    symbol_names
+                        .get(reloc.r_symbolnum())
+                        .cloned()
+                        .ok_or_else(|| format_err!("relocation has an invalid symbol"))?
+                } else {
+                    // the symbol number of a local relocation is the ordinal of its section
+                    let section = sections
+                        .get(reloc.r_symbolnum().wrapping_sub(1))
+                        .ok_or_else(|| format_err!("relocation has an invalid section"))?;
+                    section_name(section)?
+                };
+                RelocationDescription {
+                    section: section_name(section)?,
+                    offset: reloc.r_address as u64,
+                    reloc: u32::from(reloc.r_type()),
+                    target,
+                    addend: 0,
+                }
+            };
+            relocations.push(description);
+        }
+    }
+
+    let sections = sections
+        .iter()
+        .map(|section| {
+            let name = section_name(section)?;
+            let align = 1u64
+                .checked_shl(section.align)
+                .ok_or_else(|| format_err!("section {} has an invalid alignment", name))?;
+            Ok(SectionDescription {
+                name,
+                kind: section.flags & SECTION_TYPE,
+                flags: u64::from(section.flags & !SECTION_TYPE),
+                align,
+                size: section.size,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(ObjectDescription {
+        sections,
+        symbols,
+        relocations,
+    })
+}
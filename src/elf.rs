@@ -1,4 +1,9 @@
 //! The ELF32/64 bit backend for transforming an artifact to a valid, ELF object file.
+//!
+//! Like `-ffunction-sections -fdata-sections`, every definition is placed in its own section,
+//! `.text.<name>`, `.data.<name>`, `.rodata.<name>` or `.bss.<name>`, or `.tdata.<name>` or
+//! `.tbss.<name>` for thread-local variables, and relocations to it are against that section's
+//! symbol, so the linker can garbage collect each of them separately.
 // FIXME: this is temporary, we anticipate None variant and pub fn rel being used in the future
 // for: 1. object files with source file name symbols
 //      2. 32-bit object files
@@ -7,8 +12,9 @@
 
 use crate::{
     artifact::{
-        self, Artifact, Data, DataType, Decl, DefinedDecl, ImportKind, LinkAndDecl, Reloc, Scope,
-        Visibility,
+        self, Artifact, ArtifactError, Data, DataType, Decl, DefinedDecl, ImportKind, Layout,
+        LinkAndDecl, Reloc, RelocationModel, ResolvedObject, ResolvedReloc, ResolvedSection,
+        ResolvedSymbol, Scope, SymbolKind, Visibility,
     },
     target::make_ctx,
     Ctx,
@@ -18,7 +24,8 @@ use goblin;
 
 use indexmap::IndexMap;
 use scroll::{IOwrite, Pwrite};
-use std::collections::{hash_map, HashMap};
+use std::collections::{hash_map, BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::fmt;
 use std::io::SeekFrom::*;
 use std::io::{BufWriter, Cursor, Seek, Write};
@@ -29,6 +36,8 @@ use goblin::elf::header::{self, Header};
 use goblin::elf::reloc;
 use goblin::elf::section_header::{self, SectionHeader};
 
+pub(crate) mod shared;
+
 // interned string idx
 type StringIndex = usize;
 // an offset into the object file
@@ -37,13 +46,30 @@ type Relocation = goblin::elf::reloc::Reloc;
 type Symbol = goblin::elf::sym::Sym;
 type Section = SectionHeader;
 
+/// The section must not be garbage collected by the linker; goblin doesn't have this GNU extension
+const SHF_GNU_RETAIN: u64 = 0x20_0000;
+/// The OS ABI of objects using GNU extensions; goblin doesn't define it either
+const ELFOSABI_GNU: u8 = 3;
+/// The RISC-V object uses compressed instructions; goblin doesn't define the RISC-V `e_flags`
+const EF_RISCV_RVC: u32 = 0x1;
+/// The RISC-V object passes single and double-precision floats in float registers
+const EF_RISCV_FLOAT_ABI_DOUBLE: u32 = 0x4;
+/// The symbol the linker defines at the base of the GOT, which code addresses the GOT relative to
+pub(crate) const GLOBAL_OFFSET_TABLE: &str = "_GLOBAL_OFFSET_TABLE_";
+
+/// The kind of a `.gnu.linkonce.<kind>.<name>` section, e.g. `t` for text, if `name` is one
+fn linkonce_kind(name: &str) -> Option<&str> {
+    name.strip_prefix(".gnu.linkonce.")?.split('.').next()
+}
+
 struct MachineTag(u16);
 
-impl From<Architecture> for MachineTag {
-    fn from(architecture: Architecture) -> MachineTag {
+impl TryFrom<Architecture> for MachineTag {
+    type Error = ArtifactError;
+    fn try_from(architecture: Architecture) -> Result<MachineTag, ArtifactError> {
         use goblin::elf::header::*;
         use target_lexicon::Architecture::*;
-        MachineTag(match architecture {
+        Ok(MachineTag(match architecture {
             X86_64 => EM_X86_64,
             I386 | I586 | I686 => EM_386,
             Aarch64(_) => EM_AARCH64,
@@ -60,12 +86,115 @@ impl From<Architecture> for MachineTag {
             Sparc64 | Sparcv9 => EM_SPARCV9,
             Msp430 => EM_MSP430,
             Unknown => EM_NONE,
-            Hexagon => panic!("goblin does not have EM_HEXAGON yet"),
-            Nvptx64 => panic!("nvptx64 does not exist in ELF"),
-            AmdGcn => panic!("amdgcn does not exist in ELF"),
-            Asmjs => panic!("asm.js does not exist in ELF"),
-            Wasm32 => panic!("wasm32 does not exist in ELF"),
-        })
+            // goblin does not have EM_HEXAGON yet, and the others do not exist in ELF
+            Hexagon | Nvptx64 | AmdGcn | Asmjs | Wasm32 => {
+                return Err(ArtifactError::UnsupportedArchitecture(architecture))
+            }
+        }))
+    }
+}
+
+/// Whether `reloc` is a relocation type of `architecture`; those of the architectures we don't
+/// know the relocation types of are not checked
+fn is_valid_reloc(architecture: Architecture, ctx: Ctx, reloc: u32) -> bool {
+    use target_lexicon::Architecture::*;
+    match architecture {
+        X86_64 => reloc < reloc::R_X86_64_NUM,
+        I386 | I586 | I686 => reloc < reloc::R_386_NUM,
+        Aarch64(_) if ctx.is_big() => {
+            reloc == reloc::R_AARCH64_NONE
+                || (257..=reloc::R_AARCH64_TLSLE_LDST128_TPREL_LO12_NC).contains(&reloc)
+                || (1024..=reloc::R_AARCH64_IRELATIVE).contains(&reloc)
+        }
+        // the ILP32 relocations
+        Aarch64(_) => reloc < 256,
+        _ => true,
+    }
+}
+
+/// Whether relocations on `architecture` conventionally have explicit addends (`SHT_RELA`),
+/// rather than implicit addends in the contents of the section they relocate (`SHT_REL`)
+fn uses_rela(architecture: Architecture) -> bool {
+    use target_lexicon::Architecture::*;
+    match architecture {
+        // the 32-bit ABIs which predate RELA
+        I386 | I586 | I686 | Arm(_) | Mips | Mipsel | Mipsisa32r6 | Mipsisa32r6el => false,
+        _ => true,
+    }
+}
+
+/// The size of the field holding the implicit addend of a `reloc` relocation on `architecture`,
+/// if it is a plain value rather than part of an instruction's encoding
+fn implicit_addend_size(architecture: Architecture, reloc: u32) -> Option<u8> {
+    use target_lexicon::Architecture::*;
+    match architecture {
+        I386 | I586 | I686 => match reloc {
+            reloc::R_386_8 | reloc::R_386_PC8 => Some(1),
+            reloc::R_386_16 | reloc::R_386_PC16 => Some(2),
+            _ => Some(4),
+        },
+        Arm(_) => match reloc {
+            reloc::R_ARM_ABS8 => Some(1),
+            reloc::R_ARM_ABS16 => Some(2),
+            reloc::R_ARM_ABS32 | reloc::R_ARM_REL32 => Some(4),
+            _ => None,
+        },
+        Mips | Mipsel | Mipsisa32r6 | Mipsisa32r6el => match reloc {
+            reloc::R_MIPS_32 | reloc::R_MIPS_REL32 => Some(4),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The size of the field a `reloc` relocation on `architecture` relocates, if it is known
+fn relocation_size(architecture: Architecture, reloc: u32) -> Option<u8> {
+    use target_lexicon::Architecture::*;
+    match architecture {
+        X86_64 => match reloc {
+            reloc::R_X86_64_NONE | reloc::R_X86_64_COPY | reloc::R_X86_64_TLSDESC_CALL => None,
+            reloc::R_X86_64_8 | reloc::R_X86_64_PC8 => Some(1),
+            reloc::R_X86_64_16 | reloc::R_X86_64_PC16 => Some(2),
+            reloc::R_X86_64_64
+            | reloc::R_X86_64_GLOB_DAT
+            | reloc::R_X86_64_JUMP_SLOT
+            | reloc::R_X86_64_RELATIVE
+            | reloc::R_X86_64_DTPMOD64
+            | reloc::R_X86_64_DTPOFF64
+            | reloc::R_X86_64_TPOFF64
+            | reloc::R_X86_64_PC64
+            | reloc::R_X86_64_GOTOFF64
+            | reloc::R_X86_64_GOT64
+            | reloc::R_X86_64_GOTPCREL64
+            | reloc::R_X86_64_GOTPC64
+            | reloc::R_X86_64_GOTPLT64
+            | reloc::R_X86_64_PLTOFF64
+            | reloc::R_X86_64_SIZE64
+            | reloc::R_X86_64_IRELATIVE
+            | reloc::R_X86_64_RELATIVE64 => Some(8),
+            reloc::R_X86_64_TLSDESC => Some(16),
+            reloc if reloc < reloc::R_X86_64_NUM => Some(4),
+            _ => None,
+        },
+        Aarch64(_) => match reloc {
+            reloc::R_AARCH64_ABS64 | reloc::R_AARCH64_PREL64 => Some(8),
+            reloc::R_AARCH64_ABS32 | reloc::R_AARCH64_PREL32 => Some(4),
+            reloc::R_AARCH64_ABS16 | reloc::R_AARCH64_PREL16 => Some(2),
+            _ => None,
+        },
+        _ => implicit_addend_size(architecture, reloc),
+    }
+}
+
+/// The processor specific `e_flags` implied by `architecture`, which linkers check are
+/// consistent between the objects they link
+fn default_flags(architecture: Architecture) -> u32 {
+    use target_lexicon::Architecture::*;
+    match architecture {
+        // the G extension includes D, so the lp64d ABI passes doubles in float registers
+        Riscv64gc => EF_RISCV_RVC | EF_RISCV_FLOAT_ABI_DOUBLE,
+        Riscv32imac | Riscv32imc | Riscv64imac => EF_RISCV_RVC,
+        _ => 0,
     }
 }
 
@@ -73,8 +202,10 @@ impl From<Architecture> for MachineTag {
 enum SymbolType<'a> {
     /// From a definition
     Decl(&'a DefinedDecl),
-    /// An import
-    Import,
+    /// An import, which may be left unresolved if `weak`
+    Import { weak: bool },
+    /// A global label at an offset into a custom section
+    SectionLabel,
     /// A section reference
     Section,
     /// A file reference
@@ -87,6 +218,7 @@ enum SymbolType<'a> {
 struct SymbolBuilder<'a> {
     name_offset: usize,
     size: u64,
+    value: u64,
     typ: SymbolType<'a>,
     shndx: usize,
 }
@@ -98,6 +230,7 @@ impl<'a> SymbolBuilder<'a> {
             name_offset: 0,
             typ,
             size: 0,
+            value: 0,
             shndx: 0,
         }
     }
@@ -109,6 +242,11 @@ impl<'a> SymbolBuilder<'a> {
         self.size = size as u64;
         self
     }
+    /// Set the value of this symbol; for a relocatable object, its offset into its section
+    pub fn value(mut self, value: u64) -> Self {
+        self.value = value;
+        self
+    }
     /// Set the symbol name as a byte offset into the corresponding strtab
     pub fn name_offset(mut self, name_offset: usize) -> Self {
         self.name_offset = name_offset;
@@ -125,18 +263,18 @@ impl<'a> SymbolBuilder<'a> {
     pub fn create(self) -> Symbol {
         use goblin::elf::section_header::SHN_ABS;
         use goblin::elf::sym::{
-            STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FILE, STT_FUNC, STT_NOTYPE, STT_OBJECT,
-            STT_SECTION, STV_DEFAULT, STV_HIDDEN, STV_PROTECTED,
+            STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FILE, STT_FUNC, STT_GNU_IFUNC, STT_NOTYPE,
+            STT_OBJECT, STT_SECTION, STT_TLS, STV_DEFAULT, STV_HIDDEN, STV_PROTECTED,
         };
         let mut st_shndx = self.shndx;
         let mut st_info = 0;
         let mut st_other = 0;
-        let st_value = 0;
+        let st_value = self.value;
 
         fn scope_stb_flags(s: Scope) -> u8 {
             let flag = match s {
                 Scope::Local => STB_LOCAL,
-                Scope::Global => STB_GLOBAL,
+                Scope::Global | Scope::Common => STB_GLOBAL,
                 Scope::Weak => STB_WEAK,
             };
             flag << 4
@@ -150,18 +288,37 @@ impl<'a> SymbolBuilder<'a> {
             }
         }
 
+        fn symbol_kind_stt(typ: SymbolType) -> u8 {
+            match typ {
+                SymbolType::Decl(decl) => match decl.get_symbol_kind() {
+                    Some(SymbolKind::Function) => STT_FUNC,
+                    Some(SymbolKind::Object) => STT_OBJECT,
+                    Some(SymbolKind::ThreadLocal) => STT_TLS,
+                    Some(SymbolKind::IndirectFunction) => STT_GNU_IFUNC,
+                    Some(SymbolKind::NoType) | None => STT_NOTYPE,
+                },
+                _ => STT_NOTYPE,
+            }
+        }
+
         match self.typ {
             SymbolType::Decl(DefinedDecl::Function(d)) => {
-                st_info |= STT_FUNC;
+                st_info |= symbol_kind_stt(self.typ);
                 st_info |= scope_stb_flags(d.get_scope());
                 st_other |= vis_stother_flags(d.get_visibility());
             }
             SymbolType::Decl(DefinedDecl::Data(d)) => {
-                st_info |= STT_OBJECT;
+                // the value of a thread-local symbol is, as usual, its offset in its section, which
+                // the linker turns into an offset in the TLS block
+                st_info |= symbol_kind_stt(self.typ);
                 st_info |= scope_stb_flags(d.get_scope());
                 st_other |= vis_stother_flags(d.get_visibility());
             }
-            SymbolType::Import => {
+            SymbolType::Import { weak } => {
+                st_info = STT_NOTYPE;
+                st_info |= if weak { STB_WEAK } else { STB_GLOBAL } << 4;
+            }
+            SymbolType::SectionLabel => {
                 st_info = STT_NOTYPE;
                 st_info |= STB_GLOBAL << 4;
             }
@@ -193,9 +350,14 @@ enum SectionType {
     Bits,
     Data,
     String,
+    /// Mergeable constants of the given size
+    Literal(u64),
     StrTab,
     SymTab,
-    Relocation,
+    /// Relocations, with explicit addends if `rela`
+    Relocation {
+        rela: bool,
+    },
     SymTabShndx,
     None,
 }
@@ -206,6 +368,9 @@ struct SectionBuilder {
     exec: bool,
     write: bool,
     alloc: bool,
+    retain: bool,
+    tls: bool,
+    flags: u64,
     size: u64,
     name_offset: usize,
     align: Option<u64>,
@@ -219,6 +384,9 @@ impl SectionBuilder {
             exec: false,
             write: false,
             alloc: false,
+            retain: false,
+            tls: false,
+            flags: 0,
             name_offset: 0,
             size,
             align: None,
@@ -239,6 +407,21 @@ impl SectionBuilder {
         self.write = writable;
         self
     }
+    /// Prevent the linker from garbage collecting this section
+    pub fn retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+    /// Make this section hold the initial values of thread-local variables
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+    /// Add raw `sh_flags` to those this section would otherwise have
+    pub fn flags(mut self, flags: u64) -> Self {
+        self.flags = flags;
+        self
+    }
     /// Specify section alignment
     pub fn align(mut self, align: Option<u64>) -> Self {
         self.align = align;
@@ -271,6 +454,13 @@ impl SectionBuilder {
         if self.alloc {
             shdr.sh_flags |= SHF_ALLOC as u64
         }
+        if self.retain {
+            shdr.sh_flags |= SHF_GNU_RETAIN
+        }
+        if self.tls {
+            shdr.sh_flags |= SHF_TLS as u64
+        }
+        shdr.sh_flags |= self.flags;
 
         let align = if let Some(align) = self.align {
             align as u64
@@ -292,6 +482,13 @@ impl SectionBuilder {
                 shdr.sh_type = SHT_PROGBITS;
                 shdr.sh_flags |= (SHF_MERGE | SHF_STRINGS) as u64;
             }
+            SectionType::Literal(size) => {
+                // each constant must be naturally aligned for the linker to split them up
+                shdr.sh_addralign = std::cmp::max(align, size);
+                shdr.sh_type = SHT_PROGBITS;
+                shdr.sh_flags |= SHF_MERGE as u64;
+                shdr.sh_entsize = size;
+            }
             SectionType::Data => {
                 shdr.sh_addralign = align;
                 shdr.sh_type = SHT_PROGBITS;
@@ -305,12 +502,11 @@ impl SectionBuilder {
                 shdr.sh_addralign = 0x8;
                 shdr.sh_type = SHT_SYMTAB;
             }
-            SectionType::Relocation => {
-                // FIXME: hardcodes to use rela
-                shdr.sh_entsize = Relocation::size(true, *ctx) as u64;
-                shdr.sh_addralign = 0x8;
+            SectionType::Relocation { rela } => {
+                shdr.sh_entsize = Relocation::size(rela, *ctx) as u64;
+                shdr.sh_addralign = ctx.size() as u64;
                 shdr.sh_flags = 0;
-                shdr.sh_type = SHT_RELA
+                shdr.sh_type = if rela { SHT_RELA } else { SHT_REL };
             }
             SectionType::SymTabShndx => {
                 shdr.sh_entsize = 4;
@@ -318,6 +514,7 @@ impl SectionBuilder {
                 shdr.sh_type = SHT_SYMTAB_SHNDX;
             }
             SectionType::NoBits => {
+                shdr.sh_addralign = align;
                 shdr.sh_type = SHT_NOBITS;
                 // .bss is always SHF_WRITE and SHF_ALLOC
                 // TODO: warn users if self.alloc is not set
@@ -386,13 +583,38 @@ impl RelocationBuilder {
     }
 }
 
+/// Where the string table, symbol table, relocations and section headers, which follow the
+/// section contents, are placed in the file
+struct FileOffsets {
+    symbol_count: usize,
+    sizeof_symtab: usize,
+    sizeof_symtab_shndx: u64,
+    symtab_shndx_name_offset: usize,
+    need_symtab_shndx: bool,
+    nonexec_stack_note_name_offset: usize,
+    strtab_offset: u64,
+    symtab_offset: u64,
+    symtab_shndx_offset: u64,
+    reloc_offset: u64,
+    sh_offset: u64,
+}
+
 /// An intermediate ELF object file container
 struct Elf<'a> {
     name: &'a str,
-    code: IndexMap<StringIndex, &'a [u8]>,
+    code: IndexMap<StringIndex, &'a Data>,
+    /// The addends of relocations without explicit addends, written into the relocated section's
+    /// contents, as `(offset, size, addend)`
+    implicit_addends: HashMap<StringIndex, Vec<(u64, u8, i64)>>,
     relocations: IndexMap<StringIndex, (Section, Vec<Relocation>)>,
+    // the section index, name of the link target, and size of the relocated field, if known, of
+    // every relocation
+    relocation_targets: Vec<(usize, &'a str, Relocation, Option<u8>)>,
     symbols: IndexMap<StringIndex, Symbol>,
     special_symbols: Vec<Symbol>,
+    // the name, section index and offset of every custom symbol in a custom section, which are
+    // global and so must follow the local symbols of every definition
+    section_labels: Vec<(&'a str, usize, u64)>,
     imports: HashMap<StringIndex, ImportKind>,
     sections: IndexMap<StringIndex, SectionInfo>,
     offsets: HashMap<StringIndex, Offset>,
@@ -402,6 +624,10 @@ struct Elf<'a> {
     nsections: u32,
     ctx: Ctx,
     architecture: Architecture,
+    osabi: Option<u8>,
+    abiversion: u8,
+    flags: u32,
+    relocation_model: RelocationModel,
     nlocals: usize,
 }
 
@@ -445,7 +671,7 @@ impl<'a> Elf<'a> {
 
             push_strtab(".strtab");
             push_strtab(".symtab");
-            let offset = push_strtab(&artifact.name);
+            let offset = push_strtab(artifact.source_file().unwrap_or(&artifact.name));
             // ELF requires a null symbol as the first symbol.
             special_symbols.push(Symbol::default());
             special_symbols.push(
@@ -459,10 +685,13 @@ impl<'a> Elf<'a> {
         Elf {
             name: &artifact.name,
             code: IndexMap::new(),
+            implicit_addends: HashMap::new(),
             relocations: IndexMap::new(),
+            relocation_targets: Vec::new(),
             imports: HashMap::new(),
             symbols: IndexMap::new(),
             special_symbols,
+            section_labels: Vec::new(),
             sections: IndexMap::new(),
             nsections: 4,
             offsets,
@@ -471,6 +700,12 @@ impl<'a> Elf<'a> {
             sizeof_bits,
             ctx,
             architecture: artifact.target.architecture,
+            osabi: artifact.elf_osabi,
+            abiversion: artifact.elf_abiversion,
+            flags: artifact
+                .elf_flags
+                .unwrap_or_else(|| default_flags(artifact.target.architecture)),
+            relocation_model: artifact.relocation_model,
             nlocals: 0,
         }
     }
@@ -493,26 +728,54 @@ impl<'a> Elf<'a> {
         match typ {
             DataType::Bytes => SectionType::Data,
             DataType::String => SectionType::String,
+            DataType::Literal4 | DataType::Literal8 => {
+                SectionType::Literal(typ.literal_size().unwrap())
+            }
         }
     }
-    pub fn add_definition(&mut self, def: artifact::Definition<'a>) {
+    pub fn add_definition(&mut self, def: artifact::Definition<'a>) -> Result<(), Error> {
         let name = def.name;
         let decl = def.decl;
-        let def_size = def.data.file_size();
+        // the size in memory, so that .bss sections and symbols span their zero-initialized data
+        let def_size = def.data.size();
+
+        // the linker allocates common symbols, so they are in no section, and their value is
+        // their alignment
+        if decl.is_common() {
+            let (idx, offset) = self.new_string(name.to_string());
+            let symbol = SymbolBuilder::from_decl(decl)
+                .size(def_size)
+                .name_offset(offset)
+                .section_index(section_header::SHN_COMMON as usize)
+                .value(decl.get_align().unwrap_or(8))
+                .create();
+            self.symbols.insert(idx, symbol);
+            return Ok(());
+        }
 
         let section_name = match (def.data, decl) {
-            (Data::Blob(_), DefinedDecl::Function(_)) => format!(".text.{}", name),
             (Data::ZeroInit(_), DefinedDecl::Function(_)) => {
                 unreachable!("cannot define function as zero-init")
             }
-            (Data::Blob(_), DefinedDecl::Data(decl)) => format!(
+            (_, DefinedDecl::Function(_)) => format!(".text.{}", name),
+            (Data::ZeroInit(_), DefinedDecl::Data(decl)) if decl.is_tls() => {
+                format!(".tbss.{}", name)
+            }
+            (Data::ZeroInit(_), DefinedDecl::Data(_)) => format!(".bss.{}", name),
+            (_, DefinedDecl::Data(decl)) if decl.is_tls() => format!(".tdata.{}", name),
+            (_, DefinedDecl::Data(decl)) => format!(
                 ".{}.{}",
                 if decl.is_writable() { "data" } else { "rodata" },
                 name
             ),
-            (Data::ZeroInit(_), DefinedDecl::Data(_)) => format!(".bss.{}", name),
             (_, DefinedDecl::Section(_)) => name.to_owned(),
         };
+        // a custom section may be named like the section of another definition, e.g. `.text.foo`
+        if let Some(idx) = self.strings.get(section_name.as_str()) {
+            if self.sections.contains_key(&idx) {
+                return Err(ArtifactError::SectionNameCollision(section_name).into());
+            }
+        }
 
         let section = match decl {
             DefinedDecl::Function(d) => SectionBuilder::new(def_size as u64)
@@ -523,28 +786,42 @@ impl<'a> Elf<'a> {
                 .align(d.get_align()),
             DefinedDecl::Data(d) => SectionBuilder::new(def_size as u64)
                 .section_type(Self::section_type_for_data(
-                    d.get_datatype(),
+                    def.data.datatype(d.get_datatype()),
                     def.data.is_zero_init(),
                 ))
                 .alloc()
-                .writable(d.is_writable())
+                .writable(d.is_writable() || d.is_tls())
+                .tls(d.is_tls())
                 .exec(false)
                 .align(d.get_align()),
-            DefinedDecl::Section(d) => SectionBuilder::new(def_size as u64)
-                .section_type(
-                    // TODO: this behavior should be deprecated, but we need to warn users!
-                    if name == ".debug_str" || name == ".debug_line_str" {
-                        SectionType::String
-                    } else {
-                        Self::section_type_for_data(d.get_datatype(), def.data.is_zero_init())
-                    },
-                )
-                .align(d.get_align()),
+            DefinedDecl::Section(d) => {
+                let section = SectionBuilder::new(def_size as u64)
+                    .section_type(
+                        // TODO: this behavior should be deprecated, but we need to warn users!
+                        if name == ".debug_str" || name == ".debug_line_str" {
+                            SectionType::String
+                        } else {
+                            Self::section_type_for_data(d.get_datatype(), def.data.is_zero_init())
+                        },
+                    )
+                    .retain(d.is_retained())
+                    .flags(d.get_elf_flags())
+                    .align(if d.is_raw() { Some(1) } else { d.get_align() });
+                // legacy linkers keep only one `.gnu.linkonce.<kind>.<name>` section of each name,
+                // which is loaded like the section its kind abbreviates
+                match linkonce_kind(name) {
+                    Some("t") => section.alloc().exec(true),
+                    Some("d") | Some("b") => section.alloc().writable(true),
+                    Some("r") => section.alloc(),
+                    _ => section,
+                }
+            }
         };
 
-        let shndx = match def.data {
-            Data::Blob(bytes) => self.add_progbits(section_name, section, bytes),
-            Data::ZeroInit(_) => self.add_section(section_name, section).1,
+        let shndx = if def.data.is_zero_init() {
+            self.add_section(section_name, section).1
+        } else {
+            self.add_progbits(section_name, section, def.data)
         };
 
         match decl {
@@ -568,20 +845,44 @@ impl<'a> Elf<'a> {
                 if !decl.is_global() {
                     self.nlocals += 1;
                 }
+                for (symbol, &symbol_dst_offset) in def.symbols {
+                    let (idx, offset) = self.new_string(symbol.to_string());
+                    let symbol = SymbolBuilder::from_decl(decl)
+                        .name_offset(offset)
+                        .section_index(shndx)
+                        .value(symbol_dst_offset)
+                        .create();
+                    self.symbols.insert(idx, symbol);
+                    if !decl.is_global() {
+                        self.nlocals += 1;
+                    }
+                }
             }
             DefinedDecl::Section(_) => {
-                for (_symbol, _symbol_dst_offset) in def.symbols {
-                    // FIXME: implement it
-                    unimplemented!("elf: custom symbols referencing sections");
+                for (symbol, &symbol_dst_offset) in def.symbols {
+                    self.section_labels.push((symbol, shndx, symbol_dst_offset));
                 }
             }
         }
+        Ok(())
+    }
+    /// Add the custom symbols of custom sections, after every definition's local symbols
+    pub fn add_section_labels(&mut self) {
+        for (name, shndx, value) in std::mem::take(&mut self.section_labels) {
+            let (idx, offset) = self.new_string(name.to_string());
+            let symbol = SymbolBuilder::new(SymbolType::SectionLabel)
+                .name_offset(offset)
+                .section_index(shndx)
+                .value(value)
+                .create();
+            self.symbols.insert(idx, symbol);
+        }
     }
     /// Create a progbits section (and its section symbol), and return the section index.
-    fn add_progbits(&mut self, name: String, section: SectionBuilder, data: &'a [u8]) -> usize {
+    fn add_progbits(&mut self, name: String, section: SectionBuilder, data: &'a Data) -> usize {
         let (idx, shndx) = self.add_section(name, section);
         // increment the size
-        self.sizeof_bits += data.len();
+        self.sizeof_bits += data.file_size();
 
         self.code.insert(idx, data);
         shndx
@@ -614,16 +915,62 @@ impl<'a> Elf<'a> {
         self.nsections += 1;
         (idx, shndx)
     }
-    pub fn import(&mut self, import: String, kind: &ImportKind) {
+    pub fn import(&mut self, import: String, kind: ImportKind, weak: bool) {
         let (idx, offset) = self.new_string(import);
-        let symbol = SymbolBuilder::new(SymbolType::Import)
+        let symbol = SymbolBuilder::new(SymbolType::Import { weak })
             .name_offset(offset)
             .create();
-        self.imports.insert(idx, kind.clone());
+        self.imports.insert(idx, kind);
         self.symbols.insert(idx, symbol);
     }
-    pub fn link(&mut self, l: &LinkAndDecl) {
+    pub fn link(&mut self, l: &LinkAndDecl<'a>) -> Result<(), Error> {
         debug!("Link: {:?}", l);
+        let unsupported = |reason: String| ArtifactError::UnsupportedRelocation {
+            from: l.from.name.to_string(),
+            to: l.to.name.to_string(),
+            reason,
+        };
+        match l.reloc {
+            Reloc::Auto
+            | Reloc::Call
+            | Reloc::GotLoad
+            | Reloc::RelaxableGotLoad { .. }
+            | Reloc::PcRelative { .. }
+            | Reloc::Absolute { .. }
+                if self.architecture != Architecture::X86_64 =>
+            {
+                return Err(unsupported(format!(
+                    "{:?} relocations are only supported for x86_64, not {}",
+                    l.reloc, self.architecture
+                ))
+                .into());
+            }
+            Reloc::Scattered { .. } => {
+                return Err(unsupported(
+                    "scattered relocations are only supported by Mach-O".to_string(),
+                )
+                .into());
+            }
+            Reloc::Raw { reloc, .. } if !is_valid_reloc(self.architecture, self.ctx, reloc) => {
+                return Err(unsupported(format!(
+                    "{} is not a valid {} relocation",
+                    reloc, self.architecture
+                ))
+                .into());
+            }
+            _ => {}
+        }
+        match (l.reloc, l.to.decl) {
+            (Reloc::Raw { .. }, _) => {}
+            // which relocation accesses a thread-local variable depends on the TLS access model
+            (_, Decl::Defined(DefinedDecl::Data(d))) if d.is_tls() => {
+                return Err(unsupported(
+                    "only raw relocations can refer to thread-local variables".to_string(),
+                )
+                .into());
+            }
+            _ => {}
+        }
         let (to_idx, to_shndx) = {
             let to_idx = self.strings.get_or_intern(l.to.name);
             if l.to.decl.is_section() {
@@ -677,9 +1024,34 @@ impl<'a> Elf<'a> {
                             // although we're not in the worst company here: https://github.com/ocaml/ocaml/pull/1330
                             Decl::Defined(DefinedDecl::Function { .. })
                             | Decl::Import(ImportKind::Function) => (reloc::R_X86_64_PLT32, -4),
+                            // code computes the GOT base from its own address, as for
+                            // `lea _GLOBAL_OFFSET_TABLE_(%rip), %r15`
+                            Decl::Import(ImportKind::Data) if l.to.name == GLOBAL_OFFSET_TABLE => {
+                                (reloc::R_X86_64_GOTPC32, -4)
+                            }
+                            // code at a fixed address refers to data by its absolute address
+                            Decl::Defined(DefinedDecl::Data(d))
+                                if self.relocation_model == RelocationModel::Static
+                                    && !d.is_tls() =>
+                            {
+                                (reloc::R_X86_64_32S, 0)
+                            }
+                            Decl::Import(ImportKind::Data)
+                            | Decl::Import(ImportKind::SectionBoundary)
+                                if self.relocation_model == RelocationModel::Static =>
+                            {
+                                (reloc::R_X86_64_32S, 0)
+                            }
+                            // the linker defines section boundaries in the image, so code refers
+                            // to them directly
+                            Decl::Import(ImportKind::SectionBoundary) => (reloc::R_X86_64_PC32, -4),
                             Decl::Defined(DefinedDecl::Data { .. }) => (reloc::R_X86_64_PC32, -4),
                             Decl::Import(ImportKind::Data) => (reloc::R_X86_64_GOTPCREL, -4),
-                            _ => panic!("unsupported relocation {:?}", l),
+                            _ => return Err(unsupported(
+                                "Auto relocations from a function to a section are not supported"
+                                    .to_string(),
+                            )
+                            .into()),
                         }
                     }
                     Decl::Defined(DefinedDecl::Data { .. }) => {
@@ -690,19 +1062,67 @@ impl<'a> Elf<'a> {
                             (reloc::R_X86_64_32, 0)
                         }
                     }
-                    _ => panic!("unsupported relocation {:?}", l),
+                    _ => {
+                        return Err(unsupported(
+                            "Auto relocations from a section are not supported".to_string(),
+                        )
+                        .into())
+                    }
                 }
             }
+            Reloc::Call => (reloc::R_X86_64_PLT32, -4),
+            Reloc::GotLoad => (reloc::R_X86_64_GOTPCREL, -4),
+            Reloc::RelaxableGotLoad { rex: true } => (reloc::R_X86_64_REX_GOTPCRELX, -4),
+            Reloc::RelaxableGotLoad { rex: false } => (reloc::R_X86_64_GOTPCRELX, -4),
+            Reloc::PcRelative { trailing } => (reloc::R_X86_64_PC32, -4 - i32::from(trailing)),
             Reloc::Raw { reloc, addend } => (reloc, addend),
-            Reloc::Debug { size, addend } => match size {
+            Reloc::Absolute { size, addend } => match size {
                 4 => (reloc::R_X86_64_32, addend),
                 8 => (reloc::R_X86_64_64, addend),
-                _ => panic!("unsupported relocation {:?}", l),
+                size => {
+                    return Err(unsupported(format!(
+                        "absolute relocations must be 4 or 8 bytes, not {}",
+                        size
+                    ))
+                    .into())
+                }
+            },
+            Reloc::Debug { size, addend } => match (self.architecture, size) {
+                (Architecture::X86_64, 4) => (reloc::R_X86_64_32, addend),
+                (Architecture::X86_64, 8) => (reloc::R_X86_64_64, addend),
+                (Architecture::Aarch64(_), 4) if self.ctx.is_big() => {
+                    (reloc::R_AARCH64_ABS32, addend)
+                }
+                (Architecture::Aarch64(_), 8) if self.ctx.is_big() => {
+                    (reloc::R_AARCH64_ABS64, addend)
+                }
+                (_, 4) | (_, 8) => {
+                    return Err(unsupported(format!(
+                        "{}-byte debug relocations are not supported for {}",
+                        size, self.architecture
+                    ))
+                    .into())
+                }
+                (_, size) => {
+                    return Err(unsupported(format!(
+                        "debug relocations must be 4 or 8 bytes, not {}",
+                        size
+                    ))
+                    .into())
+                }
             },
+            Reloc::Scattered { .. } => unreachable!("rejected above"),
         };
         let addend = i64::from(addend);
 
         let sym_idx = match *l.to.decl {
+            // the linker needs to see the ifunc symbol itself to call its resolver, e.g. through
+            // an R_X86_64_IRELATIVE relocation in the linked image
+            Decl::Defined(d) if d.get_symbol_kind() == Some(SymbolKind::IndirectFunction) => to_idx,
+            // and TLS relocations are resolved against the thread-local symbol itself
+            Decl::Defined(DefinedDecl::Data(d)) if d.is_tls() => to_idx,
+            // common symbols are in no section
+            Decl::Defined(d) if d.is_common() => to_idx,
             Decl::Defined(_) => {
                 // We don't emit symbols for null + strtab + symtab, and
                 // section symbols come after special symbols.
@@ -711,12 +1131,96 @@ impl<'a> Elf<'a> {
             Decl::Import(_) => to_idx,
         };
 
-        let reloc = RelocationBuilder::new(reloc)
-            .sym(sym_idx)
-            .offset(l.at)
-            .addend(addend)
-            .create();
-        self.add_reloc(l.from.name, reloc, from_idx, from_shndx)
+        let builder = RelocationBuilder::new(reloc).sym(sym_idx).offset(l.at);
+        let reloc = if uses_rela(self.architecture) {
+            builder.addend(addend).create()
+        } else {
+            // without an addend, the relocated field's contents are the addend; a zero addend
+            // leaves whatever they are in place
+            if addend != 0 {
+                let size = match l.reloc {
+                    Reloc::Debug { size, .. } => Some(size),
+                    _ => implicit_addend_size(self.architecture, reloc),
+                };
+                let size = size.ok_or_else(|| {
+                    unsupported(format!(
+                        "relocation {} cannot have addend {}, since {} relocations have implicit addends",
+                        reloc, addend, self.architecture
+                    ))
+                })?;
+                let (&section, _) = self
+                    .sections
+                    .get_index(from_shndx - 3)
+                    .expect("from_shndx present in sections");
+                self.implicit_addends
+                    .entry(section)
+                    .or_default()
+                    .push((l.at, size, addend));
+            }
+            builder.rel().create()
+        };
+        let size = match l.reloc {
+            Reloc::Absolute { size, .. } | Reloc::Debug { size, .. } => Some(size),
+            _ => relocation_size(self.architecture, reloc.r_type),
+        };
+        self.relocation_targets
+            .push((from_shndx, l.to.name, reloc, size));
+        self.add_reloc(l.from.name, reloc, from_idx, from_shndx);
+        Ok(())
+    }
+    fn section_name(&self, shndx: usize) -> &str {
+        let (_, section) = self
+            .sections
+            .get_index(shndx - 3)
+            .expect("shndx present in sections");
+        self.strings
+            .resolve(section.name)
+            .expect("section name in strings")
+    }
+    /// The placement of every section added by a definition
+    fn resolved_sections(&self) -> Vec<ResolvedSection> {
+        self.sections
+            .values()
+            .map(|section| ResolvedSection {
+                name: self
+                    .strings
+                    .resolve(section.name)
+                    .expect("section name in strings")
+                    .to_string(),
+                offset: section.header.sh_offset,
+                size: section.header.sh_size,
+            })
+            .collect()
+    }
+    /// The placement of every symbol added by a definition
+    fn resolved_symbols(&self) -> Vec<ResolvedSymbol> {
+        self.symbols
+            .iter()
+            .filter(|(_, symbol)| is_placed(symbol))
+            .map(|(&name, symbol)| ResolvedSymbol {
+                name: self
+                    .strings
+                    .resolve(name)
+                    .expect("symbol name in strings")
+                    .to_string(),
+                section: self.section_name(symbol.st_shndx).to_string(),
+                offset: symbol.st_value,
+            })
+            .collect()
+    }
+    /// Every relocation added by a link
+    fn resolved_relocations(&self) -> Vec<ResolvedReloc> {
+        self.relocation_targets
+            .iter()
+            .map(|&(shndx, symbol, reloc, size)| ResolvedReloc {
+                section: self.section_name(shndx).to_string(),
+                offset: reloc.r_offset,
+                symbol: symbol.to_string(),
+                reloc: reloc.r_type,
+                size,
+                addend: reloc.r_addend.unwrap_or(0),
+            })
+            .collect()
     }
     fn add_reloc(&mut self, relocee: &str, reloc: Relocation, idx: usize, shndx: usize) {
         debug!(
@@ -733,21 +1237,16 @@ impl<'a> Elf<'a> {
         } else {
             debug!("{} does NOT have relocs", relocee);
             // now create the relocation section
-            let reloc_name = {
-                let (_, section) = self
-                    .sections
-                    .get_index(shndx - 3)
-                    .expect("shndx present in sections");
-                let section_name = self
-                    .strings
-                    .resolve(section.name)
-                    .expect("section name in strings");
-                format!(".rela{}", section_name)
-            };
+            let rela = reloc.r_addend.is_some();
+            let reloc_name = format!(
+                ".{}{}",
+                if rela { "rela" } else { "rel" },
+                self.section_name(shndx)
+            );
             let (_reloc_idx, reloc_section_offset) = self.new_string(reloc_name);
             let mut reloc_section = SectionBuilder::new(reloc_size)
                 .name_offset(reloc_section_offset)
-                .section_type(SectionType::Relocation)
+                .section_type(SectionType::Relocation { rela })
                 .create(&self.ctx);
             // its sh_link always points to the symtable
             reloc_section.sh_link = SYMTAB_LINK as u32;
@@ -764,13 +1263,10 @@ impl<'a> Elf<'a> {
             *offset += sizeof_t - alignment;
         }
     }
-    pub fn write<T: Write + Seek>(mut self, file: T) -> goblin::error::Result<()> {
-        use goblin::elf::section_header::{SHN_LORESERVE, SHN_XINDEX};
-        let mut file = BufWriter::new(file);
-
-        /////////////////////////////////////
-        // Compute Offsets
-        /////////////////////////////////////
+    /// Compute the offsets of everything following the section contents in the file, adding
+    /// the names of the sections only created when writing to the string table
+    fn file_offsets(&mut self) -> FileOffsets {
+        use goblin::elf::section_header::SHN_LORESERVE;
         let symbol_count = self.symbols.len() + self.special_symbols.len() + self.sections.len();
         let sizeof_symtab = symbol_count * Symbol::size(self.ctx.container);
         // This check is a bit lax, we really only need .symtab_shndx if there is a symbol
@@ -789,38 +1285,80 @@ impl<'a> Elf<'a> {
             .relocations
             .iter()
             .fold(0, |acc, (_, &(ref _shdr, ref rels))| rels.len() + acc)
-            * Relocation::size(true, self.ctx);
+            * Relocation::size(uses_rela(self.architecture), self.ctx);
         let nonexec_stack_note_name_offset = self.new_string(".note.GNU-stack".into()).1;
         let strtab_offset = self.sizeof_bits as u64;
 
         // alignment required for below
         let mut symtab_offset = strtab_offset + self.sizeof_strtab as u64;
-        let symtab_align = self.ctx.size() as u64;
-        Self::align(&mut symtab_offset, symtab_align);
+        Self::align(&mut symtab_offset, self.ctx.size() as u64);
         let mut symtab_shndx_offset = symtab_offset + sizeof_symtab as u64;
-        let symtab_shndx_align = 4;
         if need_symtab_shndx {
-            Self::align(&mut symtab_shndx_offset, symtab_shndx_align);
+            Self::align(&mut symtab_shndx_offset, 4);
         }
         let mut reloc_offset = symtab_shndx_offset + sizeof_symtab_shndx;
-        let reloc_align = self.ctx.size() as u64;
-        Self::align(&mut reloc_offset, reloc_align);
+        Self::align(&mut reloc_offset, self.ctx.size() as u64);
         let mut sh_offset = reloc_offset + sizeof_relocs as u64;
-        let shdr_align = self.ctx.size() as u64;
-        Self::align(&mut sh_offset, shdr_align);
+        Self::align(&mut sh_offset, self.ctx.size() as u64);
 
         info!(
             "strtab: {:#x} symtab {:#x} relocs {:#x} sh_offset {:#x}",
             strtab_offset, symtab_offset, reloc_offset, sh_offset
         );
+        FileOffsets {
+            symbol_count,
+            sizeof_symtab,
+            sizeof_symtab_shndx,
+            symtab_shndx_name_offset,
+            need_symtab_shndx,
+            nonexec_stack_note_name_offset,
+            strtab_offset,
+            symtab_offset,
+            symtab_shndx_offset,
+            reloc_offset,
+            sh_offset,
+        }
+    }
+    /// The size of the file `write` would emit, in bytes, computed without writing it
+    pub fn size(mut self) -> u64 {
+        let offsets = self.file_offsets();
+        // the section headers come last
+        offsets.sh_offset + u64::from(self.nsections) * Section::size(self.ctx) as u64
+    }
+    pub fn write<T: Write + Seek>(mut self, file: T) -> goblin::error::Result<()> {
+        use goblin::elf::section_header::{SHN_COMMON, SHN_LORESERVE, SHN_XINDEX};
+        let mut file = BufWriter::new(file);
+
+        /////////////////////////////////////
+        // Compute Offsets
+        /////////////////////////////////////
+        let FileOffsets {
+            symbol_count,
+            sizeof_symtab,
+            sizeof_symtab_shndx,
+            symtab_shndx_name_offset,
+            need_symtab_shndx,
+            nonexec_stack_note_name_offset,
+            strtab_offset,
+            symtab_offset,
+            symtab_shndx_offset,
+            reloc_offset,
+            sh_offset,
+        } = self.file_offsets();
+        let symtab_align = self.ctx.size() as u64;
+        let symtab_shndx_align = 4;
+        let reloc_align = self.ctx.size() as u64;
+        let shdr_align = self.ctx.size() as u64;
 
         /////////////////////////////////////
         // Header
         /////////////////////////////////////
         let mut header = Header::new(self.ctx);
-        let machine: MachineTag = self.architecture.into();
+        let machine =
+            MachineTag::try_from(self.architecture).expect("architecture is checked when building");
         header.e_machine = machine.0;
         header.e_type = header::ET_REL;
+        header.e_flags = self.flags;
         header.e_shoff = sh_offset;
         header.e_shnum = if self.nsections >= SHN_LORESERVE.into() {
             0
@@ -828,6 +1366,20 @@ impl<'a> Elf<'a> {
             self.nsections as u16
         };
         header.e_shstrndx = STRTAB_LINK;
+        let uses_gnu_extensions = self
+            .sections
+            .values()
+            .any(|section| section.header.sh_flags & SHF_GNU_RETAIN != 0)
+            || self
+                .symbols
+                .values()
+                .any(|symbol| symbol.st_type() == goblin::elf::sym::STT_GNU_IFUNC);
+        header.e_ident[header::EI_OSABI] = self.osabi.unwrap_or(if uses_gnu_extensions {
+            ELFOSABI_GNU
+        } else {
+            header::ELFOSABI_NONE
+        });
+        header.e_ident[header::EI_ABIVERSION] = self.abiversion;
 
         file.iowrite_with(header, self.ctx)?;
         let after_header = file.seek(Current(0))?;
@@ -838,8 +1390,25 @@ impl<'a> Elf<'a> {
         // Code
         /////////////////////////////////////
 
-        for (_idx, bytes) in self.code.drain(..) {
-            file.write_all(bytes)?;
+        for (idx, data) in self.code.drain(..) {
+            match self.implicit_addends.get(&idx) {
+                Some(addends) => {
+                    let mut bytes = Vec::with_capacity(data.file_size());
+                    data.write_to(&mut bytes)?;
+                    for &(offset, size, addend) in addends {
+                        let offset = offset as usize;
+                        match size {
+                            1 => bytes.pwrite_with(addend as i8, offset, self.ctx.le)?,
+                            2 => bytes.pwrite_with(addend as i16, offset, self.ctx.le)?,
+                            4 => bytes.pwrite_with(addend as i32, offset, self.ctx.le)?,
+                            8 => bytes.pwrite_with(addend, offset, self.ctx.le)?,
+                            size => panic!("unsupported relocation size {}", size),
+                        };
+                    }
+                    file.write_all(&bytes)?;
+                }
+                None => data.write_to(&mut file)?,
+            }
         }
         let after_code = file.seek(Current(0))?;
         debug!("after_code {:#x}", after_code);
@@ -937,7 +1506,8 @@ impl<'a> Elf<'a> {
                     .gwrite_with(sym.st_shndx as u32, &mut offset, self.ctx.le)
                     .expect("preallocated shndx vector has enough space for symbols");
             }
-            if sym.st_shndx >= SHN_LORESERVE as usize {
+            // common symbols keep their reserved section index
+            if sym.st_shndx >= SHN_LORESERVE as usize && sym.st_shndx != SHN_COMMON as usize {
                 sym.st_shndx = SHN_XINDEX as usize;
             }
             file.iowrite_with(sym, self.ctx)?;
@@ -1024,22 +1594,128 @@ impl<'a> Elf<'a> {
     }
 }
 
-pub fn to_bytes(artifact: &Artifact) -> Result<Vec<u8>, Error> {
+fn build(artifact: &Artifact) -> Result<Elf<'_>, Error> {
+    MachineTag::try_from(artifact.target.architecture)?;
+    if let Some((name, _)) = artifact
+        .emitted_imports()
+        .find(|(import, _)| artifact.reexported_import(import).is_some())
+    {
+        return Err(ArtifactError::UnsupportedSymbol {
+            name: name.to_string(),
+            reason: "re-exports are not supported by ELF".to_string(),
+        }
+        .into());
+    }
     // TODO: make new fully construct the elf object, e.g., the definitions, imports, and links don't take self
     // this means that a call to new has a fully constructed object ready to marshal into bytes, similar to the mach backend
     let mut elf = Elf::new(&artifact);
     for def in artifact.definitions() {
         debug!("Def: {:?}", def);
-        elf.add_definition(def);
+        elf.add_definition(def)?;
     }
-    for (ref import, ref kind) in artifact.imports() {
+    elf.add_section_labels();
+    for (import, kind) in artifact.emitted_imports() {
         debug!("Import: {:?} -> {:?}", import, kind);
-        elf.import(import.to_string(), kind);
+        elf.import(import.to_string(), kind, artifact.is_weak_import(import));
     }
     for link in artifact.links() {
-        elf.link(&link);
+        elf.link(&link)?;
     }
+    Ok(elf)
+}
+
+/// Whether `symbol` is placed in a section of the object file, unlike imports and common symbols
+fn is_placed(symbol: &Symbol) -> bool {
+    symbol.st_shndx != section_header::SHN_UNDEF as usize
+        && symbol.st_shndx != section_header::SHN_COMMON as usize
+}
+
+pub fn layout(artifact: &Artifact) -> Result<Layout, Error> {
+    let elf = build(artifact)?;
+    Ok(Layout {
+        sections: elf.resolved_sections(),
+        symbols: elf.resolved_symbols(),
+        relocations: elf.resolved_relocations(),
+    })
+}
+
+pub fn estimated_size(artifact: &Artifact) -> Result<u64, Error> {
+    Ok(build(artifact)?.size())
+}
+
+/// The offset of every defined symbol from the start of the file, when the zero-fill sections,
+/// which take up no space in it, follow the end of the file
+pub fn symbol_offsets(artifact: &Artifact) -> Result<BTreeMap<String, u64>, Error> {
+    let elf = build(artifact)?;
+    let headers = elf
+        .sections
+        .values()
+        .map(|section| section.header.clone())
+        .collect::<Vec<_>>();
+    let symbols = elf
+        .symbols
+        .iter()
+        .filter(|(_, symbol)| is_placed(symbol))
+        .map(|(&name, symbol)| {
+            let name = elf.strings.resolve(name).expect("symbol name in strings");
+            (name.to_string(), symbol.st_shndx, symbol.st_value)
+        })
+        .collect::<Vec<_>>();
+    let mut end = elf.size();
+    let starts = headers
+        .iter()
+        .map(|header| {
+            if header.sh_type != section_header::SHT_NOBITS {
+                return header.sh_offset;
+            }
+            Elf::align(&mut end, header.sh_addralign.max(1));
+            let start = end;
+            end += header.sh_size;
+            start
+        })
+        .collect::<Vec<_>>();
+    // the null section, the string table and the symbol table come first
+    Ok(symbols
+        .into_iter()
+        .map(|(name, shndx, value)| (name, starts[shndx - 3] + value))
+        .collect())
+}
+
+pub fn to_bytes(artifact: &Artifact) -> Result<ResolvedObject, Error> {
+    let elf = build(artifact)?;
+    let sections = elf.resolved_sections();
+    let relocations = elf.resolved_relocations();
     let mut buffer = Cursor::new(Vec::new());
     elf.write(&mut buffer)?;
-    Ok(buffer.into_inner())
+    Ok((buffer.into_inner(), sections, relocations))
+}
+
+pub fn section_contents(artifact: &Artifact) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let elf = build(artifact)?;
+    let sections = elf
+        .resolved_sections()
+        .into_iter()
+        .zip(elf.sections.values())
+        .map(|(resolved, section)| {
+            (
+                resolved,
+                section.header.sh_type == section_header::SHT_NOBITS,
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut buffer = Cursor::new(Vec::new());
+    elf.write(&mut buffer)?;
+    let bytes = buffer.into_inner();
+    Ok(sections
+        .into_iter()
+        .map(|(section, nobits)| {
+            // NOBITS sections take up no space in the file, and are zeroed when loaded
+            let contents = if nobits {
+                vec![0; section.size as usize]
+            } else {
+                bytes[section.offset as usize..][..section.size as usize].to_vec()
+            };
+            (section.name, contents)
+        })
+        .collect())
 }
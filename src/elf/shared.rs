@@ -0,0 +1,549 @@
+//! Shared libraries (`ET_DYN`) for artifacts whose definitions need no relocations.
+//!
+//! The library is laid out with one `PT_LOAD` segment for the headers, the dynamic symbol table
+//! and read-only data, one for code, and one for writable data, `.dynamic` and `.bss`, each
+//! starting on its own page and mapped at its file offset. Every global, non-hidden symbol is
+//! exported in `.dynsym`, which the dynamic linker searches with the SysV `.hash` table.
+//!
+//! PC-relative links between definitions are resolved when the library is laid out, so, as with
+//! `-Bsymbolic`, they always refer to the library's own definitions.
+
+use crate::artifact::{
+    Artifact, ArtifactError, Data, Decl, DefinedDecl, LinkAndDecl, Reloc, RelocationModel, Scope,
+    Visibility,
+};
+use crate::target::make_ctx;
+use failure::Error;
+use goblin::elf::dynamic::{
+    Dyn, DT_HASH, DT_NULL, DT_SONAME, DT_STRSZ, DT_STRTAB, DT_SYMENT, DT_SYMTAB,
+};
+use goblin::elf::header::{self, Header};
+use goblin::elf::program_header::{
+    ProgramHeader, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_GNU_STACK, PT_LOAD,
+};
+use goblin::elf::section_header::{
+    SectionHeader, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_DYNAMIC, SHT_DYNSYM, SHT_HASH,
+    SHT_NOBITS, SHT_PROGBITS, SHT_STRTAB,
+};
+use scroll::{IOwrite, Pwrite};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::SeekFrom::Start;
+use std::io::{Cursor, Seek, Write};
+use target_lexicon::Architecture;
+
+use super::{default_flags, MachineTag, Symbol, SymbolBuilder};
+
+/// The segments are aligned to, and so the linked image must be loaded at a multiple of, the
+/// smallest page size of the supported architectures
+const PAGE_SIZE: u64 = 0x1000;
+
+/// The SysV hash of a dynamic symbol's name, which selects its bucket in `.hash`
+fn elf_hash(name: &str) -> u32 {
+    let mut hash = 0u32;
+    for &byte in name.as_bytes() {
+        hash = (hash << 4).wrapping_add(u32::from(byte));
+        let high = hash & 0xf000_0000;
+        if high != 0 {
+            hash ^= high >> 24;
+        }
+        hash &= !high;
+    }
+    hash
+}
+
+fn align(offset: u64, align: u64) -> u64 {
+    match offset % align {
+        0 => offset,
+        misalignment => offset + align - misalignment,
+    }
+}
+
+/// Lay out the contents of `header`, `size` bytes aligned to `addralign`, at `offset` in both the
+/// file and memory, and advance `offset` past them
+fn place(header: &mut SectionHeader, offset: &mut u64, size: u64, addralign: u64) {
+    *offset = align(*offset, addralign);
+    header.sh_offset = *offset;
+    header.sh_addr = *offset;
+    header.sh_size = size;
+    header.sh_addralign = addralign;
+    *offset += size;
+}
+
+/// An allocated section of the library holding definitions
+#[derive(Default)]
+struct Contents<'a> {
+    /// The definitions in the section, at their offsets in it
+    definitions: Vec<(u64, &'a Data)>,
+    size: u64,
+    align: u64,
+    /// The address of the section, once it is laid out
+    addr: u64,
+    /// The index of the section's header
+    shndx: usize,
+}
+
+impl<'a> Contents<'a> {
+    /// Place `data` with `align` at the end of the section, returning its offset in it
+    fn push(&mut self, data: &'a Data, align: u64) -> u64 {
+        let offset = self::align(self.size, align);
+        self.definitions.push((offset, data));
+        self.size = offset + data.size() as u64;
+        self.align = std::cmp::max(self.align, align);
+        offset
+    }
+    fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+}
+
+/// The addend of the 4-byte PC-relative field `link` is resolved to, relative to the address of
+/// its target, or why it needs a dynamic relocation instead
+fn pc_relative_addend(artifact: &Artifact, link: &LinkAndDecl) -> Result<i64, String> {
+    if artifact.target.architecture != Architecture::X86_64 {
+        return Err(format!(
+            "links are only resolved for x86_64, not {}",
+            artifact.target.architecture
+        ));
+    }
+    match (link.reloc, link.from.decl, link.to.decl) {
+        (Reloc::PcRelative { trailing }, _, Decl::Defined(_)) => Ok(-4 - i64::from(trailing)),
+        (Reloc::Call, _, Decl::Defined(_)) => Ok(-4),
+        // code refers to functions, and to data unless it is at a fixed address, pc-relatively
+        (
+            Reloc::Auto,
+            Decl::Defined(DefinedDecl::Function(_)),
+            Decl::Defined(DefinedDecl::Function(_)),
+        ) => Ok(-4),
+        (
+            Reloc::Auto,
+            Decl::Defined(DefinedDecl::Function(_)),
+            Decl::Defined(DefinedDecl::Data(_)),
+        ) if artifact.relocation_model != RelocationModel::Static => Ok(-4),
+        _ => Err(
+            "absolute relocations, and relocations to imports, need dynamic relocations, which \
+             shared libraries do not support yet"
+                .to_string(),
+        ),
+    }
+}
+
+/// Emit `artifact` as an ELF shared library
+pub fn to_bytes(artifact: &Artifact) -> Result<Vec<u8>, Error> {
+    let machine = MachineTag::try_from(artifact.target.architecture)?;
+    let ctx = make_ctx(&artifact.target);
+    if let Some((import, _)) = artifact.imports().next() {
+        return Err(ArtifactError::UnsupportedSymbol {
+            name: import.to_string(),
+            reason:
+                "imports require dynamic relocations, which shared libraries do not support yet"
+                    .to_string(),
+        }
+        .into());
+    }
+    let mut links = Vec::new();
+    for link in artifact.links() {
+        match pc_relative_addend(artifact, &link) {
+            Ok(addend) => links.push((link, addend)),
+            Err(reason) => {
+                return Err(ArtifactError::UnsupportedRelocation {
+                    from: link.from.name.to_string(),
+                    to: link.to.name.to_string(),
+                    reason,
+                }
+                .into())
+            }
+        }
+    }
+
+    let mut rodata = Contents::default();
+    let mut text = Contents::default();
+    let mut data = Contents::default();
+    let mut bss = Contents::default();
+    let mut placements = Vec::new();
+    for def in artifact.definitions() {
+        let (section, align): (&mut Contents, u64) = match def.decl {
+            DefinedDecl::Function(d) => (&mut text, d.get_align().unwrap_or(0x10)),
+            DefinedDecl::Data(d) if d.is_tls() => {
+                return Err(ArtifactError::UnsupportedSymbol {
+                    name: def.name.to_string(),
+                    reason: "thread-local variables are not supported by shared libraries yet"
+                        .to_string(),
+                }
+                .into())
+            }
+            DefinedDecl::Data(d) if def.data.is_zero_init() => {
+                (&mut bss, d.get_align().unwrap_or(0x8))
+            }
+            DefinedDecl::Data(d) if d.is_writable() => (&mut data, d.get_align().unwrap_or(0x8)),
+            DefinedDecl::Data(d) => (&mut rodata, d.get_align().unwrap_or(1)),
+            DefinedDecl::Section(_) => {
+                return Err(ArtifactError::UnsupportedSymbol {
+                    name: def.name.to_string(),
+                    reason: "sections are not supported by shared libraries".to_string(),
+                }
+                .into())
+            }
+        };
+        let offset = section.push(def.data, align);
+        placements.push((def, offset));
+    }
+
+    /////////////////////////////////////
+    // Section headers
+    /////////////////////////////////////
+    let mut shstrtab = vec![0u8];
+    let mut section_headers = vec![SectionHeader::default()];
+    let mut add_section = |name: &str, header: SectionHeader| {
+        let mut header = header;
+        header.sh_name = shstrtab.len();
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+        section_headers.push(header);
+        section_headers.len() - 1
+    };
+    let new_section = |sh_type, sh_flags: u32| SectionHeader {
+        sh_type,
+        sh_flags: u64::from(sh_flags),
+        ..SectionHeader::default()
+    };
+    let hash_shndx = add_section(".hash", new_section(SHT_HASH, SHF_ALLOC));
+    let dynsym_shndx = add_section(".dynsym", new_section(SHT_DYNSYM, SHF_ALLOC));
+    let dynstr_shndx = add_section(".dynstr", new_section(SHT_STRTAB, SHF_ALLOC));
+    if !rodata.is_empty() {
+        rodata.shndx = add_section(".rodata", new_section(SHT_PROGBITS, SHF_ALLOC));
+    }
+    if !text.is_empty() {
+        let flags = SHF_ALLOC | SHF_EXECINSTR;
+        text.shndx = add_section(".text", new_section(SHT_PROGBITS, flags));
+    }
+    if !data.is_empty() {
+        let flags = SHF_ALLOC | SHF_WRITE;
+        data.shndx = add_section(".data", new_section(SHT_PROGBITS, flags));
+    }
+    let dynamic_shndx = add_section(".dynamic", new_section(SHT_DYNAMIC, SHF_ALLOC | SHF_WRITE));
+    if !bss.is_empty() {
+        bss.shndx = add_section(".bss", new_section(SHT_NOBITS, SHF_ALLOC | SHF_WRITE));
+    }
+    let shstrtab_shndx = add_section(".shstrtab", new_section(SHT_STRTAB, 0));
+
+    /////////////////////////////////////
+    // Dynamic symbols
+    /////////////////////////////////////
+    // the exported symbols, with the section containing each, its offset in it, and its size
+    let mut exports: Vec<(&str, &DefinedDecl, usize, u64, usize)> = Vec::new();
+    for &(ref def, offset) in &placements {
+        if def.decl.get_scope() == Scope::Local || def.decl.get_visibility() == Visibility::Hidden {
+            continue;
+        }
+        let shndx = match def.decl {
+            DefinedDecl::Function(_) => text.shndx,
+            DefinedDecl::Data(_) if def.data.is_zero_init() => bss.shndx,
+            DefinedDecl::Data(d) if d.is_writable() => data.shndx,
+            _ => rodata.shndx,
+        };
+        exports.push((def.name, def.decl, shndx, offset, def.data.size()));
+        for (symbol, &symbol_offset) in def.symbols {
+            exports.push((symbol, def.decl, shndx, offset + symbol_offset, 0));
+        }
+    }
+    let mut dynstr = vec![0u8];
+    let mut add_string = |string: &str| {
+        let offset = dynstr.len();
+        dynstr.extend_from_slice(string.as_bytes());
+        dynstr.push(0);
+        offset
+    };
+    let export_names = exports
+        .iter()
+        .map(|&(name, ..)| add_string(name))
+        .collect::<Vec<_>>();
+    let soname = if artifact.name.is_empty() {
+        None
+    } else {
+        Some(add_string(&artifact.name))
+    };
+
+    // the null symbol, and then the exports
+    let nsymbols = exports.len() + 1;
+    let nbuckets = std::cmp::max(1, nsymbols / 2);
+    let mut buckets = vec![0u32; nbuckets];
+    let mut chains = vec![0u32; nsymbols];
+    for (index, &(name, ..)) in exports.iter().enumerate() {
+        // each bucket is the head of a chain of the symbols with its hash
+        let index = index + 1;
+        let bucket = elf_hash(name) as usize % nbuckets;
+        chains[index] = buckets[bucket];
+        buckets[bucket] = index as u32;
+    }
+
+    let mut dynamic = vec![
+        (DT_HASH, 0),
+        (DT_STRTAB, 0),
+        (DT_SYMTAB, 0),
+        (DT_STRSZ, dynstr.len() as u64),
+        (DT_SYMENT, Symbol::size(ctx.container) as u64),
+    ];
+    if let Some(soname) = soname {
+        dynamic.push((DT_SONAME, soname as u64));
+    }
+    dynamic.push((DT_NULL, 0));
+
+    /////////////////////////////////////
+    // Layout
+    /////////////////////////////////////
+    let word = ctx.size() as u64;
+    let nsegments = if text.is_empty() { 2 } else { 3 };
+    // the segments, and PT_DYNAMIC and PT_GNU_STACK
+    let phnum = nsegments + 2;
+    let mut offset = (Header::size(ctx) + phnum * ProgramHeader::size(ctx)) as u64;
+    let sizeof_sym = Symbol::size(ctx.container) as u64;
+    let sizeof_hash = (2 + nbuckets + nsymbols) as u64 * 4;
+    place(
+        &mut section_headers[hash_shndx],
+        &mut offset,
+        sizeof_hash,
+        4,
+    );
+    section_headers[hash_shndx].sh_entsize = 4;
+    let sizeof_dynsym = nsymbols as u64 * sizeof_sym;
+    place(
+        &mut section_headers[dynsym_shndx],
+        &mut offset,
+        sizeof_dynsym,
+        word,
+    );
+    section_headers[dynsym_shndx].sh_entsize = sizeof_sym;
+    place(
+        &mut section_headers[dynstr_shndx],
+        &mut offset,
+        dynstr.len() as u64,
+        1,
+    );
+    // the segments, as their flags, file offset, end in the file, and end in memory
+    let mut segments = Vec::new();
+    if !rodata.is_empty() {
+        let header = &mut section_headers[rodata.shndx];
+        place(header, &mut offset, rodata.size, rodata.align);
+        rodata.addr = header.sh_addr;
+    }
+    segments.push((PF_R, 0, offset, offset));
+    if !text.is_empty() {
+        offset = align(offset, PAGE_SIZE);
+        let start = offset;
+        let header = &mut section_headers[text.shndx];
+        place(header, &mut offset, text.size, text.align);
+        text.addr = header.sh_addr;
+        segments.push((PF_R | PF_X, start, offset, offset));
+    }
+    offset = align(offset, PAGE_SIZE);
+    let start = offset;
+    if !data.is_empty() {
+        let header = &mut section_headers[data.shndx];
+        place(header, &mut offset, data.size, data.align);
+        data.addr = header.sh_addr;
+    }
+    let sizeof_dyn = Dyn::size(ctx.container) as u64;
+    let sizeof_dynamic = dynamic.len() as u64 * sizeof_dyn;
+    place(
+        &mut section_headers[dynamic_shndx],
+        &mut offset,
+        sizeof_dynamic,
+        word,
+    );
+    section_headers[dynamic_shndx].sh_entsize = sizeof_dyn;
+    let mut end_of_memory = offset;
+    if !bss.is_empty() {
+        let header = &mut section_headers[bss.shndx];
+        header.sh_addralign = bss.align;
+        header.sh_addr = align(offset, bss.align);
+        // .bss takes no space in the file
+        header.sh_offset = offset;
+        header.sh_size = bss.size;
+        bss.addr = header.sh_addr;
+        end_of_memory = header.sh_addr + bss.size;
+    }
+    segments.push((PF_R | PF_W, start, offset, end_of_memory));
+    let sizeof_shstrtab = shstrtab.len() as u64;
+    place(
+        &mut section_headers[shstrtab_shndx],
+        &mut offset,
+        sizeof_shstrtab,
+        1,
+    );
+    let sh_offset = align(offset, word);
+
+    // the address and size of every definition, whether it is zero-initialized, and the value of
+    // every link's field
+    let addresses = placements
+        .iter()
+        .map(|&(ref def, offset)| {
+            let (contents, zero_init) = match def.decl {
+                DefinedDecl::Function(_) => (&text, false),
+                DefinedDecl::Data(_) if def.data.is_zero_init() => (&bss, true),
+                DefinedDecl::Data(d) if d.is_writable() => (&data, false),
+                _ => (&rodata, false),
+            };
+            (
+                def.name,
+                (contents.addr + offset, def.data.size() as u64, zero_init),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let mut fields = Vec::with_capacity(links.len());
+    for (link, addend) in &links {
+        let (from, size, zero_init) = addresses[link.from.name];
+        if zero_init {
+            return Err(ArtifactError::UnsupportedRelocation {
+                from: link.from.name.to_string(),
+                to: link.to.name.to_string(),
+                reason: "zero-initialized data has no contents to relocate".to_string(),
+            }
+            .into());
+        }
+        if link.at + 4 > size {
+            return Err(ArtifactError::LinkOutOfBounds {
+                from: link.from.name.to_string(),
+                at: link.at,
+                size,
+            }
+            .into());
+        }
+        let place = from + link.at;
+        let value = addresses[link.to.name].0 as i64 + addend - place as i64;
+        let value = i32::try_from(value).map_err(|_| ArtifactError::RelocationOverflow {
+            field: "a 4-byte pc-relative field",
+            value: value as u64,
+        })?;
+        fields.push((place, value));
+    }
+
+    section_headers[hash_shndx].sh_link = dynsym_shndx as u32;
+    section_headers[dynsym_shndx].sh_link = dynstr_shndx as u32;
+    // every dynamic symbol but the null symbol is global
+    section_headers[dynsym_shndx].sh_info = 1;
+    section_headers[dynamic_shndx].sh_link = dynstr_shndx as u32;
+    for entry in dynamic.iter_mut() {
+        match entry.0 {
+            DT_HASH => entry.1 = section_headers[hash_shndx].sh_addr,
+            DT_STRTAB => entry.1 = section_headers[dynstr_shndx].sh_addr,
+            DT_SYMTAB => entry.1 = section_headers[dynsym_shndx].sh_addr,
+            _ => {}
+        }
+    }
+
+    /////////////////////////////////////
+    // Write
+    /////////////////////////////////////
+    let mut file = Cursor::new(Vec::new());
+    let mut header = Header::new(ctx);
+    header.e_type = header::ET_DYN;
+    header.e_machine = machine.0;
+    header.e_flags = artifact
+        .elf_flags
+        .unwrap_or_else(|| default_flags(artifact.target.architecture));
+    header.e_phoff = Header::size(ctx) as u64;
+    header.e_phnum = phnum as u16;
+    header.e_shoff = sh_offset;
+    header.e_shnum = section_headers.len() as u16;
+    header.e_shstrndx = shstrtab_shndx as u16;
+    header.e_ident[header::EI_OSABI] = artifact.elf_osabi.unwrap_or(header::ELFOSABI_NONE);
+    header.e_ident[header::EI_ABIVERSION] = artifact.elf_abiversion;
+    file.iowrite_with(header, ctx)?;
+
+    let dynamic_header = &section_headers[dynamic_shndx];
+    let mut program_headers = segments
+        .iter()
+        .map(
+            |&(p_flags, start, end_of_file, end_of_memory)| ProgramHeader {
+                p_type: PT_LOAD,
+                p_flags,
+                p_offset: start,
+                p_vaddr: start,
+                p_paddr: start,
+                p_filesz: end_of_file - start,
+                p_memsz: end_of_memory - start,
+                p_align: PAGE_SIZE,
+            },
+        )
+        .collect::<Vec<_>>();
+    program_headers.push(ProgramHeader {
+        p_type: PT_DYNAMIC,
+        p_flags: PF_R | PF_W,
+        p_offset: dynamic_header.sh_offset,
+        p_vaddr: dynamic_header.sh_addr,
+        p_paddr: dynamic_header.sh_addr,
+        p_filesz: dynamic_header.sh_size,
+        p_memsz: dynamic_header.sh_size,
+        p_align: word,
+    });
+    // the stack is not executable
+    program_headers.push(ProgramHeader {
+        p_type: PT_GNU_STACK,
+        p_flags: PF_R | PF_W,
+        p_align: 0x10,
+        ..ProgramHeader::default()
+    });
+    // goblin can only write program headers and dynamic entries into buffers
+    let mut buffer = vec![0; ProgramHeader::size(ctx)];
+    for program_header in program_headers {
+        buffer.pwrite_with(program_header, 0, ctx)?;
+        file.write_all(&buffer)?;
+    }
+
+    file.seek(Start(section_headers[hash_shndx].sh_offset))?;
+    for &value in [nbuckets as u32, nsymbols as u32]
+        .iter()
+        .chain(&buckets)
+        .chain(&chains)
+    {
+        file.iowrite_with(value, ctx.le)?;
+    }
+
+    file.seek(Start(section_headers[dynsym_shndx].sh_offset))?;
+    file.iowrite_with(Symbol::default(), ctx)?;
+    for (&(_, decl, shndx, offset, size), &name_offset) in exports.iter().zip(&export_names) {
+        let section_addr = [&rodata, &text, &data, &bss]
+            .iter()
+            .find(|contents| contents.shndx == shndx)
+            .map(|contents| contents.addr)
+            .expect("exported symbol's section is laid out");
+        let symbol = SymbolBuilder::from_decl(decl)
+            .name_offset(name_offset)
+            .section_index(shndx)
+            .value(section_addr + offset)
+            .size(size)
+            .create();
+        file.iowrite_with(symbol, ctx)?;
+    }
+
+    file.seek(Start(section_headers[dynstr_shndx].sh_offset))?;
+    file.write_all(&dynstr)?;
+
+    for contents in &[&rodata, &text, &data] {
+        for &(offset, data) in &contents.definitions {
+            file.seek(Start(contents.addr + offset))?;
+            data.write_to(&mut file)?;
+        }
+    }
+    for &(place, value) in &fields {
+        file.seek(Start(place))?;
+        file.iowrite_with(value, ctx.le)?;
+    }
+
+    file.seek(Start(section_headers[dynamic_shndx].sh_offset))?;
+    let mut buffer = vec![0; Dyn::size(ctx.container)];
+    for &(d_tag, d_val) in &dynamic {
+        buffer.pwrite_with(Dyn { d_tag, d_val }, 0, ctx)?;
+        file.write_all(&buffer)?;
+    }
+
+    file.seek(Start(section_headers[shstrtab_shndx].sh_offset))?;
+    file.write_all(&shstrtab)?;
+
+    file.seek(Start(sh_offset))?;
+    for section_header in section_headers {
+        file.iowrite_with(section_header, ctx)?;
+    }
+    Ok(file.into_inner())
+}
@@ -15,15 +15,27 @@ use goblin::container;
 
 type Ctx = container::Ctx;
 
+mod archive;
+mod describe;
 mod elf;
 mod mach;
+mod object;
 mod target;
 
 pub mod artifact;
+pub use crate::archive::{to_archive_bytes, to_archive_bytes_as, ArchiveFormat};
 pub use crate::artifact::{
     decl::{
         DataDecl, DataImportDecl, DataType, Decl, FunctionDecl, FunctionImportDecl, Scope,
-        SectionDecl, SectionKind, Visibility,
+        SectionDecl, SectionKind, SymbolKind, Visibility,
     },
-    Artifact, ArtifactBuilder, ArtifactError, Data, ImportKind, Link, Reloc,
+    Artifact, ArtifactBuilder, ArtifactError, Data, ImportKind, Layout, LazyData, Link,
+    MachFileType, MachProtection, MachSymbolOrder, MachUuid, Reloc, RelocationModel,
+    ResolvedObject, ResolvedReloc, ResolvedSection, ResolvedSymbol, SymbolSection,
 };
+pub use crate::describe::{
+    describe, ObjectDescription, RelocationDescription, SectionDescription, SymbolDescription,
+    ABSOLUTE, COMMON,
+};
+pub use crate::mach::to_fat_bytes;
+pub use crate::object::{Object, SymbolId};
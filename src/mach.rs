@@ -1,7 +1,9 @@
 //! The Mach 32/64 bit backend for transforming an artifact to a valid, mach-o object file.
 
 use crate::artifact::{
-    Data, DataType, Decl, DefinedDecl, Definition, ImportKind, Reloc, SectionKind,
+    ArtifactError, Data, DataType, Decl, DefinedDecl, Definition, ImportKind, Layout, MachFileType,
+    MachProtection, MachSymbolOrder, MachUuid, Reloc, RelocationModel, ResolvedObject,
+    ResolvedReloc, ResolvedSection, ResolvedSymbol, Scope, SectionKind, SymbolKind, Visibility,
 };
 use crate::target::make_ctx;
 use crate::{Artifact, Ctx};
@@ -9,31 +11,44 @@ use crate::{Artifact, Ctx};
 use failure::Error;
 use indexmap::IndexMap;
 use scroll::ctx::SizeWith;
-use scroll::{IOwrite, Pwrite};
-use std::collections::HashMap;
+use scroll::{IOwrite, Pread, Pwrite};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::io::SeekFrom::*;
-use std::io::{BufWriter, Cursor, Seek, Write};
+use std::io::{self, BufWriter, Cursor, Seek, Write};
 use string_interner::StringInterner;
 use target_lexicon::Architecture;
 
 use goblin::mach::constants::{
-    S_ATTR_DEBUG, S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS, S_CSTRING_LITERALS,
-    S_REGULAR, S_ZEROFILL,
+    SECTION_TYPE, S_4BYTE_LITERALS, S_8BYTE_LITERALS, S_ATTR_DEBUG, S_ATTR_LIVE_SUPPORT,
+    S_ATTR_NO_DEAD_STRIP, S_ATTR_NO_TOC, S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS,
+    S_ATTR_STRIP_STATIC_SYMS, S_COALESCED, S_CSTRING_LITERALS, S_LAZY_SYMBOL_POINTERS, S_REGULAR,
+    S_SYMBOL_STUBS, S_THREAD_LOCAL_REGULAR, S_THREAD_LOCAL_VARIABLES, S_THREAD_LOCAL_ZEROFILL,
+    S_ZEROFILL, VM_PROT_EXECUTE, VM_PROT_READ, VM_PROT_WRITE,
 };
 use goblin::mach::cputype;
-use goblin::mach::header::{Header, MH_OBJECT, MH_SUBSECTIONS_VIA_SYMBOLS};
-use goblin::mach::load_command::SymtabCommand;
+use goblin::mach::fat::{FatArch, FatHeader, FAT_MAGIC, SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER};
+use goblin::mach::header::{
+    Header, MH_BUNDLE, MH_DYLIB, MH_NOUNDEFS, MH_OBJECT, MH_SUBSECTIONS_VIA_SYMBOLS,
+};
+use goblin::mach::load_command::{
+    cmd_to_str, Dylib, DylibCommand, DysymtabCommand, LinkeditDataCommand, Section32, Section64,
+    SymtabCommand, UuidCommand, LC_DYLD_INFO, LC_DYLD_INFO_ONLY, LC_DYSYMTAB, LC_FUNCTION_STARTS,
+    LC_ID_DYLIB, LC_LOAD_DYLIB, LC_SEGMENT, LC_SEGMENT_64, LC_SYMTAB, LC_UUID,
+    SIZEOF_DYSYMTAB_COMMAND, SIZEOF_LOAD_COMMAND, SIZEOF_UUID_COMMAND,
+};
 use goblin::mach::relocation::{RelocType, RelocationInfo, SIZEOF_RELOCATION_INFO};
 use goblin::mach::segment::{Section, Segment};
 use goblin::mach::symbols::Nlist;
 
 struct CpuType(cputype::CpuType);
 
-impl From<Architecture> for CpuType {
-    fn from(architecture: Architecture) -> CpuType {
+impl TryFrom<Architecture> for CpuType {
+    type Error = ArtifactError;
+    fn try_from(architecture: Architecture) -> Result<CpuType, ArtifactError> {
         use goblin::mach::cputype::*;
         use target_lexicon::Architecture::*;
-        CpuType(match architecture {
+        Ok(CpuType(match architecture {
             X86_64 => CPU_TYPE_X86_64,
             I386 | I586 | I686 => CPU_TYPE_X86,
             Aarch64(_) => CPU_TYPE_ARM64,
@@ -42,49 +57,117 @@ impl From<Architecture> for CpuType {
             Powerpc => CPU_TYPE_POWERPC,
             Powerpc64 | Powerpc64le => CPU_TYPE_POWERPC64,
             Unknown => 0,
-            _ => panic!("requested architecture does not exist in MachO"),
-        })
+            _ => return Err(ArtifactError::UnsupportedArchitecture(architecture)),
+        }))
     }
 }
 
+/// The exponent of the power of two `align`; alignments are checked to be powers of two when they
+/// are declared
 fn align_to_align_exp(align: u64) -> u64 {
-    assert!(align != 0);
-    assert!(align.is_power_of_two());
-    let mut align_exp = 0;
-    while 1 << align_exp != align {
-        align_exp += 1;
-    }
-    align_exp
+    debug_assert!(align.is_power_of_two());
+    u64::from(align.trailing_zeros())
 }
 
 type SectionIndex = usize;
 type StrtableOffset = u64;
 
-const CODE_SECTION_INDEX: SectionIndex = 0;
-const DATA_SECTION_INDEX: SectionIndex = 1;
-const CSTRING_SECTION_INDEX: SectionIndex = 2;
-const BSS_SECTION_INDEX: SectionIndex = 3;
-const NUM_DEFAULT_SECTIONS: SectionIndex = 4;
+/// The sections of coalesced functions and data
+const TEXT_COALESCED_SECTNAME: &str = "__textcoal_nt";
+const DATA_COALESCED_SECTNAME: &str = "__datacoal_nt";
+
+/// The thunk the runtime calls to find the calling thread's copy of a thread-local variable
+const TLV_BOOTSTRAP: &str = "_tlv_bootstrap";
+
+/// The sections holding mergeable constants, which are only emitted when they are not empty, as
+/// `(datatype, sectname, flags)`
+const LITERAL_SECTIONS: [(DataType, &str, u32); 2] = [
+    (DataType::Literal4, "__literal4", S_4BYTE_LITERALS),
+    (DataType::Literal8, "__literal8", S_8BYTE_LITERALS),
+];
+
+/// The section of DWARF call frame information used for unwinding, named `.eh_frame` on ELF
+const EH_FRAME_SECTNAME: &str = "__eh_frame";
+
+/// The section of compact unwind entries, which ld64 turns into the image's `__unwind_info`
+const COMPACT_UNWIND_SECTNAME: &str = "__compact_unwind";
+
+/// The size of a compact unwind entry: the function's address, its length and compact unwind
+/// encoding, and the addresses of its personality routine and language-specific data area
+fn compact_unwind_entry_size(pointer_size: u64) -> u64 {
+    3 * pointer_size + 8
+}
+
+/// The functions with a compact unwind encoding, each of which has an entry in __compact_unwind
+fn compact_unwind_entries<'a>(
+    artifact: &'a Artifact,
+) -> impl Iterator<Item = (Definition<'a>, u32)> + 'a {
+    artifact.definitions().filter_map(|def| match def.decl {
+        DefinedDecl::Function(f) => f.get_compact_unwind().map(|encoding| (def, encoding)),
+        _ => None,
+    })
+}
+
+/// The section of the stubs of imports, each an indirect jump through its lazy pointer
+const STUBS_SECTNAME: &str = "__stubs";
+
+/// The section of the lazy pointers of imports with stubs, which the dynamic linker binds
+const LAZY_POINTERS_SECTNAME: &str = "__la_symbol_ptr";
+
+/// The size of an x86_64 stub, `jmp *ptr(%rip)`
+const STUB_SIZE: u64 = 6;
+
+/// The function imports with stubs, in the order of their stubs and lazy pointers
+fn stubbed_imports(artifact: &Artifact) -> Vec<&str> {
+    artifact
+        .emitted_imports()
+        .filter(|&(import, kind)| {
+            kind == ImportKind::Function
+                && artifact.is_stubbed_import(import)
+                && artifact.reexported_import(import).is_none()
+        })
+        .map(|(import, _)| import)
+        .collect()
+}
 
 /// A builder for creating a 32/64 bit Mach-o Nlist symbol
 #[derive(Debug)]
 struct SymbolBuilder {
     name: StrtableOffset,
     section: Option<SectionIndex>,
-    global: bool,
+    scope: Scope,
+    visibility: Visibility,
     import: bool,
+    library_ordinal: u8,
+    weak_ref: bool,
+    indirect: Option<StrtableOffset>,
+    common_align_exponent: Option<u8>,
     offset: u64,
     segment_relative_offset: u64,
 }
 
 impl SymbolBuilder {
+    /// The group of this symbol in the symbol table: locals, then defined externals, then
+    /// undefined externals
+    fn binding(&self) -> usize {
+        match (self.import, self.scope) {
+            (false, Scope::Local) => 0,
+            (false, _) => 1,
+            (true, _) => 2,
+        }
+    }
     /// Create a new symbol with `typ`
     pub fn new(name: StrtableOffset) -> Self {
         SymbolBuilder {
             name,
             section: None,
-            global: false,
+            scope: Scope::Local,
+            visibility: Visibility::Default,
             import: false,
+            library_ordinal: 0,
+            weak_ref: false,
+            indirect: None,
+            common_align_exponent: None,
             offset: 0,
             segment_relative_offset: 0,
         }
@@ -94,9 +177,14 @@ impl SymbolBuilder {
         self.section = Some(section_index);
         self
     }
-    /// Is this symbol global?
-    pub fn global(mut self, global: bool) -> Self {
-        self.global = global;
+    /// The linker binding scope of this symbol
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+    /// The linker visibility of this symbol
+    pub fn visibility(mut self, visibility: Visibility) -> Self {
+        self.visibility = visibility;
         self
     }
     pub fn offset(mut self, offset: u64) -> Self {
@@ -117,29 +205,74 @@ impl SymbolBuilder {
         self.import = true;
         self
     }
+    /// The ordinal of the `LC_LOAD_DYLIB` command this import is found in, if nonzero
+    pub fn library_ordinal(mut self, library_ordinal: u8) -> Self {
+        self.library_ordinal = library_ordinal;
+        self
+    }
+    /// This import may be left unresolved by the linker
+    pub fn weak_ref(mut self, weak_ref: bool) -> Self {
+        self.weak_ref = weak_ref;
+        self
+    }
+    /// This import is an indirect symbol for the symbol at `name` in the string table
+    pub fn indirect(mut self, name: StrtableOffset) -> Self {
+        self.indirect = Some(name);
+        self
+    }
+    /// This import is a common symbol of `size` bytes, aligned to `1 << align_exponent`
+    pub fn common(mut self, size: u64, align_exponent: u8) -> Self {
+        self.offset = size;
+        self.common_align_exponent = Some(align_exponent);
+        self
+    }
     /// Finalize and create the symbol
     pub fn create(self) -> Nlist {
-        use goblin::mach::symbols::{NO_SECT, N_EXT, N_SECT, N_UNDF};
+        use goblin::mach::symbols::{
+            NO_SECT, N_EXT, N_INDR, N_PEXT, N_SECT, N_UNDF, N_WEAK_DEF, N_WEAK_REF,
+        };
         let n_strx = self.name;
         let mut n_sect = 0;
         let mut n_type = N_UNDF;
         let mut n_value = self.offset;
-        let n_desc = 0;
-        if self.global {
-            n_type |= N_EXT;
-        } else {
-            n_type &= !N_EXT;
+        let mut n_desc = 0;
+        match self.scope {
+            Scope::Local => n_type &= !N_EXT,
+            Scope::Global | Scope::Common => n_type |= N_EXT,
+            Scope::Weak => {
+                n_type |= N_EXT;
+                n_desc |= N_WEAK_DEF;
+            }
+        }
+        // mach-o has no notion of protected visibility, so it is the same as default
+        if self.scope != Scope::Local && self.visibility == Visibility::Hidden {
+            n_type |= N_PEXT;
         }
         if let Some(idx) = self.section {
             n_sect = idx + 1; // add 1 because n_sect expects ordinal
             n_type |= N_SECT;
         }
 
-        if self.import {
+        if let Some(name) = self.indirect {
+            n_sect = NO_SECT as usize;
+            n_type = N_INDR | N_EXT;
+            n_value = name;
+            n_desc = 0;
+        } else if let Some(align_exponent) = self.common_align_exponent {
+            // a common symbol is undefined, and its value is its size
+            n_sect = NO_SECT as usize;
+            n_type = N_UNDF | N_EXT | (n_type & N_PEXT);
+            n_value = self.offset;
+            n_desc = u16::from(align_exponent & 0xf) << 8;
+        } else if self.import {
             n_sect = NO_SECT as usize;
             // FIXME: this is broken i believe; we need to make it both undefined + global for imports
             n_type = N_EXT;
             n_value = 0;
+            n_desc |= u16::from(self.library_ordinal) << 8;
+            if self.weak_ref {
+                n_desc |= N_WEAK_REF;
+            }
         } else {
             n_type |= N_SECT;
         }
@@ -163,6 +296,7 @@ struct RelocationBuilder {
     symbol: SymbolIndex,
     relocation_offset: u64,
     absolute: bool,
+    local: bool,
     size: u8,
     r_type: RelocType,
 }
@@ -174,6 +308,7 @@ impl RelocationBuilder {
             symbol,
             relocation_offset,
             absolute: false,
+            local: false,
             size: 0,
             r_type,
         }
@@ -183,6 +318,12 @@ impl RelocationBuilder {
         self.absolute = true;
         self
     }
+    /// This relocation refers to the section with the ordinal `symbol`, rather than to a symbol,
+    /// and the relocated contents are the address it refers to
+    pub fn local(mut self) -> Self {
+        self.local = true;
+        self
+    }
     /// The size in bytes of the relocated value (defaults to the address size).
     pub fn size(mut self, size: u8) -> Self {
         self.size = size;
@@ -205,7 +346,7 @@ impl RelocationBuilder {
             8 => 3,
             size => panic!("unsupported relocation size {}", size),
         } << 25;
-        let r_extern: u32 = 1 << 27;
+        let r_extern: u32 = if self.local { 0 } else { 1 } << 27;
         let r_type = (self.r_type as u32) << 28;
         // r_symbolnum, 24 bits, r_pcrel 1 bit, r_length 2 bits, r_extern 1 bit, r_type 4 bits
         let r_info = r_symbolnum | r_pcrel | r_length | r_extern | r_type;
@@ -216,30 +357,110 @@ impl RelocationBuilder {
     }
 }
 
+/// The bit set in the first word of a scattered relocation
+const R_SCATTERED: u32 = 0x8000_0000;
+
+/// Mach scattered relocation builder, for absolute 4-byte relocations which refer to their
+/// target by address rather than by symbol
+#[derive(Debug)]
+struct ScatteredRelocationBuilder {
+    relocation_offset: u64,
+    value: u32,
+    r_type: RelocType,
+}
+
+impl ScatteredRelocationBuilder {
+    /// Create a relocation of the address `value`, starting at `relocation_offset`
+    pub fn new(relocation_offset: u64, value: u32, r_type: RelocType) -> Self {
+        ScatteredRelocationBuilder {
+            relocation_offset,
+            value,
+            r_type,
+        }
+    }
+    /// Finalize and create the relocation, which has the same size as a regular relocation, the
+    /// first word holding the bitfields and the second the value
+    pub fn create(self) -> RelocationInfo {
+        // r_address 24 bits, r_type 4 bits, r_length 2 bits, r_pcrel 1 bit, r_scattered 1 bit
+        debug_assert!(self.relocation_offset < 1 << 24);
+        let r_address = self.relocation_offset as u32;
+        let r_type = u32::from(self.r_type) << 24;
+        // 4 bytes
+        let r_length: u32 = 2 << 28;
+        RelocationInfo {
+            r_address: (R_SCATTERED | r_length | r_type | r_address) as i32,
+            r_info: self.value,
+        }
+    }
+}
+
+/// The offset in its section of the contents `reloc` relocates, whether it is scattered or not
+fn relocation_address(reloc: &RelocationInfo) -> u32 {
+    if reloc.r_address as u32 & R_SCATTERED != 0 {
+        reloc.r_address as u32 & 0x00ff_ffff
+    } else {
+        reloc.r_address as u32
+    }
+}
+
+/// A section header: goblin's `Section`, which does not model the reserved fields, along with them
+#[derive(Debug)]
+struct SectionHeader {
+    section: Section,
+    reserved1: u32,
+    reserved2: u32,
+}
+
+impl SectionHeader {
+    /// Write this header to `sink` as a 32 or 64-bit section header, as `ctx` requires
+    fn write<W: Write>(self, sink: &mut W, ctx: Ctx) -> Result<(), Error> {
+        if ctx.is_big() {
+            let mut header = Section64::from(self.section);
+            header.reserved1 = self.reserved1;
+            header.reserved2 = self.reserved2;
+            sink.iowrite_with(header, ctx.le)?;
+        } else {
+            let mut header = Section32::from(self.section);
+            header.reserved1 = self.reserved1;
+            header.reserved2 = self.reserved2;
+            sink.iowrite_with(header, ctx.le)?;
+        }
+        Ok(())
+    }
+}
+
 /// Helper to build sections
 #[derive(Debug, Clone)]
 struct SectionBuilder {
     addr: u64,
     align: u64,
     offset: u64,
+    pad: u64,
     size: u64,
     flags: u32,
+    reserved1: u32,
+    reserved2: u32,
     sectname: String,
-    segname: &'static str,
+    segname: [u8; 16],
     relocations: Vec<RelocationInfo>,
 }
 
 impl SectionBuilder {
     /// Create a new section builder with `sectname`, `segname` and `size`
-    pub fn new(sectname: String, segname: &'static str, size: u64) -> Self {
+    pub fn new(sectname: String, segname: &str, size: u64) -> Self {
+        let mut segname_bytes = [0u8; 16];
+        segname_bytes.pwrite(segname, 0).unwrap();
         SectionBuilder {
             addr: 0,
             align: 4,
             offset: 0,
+            pad: 0,
             flags: S_REGULAR,
+            reserved1: 0,
+            reserved2: 0,
             size,
             sectname,
-            segname,
+            segname: segname_bytes,
             relocations: Vec::new(),
         }
     }
@@ -258,20 +479,34 @@ impl SectionBuilder {
         self.align = align;
         self
     }
+    /// Set the number of padding bytes preceding this section, which align its start
+    pub fn pad(mut self, pad: u64) -> Self {
+        self.pad = pad;
+        self
+    }
     /// Set the flags of this section
     pub fn flags(mut self, flags: u32) -> Self {
         self.flags = flags;
         self
     }
-    /// Finalize and create the actual Mach-o section
-    pub fn create(&self, section_offset: &mut u64, relocation_offset: &mut u64) -> Section {
+    /// Set the first `reserved1` and second `reserved2` reserved fields of this section, e.g. the
+    /// index of its first entry in the indirect symbol table and the size of its stubs
+    pub fn reserved(mut self, reserved1: u32, reserved2: u32) -> Self {
+        self.reserved1 = reserved1;
+        self.reserved2 = reserved2;
+        self
+    }
+    /// Whether this section is zero-initialized, taking up no space in the file
+    pub fn is_zerofill(&self) -> bool {
+        is_zerofill(self.flags)
+    }
+    /// Finalize and create the actual Mach-o section header
+    pub fn create(&self, section_offset: &mut u64, relocation_offset: &mut u64) -> SectionHeader {
         let mut sectname = [0u8; 16];
         sectname.pwrite(&*self.sectname, 0).unwrap();
-        let mut segname = [0u8; 16];
-        segname.pwrite(self.segname, 0).unwrap();
         let mut section = Section {
             sectname,
-            segname,
+            segname: self.segname,
             addr: self.addr,
             size: self.size,
             offset: self.offset as u32,
@@ -281,15 +516,24 @@ impl SectionBuilder {
             nreloc: 0,
             flags: self.flags,
         };
-        section.offset = *section_offset as u32;
-        *section_offset += section.size;
+        if self.is_zerofill() {
+            section.offset = 0;
+        } else {
+            *section_offset += self.pad;
+            section.offset = *section_offset as u32;
+            *section_offset += section.size;
+        }
         if !self.relocations.is_empty() {
             let nrelocs = self.relocations.len();
             section.nreloc = nrelocs as _;
             section.reloff = *relocation_offset as u32;
             *relocation_offset += nrelocs as u64 * SIZEOF_RELOCATION_INFO as u64;
         }
-        section
+        SectionHeader {
+            section,
+            reserved1: self.reserved1,
+            reserved2: self.reserved2,
+        }
     }
 }
 
@@ -307,6 +551,8 @@ struct SymbolTable {
     strtable: StrTable,
     indexes: IndexMap<StrTableIndex, SymbolIndex>,
     strtable_size: StrtableOffset,
+    /// The names which are written as they are, without the `_` prefix
+    unprefixed: HashSet<StrTableIndex>,
 }
 
 // A manual implementation for Default because StringInterner<usize> does not have a Default impl:
@@ -317,22 +563,38 @@ impl Default for SymbolTable {
             strtable: StrTable::new(),
             indexes: IndexMap::default(),
             strtable_size: StrtableOffset::default(),
+            unprefixed: HashSet::new(),
         }
     }
 }
 
 /// The kind of symbol this is
-enum SymbolType {
+enum SymbolType<'a> {
     /// Which `section` this is defined in, the `absolute_offset` in the binary, and its
     /// `segment_relative_offset`
     Defined {
         section: SectionIndex,
         absolute_offset: u64,
         segment_relative_offset: u64,
-        global: bool,
+        scope: Scope,
+        visibility: Visibility,
+    },
+    /// An undefined symbol (an import), found in the dylib with `library_ordinal`, if nonzero,
+    /// which may be left unresolved if `weak`
+    Undefined { library_ordinal: u8, weak: bool },
+    /// An undefined symbol the linker defines at a boundary of a section; its name is not a C
+    /// symbol, so it has no `_` prefix
+    SectionBoundary,
+    /// An undefined symbol which is an indirect reference to the already inserted `target`, e.g.
+    /// a re-export
+    Indirect { target: &'a str },
+    /// A common symbol of `size` bytes, aligned to `1 << align_exponent`, which the linker
+    /// allocates
+    Common {
+        size: u64,
+        align_exponent: u8,
+        visibility: Visibility,
     },
-    /// An undefined symbol (an import)
-    Undefined,
 }
 
 impl SymbolTable {
@@ -346,16 +608,32 @@ impl SymbolTable {
             strtable,
             strtable_size,
             indexes: IndexMap::new(),
+            unprefixed: HashSet::new(),
         }
     }
     /// The number of symbols in this table
     pub fn len(&self) -> usize {
         self.symbols.len()
     }
+    /// Lookup this symbols address, i.e. its `n_value`, if it is defined
+    pub fn address(&self, symbol_name: &str) -> Option<u64> {
+        self.strtable
+            .get(symbol_name)
+            .and_then(|idx| self.symbols.get(&idx))
+            .filter(|sym| !sym.import)
+            .map(|sym| sym.offset)
+    }
     /// Returns size of the string table, in bytes
     pub fn sizeof_strtable(&self) -> u64 {
         self.strtable_size
     }
+    /// Lookup the index of the section this symbol is defined in, if it is defined
+    pub fn section(&self, symbol_name: &str) -> Option<SectionIndex> {
+        self.strtable
+            .get(symbol_name)
+            .and_then(|idx| self.symbols.get(&idx))
+            .and_then(|sym| sym.section)
+    }
     /// Lookup this symbols offset in the segment
     pub fn offset(&self, symbol_name: &str) -> Option<u64> {
         self.strtable
@@ -374,8 +652,6 @@ impl SymbolTable {
         // mach-o requires _ prefixes on every symbol, we will allow this to be configurable later
         //let name = format!("_{}", symbol_name);
         let name = symbol_name;
-        // 1 for null terminator and 1 for _ prefix (defered until write time);
-        let name_len = name.len() as u64 + 1 + 1;
         let last_index = self.strtable.len();
         let name_index = self.strtable.get_or_intern(name);
         debug!("{}: {} <= {}", symbol_name, last_index, name_index);
@@ -387,16 +663,49 @@ impl SymbolTable {
             );
             // TODO: add code offset into symbol n_value
             let builder = match kind {
-                SymbolType::Undefined => {
-                    SymbolBuilder::new(self.strtable_size).global(true).import()
+                SymbolType::Undefined {
+                    library_ordinal,
+                    weak,
+                } => SymbolBuilder::new(self.strtable_size)
+                    .scope(Scope::Global)
+                    .import()
+                    .library_ordinal(library_ordinal)
+                    .weak_ref(weak),
+                SymbolType::SectionBoundary => {
+                    self.unprefixed.insert(name_index);
+                    SymbolBuilder::new(self.strtable_size)
+                        .scope(Scope::Global)
+                        .import()
+                }
+                SymbolType::Common {
+                    size,
+                    align_exponent,
+                    visibility,
+                } => SymbolBuilder::new(self.strtable_size)
+                    .scope(Scope::Common)
+                    .visibility(visibility)
+                    .import()
+                    .common(size, align_exponent),
+                SymbolType::Indirect { target } => {
+                    let target = self
+                        .strtable
+                        .get(target)
+                        .and_then(|idx| self.symbols.get(&idx))
+                        .expect("indirect symbol's target is in the symbol table");
+                    SymbolBuilder::new(self.strtable_size)
+                        .scope(Scope::Global)
+                        .import()
+                        .indirect(target.name)
                 }
                 SymbolType::Defined {
                     section,
                     absolute_offset,
-                    global,
+                    scope,
+                    visibility,
                     segment_relative_offset,
                 } => SymbolBuilder::new(self.strtable_size)
-                    .global(global)
+                    .scope(scope)
+                    .visibility(visibility)
                     .offset(absolute_offset)
                     .relative_offset(segment_relative_offset)
                     .section(section),
@@ -405,10 +714,113 @@ impl SymbolTable {
             self.symbols.insert(name_index, builder);
             // now create the symbols index, and using strtab name as lookup
             self.indexes.insert(name_index, self.symbols.len() - 1);
+            // 1 for null terminator and 1 for _ prefix (defered until write time), if it has one
+            let name_len =
+                name.len() as u64 + 1 + u64::from(!self.unprefixed.contains(&name_index));
             // NB do not move this, otherwise all offsets will be off
             self.strtable_size += name_len;
         }
     }
+    /// Reorder the symbols, and so their indexes, by `order`; the string table is unchanged
+    pub fn reorder(&mut self, order: MachSymbolOrder) {
+        let strtable = &self.strtable;
+        let name = |idx: &StrTableIndex| strtable.resolve(*idx).expect("symbol has a name");
+        match order {
+            MachSymbolOrder::InsertionOrder => return,
+            MachSymbolOrder::Alphabetical => {
+                self.symbols.sort_by(|a, _, b, _| name(a).cmp(name(b)))
+            }
+            MachSymbolOrder::LocalsThenGlobals => self.symbols.sort_by(|a, a_sym, b, b_sym| {
+                (a_sym.binding(), name(a)).cmp(&(b_sym.binding(), name(b)))
+            }),
+            MachSymbolOrder::Address => {
+                // imports come last, and all have an offset of 0, so they are sorted by name
+                self.symbols.sort_by(|a, a_sym, b, b_sym| {
+                    (a_sym.import, a_sym.offset, name(a)).cmp(&(
+                        b_sym.import,
+                        b_sym.offset,
+                        name(b),
+                    ))
+                })
+            }
+        }
+        self.reindex();
+    }
+    /// Group the symbols by their binding, keeping their order within each group, as the dynamic
+    /// symbol table requires
+    pub fn group(&mut self) {
+        self.symbols
+            .sort_by(|_, a_sym, _, b_sym| a_sym.binding().cmp(&b_sym.binding()));
+        self.reindex();
+    }
+    /// The number of symbols with each binding: locals, defined externals and undefined externals
+    pub fn binding_counts(&self) -> [u32; 3] {
+        let mut counts = [0; 3];
+        for symbol in self.symbols.values() {
+            counts[symbol.binding()] += 1;
+        }
+        counts
+    }
+    /// Update the index of every symbol to its place in the table
+    fn reindex(&mut self) {
+        for (index, name_index) in self.symbols.keys().enumerate() {
+            self.indexes.insert(*name_index, index);
+        }
+    }
+}
+
+/// Whether a section with `flags` is zero-initialized, taking up no space in the file
+fn is_zerofill(flags: u32) -> bool {
+    let section_type = flags & SECTION_TYPE;
+    section_type == S_ZEROFILL || section_type == S_THREAD_LOCAL_ZEROFILL
+}
+
+/// The name of the Mach-O section a custom section `name` is emitted as
+fn custom_sectname(name: &str) -> String {
+    if let Some(suffix) = name.strip_prefix(".debug") {
+        format!("__debug{}", suffix)
+    } else if name == ".eh_frame" {
+        EH_FRAME_SECTNAME.to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// A section to be laid out in the segment, once the order of the sections is known
+enum PlannedSection<'a, 'b> {
+    /// A section of definitions
+    Definitions {
+        sectname: &'static str,
+        segname: &'static str,
+        definitions: &'b [Definition<'a>],
+        min_alignment_exponent: u64,
+        section_alignment_exponent: u64,
+        flags: Option<u32>,
+    },
+    /// The descriptors of the thread-local variables
+    ThreadVars,
+    /// A custom section
+    Custom(&'b Definition<'a>),
+    /// The compact unwind entries of the functions
+    CompactUnwind,
+    /// The stubs of the imports with stubs
+    Stubs,
+    /// The lazy pointers of the imports with stubs
+    LazyPointers,
+}
+
+impl<'a, 'b> PlannedSection<'a, 'b> {
+    /// The name of the section, by which its place in the section order is given
+    fn sectname(&self) -> String {
+        match self {
+            PlannedSection::Definitions { sectname, .. } => sectname.to_string(),
+            PlannedSection::ThreadVars => "__thread_vars".to_string(),
+            PlannedSection::Custom(def) => custom_sectname(def.name),
+            PlannedSection::CompactUnwind => COMPACT_UNWIND_SECTNAME.to_string(),
+            PlannedSection::Stubs => STUBS_SECTNAME.to_string(),
+            PlannedSection::LazyPointers => LAZY_POINTERS_SECTNAME.to_string(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -416,10 +828,12 @@ impl SymbolTable {
 struct SegmentBuilder {
     /// The sections that belong to this program segment
     pub sections: IndexMap<String, SectionBuilder>,
-    /// A stupid offset value I need to refactor out
-    pub offset: u64,
     size: u64,
+    vmsize: u64,
     align_pad_map: HashMap<String, u64>,
+    /// Mach-O relocations have implicit addends, so any explicit addends are written into the
+    /// relocated section's contents, as `(offset, size, addend)`
+    implicit_addends: HashMap<String, Vec<(u64, u8, i32)>>,
 }
 
 impl SegmentBuilder {
@@ -427,6 +841,10 @@ impl SegmentBuilder {
     pub fn size(&self) -> u64 {
         self.size
     }
+    /// The size of this segment in memory, including zero-initialized data, in bytes
+    pub fn vmsize(&self) -> u64 {
+        self.vmsize
+    }
     /// The size of this segment's _load command_, including its associated sections, in bytes
     pub fn load_command_size(&self, ctx: &Ctx) -> u64 {
         Segment::size_with(&ctx) as u64
@@ -436,6 +854,30 @@ impl SegmentBuilder {
         // section data
         Header::size_with(&ctx.container) as u64 + self.load_command_size(ctx)
     }
+    /// Advance the segment's `offset`, `addr` and `symbol_offset` so that the next section starts
+    /// at a multiple of `1 << alignment_exponent`, returning the number of padding bytes
+    fn align_section_start(
+        offset: &mut u64,
+        addr: &mut u64,
+        symbol_offset: &mut u64,
+        alignment_exponent: u64,
+    ) -> u64 {
+        let alignment = 1 << alignment_exponent;
+        let pad = (alignment - *addr % alignment) % alignment;
+        *offset += pad;
+        *addr += pad;
+        *symbol_offset += pad;
+        pad
+    }
+    /// The alignment exponent of the most aligned section, to which the segment's file offset
+    /// is aligned so that the sections' file offsets are as aligned as their addresses
+    pub fn max_alignment_exponent(&self) -> u64 {
+        self.sections
+            .values()
+            .map(|section| section.align)
+            .max()
+            .unwrap_or(0)
+    }
     // FIXME: this is in desperate need of refactoring, obviously
     fn build_section(
         symtab: &mut SymbolTable,
@@ -448,12 +890,22 @@ impl SegmentBuilder {
         section: SectionIndex,
         definitions: &[Definition],
         min_alignment_exponent: u64,
+        section_alignment_exponent: u64,
         flags: Option<u32>,
         align_pad_map: &mut HashMap<String, u64>,
     ) {
         let mut local_size = 0;
         let mut section_relative_offset = 0;
-        let mut alignment_exponent = min_alignment_exponent;
+        // the section start may be more aligned than any of its members, and keeps its minimum
+        // alignment even when it has none
+        let alignment_exponent = definitions
+            .iter()
+            .map(|def| align_to_align_exp(def.decl.get_align().unwrap_or(1)))
+            .fold(
+                std::cmp::max(min_alignment_exponent, section_alignment_exponent),
+                std::cmp::max,
+            );
+        let pad = Self::align_section_start(offset, addr, symbol_offset, alignment_exponent);
         let mut def_iter = definitions.iter().peekable();
         while let Some(def) = def_iter.next() {
             if let DefinedDecl::Section { .. } = def.decl {
@@ -466,12 +918,26 @@ impl SegmentBuilder {
                     section,
                     segment_relative_offset: section_relative_offset,
                     absolute_offset: *symbol_offset,
-                    global: def.decl.is_global(),
+                    scope: def.decl.get_scope(),
+                    visibility: def.decl.get_visibility(),
                 },
             );
-            *symbol_offset += def.data.file_size() as u64;
-            section_relative_offset += def.data.file_size() as u64;
-            local_size += def.data.file_size() as u64;
+            for (symbol, symbol_dst_offset) in def.symbols {
+                symtab.insert(
+                    symbol,
+                    SymbolType::Defined {
+                        section,
+                        segment_relative_offset: section_relative_offset + *symbol_dst_offset,
+                        absolute_offset: *symbol_offset + *symbol_dst_offset,
+                        scope: def.decl.get_scope(),
+                        visibility: def.decl.get_visibility(),
+                    },
+                );
+            }
+            // zero-initialized data only takes up space in memory, so its vm extent is used
+            *symbol_offset += def.data.size() as u64;
+            section_relative_offset += def.data.size() as u64;
+            local_size += def.data.size() as u64;
 
             let next_def_alignment_exponent = std::cmp::max(
                 min_alignment_exponent,
@@ -480,7 +946,6 @@ impl SegmentBuilder {
                     .map(|def| align_to_align_exp(def.decl.get_align().unwrap_or(1)))
                     .unwrap_or(0),
             );
-            alignment_exponent = std::cmp::max(alignment_exponent, next_def_alignment_exponent);
 
             let align_pad = (1 << next_def_alignment_exponent)
                 - (section_relative_offset % (1 << next_def_alignment_exponent));
@@ -498,7 +963,8 @@ impl SegmentBuilder {
         let mut section = SectionBuilder::new(sectname.to_string(), segname, local_size)
             .offset(*offset)
             .addr(*addr)
-            .align(alignment_exponent);
+            .align(alignment_exponent)
+            .pad(pad);
         if let Some(flags) = flags {
             section = section.flags(flags);
         }
@@ -520,23 +986,39 @@ impl SegmentBuilder {
             _ => unreachable!("in build_custom_section: def.decl != Section"),
         };
 
-        let segment_name = match s.kind() {
+        let mut segment_name = match s.kind() {
             SectionKind::Data => "__DATA",
             SectionKind::Debug => "__DWARF",
             SectionKind::Text => "__TEXT",
         };
 
-        let sectname = if def.name.starts_with(".debug") {
-            format!("__debug{}", &def.name[".debug".len()..])
-        } else {
-            def.name.to_string()
-        };
+        let sectname = custom_sectname(def.name);
 
         let mut flags = 0;
 
+        if sectname == EH_FRAME_SECTNAME {
+            // the flags the assembler uses; ld64 parses the CFI itself to split it up per function
+            segment_name = "__TEXT";
+            flags |= S_COALESCED | S_ATTR_NO_TOC | S_ATTR_STRIP_STATIC_SYMS | S_ATTR_LIVE_SUPPORT;
+        }
         if s.kind() == SectionKind::Debug {
             flags |= S_ATTR_DEBUG;
         }
+        if s.is_retained() {
+            flags |= S_ATTR_NO_DEAD_STRIP;
+        }
+        flags |= s.get_mach_flags();
+        if let Some(segname) = s.get_mach_segname() {
+            segment_name = segname;
+        }
+
+        // raw sections are byte aligned; validation ensures they have no symbols
+        let alignment_exponent = if s.is_raw() {
+            0
+        } else {
+            align_to_align_exp(s.get_align().unwrap_or(1))
+        };
+        let pad = Self::align_section_start(offset, addr, symbol_offset, alignment_exponent);
 
         for (symbol, symbol_dst_offset) in def.symbols {
             symtab.insert(
@@ -545,7 +1027,8 @@ impl SegmentBuilder {
                     section: section_idx,
                     segment_relative_offset: *symbol_dst_offset,
                     absolute_offset: *symbol_offset + *symbol_dst_offset,
-                    global: true,
+                    scope: Scope::Global,
+                    visibility: Visibility::Default,
                 },
             );
         }
@@ -555,7 +1038,8 @@ impl SegmentBuilder {
         let section = SectionBuilder::new(sectname, segment_name, local_size)
             .offset(*offset)
             .addr(*addr)
-            .align(align_to_align_exp(s.get_align().unwrap_or(1)))
+            .align(alignment_exponent)
+            .pad(pad)
             .flags(flags);
         *offset += local_size;
         *addr += local_size;
@@ -569,98 +1053,438 @@ impl SegmentBuilder {
         blob_data: &[Definition],
         zeroed_data: &[Definition],
         cstrings: &[Definition],
+        literals: &[Vec<Definition>],
+        coalesced_code: &[Definition],
+        coalesced_data: &[Definition],
+        thread_locals: &[Definition],
         custom_sections: &[Definition],
+        compact_unwind: &[(Definition, u32)],
+        stubs: &[&str],
         symtab: &mut SymbolTable,
         ctx: &Ctx,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         let mut offset = Header::size_with(&ctx.container) as u64;
-        let mut size = 0;
+        let mut vmsize = 0;
         let mut symbol_offset = 0;
         let mut sections = IndexMap::new();
         let mut align_pad_map = HashMap::new();
+        let section_alignment_exponent = |sectname| {
+            artifact
+                .mach_section_alignments
+                .get(sectname)
+                .map_or(0, |&align| align_to_align_exp(align))
+        };
 
-        Self::build_section(
-            symtab,
+        // each thread-local variable's symbol is a descriptor in __thread_vars, which the runtime
+        // resolves to the calling thread's copy, initialized from the variable's `$tlv$init`
+        // symbol in __thread_data, or __thread_bss when it is zero-initialized
+        let tlv_init_names = thread_locals
+            .iter()
+            .map(|def| tlv_init_name(def.name))
+            .collect::<Vec<_>>();
+        let tlv_init_decls = thread_locals
+            .iter()
+            .map(|def| match def.decl {
+                DefinedDecl::Data(d) => DefinedDecl::Data(d.local()),
+                _ => unreachable!("in SegmentBuilder::new: thread local is not data"),
+            })
+            .collect::<Vec<_>>();
+        let (tlv_zeroed_data, tlv_blob_data): (Vec<_>, Vec<_>) = thread_locals
+            .iter()
+            .zip(tlv_init_names.iter().zip(tlv_init_decls.iter()))
+            .map(|(def, (name, decl))| Definition {
+                name,
+                data: def.data,
+                symbols: def.symbols,
+                decl,
+            })
+            .partition(|def| def.data.is_zero_init());
+        // the sections are only emitted, and numbered, when they have definitions
+        let mut planned = Vec::new();
+        let plan = |planned: &mut Vec<_>,
+                    sectname,
+                    segname,
+                    definitions,
+                    min_alignment_exponent,
+                    alignment,
+                    flags| {
+            if !<[Definition]>::is_empty(definitions) {
+                planned.push(PlannedSection::Definitions {
+                    sectname,
+                    segname,
+                    definitions,
+                    min_alignment_exponent,
+                    section_alignment_exponent: alignment,
+                    flags,
+                });
+            }
+        };
+        plan(
+            &mut planned,
             "__text",
             "__TEXT",
-            &mut sections,
-            &mut offset,
-            &mut size,
-            &mut symbol_offset,
-            CODE_SECTION_INDEX,
-            &code,
+            code,
             4,
+            section_alignment_exponent("__text"),
             Some(S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS),
-            &mut align_pad_map,
         );
-        Self::build_section(
-            symtab,
+        plan(
+            &mut planned,
             "__data",
             "__DATA",
-            &mut sections,
-            &mut offset,
-            &mut size,
-            &mut symbol_offset,
-            DATA_SECTION_INDEX,
-            &blob_data,
+            blob_data,
             3,
+            section_alignment_exponent("__data"),
             None,
-            &mut align_pad_map,
         );
-        Self::build_section(
-            symtab,
+        plan(
+            &mut planned,
             "__cstring",
             "__TEXT",
-            &mut sections,
-            &mut offset,
-            &mut size,
-            &mut symbol_offset,
-            CSTRING_SECTION_INDEX,
-            &cstrings,
+            cstrings,
             0,
+            section_alignment_exponent("__cstring"),
             Some(S_CSTRING_LITERALS),
-            &mut align_pad_map,
         );
-        Self::build_section(
-            symtab,
+        plan(
+            &mut planned,
             "__bss",
             "__DATA",
-            &mut sections,
-            &mut offset,
-            &mut size,
-            &mut symbol_offset,
-            BSS_SECTION_INDEX,
-            &zeroed_data,
+            zeroed_data,
             0,
+            section_alignment_exponent("__bss"),
             Some(S_ZEROFILL),
-            &mut align_pad_map,
         );
-        for (idx, def) in custom_sections.iter().enumerate() {
-            Self::build_custom_section(
-                symtab,
-                &mut sections,
-                &mut offset,
-                &mut size,
-                &mut symbol_offset,
-                idx + NUM_DEFAULT_SECTIONS,
-                def,
+        for (&(datatype, sectname, flags), definitions) in LITERAL_SECTIONS.iter().zip(literals) {
+            plan(
+                &mut planned,
+                sectname,
+                "__TEXT",
+                definitions,
+                align_to_align_exp(datatype.literal_size().unwrap()),
+                0,
+                Some(flags),
             );
         }
-        for (ref import, _) in artifact.imports() {
-            symtab.insert(import, SymbolType::Undefined);
+        // coalesced definitions are weak, and the linker keeps one copy of each of them
+        plan(
+            &mut planned,
+            TEXT_COALESCED_SECTNAME,
+            "__TEXT",
+            coalesced_code,
+            4,
+            section_alignment_exponent(TEXT_COALESCED_SECTNAME),
+            Some(S_COALESCED | S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS),
+        );
+        plan(
+            &mut planned,
+            DATA_COALESCED_SECTNAME,
+            "__DATA",
+            coalesced_data,
+            3,
+            section_alignment_exponent(DATA_COALESCED_SECTNAME),
+            Some(S_COALESCED),
+        );
+        if !thread_locals.is_empty() {
+            planned.push(PlannedSection::ThreadVars);
+        }
+        plan(
+            &mut planned,
+            "__thread_data",
+            "__DATA",
+            &tlv_blob_data,
+            0,
+            section_alignment_exponent("__thread_data"),
+            Some(S_THREAD_LOCAL_REGULAR),
+        );
+        plan(
+            &mut planned,
+            "__thread_bss",
+            "__DATA",
+            &tlv_zeroed_data,
+            0,
+            section_alignment_exponent("__thread_bss"),
+            Some(S_THREAD_LOCAL_ZEROFILL),
+        );
+        planned.extend(custom_sections.iter().map(PlannedSection::Custom));
+        if !compact_unwind.is_empty() {
+            planned.push(PlannedSection::CompactUnwind);
+        }
+        if !stubs.is_empty() {
+            planned.push(PlannedSection::Stubs);
+            planned.push(PlannedSection::LazyPointers);
+        }
+        // the sections which are given an order come first, in that order, and the others keep
+        // their places relative to each other
+        planned.sort_by_key(|section| {
+            let sectname = section.sectname();
+            artifact
+                .mach_section_order
+                .iter()
+                .position(|name| *name == sectname)
+                .unwrap_or(artifact.mach_section_order.len())
+        });
+
+        // a custom section may be named like one of the sections faerie lays out, e.g. `__text`
+        let mut sectnames = HashSet::new();
+        for section in &planned {
+            // a custom section is keyed by its own name, which may differ from its sectname
+            let mut names = vec![section.sectname()];
+            if let PlannedSection::Custom(def) = section {
+                if def.name != names[0] {
+                    names.push(def.name.to_string());
+                }
+            }
+            for name in names {
+                if !sectnames.insert(name.clone()) {
+                    return Err(ArtifactError::SectionNameCollision(name).into());
+                }
+            }
+        }
+
+        // zero-fill sections take up no space in the file, so they go at the end of the
+        // segment's memory; their places in the section order are reserved until then
+        let mut zerofill = Vec::new();
+        for planned_section in planned {
+            let section = sections.len();
+            match planned_section {
+                PlannedSection::Definitions {
+                    sectname,
+                    segname,
+                    flags: Some(flags),
+                    ..
+                } if is_zerofill(flags) => {
+                    sections.insert(
+                        sectname.to_string(),
+                        SectionBuilder::new(sectname.to_string(), segname, 0).flags(flags),
+                    );
+                    zerofill.push((section, planned_section));
+                }
+                PlannedSection::Definitions {
+                    sectname,
+                    segname,
+                    definitions,
+                    min_alignment_exponent,
+                    section_alignment_exponent,
+                    flags,
+                } => Self::build_section(
+                    symtab,
+                    sectname,
+                    segname,
+                    &mut sections,
+                    &mut offset,
+                    &mut vmsize,
+                    &mut symbol_offset,
+                    section,
+                    definitions,
+                    min_alignment_exponent,
+                    section_alignment_exponent,
+                    flags,
+                    &mut align_pad_map,
+                ),
+                PlannedSection::ThreadVars => {
+                    let pointer_size = if ctx.is_big() { 8 } else { 4 };
+                    let descriptor_size = 3 * pointer_size;
+                    let alignment_exponent = align_to_align_exp(pointer_size);
+                    let pad = Self::align_section_start(
+                        &mut offset,
+                        &mut vmsize,
+                        &mut symbol_offset,
+                        alignment_exponent,
+                    );
+                    for (i, def) in thread_locals.iter().enumerate() {
+                        let descriptor_offset = i as u64 * descriptor_size;
+                        symtab.insert(
+                            def.name,
+                            SymbolType::Defined {
+                                section,
+                                segment_relative_offset: descriptor_offset,
+                                absolute_offset: symbol_offset + descriptor_offset,
+                                scope: def.decl.get_scope(),
+                                visibility: def.decl.get_visibility(),
+                            },
+                        );
+                    }
+                    let size = thread_locals.len() as u64 * descriptor_size;
+                    symbol_offset += size;
+                    sections.insert(
+                        "__thread_vars".to_string(),
+                        SectionBuilder::new("__thread_vars".to_string(), "__DATA", size)
+                            .offset(offset)
+                            .addr(vmsize)
+                            .align(alignment_exponent)
+                            .pad(pad)
+                            .flags(S_THREAD_LOCAL_VARIABLES),
+                    );
+                    offset += size;
+                    vmsize += size;
+                }
+                PlannedSection::Custom(def) => Self::build_custom_section(
+                    symtab,
+                    &mut sections,
+                    &mut offset,
+                    &mut vmsize,
+                    &mut symbol_offset,
+                    section,
+                    def,
+                ),
+                PlannedSection::CompactUnwind => {
+                    let pointer_size = if ctx.is_big() { 8 } else { 4 };
+                    let size =
+                        compact_unwind.len() as u64 * compact_unwind_entry_size(pointer_size);
+                    let alignment_exponent = align_to_align_exp(pointer_size);
+                    let pad = Self::align_section_start(
+                        &mut offset,
+                        &mut vmsize,
+                        &mut symbol_offset,
+                        alignment_exponent,
+                    );
+                    symbol_offset += size;
+                    sections.insert(
+                        COMPACT_UNWIND_SECTNAME.to_string(),
+                        SectionBuilder::new(COMPACT_UNWIND_SECTNAME.to_string(), "__LD", size)
+                            .offset(offset)
+                            .addr(vmsize)
+                            .align(alignment_exponent)
+                            .pad(pad)
+                            .flags(S_ATTR_DEBUG),
+                    );
+                    offset += size;
+                    vmsize += size;
+                }
+                // the stubs' entries in the indirect symbol table come first, then the lazy
+                // pointers'
+                PlannedSection::Stubs | PlannedSection::LazyPointers => {
+                    let (sectname, segname, entry_size, flags, reserved) = match planned_section {
+                        PlannedSection::Stubs => (
+                            STUBS_SECTNAME,
+                            "__TEXT",
+                            STUB_SIZE,
+                            S_SYMBOL_STUBS | S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS,
+                            (0, STUB_SIZE as u32),
+                        ),
+                        _ => (
+                            LAZY_POINTERS_SECTNAME,
+                            "__DATA",
+                            8,
+                            S_LAZY_SYMBOL_POINTERS,
+                            (stubs.len() as u32, 0),
+                        ),
+                    };
+                    let size = stubs.len() as u64 * entry_size;
+                    let alignment_exponent = if entry_size == 8 { 3 } else { 1 };
+                    let pad = Self::align_section_start(
+                        &mut offset,
+                        &mut vmsize,
+                        &mut symbol_offset,
+                        alignment_exponent,
+                    );
+                    symbol_offset += size;
+                    sections.insert(
+                        sectname.to_string(),
+                        SectionBuilder::new(sectname.to_string(), segname, size)
+                            .offset(offset)
+                            .addr(vmsize)
+                            .align(alignment_exponent)
+                            .pad(pad)
+                            .flags(flags)
+                            .reserved(reserved.0, reserved.1),
+                    );
+                    offset += size;
+                    vmsize += size;
+                }
+            }
+        }
+        let filesize = vmsize;
+        for (section, planned_section) in zerofill {
+            if let PlannedSection::Definitions {
+                sectname,
+                segname,
+                definitions,
+                min_alignment_exponent,
+                section_alignment_exponent,
+                flags,
+            } = planned_section
+            {
+                Self::build_section(
+                    symtab,
+                    sectname,
+                    segname,
+                    &mut sections,
+                    &mut offset,
+                    &mut vmsize,
+                    &mut symbol_offset,
+                    section,
+                    definitions,
+                    min_alignment_exponent,
+                    section_alignment_exponent,
+                    flags,
+                    &mut align_pad_map,
+                );
+            }
+        }
+        // common symbols are in no section, and are undefined like imports
+        for def in artifact.definitions().filter(|def| def.decl.is_common()) {
+            symtab.insert(
+                def.name,
+                SymbolType::Common {
+                    size: def.data.size() as u64,
+                    align_exponent: align_to_align_exp(def.decl.get_align().unwrap_or(8)) as u8,
+                    visibility: def.decl.get_visibility(),
+                },
+            );
+        }
+        let dylibs = dylibs(artifact);
+        for (import, kind) in artifact.emitted_imports() {
+            // re-exports are inserted after the imports they refer to
+            if artifact.reexported_import(import).is_some() {
+                continue;
+            }
+            if kind == ImportKind::SectionBoundary {
+                symtab.insert(import, SymbolType::SectionBoundary);
+                continue;
+            }
+            // library ordinals are 1-based, 0 meaning no particular library
+            let library_ordinal = artifact
+                .import_library(import)
+                .and_then(|library| dylibs.iter().position(|&dylib| dylib == library))
+                .map_or(0, |idx| idx as u8 + 1);
+            symtab.insert(
+                import,
+                SymbolType::Undefined {
+                    library_ordinal,
+                    weak: artifact.is_weak_import(import),
+                },
+            );
+        }
+        for (import, _) in artifact.emitted_imports() {
+            if let Some(target) = artifact.reexported_import(import) {
+                symtab.insert(import, SymbolType::Indirect { target });
+            }
+        }
+        if !thread_locals.is_empty() {
+            // the thunk every thread-local variable's descriptor starts with
+            symtab.insert(
+                TLV_BOOTSTRAP,
+                SymbolType::Undefined {
+                    library_ordinal: 0,
+                    weak: false,
+                },
+            );
         }
         // FIXME re add assert
         //assert_eq!(offset, Header::size_with(&ctx.container) + Self::load_command_size(ctx));
         debug!(
-            "Segment Size: {} Symtable LoadCommand Offset: {}",
-            size, offset
+            "Segment Size: {} VM Size: {} Symtable LoadCommand Offset: {}",
+            filesize, vmsize, offset
         );
-        SegmentBuilder {
-            size,
+        Ok(SegmentBuilder {
+            size: filesize,
+            vmsize,
             sections,
-            offset,
             align_pad_map,
-        }
+            implicit_addends: HashMap::new(),
+        })
     }
 }
 
@@ -669,39 +1493,64 @@ impl SegmentBuilder {
 struct Mach<'a> {
     ctx: Ctx,
     architecture: Architecture,
+    filetype: MachFileType,
+    header_flags: u32,
+    dylibs: Vec<&'a str>,
+    load_commands: &'a [(u32, Vec<u8>)],
+    fills: &'a BTreeMap<String, u8>,
+    uuid: Option<MachUuid>,
+    function_starts: bool,
+    protection: u32,
+    segname: &'a str,
     symtab: SymbolTable,
     segment: SegmentBuilder,
     code: ArtifactCode<'a>,
     data: ArtifactData<'a>,
-    bss_size: usize,
     cstrings: Vec<Definition<'a>>,
+    literals: Vec<Vec<Definition<'a>>>,
+    coalesced_code: Vec<Definition<'a>>,
+    coalesced_data: Vec<Definition<'a>>,
+    thread_locals: Vec<Definition<'a>>,
     sections: Vec<Definition<'a>>,
+    compact_unwind: Vec<(Definition<'a>, u32)>,
+    stubs: Vec<&'a str>,
     _p: ::std::marker::PhantomData<&'a ()>,
 }
 
 impl<'a> Mach<'a> {
-    pub fn new(artifact: &'a Artifact) -> Self {
+    pub fn new(artifact: &'a Artifact) -> Result<Self, Error> {
         let ctx = make_ctx(&artifact.target);
         // FIXME: I believe we can avoid this partition by refactoring SegmentBuilder::new
-        let (mut code, mut data, mut bss, mut cstrings, mut sections, mut bss_size) = (
-            Vec::new(),
-            Vec::new(),
-            Vec::new(),
-            Vec::new(),
-            Vec::new(),
-            0,
-        );
+        let (mut code, mut data, mut bss, mut cstrings, mut sections) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let mut literals = vec![Vec::new(); LITERAL_SECTIONS.len()];
+        let (mut coalesced_code, mut coalesced_data) = (Vec::new(), Vec::new());
+        let mut thread_locals = Vec::new();
         for def in artifact.definitions() {
             match def.decl {
+                // common symbols are in no section
+                _ if def.decl.is_common() => {}
+                DefinedDecl::Function(f) if f.is_coalesced() => {
+                    coalesced_code.push(def);
+                }
                 DefinedDecl::Function { .. } => {
                     code.push(def);
                 }
+                DefinedDecl::Data(d) if d.is_tls() => {
+                    thread_locals.push(def);
+                }
                 DefinedDecl::Data(d) => {
-                    if let Data::ZeroInit(size) = def.data {
+                    if def.data.is_zero_init() {
                         bss.push(def);
-                        bss_size += size;
-                    } else if d.get_datatype() == DataType::String {
+                    } else if d.is_coalesced() {
+                        coalesced_data.push(def);
+                    } else if def.data.datatype(d.get_datatype()) == DataType::String {
                         cstrings.push(def);
+                    } else if let Some(idx) = LITERAL_SECTIONS
+                        .iter()
+                        .position(|&(datatype, _, _)| datatype == d.get_datatype())
+                    {
+                        literals[idx].push(def);
                     } else {
                         data.push(def);
                     }
@@ -712,6 +1561,35 @@ impl<'a> Mach<'a> {
             }
         }
 
+        let protection = match artifact.mach_protection {
+            MachProtection::ReadWriteExecute => VM_PROT_READ | VM_PROT_WRITE | VM_PROT_EXECUTE,
+            MachProtection::Computed => {
+                let mut protection = VM_PROT_READ;
+                for def in artifact.definitions() {
+                    if def.decl.is_writable() || def.data.is_zero_init() {
+                        protection |= VM_PROT_WRITE;
+                    }
+                    match def.decl {
+                        DefinedDecl::Function(_) => protection |= VM_PROT_EXECUTE,
+                        DefinedDecl::Data(d) if d.is_tls() => protection |= VM_PROT_WRITE,
+                        DefinedDecl::Section(s) if s.kind() == SectionKind::Text => {
+                            protection |= VM_PROT_EXECUTE
+                        }
+                        _ => {}
+                    }
+                }
+                protection
+            }
+        };
+        let stubs = stubbed_imports(artifact);
+        // the stubs are executed, and the lazy pointers are bound at runtime
+        let protection = if stubs.is_empty() {
+            protection
+        } else {
+            protection | VM_PROT_WRITE | VM_PROT_EXECUTE
+        };
+
+        let compact_unwind = compact_unwind_entries(artifact).collect::<Vec<_>>();
         let mut symtab = SymbolTable::new();
         let mut segment = SegmentBuilder::new(
             &artifact,
@@ -719,88 +1597,413 @@ impl<'a> Mach<'a> {
             &data,
             &bss,
             &cstrings,
+            &literals,
+            &coalesced_code,
+            &coalesced_data,
+            &thread_locals,
             &sections,
+            &compact_unwind,
+            &stubs,
             &mut symtab,
             &ctx,
-        );
-        build_relocations(&mut segment, &artifact, &symtab);
+        )?;
+        symtab.reorder(artifact.mach_symbol_order);
+        // the dynamic symbol table requires the symbols to be grouped by their binding
+        symtab.group();
+        build_relocations(&mut segment, &artifact, &symtab, &stubs)?;
 
-        Mach {
+        Ok(Mach {
             ctx,
             architecture: artifact.target.architecture,
+            filetype: artifact.mach_filetype,
+            // safe to divide up the sections into sub-sections via symbols for dead code stripping
+            header_flags: artifact
+                .mach_header_flags
+                .unwrap_or(MH_SUBSECTIONS_VIA_SYMBOLS),
+            dylibs: dylibs(artifact),
+            load_commands: &artifact.mach_load_commands,
+            fills: &artifact.mach_section_fills,
+            uuid: artifact.mach_uuid,
+            function_starts: artifact.mach_function_starts,
+            protection,
+            // object files conventionally put every section in a single unnamed segment
+            segname: artifact.mach_segment_name.as_deref().unwrap_or(""),
             symtab,
             segment,
             _p: ::std::marker::PhantomData::default(),
             code,
             data,
-            bss_size,
             cstrings,
+            literals,
+            coalesced_code,
+            coalesced_data,
+            thread_locals,
             sections,
-        }
+            compact_unwind,
+            stubs,
+        })
     }
     fn header(&self, sizeofcmds: u64) -> Header {
         let mut header = Header::new(self.ctx);
-        header.filetype = MH_OBJECT;
-        // safe to divide up the sections into sub-sections via symbols for dead code stripping
-        header.flags = MH_SUBSECTIONS_VIA_SYMBOLS;
-        header.cputype = CpuType::from(self.architecture).0;
+        header.filetype = match self.filetype {
+            MachFileType::Object => MH_OBJECT,
+            MachFileType::Bundle => MH_BUNDLE,
+            MachFileType::Dylib => MH_DYLIB,
+        };
+        header.flags = self.header_flags;
+        header.cputype = CpuType::try_from(self.architecture)
+            .expect("architecture is checked when building")
+            .0;
         header.cpusubtype = 3;
-        header.ncmds = 2;
+        header.ncmds = 3
+            + self.dylibs.len()
+            + self.function_starts as usize
+            + self.uuid.is_some() as usize
+            + self.load_commands.len();
         header.sizeofcmds = sizeofcmds as u32;
         header
     }
+    /// The size of all of the load commands, in bytes
+    fn sizeof_load_commands(&self) -> u64 {
+        self.segment.load_command_size(&self.ctx)
+            + SymtabCommand::new().cmdsize as u64
+            + self.sizeof_dylib_commands()
+            + self.sizeof_function_starts_command()
+            + SIZEOF_DYSYMTAB_COMMAND as u64
+            + self.sizeof_uuid_command()
+            + self.sizeof_additional_load_commands()
+    }
+    /// The size of the `LC_UUID` command, if one is emitted
+    fn sizeof_uuid_command(&self) -> u64 {
+        if self.uuid.is_some() {
+            SIZEOF_UUID_COMMAND as u64
+        } else {
+            0
+        }
+    }
+    /// The file offset of the UUID in the `LC_UUID` command, which follows the segment, symbol
+    /// table and dynamic symbol table commands
+    fn uuid_offset(&self) -> u64 {
+        Header::size_with(&self.ctx) as u64
+            + self.segment.load_command_size(&self.ctx)
+            + SymtabCommand::new().cmdsize as u64
+            + SIZEOF_DYSYMTAB_COMMAND as u64
+            + SIZEOF_LOAD_COMMAND as u64
+    }
+    /// The indirect symbol table: the symbol index of each stub's import, then of each lazy
+    /// pointer's
+    fn indirect_symbols(&self) -> Vec<u32> {
+        let indexes = self.stubs.iter().map(|stub| {
+            self.symtab
+                .index(stub)
+                .expect("stubbed import is in the symbol table") as u32
+        });
+        indexes.clone().chain(indexes).collect()
+    }
+    /// The size of the `LC_FUNCTION_STARTS` command, if one is emitted
+    fn sizeof_function_starts_command(&self) -> u64 {
+        if self.function_starts {
+            ::std::mem::size_of::<LinkeditDataCommand>() as u64
+        } else {
+            0
+        }
+    }
+    /// The `LC_FUNCTION_STARTS` table: the ULEB128 encoded deltas between the file offsets of
+    /// consecutive functions, starting from 0, terminated by a zero and padded to a pointer size
+    fn function_starts_table(&self) -> Vec<u8> {
+        let first_section_offset = self.first_section_offset();
+        let mut offsets = self
+            .code
+            .iter()
+            .chain(&self.coalesced_code)
+            .map(|def| {
+                first_section_offset + self.symtab.address(def.name).expect("function in symtab")
+            })
+            .collect::<Vec<_>>();
+        offsets.sort();
+        let mut table = Vec::new();
+        let mut previous = 0;
+        for offset in offsets {
+            let mut delta = offset - previous;
+            previous = offset;
+            loop {
+                let byte = (delta & 0x7f) as u8;
+                delta >>= 7;
+                if delta == 0 {
+                    table.push(byte);
+                    break;
+                }
+                table.push(byte | 0x80);
+            }
+        }
+        table.push(0);
+        let align = if self.ctx.is_big() { 8 } else { 4 };
+        while table.len() % align != 0 {
+            table.push(0);
+        }
+        table
+    }
+    /// The size of a `LC_LOAD_DYLIB` command for `dylib`, including its padded path name
+    fn sizeof_dylib_command(&self, dylib: &str) -> u64 {
+        let align = if self.ctx.is_big() { 8 } else { 4 };
+        let size = ::std::mem::size_of::<DylibCommand>() as u64 + dylib.len() as u64 + 1;
+        (size + align - 1) & !(align - 1)
+    }
+    /// The size of all of the `LC_LOAD_DYLIB` commands, in bytes
+    fn sizeof_dylib_commands(&self) -> u64 {
+        self.dylibs
+            .iter()
+            .map(|dylib| self.sizeof_dylib_command(dylib))
+            .sum()
+    }
+    /// The size of an additional load command with `payload`, including its `cmd` and `cmdsize`
+    /// fields and its padding
+    fn sizeof_additional_load_command(&self, payload: &[u8]) -> u64 {
+        let align = if self.ctx.is_big() { 8 } else { 4 };
+        let size = SIZEOF_LOAD_COMMAND as u64 + payload.len() as u64;
+        (size + align - 1) & !(align - 1)
+    }
+    /// The size of all of the additional load commands, in bytes
+    fn sizeof_additional_load_commands(&self) -> u64 {
+        self.load_commands
+            .iter()
+            .map(|(_, payload)| self.sizeof_additional_load_command(payload))
+            .sum()
+    }
+    /// The file offset of the first section's data, which follows the header and load commands
+    fn first_section_offset(&self) -> u64 {
+        let end_of_load_commands =
+            Header::size_with(&self.ctx) as u64 + self.sizeof_load_commands();
+        let alignment = 1 << self.segment.max_alignment_exponent();
+        (end_of_load_commands + alignment - 1) & !(alignment - 1)
+    }
+    /// The file offset of the symbol table, which follows the segment's data
+    fn symtable_offset(&self) -> u64 {
+        self.first_section_offset() + self.segment.size()
+    }
+    /// The file offset of the string table, which follows the symbol table
+    fn strtable_offset(&self) -> u64 {
+        self.symtable_offset() + (self.symtab.len() as u64 * Nlist::size_with(&self.ctx) as u64)
+    }
+    /// The file offset of the first section's relocations, which follow the string table
+    fn relocation_offset(&self) -> u64 {
+        self.strtable_offset() + self.symtab.sizeof_strtable()
+    }
+    /// The file offset of the function starts table, which follows the relocations and indirect
+    /// symbols ending at `end`, aligned to a pointer size
+    fn function_starts_offset(&self, end: u64) -> u64 {
+        let pointer_size = if self.ctx.is_big() { 8 } else { 4 };
+        (end + pointer_size - 1) & !(pointer_size - 1)
+    }
+    /// The file offset of the end of the relocations of every section
+    fn relocation_end(&self) -> u64 {
+        let nrelocs = self
+            .segment
+            .sections
+            .values()
+            .map(|section| section.relocations.len() as u64)
+            .sum::<u64>();
+        self.relocation_offset() + nrelocs * SIZEOF_RELOCATION_INFO as u64
+    }
+    /// The file offset of the indirect symbol table, which follows the relocations, aligned to 4
+    /// bytes
+    fn indirect_symbols_offset(&self) -> u64 {
+        (self.relocation_end() + 3) & !3
+    }
+    /// The file offset of the end of the indirect symbol table, if there is one, or else of the
+    /// relocations
+    fn indirect_symbols_end(&self) -> u64 {
+        if self.stubs.is_empty() {
+            self.relocation_end()
+        } else {
+            self.indirect_symbols_offset() + 4 * 2 * self.stubs.len() as u64
+        }
+    }
+    /// The size of the file `write` would emit, in bytes, computed without writing it
+    fn size(&self) -> u64 {
+        let indirect_symbols_end = self.indirect_symbols_end();
+        let end = if self.function_starts {
+            self.function_starts_offset(indirect_symbols_end)
+                + self.function_starts_table().len() as u64
+        } else {
+            indirect_symbols_end
+        };
+        // the file ends with a null byte
+        end + 1
+    }
+    /// The placement of every section in the segment
+    fn resolved_sections(&self) -> Vec<ResolvedSection> {
+        let mut offset = self.first_section_offset();
+        self.segment
+            .sections
+            .values()
+            .map(|section| {
+                if section.is_zerofill() {
+                    return ResolvedSection {
+                        name: section.sectname.clone(),
+                        offset: 0,
+                        size: section.size,
+                    };
+                }
+                offset += section.pad;
+                let resolved = ResolvedSection {
+                    name: section.sectname.clone(),
+                    offset,
+                    size: section.size,
+                };
+                offset += section.size;
+                resolved
+            })
+            .collect()
+    }
+    /// The placement of every defined symbol
+    fn resolved_symbols(&self) -> Vec<ResolvedSymbol> {
+        self.symtab
+            .symbols
+            .iter()
+            .filter_map(|(&name, symbol)| {
+                let (_, section) = self.segment.sections.get_index(symbol.section?)?;
+                Some(ResolvedSymbol {
+                    name: self
+                        .symtab
+                        .strtable
+                        .resolve(name)
+                        .expect("symbol name in strtable")
+                        .to_string(),
+                    section: section.sectname.clone(),
+                    offset: symbol.offset - section.addr,
+                })
+            })
+            .collect()
+    }
+    /// Every relocation in the segment's sections
+    fn resolved_relocations(&self) -> Vec<ResolvedReloc> {
+        let mut relocations = Vec::new();
+        for section in self.segment.sections.values() {
+            for reloc in section.relocations.iter() {
+                let resolve = |name| {
+                    self.symtab
+                        .strtable
+                        .resolve(name)
+                        .expect("symbol name in strtable")
+                        .to_string()
+                };
+                if reloc.r_address as u32 & R_SCATTERED != 0 {
+                    // the target is the symbol, or else the section, at the relocation's value
+                    let value = u64::from(reloc.r_info);
+                    let symbol = self
+                        .symtab
+                        .symbols
+                        .iter()
+                        .find(|(_, symbol)| !symbol.import && symbol.offset == value)
+                        .map(|(&name, _)| resolve(name))
+                        .or_else(|| {
+                            self.segment
+                                .sections
+                                .values()
+                                .find(|section| section.addr == value)
+                                .map(|section| section.sectname.clone())
+                        })
+                        .expect("scattered relocation target is defined");
+                    relocations.push(ResolvedReloc {
+                        section: section.sectname.clone(),
+                        offset: u64::from(relocation_address(reloc)),
+                        symbol,
+                        reloc: (reloc.r_address as u32 >> 24) & 0xf,
+                        size: Some(1 << ((reloc.r_address as u32 >> 28) & 0x3)),
+                        addend: 0,
+                    });
+                    continue;
+                }
+                let symbol = if reloc.r_extern() == 0 {
+                    // a local relocation refers to the section with its ordinal
+                    let (_, section) = self
+                        .segment
+                        .sections
+                        .get_index(reloc.r_symbolnum() - 1)
+                        .expect("relocation section in segment");
+                    section.sectname.clone()
+                } else {
+                    let (&name, _) = self
+                        .symtab
+                        .symbols
+                        .get_index(reloc.r_symbolnum())
+                        .expect("relocation symbol in symtab");
+                    resolve(name)
+                };
+                relocations.push(ResolvedReloc {
+                    section: section.sectname.clone(),
+                    offset: reloc.r_address as u64,
+                    symbol,
+                    reloc: u32::from(reloc.r_type()),
+                    size: Some(1 << reloc.r_length()),
+                    addend: 0,
+                });
+            }
+        }
+        relocations
+    }
     pub fn write<T: Write + Seek>(self, file: T) -> Result<(), Error> {
         let mut file = BufWriter::new(file);
         // FIXME: this is ugly af, need cmdsize to get symtable offset
         // construct symtab command
         let mut symtab_load_command = SymtabCommand::new();
-        let segment_load_command_size = self.segment.load_command_size(&self.ctx);
-        let sizeof_load_commands = segment_load_command_size + symtab_load_command.cmdsize as u64;
-        let symtable_offset = self.segment.offset + sizeof_load_commands;
-        let strtable_offset =
-            symtable_offset + (self.symtab.len() as u64 * Nlist::size_with(&self.ctx) as u64);
-        let relocation_offset_start = strtable_offset + self.symtab.sizeof_strtable();
-        let first_section_offset = Header::size_with(&self.ctx) as u64 + sizeof_load_commands;
+        let sizeof_load_commands = self.sizeof_load_commands();
+        let first_section_offset = self.first_section_offset();
+        let symtable_offset = self.symtable_offset();
+        let strtable_offset = self.strtable_offset();
+        let relocation_offset_start = self.relocation_offset();
         // start with setting the headers dependent value
         let header = self.header(sizeof_load_commands);
 
         debug!("Symtable: {:#?}", self.symtab);
-        // marshall the sections into something we can actually write
-        let mut raw_sections = Cursor::new(Vec::<u8>::new());
-        let mut relocation_offset = relocation_offset_start;
-        let mut section_offset = first_section_offset;
-        for section in self.segment.sections.values() {
-            let header = section.create(&mut section_offset, &mut relocation_offset);
-            debug!("Section: {:#?}", header);
-            raw_sections.iowrite_with(header, self.ctx)?;
-        }
-        let raw_sections = raw_sections.into_inner();
+        // the function starts table follows the relocations, aligned to a pointer size
+        let function_starts_table = if self.function_starts {
+            Some(self.function_starts_table())
+        } else {
+            None
+        };
+        let function_starts_offset = self.function_starts_offset(self.indirect_symbols_end());
+        let indirect_symbols_offset = self.indirect_symbols_offset();
+        let indirect_symbols = self.indirect_symbols();
         debug!(
-            "Raw sections len: {} - Section start: {} Strtable size: {} - Segment size: {}",
-            raw_sections.len(),
+            "Section start: {} Strtable size: {} - Segment size: {}",
             first_section_offset,
             self.symtab.sizeof_strtable(),
             self.segment.size()
         );
 
-        let mut segment_load_command = Segment::new(self.ctx, &raw_sections);
+        // the section headers are written straight after the segment load command, which they
+        // are a part of
+        let mut segment_load_command = Segment::new(self.ctx, &[]);
+        segment_load_command.segname = [0u8; 16];
+        segment_load_command
+            .segname
+            .pwrite(self.segname, 0)
+            .expect("segment name is checked to fit");
+        segment_load_command.cmdsize = self.segment.load_command_size(&self.ctx) as u32;
         segment_load_command.nsects = self.segment.sections.len() as u32;
-        // FIXME: de-magic number these
-        segment_load_command.initprot = 7;
-        segment_load_command.maxprot = 7;
+        segment_load_command.initprot = self.protection;
+        segment_load_command.maxprot = self.protection;
         segment_load_command.filesize = self.segment.size();
         // segment size, with __bss data sizes added
-        segment_load_command.vmsize = segment_load_command.filesize + self.bss_size as u64;
+        segment_load_command.vmsize = self.segment.vmsize();
         segment_load_command.fileoff = first_section_offset;
         debug!("Segment: {:#?}", segment_load_command);
 
         debug!("Symtable Offset: {:#?}", symtable_offset);
         assert_eq!(
-            symtable_offset,
-            self.segment.offset
-                + segment_load_command.cmdsize as u64
+            sizeof_load_commands,
+            segment_load_command.cmdsize as u64
                 + symtab_load_command.cmdsize as u64
+                + self.sizeof_dylib_commands()
+                + self.sizeof_function_starts_command()
+                + SIZEOF_DYSYMTAB_COMMAND as u64
+                + self.sizeof_uuid_command()
+                + self.sizeof_additional_load_commands()
         );
+        // with no symbols, the symbol table is empty and the string table is only its leading
+        // null byte, so `symoff == stroff` and `strsize == 1`
         symtab_load_command.nsyms = self.symtab.len() as u32;
         symtab_load_command.symoff = symtable_offset as u32;
         symtab_load_command.stroff = strtable_offset as u32;
@@ -818,86 +2021,196 @@ impl<'a> Mach<'a> {
         // write load commands
         //////////////////////////////
         file.iowrite_with(segment_load_command, self.ctx)?;
-        file.write_all(&raw_sections)?;
+        let mut relocation_offset = relocation_offset_start;
+        let mut section_offset = first_section_offset;
+        for section in self.segment.sections.values() {
+            let header = section.create(&mut section_offset, &mut relocation_offset);
+            debug!("Section: {:#?}", header);
+            header.write(&mut file, self.ctx)?;
+        }
         file.iowrite_with(symtab_load_command, self.ctx.le)?;
-        debug!("SEEK: after load commands: {}", file.seek(Current(0))?);
-
-        //////////////////////////////
-        // write code
-        //////////////////////////////
-        for code in self.code {
-            if let Data::Blob(bytes) = code.data {
-                file.write_all(&bytes)?;
+        // the symbols are grouped into the locals, then the defined externals, then the undefined
+        // externals
+        let [nlocalsym, nextdefsym, nundefsym] = self.symtab.binding_counts();
+        let dysymtab_load_command = DysymtabCommand {
+            ilocalsym: 0,
+            nlocalsym,
+            iextdefsym: nlocalsym,
+            nextdefsym,
+            iundefsym: nlocalsym + nextdefsym,
+            nundefsym,
+            indirectsymoff: if indirect_symbols.is_empty() {
+                0
             } else {
-                unreachable!()
-            }
-
-            if let Some(&align_pad) = self.segment.align_pad_map.get(code.name) {
-                for _ in 0..align_pad {
-                    // `0xcc` generates a debug interrupt on x86. When there is no debugger attached
-                    // this will abort the program.
-                    file.write_all(&[0xcc])?;
-                }
-            }
+                indirect_symbols_offset as u32
+            },
+            nindirectsyms: indirect_symbols.len() as u32,
+            ..DysymtabCommand::default()
+        };
+        debug!("Dysymtab Load command: {:#?}", dysymtab_load_command);
+        file.iowrite_with(dysymtab_load_command, self.ctx.le)?;
+        if let Some(uuid) = self.uuid {
+            let uuid_load_command = UuidCommand {
+                cmd: LC_UUID,
+                cmdsize: SIZEOF_UUID_COMMAND as u32,
+                // a hashed UUID is filled in once the rest of the file is written
+                uuid: match uuid {
+                    MachUuid::Fixed(uuid) => uuid,
+                    MachUuid::Hashed => [0; 16],
+                },
+            };
+            file.iowrite_with(uuid_load_command, self.ctx.le)?;
         }
-        debug!("SEEK: after code: {}", file.seek(Current(0))?);
-
-        //////////////////////////////
-        // write data
-        //////////////////////////////
-        for data in self.data {
-            if let Data::Blob(bytes) = data.data {
-                file.write_all(bytes)?;
-            }
-
-            if let Some(&align_pad) = self.segment.align_pad_map.get(data.name) {
-                for _ in 0..align_pad {
-                    // Exact padding value doesn't matter. Not using zero to prevent confusion
-                    // with a zero pointer when the final executable accidentially reads past
-                    // the end of a data object.
-                    file.write_all(&[0xaa])?;
-                }
+        for dylib in self.dylibs.iter() {
+            let cmdsize = self.sizeof_dylib_command(dylib);
+            let name_offset = ::std::mem::size_of::<DylibCommand>() as u64;
+            let dylib_load_command = DylibCommand {
+                cmd: LC_LOAD_DYLIB,
+                cmdsize: cmdsize as u32,
+                dylib: Dylib {
+                    name: name_offset as u32,
+                    timestamp: 0,
+                    current_version: 0,
+                    compatibility_version: 0,
+                },
+            };
+            file.iowrite_with(dylib_load_command, self.ctx.le)?;
+            file.write_all(dylib.as_bytes())?;
+            // the path name is NUL terminated and padded out to the command size
+            for _ in name_offset + dylib.len() as u64..cmdsize {
+                file.iowrite(0u8)?;
             }
         }
-        debug!("SEEK: after data: {}", file.seek(Current(0))?);
-
-        //////////////////////////////
-        // write cstrings
-        //////////////////////////////
-        for cstring in self.cstrings {
-            if let Data::Blob(bytes) = cstring.data {
-                file.write_all(bytes)?;
-            } else {
-                unreachable!();
-            }
-
-            if let Some(&align_pad) = self.segment.align_pad_map.get(cstring.name) {
-                for _ in 0..align_pad {
-                    // See comment above for explanation of 0xaa
-                    file.write_all(&[0xaa])?;
-                }
-            }
+        if let Some(table) = function_starts_table.as_ref() {
+            let function_starts_load_command = LinkeditDataCommand {
+                cmd: LC_FUNCTION_STARTS,
+                cmdsize: self.sizeof_function_starts_command() as u32,
+                dataoff: function_starts_offset as u32,
+                datasize: table.len() as u32,
+            };
+            file.iowrite_with(function_starts_load_command, self.ctx.le)?;
+        }
+        for (cmd, payload) in self.load_commands {
+            let cmdsize = self.sizeof_additional_load_command(payload);
+            file.iowrite_with(*cmd, self.ctx.le)?;
+            file.iowrite_with(cmdsize as u32, self.ctx.le)?;
+            file.write_all(payload)?;
+            write_pad(
+                &mut file,
+                cmdsize - SIZEOF_LOAD_COMMAND as u64 - payload.len() as u64,
+            )?;
         }
-        debug!("SEEK: after cstrings: {}", file.seek(Current(0))?);
+        // the first section starts at an offset as aligned as the most aligned section
+        let load_commands_end = Header::size_with(&self.ctx) as u64 + sizeof_load_commands;
+        write_pad(&mut file, first_section_offset - load_commands_end)?;
+        debug!("SEEK: after load commands: {}", file.seek(Current(0))?);
 
         //////////////////////////////
-        // write custom sections
+        // write sections
         //////////////////////////////
-        for section in self.sections {
-            if let Data::Blob(bytes) = section.data {
-                file.write_all(bytes)?;
-            } else {
-                unreachable!()
+        // the contents are in the order of the section headers, without the zero-fill sections
+        let pointer_size = if self.ctx.is_big() { 8 } else { 4 };
+        for (name, section) in &self.segment.sections {
+            if section.is_zerofill() {
+                continue;
             }
-
-            if let Some(&align_pad) = self.segment.align_pad_map.get(section.name) {
-                for _ in 0..align_pad {
-                    // See comment above for explanation of 0xaa
-                    file.write_all(&[0xaa])?;
+            write_pad(&mut file, section.pad)?;
+            // `0xcc` generates a debug interrupt on x86. When there is no debugger attached this
+            // will abort the program.
+            let code_fill = self.fills.get(name).cloned().unwrap_or(0xcc);
+            // Exact padding value doesn't matter. Not using zero to prevent confusion with a zero
+            // pointer when the final executable accidentially reads past the end of a data
+            // object.
+            let data_fill = self.fills.get(name).cloned().unwrap_or(0xaa);
+            if let Some(custom) = self.sections.iter().find(|def| def.name == name) {
+                write_definitions(
+                    &mut file,
+                    std::slice::from_ref(custom),
+                    &self.segment,
+                    self.ctx,
+                    data_fill,
+                )?;
+                continue;
+            }
+            match name.as_str() {
+                "__text" => {
+                    write_definitions(&mut file, &self.code, &self.segment, self.ctx, code_fill)?
+                }
+                "__data" => {
+                    write_definitions(&mut file, &self.data, &self.segment, self.ctx, data_fill)?
+                }
+                "__cstring" => write_definitions(
+                    &mut file,
+                    &self.cstrings,
+                    &self.segment,
+                    self.ctx,
+                    data_fill,
+                )?,
+                TEXT_COALESCED_SECTNAME => write_definitions(
+                    &mut file,
+                    &self.coalesced_code,
+                    &self.segment,
+                    self.ctx,
+                    code_fill,
+                )?,
+                DATA_COALESCED_SECTNAME => write_definitions(
+                    &mut file,
+                    &self.coalesced_data,
+                    &self.segment,
+                    self.ctx,
+                    data_fill,
+                )?,
+                // the descriptors are entirely filled in by their relocations
+                "__thread_vars" => write_pad(&mut file, section.size)?,
+                "__thread_data" => {
+                    for tlv in &self.thread_locals {
+                        if tlv.data.is_zero_init() {
+                            continue;
+                        }
+                        let addends = self.segment.implicit_addends.get(tlv.name);
+                        write_contents(&mut file, tlv.data, addends, self.ctx)?;
+
+                        if let Some(&align_pad) =
+                            self.segment.align_pad_map.get(&tlv_init_name(tlv.name))
+                        {
+                            for _ in 0..align_pad {
+                                file.write_all(&[data_fill])?;
+                            }
+                        }
+                    }
+                }
+                // each stub jumps through its lazy pointer, which the dynamic linker binds
+                STUBS_SECTNAME => {
+                    let lazy_pointers = self.segment.sections[LAZY_POINTERS_SECTNAME].addr;
+                    for i in 0..self.stubs.len() as u64 {
+                        let next_instruction = section.addr + (i + 1) * STUB_SIZE;
+                        let displacement = (lazy_pointers + i * 8) as i64 - next_instruction as i64;
+                        file.write_all(&[0xff, 0x25])?;
+                        file.iowrite_with(displacement as i32, self.ctx.le)?;
+                    }
+                }
+                LAZY_POINTERS_SECTNAME => write_pad(&mut file, section.size)?,
+                COMPACT_UNWIND_SECTNAME => {
+                    for (function, encoding) in &self.compact_unwind {
+                        // the function's address is filled in by its relocation, and there is no
+                        // personality routine or language-specific data area
+                        write_pad(&mut file, pointer_size)?;
+                        file.iowrite_with(function.data.file_size() as u32, self.ctx.le)?;
+                        file.iowrite_with(*encoding, self.ctx.le)?;
+                        write_pad(&mut file, 2 * pointer_size)?;
+                    }
+                }
+                sectname => {
+                    let literals = LITERAL_SECTIONS
+                        .iter()
+                        .position(|&(_, literal_sectname, _)| literal_sectname == sectname)
+                        .map(|idx| &self.literals[idx])
+                        .expect("section has contents");
+                    write_definitions(&mut file, literals, &self.segment, self.ctx, data_fill)?;
                 }
             }
+            debug!("SEEK: after {}: {}", name, file.seek(Current(0))?);
         }
-        debug!("SEEK: after custom sections: {}", file.seek(Current(0))?);
 
         //////////////////////////////
         // write symtable
@@ -917,7 +2230,9 @@ impl<'a> Mach<'a> {
         for (idx, string) in self.symtab.strtable.into_iter().skip(1) {
             debug!("{}: {:?}", idx, string);
             // yup, an underscore
-            file.iowrite(0x5fu8)?;
+            if !self.symtab.unprefixed.contains(&idx) {
+                file.iowrite(0x5fu8)?;
+            }
             file.write_all(string.as_bytes())?;
             file.iowrite(0u8)?;
         }
@@ -935,26 +2250,326 @@ impl<'a> Mach<'a> {
         }
         debug!("SEEK: after relocations: {}", file.seek(Current(0))?);
 
+        //////////////////////////////
+        // write indirect symbols
+        //////////////////////////////
+        let mut end = relocation_offset;
+        if !indirect_symbols.is_empty() {
+            write_pad(&mut file, indirect_symbols_offset - end)?;
+            for index in &indirect_symbols {
+                file.iowrite_with(*index, self.ctx.le)?;
+            }
+            end = indirect_symbols_offset + 4 * indirect_symbols.len() as u64;
+        }
+
+        //////////////////////////////
+        // write function starts
+        //////////////////////////////
+        if let Some(table) = function_starts_table.as_ref() {
+            write_pad(&mut file, function_starts_offset - end)?;
+            file.write_all(table)?;
+        }
+
         file.iowrite(0u8)?;
+        file.flush()?;
 
         Ok(())
     }
 }
 
+/// The name of the symbol for the initial value of the thread-local variable `name`
+fn tlv_init_name(name: &str) -> String {
+    format!("{}$tlv$init", name)
+}
+
+/// Forwards writes to `inner`, writing each of `patches`, as `(offset, size, bytes)` in order of
+/// offset, in place of the `size` bytes at its offset
+struct PatchingWriter<'a, T: ?Sized> {
+    inner: &'a mut T,
+    position: u64,
+    patches: &'a [(u64, usize, [u8; 8])],
+}
+
+impl<'a, T: Write + ?Sized> Write for PatchingWriter<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            while let Some(&(offset, size, _)) = self.patches.first() {
+                if offset + size as u64 > self.position {
+                    break;
+                }
+                self.patches = &self.patches[1..];
+            }
+            let written = match self.patches.first() {
+                Some(&(offset, size, ref bytes)) if offset <= self.position => {
+                    let start = (self.position - offset) as usize;
+                    let len = std::cmp::min(size - start, rest.len());
+                    self.inner.write_all(&bytes[start..start + len])?;
+                    len
+                }
+                Some(&(offset, _, _)) => {
+                    let len = std::cmp::min((offset - self.position) as usize, rest.len());
+                    self.inner.write_all(&rest[..len])?;
+                    len
+                }
+                None => {
+                    self.inner.write_all(rest)?;
+                    rest.len()
+                }
+            };
+            self.position += written as u64;
+            rest = &rest[written..];
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write the contents of `data`, with the implicit `addends` of the relocations in it, as
+/// `(offset, size, addend)`, written over them as the contents are streamed out
+fn write_contents<T: Write>(
+    file: &mut T,
+    data: &Data,
+    addends: Option<&Vec<(u64, u8, i32)>>,
+    ctx: Ctx,
+) -> Result<(), Error> {
+    match addends {
+        Some(addends) => {
+            let mut patches = Vec::with_capacity(addends.len());
+            for &(offset, size, addend) in addends {
+                let mut bytes = [0; 8];
+                match size {
+                    4 => bytes.pwrite_with(addend, 0, ctx.le)?,
+                    8 => bytes.pwrite_with(i64::from(addend), 0, ctx.le)?,
+                    size => panic!("unsupported relocation size {}", size),
+                };
+                patches.push((offset, usize::from(size), bytes));
+            }
+            patches.sort_by_key(|&(offset, _, _)| offset);
+            data.write_to(&mut PatchingWriter {
+                inner: file,
+                position: 0,
+                patches: &patches,
+            })?;
+        }
+        None => data.write_to(file)?,
+    }
+    Ok(())
+}
+
+/// Write the contents of `definitions`, each followed by the padding to the next one's alignment,
+/// filled with `fill`
+fn write_definitions<T: Write>(
+    file: &mut T,
+    definitions: &[Definition],
+    segment: &SegmentBuilder,
+    ctx: Ctx,
+    fill: u8,
+) -> Result<(), Error> {
+    for def in definitions {
+        let addends = segment.implicit_addends.get(def.name);
+        write_contents(file, def.data, addends, ctx)?;
+
+        if let Some(&align_pad) = segment.align_pad_map.get(def.name) {
+            for _ in 0..align_pad {
+                file.write_all(&[fill])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write `pad` zero bytes of padding between sections
+fn write_pad<T: Write>(file: &mut T, pad: u64) -> Result<(), Error> {
+    for _ in 0..pad {
+        file.iowrite(0u8)?;
+    }
+    Ok(())
+}
+
+/// The distinct dynamic libraries the artifact's imports are found in, in order of first use
+fn dylibs(artifact: &Artifact) -> Vec<&str> {
+    let mut dylibs = Vec::new();
+    for (import, _) in artifact.emitted_imports() {
+        if let Some(library) = artifact.import_library(import) {
+            if !dylibs.contains(&library) {
+                dylibs.push(library);
+            }
+        }
+    }
+    dylibs
+}
+
 // FIXME: this should actually return a runtime error if we encounter a from.decl to.decl pair which we don't explicitly match on
-fn build_relocations(segment: &mut SegmentBuilder, artifact: &Artifact, symtab: &SymbolTable) {
+/// Whether `reloc` is a relocation type of `architecture`
+fn is_valid_reloc(architecture: Architecture, reloc: u32) -> bool {
+    use goblin::mach::relocation::{ARM64_RELOC_ADDEND, X86_64_RELOC_TLV};
+    match architecture {
+        Architecture::X86_64 => reloc <= u32::from(X86_64_RELOC_TLV),
+        Architecture::Aarch64(_) => reloc <= u32::from(ARM64_RELOC_ADDEND),
+        // the type is 4 bits wide
+        _ => reloc < 16,
+    }
+}
+
+fn build_relocations(
+    segment: &mut SegmentBuilder,
+    artifact: &Artifact,
+    symtab: &SymbolTable,
+    stubs: &[&str],
+) -> Result<(), Error> {
     use goblin::mach::relocation::{
-        R_ABS, X86_64_RELOC_BRANCH, X86_64_RELOC_GOT_LOAD, X86_64_RELOC_SIGNED,
-        X86_64_RELOC_UNSIGNED,
+        ARM64_RELOC_UNSIGNED, GENERIC_RELOC_VANILLA, R_ABS, X86_64_RELOC_BRANCH, X86_64_RELOC_GOT,
+        X86_64_RELOC_GOT_LOAD, X86_64_RELOC_SIGNED, X86_64_RELOC_SIGNED_1, X86_64_RELOC_SIGNED_2,
+        X86_64_RELOC_SIGNED_4, X86_64_RELOC_TLV, X86_64_RELOC_UNSIGNED,
     };
-    let text_idx = segment.sections.get_full("__text").unwrap().0;
-    let data_idx = segment.sections.get_full("__data").unwrap().0;
     debug!("Generating relocations");
     for link in artifact.links() {
         debug!(
             "Import links for: from {} to {} at {:#x} with {:?}",
             link.from.name, link.to.name, link.at, link.reloc
         );
+        let unsupported = |reason: String| ArtifactError::UnsupportedRelocation {
+            from: link.from.name.to_string(),
+            to: link.to.name.to_string(),
+            reason,
+        };
+        let missing_symbol = || ArtifactError::Undeclared(link.to.name.to_string());
+        // the contents of a thread-local variable are its initial value, not its descriptor
+        let from_symbol = match link.from.decl {
+            Decl::Defined(DefinedDecl::Data(d)) if d.is_tls() => tlv_init_name(link.from.name),
+            _ => link.from.name.to_string(),
+        };
+        let architecture = artifact.target.architecture;
+        let from_data = if let Decl::Defined(decl) = link.from.decl {
+            decl.is_data()
+        } else {
+            false
+        };
+        match link.reloc {
+            // pointers in data are absolute on every architecture, but code is only understood
+            // for x86_64
+            Reloc::Auto if from_data => {}
+            Reloc::Auto
+            | Reloc::Call
+            | Reloc::GotLoad
+            | Reloc::RelaxableGotLoad { .. }
+            | Reloc::PcRelative { .. }
+                if architecture != Architecture::X86_64 =>
+            {
+                return Err(unsupported(format!(
+                    "{:?} relocations are only supported for x86_64, not {}",
+                    link.reloc, architecture
+                ))
+                .into());
+            }
+            Reloc::PcRelative { trailing } if ![0, 1, 2, 4].contains(&trailing) => {
+                return Err(unsupported(format!(
+                    "PC-relative relocations cannot be followed by {} bytes",
+                    trailing
+                ))
+                .into());
+            }
+            Reloc::Scattered { .. } if make_ctx(&artifact.target).is_big() => {
+                return Err(unsupported(
+                    "scattered relocations are not supported by 64-bit Mach-O".to_string(),
+                )
+                .into());
+            }
+            Reloc::Scattered { .. } if link.to.decl.is_import() || link.to.decl.is_common() => {
+                return Err(unsupported(
+                    "scattered relocations must target a definition".to_string(),
+                )
+                .into());
+            }
+            Reloc::Raw { reloc, .. } | Reloc::Scattered { reloc, .. }
+                if !is_valid_reloc(architecture, reloc) =>
+            {
+                return Err(unsupported(format!(
+                    "{} is not a valid {} relocation",
+                    reloc, architecture
+                ))
+                .into());
+            }
+            Reloc::Raw { addend, .. } if addend != 0 => {
+                return Err(unsupported(format!(
+                    "raw relocations cannot have addend {}, since Mach-O addends are implicit",
+                    addend
+                ))
+                .into());
+            }
+            Reloc::Debug { size, .. } if size != 4 && size != 8 => {
+                return Err(unsupported(format!(
+                    "debug relocations must be 4 or 8 bytes, not {}",
+                    size
+                ))
+                .into());
+            }
+            Reloc::Debug { .. } if !link.from.decl.is_section() => {
+                return Err(unsupported(format!(
+                    "debug relocations must be from a section, not {}",
+                    link.from.name
+                ))
+                .into());
+            }
+            Reloc::Absolute { size, .. } if size != 4 && size != 8 => {
+                return Err(unsupported(format!(
+                    "absolute relocations must be 4 or 8 bytes, not {}",
+                    size
+                ))
+                .into());
+            }
+            Reloc::Absolute { .. } if link.to.decl.is_section() => {
+                return Err(unsupported(
+                    "absolute relocations to sections are not supported by Mach-O".to_string(),
+                )
+                .into());
+            }
+            _ => {}
+        }
+        if link.from.decl.is_section()
+            && segment.sections[link.from.name].sectname == EH_FRAME_SECTNAME
+        {
+            match (link.reloc, link.to.decl) {
+                // FDEs refer to their functions pc-relatively, and ld64 finds each function by the
+                // address this resolves to, so no relocation is needed
+                (Reloc::Auto, Decl::Defined(decl)) if !decl.is_common() => {
+                    let target = if link.to.decl.is_section() {
+                        segment.sections[link.to.name].addr
+                    } else {
+                        symtab
+                            .address(link.to.name)
+                            .expect("defined symbol has an address")
+                    };
+                    let place = segment.sections[link.from.name].addr + link.at;
+                    segment
+                        .implicit_addends
+                        .entry(link.from.name.to_string())
+                        .or_default()
+                        .push((link.at, 4, (target as i64 - place as i64) as i32));
+                    continue;
+                }
+                // references to imports, like personality routines, and to common symbols, which
+                // have no address yet, are indirect through the GOT
+                (Reloc::Auto, _) | (Reloc::GotLoad, _) | (Reloc::RelaxableGotLoad { .. }, _) => {
+                    match symtab.index(link.to.name) {
+                        Some(to_symbol_index) => {
+                            let builder =
+                                RelocationBuilder::new(to_symbol_index, link.at, X86_64_RELOC_GOT)
+                                    .size(4);
+                            segment.sections[link.from.name]
+                                .relocations
+                                .push(builder.create());
+                        }
+                        _ => return Err(missing_symbol().into()),
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+        }
         let (absolute, reloc) = match link.reloc {
             Reloc::Auto => {
                 // NB: we currently deduce the meaning of our relocation from from decls -> to decl relocations
@@ -964,31 +2579,64 @@ fn build_relocations(segment: &mut SegmentBuilder, artifact: &Artifact, symtab:
                     (Decl::Defined(DefinedDecl::Section(s)), _)
                         if s.kind() == SectionKind::Debug =>
                     {
-                        panic!("must use Reloc::Debug for debug section links")
+                        return Err(unsupported(
+                            "links from debug sections must use Reloc::Debug".to_string(),
+                        )
+                        .into());
                     }
                     // only debug sections should link to debug sections
                     (_, Decl::Defined(DefinedDecl::Section(s)))
                         if s.kind() == SectionKind::Debug =>
                     {
-                        panic!("invalid DebugSection link")
+                        return Err(unsupported(
+                            "only debug sections can link to debug sections".to_string(),
+                        )
+                        .into());
                     }
 
                     // from/to custom section
                     (Decl::Defined(DefinedDecl::Section(_)), _)
                     | (_, Decl::Defined(DefinedDecl::Section(_))) => {
-                        panic!("relocations are not yet supported for custom sections")
+                        return Err(unsupported(
+                            "Auto relocations are not yet supported for custom sections"
+                                .to_string(),
+                        )
+                        .into());
                     }
 
-                    // from data object
-                    (Decl::Defined(DefinedDecl::Data { .. }), _) => (true, X86_64_RELOC_UNSIGNED),
+                    // from data object: an absolute pointer to data, or to a function as in a
+                    // function pointer table
+                    (Decl::Defined(DefinedDecl::Data { .. }), _) => {
+                        let unsigned = match architecture {
+                            Architecture::X86_64 => X86_64_RELOC_UNSIGNED,
+                            Architecture::Aarch64(_) => ARM64_RELOC_UNSIGNED,
+                            _ => GENERIC_RELOC_VANILLA,
+                        };
+                        (true, unsigned)
+                    }
 
                     // from function
                     (Decl::Defined(DefinedDecl::Function { .. }), to) => match to {
                         Decl::Defined(DefinedDecl::Function { .. }) => (false, X86_64_RELOC_BRANCH),
                         Decl::Import(ImportKind::Function) => (false, X86_64_RELOC_BRANCH),
 
+                        // thread-local variables are accessed through their descriptors
+                        Decl::Defined(DefinedDecl::Data(d)) if d.is_tls() => {
+                            (false, X86_64_RELOC_TLV)
+                        }
+
                         Decl::Defined(DefinedDecl::Data { .. }) => (false, X86_64_RELOC_SIGNED),
+                        // code at a fixed address refers to imported data directly, not through
+                        // the GOT
+                        Decl::Import(ImportKind::Data)
+                            if artifact.relocation_model == RelocationModel::Static =>
+                        {
+                            (false, X86_64_RELOC_SIGNED)
+                        }
                         Decl::Import(ImportKind::Data) => (false, X86_64_RELOC_GOT_LOAD),
+                        // the linker defines section boundaries in the image, so code refers
+                        // to them directly
+                        Decl::Import(ImportKind::SectionBoundary) => (false, X86_64_RELOC_SIGNED),
 
                         // handled above
                         Decl::Defined(DefinedDecl::Section { .. }) => unreachable!(),
@@ -999,49 +2647,584 @@ fn build_relocations(segment: &mut SegmentBuilder, artifact: &Artifact, symtab:
                     }
                 }
             }
-            Reloc::Raw { reloc, addend } => {
-                debug_assert!(reloc <= u8::max_value() as u32);
-                assert!(addend == 0);
-                match reloc as u8 {
-                    R_ABS => (true, R_ABS),
-                    reloc => (false, reloc),
+            Reloc::Call => (false, X86_64_RELOC_BRANCH),
+            Reloc::GotLoad | Reloc::RelaxableGotLoad { .. } => (false, X86_64_RELOC_GOT_LOAD),
+            // the displacement is relative to the end of the instruction
+            Reloc::PcRelative { trailing } => match trailing {
+                1 => (false, X86_64_RELOC_SIGNED_1),
+                2 => (false, X86_64_RELOC_SIGNED_2),
+                4 => (false, X86_64_RELOC_SIGNED_4),
+                _ => (false, X86_64_RELOC_SIGNED),
+            },
+            Reloc::Raw { reloc, .. } => match reloc as u8 {
+                R_ABS => (true, R_ABS),
+                reloc => (false, reloc),
+            },
+            Reloc::Scattered { reloc, addend } => {
+                let (section_idx, base_offset) = if link.from.decl.is_section() {
+                    (segment.sections.get_full(link.from.name).unwrap().0, 0)
+                } else {
+                    let section_idx = symtab
+                        .section(&from_symbol)
+                        .expect("defined symbol has a section");
+                    let base_offset = symtab
+                        .offset(&from_symbol)
+                        .expect("defined symbol has an offset");
+                    (section_idx, base_offset)
+                };
+                let target = if link.to.decl.is_section() {
+                    segment.sections[link.to.name].addr
+                } else {
+                    symtab
+                        .address(link.to.name)
+                        .expect("defined symbol has an address")
+                };
+                if base_offset + link.at >= 1 << 24 {
+                    return Err(ArtifactError::RelocationOverflow {
+                        field: "the 24-bit r_address of a scattered relocation",
+                        value: base_offset + link.at,
+                    }
+                    .into());
                 }
+                // the relocated contents are the address the relocation refers to, and the
+                // linker finds the target's part of the section by the address in the relocation
+                segment
+                    .implicit_addends
+                    .entry(link.from.name.to_string())
+                    .or_default()
+                    .push((link.at, 4, (target as i64 + i64::from(addend)) as i32));
+                let builder = ScatteredRelocationBuilder::new(
+                    base_offset + link.at,
+                    target as u32,
+                    reloc as u8,
+                );
+                segment
+                    .sections
+                    .get_index_mut(section_idx)
+                    .unwrap()
+                    .1
+                    .relocations
+                    .push(builder.create());
+                continue;
             }
-            Reloc::Debug { size, .. } => {
+            Reloc::Absolute { size, addend } => {
+                if addend != 0 {
+                    segment
+                        .implicit_addends
+                        .entry(link.from.name.to_string())
+                        .or_default()
+                        .push((link.at, size, addend));
+                }
+                let to_symbol_index = symtab.index(link.to.name).ok_or_else(missing_symbol)?;
+                let (section_idx, base_offset) = if link.from.decl.is_section() {
+                    (segment.sections.get_full(link.from.name).unwrap().0, 0)
+                } else {
+                    let section_idx = symtab
+                        .section(&from_symbol)
+                        .expect("defined symbol has a section");
+                    let base_offset = symtab
+                        .offset(&from_symbol)
+                        .expect("defined symbol has an offset");
+                    (section_idx, base_offset)
+                };
+                // the generic, x86_64 and arm64 unsigned relocations are all 0
+                let builder = RelocationBuilder::new(
+                    to_symbol_index,
+                    base_offset + link.at,
+                    X86_64_RELOC_UNSIGNED,
+                )
+                .absolute()
+                .size(size);
+                segment
+                    .sections
+                    .get_index_mut(section_idx)
+                    .unwrap()
+                    .1
+                    .relocations
+                    .push(builder.create());
+                continue;
+            }
+            Reloc::Debug { size, addend } => {
                 if link.to.decl.is_section() {
-                    // TODO: not sure if these are needed for Mach
+                    // offsets into other debug sections are relocated by the section's ordinal,
+                    // and the relocated contents are the address of the offset
+                    let (to_section_idx, _, to_section) = segment
+                        .sections
+                        .get_full(link.to.name)
+                        .expect("section is in the segment");
+                    let target = to_section.addr as i64 + i64::from(addend);
+                    segment
+                        .implicit_addends
+                        .entry(link.from.name.to_string())
+                        .or_default()
+                        .push((link.at, size, target as i32));
+                    let builder =
+                        RelocationBuilder::new(to_section_idx + 1, link.at, X86_64_RELOC_UNSIGNED)
+                            .absolute()
+                            .local()
+                            .size(size);
+                    segment.sections[link.from.name]
+                        .relocations
+                        .push(builder.create());
                 } else {
+                    if addend != 0 {
+                        segment
+                            .implicit_addends
+                            .entry(link.from.name.to_string())
+                            .or_default()
+                            .push((link.at, size, addend));
+                    }
                     match symtab.index(link.to.name) {
                         Some(to_symbol_index) => {
-                            let builder = RelocationBuilder::new(to_symbol_index, link.at, X86_64_RELOC_UNSIGNED).absolute().size(size);
-                            segment.sections[link.from.name].relocations.push(builder.create());
+                            let builder = RelocationBuilder::new(
+                                to_symbol_index,
+                                link.at,
+                                X86_64_RELOC_UNSIGNED,
+                            )
+                            .absolute()
+                            .size(size);
+                            segment.sections[link.from.name]
+                                .relocations
+                                .push(builder.create());
                         }
-                        _ => error!("Import Relocation from {} to {} at {:#x} has a missing symbol. Dumping symtab {:?}", link.from.name, link.to.name, link.at, symtab)
+                        _ => return Err(missing_symbol().into()),
                     }
                 }
                 continue;
             }
         };
-        match (symtab.offset(link.from.name), symtab.index(link.to.name)) {
+        // calls to a stubbed import go through its stub, which is local to this object
+        let stub = stubs.iter().position(|&stub| stub == link.to.name);
+        if let (X86_64_RELOC_BRANCH, Some(i)) = (reloc, stub) {
+            let (stubs_idx, _, stubs_section) = segment
+                .sections
+                .get_full(STUBS_SECTNAME)
+                .expect("stubs are in the segment");
+            let stub_address = stubs_section.addr + i as u64 * STUB_SIZE;
+            // custom sections have no symbol, and are relocated from their start
+            let (section_idx, base_offset, from_address) = if link.from.decl.is_section() {
+                let (section_idx, _, section) = segment.sections.get_full(link.from.name).unwrap();
+                (section_idx, 0, section.addr)
+            } else {
+                let section_idx = symtab
+                    .section(&from_symbol)
+                    .expect("defined symbol has a section");
+                let base_offset = symtab
+                    .offset(&from_symbol)
+                    .expect("defined symbol has an offset");
+                let from_address = symtab
+                    .address(&from_symbol)
+                    .expect("defined symbol has an address");
+                (section_idx, base_offset, from_address)
+            };
+            let displacement = stub_address as i64 - (from_address + link.at + 4) as i64;
+            segment
+                .implicit_addends
+                .entry(link.from.name.to_string())
+                .or_default()
+                .push((link.at, 4, displacement as i32));
+            let builder =
+                RelocationBuilder::new(stubs_idx + 1, base_offset + link.at, reloc).local();
+            segment
+                .sections
+                .get_index_mut(section_idx)
+                .unwrap()
+                .1
+                .relocations
+                .push(builder.create());
+            continue;
+        }
+        match (symtab.offset(&from_symbol), symtab.index(link.to.name)) {
             (Some(base_offset), Some(to_symbol_index)) => {
+                // the symbol's offset in its section already accounts for the alignment padding
+                // before it, so `at` is relative to the definition's contents, wherever in them,
+                // code or embedded data, it lands
                 debug!("{} offset: {}", link.to.name, base_offset + link.at);
                 let builder = RelocationBuilder::new(to_symbol_index, base_offset + link.at, reloc);
-                // NB: we currently associate absolute relocations with data relocations; this may prove
-                // too fragile for future additions; needs analysis
-                if absolute {
-                    segment.sections.get_index_mut(data_idx).unwrap().1.relocations.push(builder.absolute().create());
+                let builder = if absolute {
+                    builder.absolute()
                 } else {
-                    segment.sections.get_index_mut(text_idx).unwrap().1.relocations.push(builder.create());
+                    builder
+                };
+                // the relocation belongs to the section the definition is laid out in
+                let section_idx = symtab
+                    .section(&from_symbol)
+                    .expect("defined symbol has a section");
+                segment
+                    .sections
+                    .get_index_mut(section_idx)
+                    .unwrap()
+                    .1
+                    .relocations
+                    .push(builder.create());
+            }
+            _ => return Err(missing_symbol().into()),
+        }
+    }
+    // each thread-local variable's descriptor is the `_tlv_bootstrap` thunk, a key the runtime
+    // fills in, and a pointer to the variable's initial value
+    let pointer_size = if make_ctx(&artifact.target).is_big() {
+        8
+    } else {
+        4
+    };
+    for def in artifact.definitions() {
+        match def.decl {
+            DefinedDecl::Data(d) if d.is_tls() => {}
+            _ => continue,
+        }
+        let descriptor_offset = symtab
+            .offset(def.name)
+            .expect("thread local has a descriptor");
+        let thunk = symtab.index(TLV_BOOTSTRAP).expect("_tlv_bootstrap symbol");
+        let init = symtab
+            .index(&tlv_init_name(def.name))
+            .expect("thread local has an initial value");
+        let relocations = &mut segment.sections["__thread_vars"].relocations;
+        for &(symbol, offset) in &[(thunk, 0), (init, 2 * pointer_size)] {
+            let builder =
+                RelocationBuilder::new(symbol, descriptor_offset + offset, X86_64_RELOC_UNSIGNED)
+                    .absolute()
+                    .size(pointer_size as u8);
+            relocations.push(builder.create());
+        }
+    }
+    // each stub's jump refers to its lazy pointer
+    if let Some((lazy_pointers_idx, _, _)) = segment.sections.get_full(LAZY_POINTERS_SECTNAME) {
+        for i in 0..stubs.len() as u64 {
+            let builder = RelocationBuilder::new(
+                lazy_pointers_idx + 1,
+                i * STUB_SIZE + 2,
+                X86_64_RELOC_SIGNED,
+            )
+            .local();
+            segment.sections[STUBS_SECTNAME]
+                .relocations
+                .push(builder.create());
+        }
+    }
+    // each compact unwind entry starts with the address of its function
+    for (i, (def, _)) in compact_unwind_entries(artifact).enumerate() {
+        let function = symtab.index(def.name).expect("function has a symbol");
+        let entry_offset = i as u64 * compact_unwind_entry_size(pointer_size);
+        let builder = RelocationBuilder::new(function, entry_offset, X86_64_RELOC_UNSIGNED)
+            .absolute()
+            .size(pointer_size as u8);
+        segment.sections[COMPACT_UNWIND_SECTNAME]
+            .relocations
+            .push(builder.create());
+    }
+    // ld64 emits, and expects, each section's relocations in descending address order; the sort
+    // is stable, so pairs of relocations at the same address stay in order
+    for section in segment.sections.values_mut() {
+        section
+            .relocations
+            .sort_by_key(|reloc| std::cmp::Reverse(relocation_address(reloc)));
+    }
+    Ok(())
+}
+
+fn build(artifact: &Artifact) -> Result<Mach<'_>, Error> {
+    CpuType::try_from(artifact.target.architecture)?;
+    // linked images need load commands faerie does not emit, which must be added instead
+    let has_load_command = |cmds: &[u32]| {
+        artifact
+            .mach_load_commands
+            .iter()
+            .any(|(cmd, _)| cmds.contains(cmd))
+    };
+    let filetype = artifact.mach_filetype;
+    if filetype != MachFileType::Object && !has_load_command(&[LC_DYLD_INFO, LC_DYLD_INFO_ONLY]) {
+        return Err(ArtifactError::MissingLoadCommand {
+            filetype,
+            command: "LC_DYLD_INFO",
+        }
+        .into());
+    }
+    if filetype == MachFileType::Dylib && !has_load_command(&[LC_ID_DYLIB]) {
+        return Err(ArtifactError::MissingLoadCommand {
+            filetype,
+            command: "LC_ID_DYLIB",
+        }
+        .into());
+    }
+    if let Some((sectname, align)) = artifact
+        .mach_section_alignments
+        .iter()
+        .find(|(_, align)| !align.is_power_of_two())
+    {
+        return Err(ArtifactError::InvalidAlignment {
+            name: sectname.clone(),
+            align: *align,
+        }
+        .into());
+    }
+    if let Some(segname) = &artifact.mach_segment_name {
+        if segname.len() > 16 {
+            return Err(ArtifactError::InvalidSegmentName(segname.clone()).into());
+        }
+    }
+    if let Some(&(cmd, _)) = artifact
+        .mach_load_commands
+        .iter()
+        .find(|&&(cmd, _)| [LC_SEGMENT, LC_SEGMENT_64, LC_SYMTAB, LC_DYSYMTAB].contains(&cmd))
+    {
+        return Err(ArtifactError::ReservedLoadCommand(cmd_to_str(cmd)).into());
+    }
+    if let (MachFileType::Object, Some(flags)) = (filetype, artifact.mach_header_flags) {
+        // the other flags describe how a linked image was linked or is to be loaded
+        let invalid = flags & !(MH_NOUNDEFS | MH_SUBSECTIONS_VIA_SYMBOLS);
+        if invalid != 0 {
+            return Err(ArtifactError::InvalidHeaderFlags(invalid).into());
+        }
+    }
+    if let Some(def) = artifact
+        .definitions()
+        .find(|def| def.decl.get_symbol_kind() == Some(SymbolKind::IndirectFunction))
+    {
+        return Err(ArtifactError::UnsupportedSymbol {
+            name: def.name.to_string(),
+            reason: "indirect functions are not supported by Mach-O".to_string(),
+        }
+        .into());
+    }
+    if artifact.target.architecture != Architecture::X86_64 {
+        if let Some(import) = stubbed_imports(artifact).first() {
+            return Err(ArtifactError::UnsupportedSymbol {
+                name: import.to_string(),
+                reason: format!(
+                    "stubs are only supported for x86_64, not {}",
+                    artifact.target.architecture
+                ),
+            }
+            .into());
+        }
+    }
+    for def in artifact.definitions() {
+        if let DefinedDecl::Data(d) = def.decl {
+            // the alignment exponent of a common symbol is 4 bits of its n_desc
+            if def.decl.is_common() && d.get_align().unwrap_or(1) > 1 << 15 {
+                return Err(ArtifactError::UnsupportedSymbol {
+                    name: def.name.to_string(),
+                    reason: "common symbols can be aligned to at most 32768 bytes".to_string(),
                 }
-            },
-            _ => error!("Import Relocation from {} to {} at {:#x} has a missing symbol. Dumping symtab {:?}", link.from.name, link.to.name, link.at, symtab)
+                .into());
+            }
+            if d.is_tls() && !def.symbols.is_empty() {
+                return Err(ArtifactError::UnsupportedSymbol {
+                    name: def.name.to_string(),
+                    reason: "thread-local variables cannot have custom symbols".to_string(),
+                }
+                .into());
+            }
+            match d.get_datatype().literal_size() {
+                // zero-initialized literals are placed in __bss like any other data
+                Some(size) if !def.data.is_zero_init() && def.data.size() as u64 != size => {
+                    return Err(ArtifactError::SizeMismatch {
+                        name: def.name.to_string(),
+                        declared: size,
+                        defined: def.data.size() as u64,
+                    }
+                    .into());
+                }
+                // the literal sections are read-only, and the linker merges identical constants
+                Some(_) if !def.data.is_zero_init() && d.is_writable() => {
+                    return Err(ArtifactError::UnsupportedSymbol {
+                        name: def.name.to_string(),
+                        reason: "literals cannot be writable".to_string(),
+                    }
+                    .into());
+                }
+                _ => {}
+            }
         }
     }
+    // the linker merges literals by their contents alone, so it cannot relocate them
+    for link in artifact.links() {
+        if let Decl::Defined(DefinedDecl::Data(d)) = link.from.decl {
+            if d.get_datatype().literal_size().is_some() {
+                return Err(ArtifactError::UnsupportedRelocation {
+                    from: link.from.name.to_string(),
+                    to: link.to.name.to_string(),
+                    reason: "literals cannot be relocated".to_string(),
+                }
+                .into());
+            }
+        }
+    }
+    Mach::new(&artifact)
+}
+
+pub fn layout(artifact: &Artifact) -> Result<Layout, Error> {
+    let mach = build(artifact)?;
+    Ok(Layout {
+        sections: mach.resolved_sections(),
+        symbols: mach.resolved_symbols(),
+        relocations: mach.resolved_relocations(),
+    })
+}
+
+pub fn estimated_size(artifact: &Artifact) -> Result<u64, Error> {
+    Ok(build(artifact)?.size())
+}
+
+/// The offset of every defined symbol from the start of the segment, i.e. its `n_value`
+pub fn symbol_offsets(artifact: &Artifact) -> Result<BTreeMap<String, u64>, Error> {
+    let mach = build(artifact)?;
+    Ok(mach
+        .symtab
+        .symbols
+        .iter()
+        .filter(|(_, symbol)| symbol.section.is_some())
+        .map(|(&name, symbol)| {
+            let name = mach
+                .symtab
+                .strtable
+                .resolve(name)
+                .expect("symbol name in strtable");
+            (name.to_string(), symbol.offset)
+        })
+        .collect())
+}
+
+pub fn to_bytes(artifact: &Artifact) -> Result<ResolvedObject, Error> {
+    let mach = build(artifact)?;
+    let sections = mach.resolved_sections();
+    let relocations = mach.resolved_relocations();
+    let uuid_offset = mach.uuid_offset() as usize;
+    let hashed = mach.uuid == Some(MachUuid::Hashed);
+    let mut buffer = Cursor::new(Vec::new());
+    mach.write(&mut buffer)?;
+    let mut bytes = buffer.into_inner();
+    if hashed {
+        let uuid = hashed_uuid(&bytes);
+        bytes[uuid_offset..][..16].copy_from_slice(&uuid);
+    }
+    Ok((bytes, sections, relocations))
+}
+
+/// Write the object for `artifact` to `sink` as it is laid out, without buffering all of it; its
+/// UUID must not be hashed, since that is patched in once the whole object is known
+pub fn write_sequentially<T: Write + Seek>(artifact: &Artifact, sink: T) -> Result<(), Error> {
+    let mach = build(artifact)?;
+    debug_assert!(mach.uuid != Some(MachUuid::Hashed));
+    mach.write(sink)
 }
 
-pub fn to_bytes(artifact: &Artifact) -> Result<Vec<u8>, Error> {
-    let mach = Mach::new(&artifact);
+/// A name-based (version 3) UUID derived from the 128-bit FNV-1a hash of `bytes`, which is stable
+/// across Rust versions and platforms, unlike the standard library's hashers
+fn hashed_uuid(bytes: &[u8]) -> [u8; 16] {
+    const OFFSET_BASIS: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+    let hash = bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u128::from(byte)).wrapping_mul(PRIME)
+    });
+    let mut uuid = hash.to_be_bytes();
+    uuid[6] = (uuid[6] & 0x0f) | 0x30;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
+
+pub fn section_contents(artifact: &Artifact) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mach = build(artifact)?;
+    let sections = mach
+        .resolved_sections()
+        .into_iter()
+        .zip(mach.segment.sections.values())
+        .map(|(resolved, section)| (resolved, section.is_zerofill()))
+        .collect::<Vec<_>>();
     let mut buffer = Cursor::new(Vec::new());
     mach.write(&mut buffer)?;
-    Ok(buffer.into_inner())
+    let bytes = buffer.into_inner();
+    Ok(sections
+        .into_iter()
+        .map(|(section, zerofill)| {
+            // zero-fill sections take up no space in the file, and are zeroed when loaded
+            let contents = if zerofill {
+                vec![0; section.size as usize]
+            } else {
+                bytes[section.offset as usize..][..section.size as usize].to_vec()
+            };
+            (section.name, contents)
+        })
+        .collect())
+}
+
+/// Emit each artifact as a Mach-O object for its architecture, and wrap the objects in a fat
+/// (universal) object, e.g. so that a single object can be linked for both x86_64 and arm64
+///
+/// Every artifact must target the architecture it is paired with, and no two may share an
+/// architecture. Like `lipo`, each object is aligned to the page size of its architecture.
+pub fn to_fat_bytes(artifacts: &[(Architecture, &Artifact)]) -> Result<Vec<u8>, Error> {
+    if artifacts.is_empty() {
+        return Err(ArtifactError::EmptyFatObject.into());
+    }
+    let mut slices = Vec::with_capacity(artifacts.len());
+    for (index, &(architecture, artifact)) in artifacts.iter().enumerate() {
+        if artifact.target.architecture != architecture {
+            return Err(ArtifactError::ArchitectureMismatch {
+                name: artifact.name.clone(),
+                target: artifact.target.architecture,
+                architecture,
+            }
+            .into());
+        }
+        if artifacts[..index]
+            .iter()
+            .any(|&(other, _)| other == architecture)
+        {
+            return Err(ArtifactError::DuplicateArchitecture(architecture).into());
+        }
+        artifact.check_valid()?;
+        let (bytes, _, _) = to_bytes(artifact)?;
+        // the fat header repeats the cpu type and subtype of the object's own header
+        let header: Header = bytes.pread_with(0, make_ctx(&artifact.target))?;
+        let align = match architecture {
+            Architecture::Aarch64(_) => 14,
+            _ => 12,
+        };
+        slices.push((header, align, bytes));
+    }
+
+    let mut offset = (SIZEOF_FAT_HEADER + slices.len() * SIZEOF_FAT_ARCH) as u64;
+    let mut fat_arches = Vec::with_capacity(slices.len());
+    for (header, align, bytes) in &slices {
+        offset = (offset + (1 << align) - 1) & !((1 << align) - 1);
+        let fat_arch = FatArch {
+            cputype: header.cputype,
+            cpusubtype: header.cpusubtype,
+            offset: u32::try_from(offset).map_err(|_| ArtifactError::RelocationOverflow {
+                field: "the 32-bit offset of an object in a fat object",
+                value: offset,
+            })?,
+            size: u32::try_from(bytes.len()).map_err(|_| ArtifactError::RelocationOverflow {
+                field: "the 32-bit size of an object in a fat object",
+                value: bytes.len() as u64,
+            })?,
+            align: *align,
+        };
+        offset += bytes.len() as u64;
+        fat_arches.push(fat_arch);
+    }
+
+    // the fat header and its architectures are always big endian
+    let mut buffer = vec![0; offset as usize];
+    buffer.pwrite_with(
+        FatHeader {
+            magic: FAT_MAGIC,
+            nfat_arch: slices.len() as u32,
+        },
+        0,
+        scroll::BE,
+    )?;
+    for (index, fat_arch) in fat_arches.iter().enumerate() {
+        buffer.pwrite_with(
+            *fat_arch,
+            SIZEOF_FAT_HEADER + index * SIZEOF_FAT_ARCH,
+            scroll::BE,
+        )?;
+        let (_, _, bytes) = &slices[index];
+        buffer[fat_arch.offset as usize..][..bytes.len()].copy_from_slice(bytes);
+    }
+    Ok(buffer)
 }
@@ -0,0 +1,150 @@
+//! A higher-level interface to an artifact, in which every symbol is referred to by the handle
+//! returned when it is added, rather than by name, so that a link can only refer to a symbol
+//! which has been declared.
+
+use failure::Error;
+use std::collections::{BTreeMap, HashMap};
+use target_lexicon::Triple;
+
+use crate::artifact::{Artifact, Data, Decl, ImportKind, Link, Reloc};
+
+/// A handle to a symbol added to an [`Object`](struct.Object.html)
+///
+/// A handle is only meaningful to the object which returned it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SymbolId(usize);
+
+/// An artifact built from symbols which are declared and defined in one step, and linked by
+/// their handles
+#[derive(Debug)]
+pub struct Object {
+    artifact: Artifact,
+    /// The name of each symbol, indexed by its handle
+    names: Vec<String>,
+    /// The handle of each symbol, by name
+    handles: HashMap<String, SymbolId>,
+}
+
+impl Object {
+    /// Create a new object for `target` with `name`
+    pub fn new(target: Triple, name: String) -> Self {
+        Object::from(Artifact::new(target, name))
+    }
+
+    /// Declare and define a global function `name` with the machine code `code`
+    pub fn add_function<T: AsRef<str>>(
+        &mut self,
+        name: T,
+        code: Vec<u8>,
+    ) -> Result<SymbolId, Error> {
+        self.add(name, Decl::function().global(), code)
+    }
+
+    /// Declare and define a global, read-only data object `name` with the contents `data`
+    pub fn add_data<T: AsRef<str>>(&mut self, name: T, data: Vec<u8>) -> Result<SymbolId, Error> {
+        self.add(name, Decl::data().global(), data)
+    }
+
+    /// Declare `name` with `decl`, and define it with `data`. If it cannot be defined, a
+    /// declaration this made is undone.
+    pub fn add<T: AsRef<str>, D: Into<Decl>, E: Into<Data>>(
+        &mut self,
+        name: T,
+        decl: D,
+        data: E,
+    ) -> Result<SymbolId, Error> {
+        let name = name.as_ref();
+        let declared = self.artifact.is_declared(name);
+        self.artifact.declare(name, decl)?;
+        if let Err(error) = self
+            .artifact
+            .define_with_symbols(name, data, BTreeMap::new())
+        {
+            if !declared {
+                self.artifact.undeclare(name)?;
+            }
+            return Err(error.into());
+        }
+        Ok(self.handle(name))
+    }
+
+    /// Import `name`, of `kind`, from another object
+    pub fn import<T: AsRef<str>>(&mut self, name: T, kind: ImportKind) -> Result<SymbolId, Error> {
+        self.artifact.import(name.as_ref(), kind)?;
+        Ok(self.handle(name))
+    }
+
+    /// Link a call at `at` in `from` to `to`, with a `Reloc::Call` relocation
+    pub fn call(&mut self, from: SymbolId, to: SymbolId, at: u64) -> Result<(), Error> {
+        self.link_with(from, to, at, Reloc::Call)
+    }
+
+    /// Link a reference at `at` in `from` to `to`, with the relocation `Reloc::Auto` selects
+    pub fn link(&mut self, from: SymbolId, to: SymbolId, at: u64) -> Result<(), Error> {
+        self.link_with(from, to, at, Reloc::Auto)
+    }
+
+    /// Link a reference at `at` in `from` to `to`, with the relocation `reloc`
+    pub fn link_with(
+        &mut self,
+        from: SymbolId,
+        to: SymbolId,
+        at: u64,
+        reloc: Reloc,
+    ) -> Result<(), Error> {
+        let (from, to) = (name(&self.names, from)?, name(&self.names, to)?);
+        self.artifact.link_with(Link { from, to, at }, reloc)
+    }
+
+    /// The name of the symbol `id` refers to
+    pub fn name(&self, id: SymbolId) -> Result<&str, Error> {
+        name(&self.names, id)
+    }
+
+    /// The artifact the symbols are added to
+    pub fn artifact(&self) -> &Artifact {
+        &self.artifact
+    }
+
+    /// Consume the object, returning the artifact the symbols were added to
+    pub fn into_artifact(self) -> Artifact {
+        self.artifact
+    }
+
+    /// Emit the object as a `Vec` of bytes, in the binary format of its target
+    pub fn emit(&self) -> Result<Vec<u8>, Error> {
+        self.artifact.emit()
+    }
+
+    /// The handle of the symbol `name`, which has just been declared
+    fn handle<T: AsRef<str>>(&mut self, name: T) -> SymbolId {
+        let name = name.as_ref();
+        if let Some(&id) = self.handles.get(name) {
+            return id;
+        }
+        let id = SymbolId(self.names.len());
+        self.names.push(name.to_string());
+        self.handles.insert(name.to_string(), id);
+        id
+    }
+}
+
+/// The name of the symbol `id` refers to, in `names`
+fn name(names: &[String], id: SymbolId) -> Result<&str, Error> {
+    names
+        .get(id.0)
+        .map(String::as_str)
+        .ok_or_else(|| format_err!("{:?} is not a symbol of this object", id))
+}
+
+impl From<Artifact> for Object {
+    /// Add symbols to `artifact`, which may already have some; they are only referred to by name
+    /// until they are added through the object, which returns their handles
+    fn from(artifact: Artifact) -> Self {
+        Object {
+            artifact,
+            names: Vec::new(),
+            handles: HashMap::new(),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+extern crate faerie;
+extern crate goblin;
+#[macro_use]
+extern crate target_lexicon;
+
+use faerie::{to_archive_bytes, to_archive_bytes_as, ArchiveFormat, Artifact, Decl};
+use goblin::archive::Archive;
+use std::str::FromStr;
+
+/// Two artifacts for `target`: one with a short name defining `f`, and one with a name too long
+/// for a member header defining `g` and `h`
+fn artifacts(target: &str) -> (Artifact, Artifact) {
+    let mut short = Artifact::new(triple!(target), "f.o".into());
+    short
+        .declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    short
+        .declare_with("local", Decl::function(), vec![0xc3])
+        .expect("can declare and define local");
+    let mut long = Artifact::new(triple!(target), "a very long object name.o".into());
+    long.declare_with("g", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define g");
+    long.declare_with("h", Decl::data().global(), vec![1, 2, 3, 4])
+        .expect("can declare and define h");
+    (short, long)
+}
+
+/// Check that `bytes` is an archive of `short` and `long`, indexed by `index_type`, whose symbols
+/// are named with `prefix`
+fn check(bytes: &[u8], short: &Artifact, long: &Artifact, index_type: &str, prefix: &str) {
+    let archive = Archive::parse(bytes).expect("can parse archive");
+    assert!(format!("{:?}", archive).contains(&format!("index_type: {}", index_type)));
+    let mut members = archive.members();
+    members.sort();
+    assert_eq!(members, ["a very long object name.o", "f.o"]);
+    for artifact in &[short, long] {
+        let contents = archive
+            .extract(&artifact.name, bytes)
+            .expect("can extract member");
+        assert_eq!(contents, &artifact.emit().expect("can emit artifact")[..]);
+    }
+    let symbol = |name: &str| archive.member_of_symbol(&format!("{}{}", prefix, name));
+    assert_eq!(symbol("f"), Some("f.o"));
+    assert_eq!(symbol("g"), Some("a very long object name.o"));
+    assert_eq!(symbol("h"), Some("a very long object name.o"));
+    assert_eq!(symbol("local"), None);
+}
+
+#[test]
+fn gnu_archive() {
+    let (short, long) = artifacts("x86_64-unknown-unknown-unknown-elf");
+    let bytes = to_archive_bytes(&[&short, &long]).expect("can emit archive");
+    check(&bytes, &short, &long, "SysV", "");
+    // the long name is in the `//` member, and the symbol index is first
+    assert!(bytes[8..].starts_with(b"/               "));
+    assert!(bytes.windows(16).any(|name| name == b"//              "));
+}
+
+#[test]
+fn bsd_archive() {
+    let (short, long) = artifacts("x86_64-apple-darwin");
+    let bytes = to_archive_bytes(&[&short, &long]).expect("can emit archive");
+    check(&bytes, &short, &long, "BSD", "_");
+    assert!(bytes[8..].starts_with(b"#1/20           "));
+    assert_eq!(&bytes[68..84], b"__.SYMDEF SORTED");
+    // ld64 expects the contents of members with long names to be 8-byte aligned
+    let archive = Archive::parse(&bytes).expect("can parse archive");
+    let member = archive
+        .get("a very long object name.o")
+        .expect("long member exists");
+    assert_eq!(member.offset % 8, 0);
+}
+
+#[test]
+fn archive_format_override() {
+    let (short, long) = artifacts("x86_64-apple-darwin");
+    let bytes =
+        to_archive_bytes_as(&[&short, &long], ArchiveFormat::Gnu).expect("can emit archive");
+    check(&bytes, &short, &long, "SysV", "_");
+
+    let (short, long) = artifacts("x86_64-unknown-unknown-unknown-elf");
+    let bytes =
+        to_archive_bytes_as(&[&short, &long], ArchiveFormat::Bsd).expect("can emit archive");
+    check(&bytes, &short, &long, "BSD", "");
+
+    assert_eq!(
+        ArchiveFormat::for_os(triple!("aarch64-apple-ios").operating_system),
+        ArchiveFormat::Bsd
+    );
+    assert!(to_archive_bytes(&[]).is_err());
+}
+
+#[test]
+fn path_member_names() {
+    for target in &["x86_64-unknown-unknown-unknown-elf", "x86_64-apple-darwin"] {
+        let mut obj = Artifact::new(triple!(target), "out/foo.o".into());
+        obj.declare_with("f", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define f");
+        let mut nested = Artifact::new(triple!(target), "out/nested/a longer name.o".into());
+        nested
+            .declare_with("g", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define g");
+
+        // members are named after the final component of the artifact's name
+        let bytes = to_archive_bytes(&[&obj, &nested]).expect("can emit archive");
+        let archive = Archive::parse(&bytes).expect("can parse archive");
+        let mut members = archive.members();
+        members.sort();
+        assert_eq!(members, ["a longer name.o", "foo.o"]);
+        let contents = archive
+            .extract("foo.o", &bytes)
+            .expect("can extract member");
+        assert_eq!(contents, &obj.emit().expect("can emit artifact")[..]);
+    }
+
+    let mut obj = Artifact::new(
+        triple!("x86_64-unknown-unknown-unknown-elf"),
+        "out/..".into(),
+    );
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    assert!(to_archive_bytes(&[&obj]).is_err());
+}
@@ -225,3 +225,1483 @@ fn invalid_bss() {
         .unwrap();
     assert!(artifact.define_zero_init("my_section", 100).is_err());
 }
+
+#[test]
+fn undeclare() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define f");
+    obj.declare("g", Decl::function_import())
+        .expect("can declare g");
+    obj.declare("h", Decl::data()).expect("can declare h");
+    obj.link(Link {
+        from: "f",
+        to: "g",
+        at: 1,
+    })
+    .expect("can link from f to g");
+
+    // referenced by a link
+    assert!(obj.undeclare("g").is_err());
+    // never declared
+    assert!(obj.undeclare("i").is_err());
+
+    obj.unlink(Link {
+        from: "f",
+        to: "g",
+        at: 1,
+    })
+    .expect("can unlink from f to g");
+    obj.undeclare("g").expect("can undeclare unreferenced g");
+    assert_eq!(obj.imports().count(), 0);
+
+    // declared but undefined symbols block emitting until removed
+    assert!(obj.emit().is_err());
+    obj.undeclare("h").expect("can undeclare undefined h");
+    assert!(obj.undefined_symbols().is_empty());
+
+    // defined
+    assert!(obj.undeclare("f").is_err());
+
+    obj.emit().expect("can emit");
+}
+
+#[test]
+fn call_and_got_load_same_import() {
+    use goblin::{mach::Mach, Object};
+    use target_lexicon::BinaryFormat;
+
+    let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    obj.import("f", ImportKind::Function)
+        .expect("can import f as function");
+    obj.import("f", ImportKind::Data)
+        .expect("can also import f as data");
+    assert_eq!(obj.imports().count(), 1);
+    // call f; mov f@GOTPCREL(%rip), %rax
+    obj.declare_with(
+        "main",
+        Decl::function().global(),
+        vec![0xe8, 0, 0, 0, 0, 0x48, 0x8b, 0x05, 0, 0, 0, 0],
+    )
+    .expect("can declare and define main");
+    obj.link_with(
+        Link {
+            from: "main",
+            to: "f",
+            at: 1,
+        },
+        Reloc::Call,
+    )
+    .expect("can link call");
+    obj.link_with(
+        Link {
+            from: "main",
+            to: "f",
+            at: 8,
+        },
+        Reloc::GotLoad,
+    )
+    .expect("can link got load");
+
+    let elf = obj.emit_as(BinaryFormat::Elf).unwrap();
+    match Object::parse(&elf).unwrap() {
+        Object::Elf(elf) => {
+            let mut relocs = elf
+                .shdr_relocs
+                .iter()
+                .flat_map(|(_, relocs)| relocs.iter())
+                .map(|reloc| {
+                    let sym = elf.syms.get(reloc.r_sym).unwrap();
+                    (reloc.r_offset, reloc.r_type, &elf.strtab[sym.st_name])
+                })
+                .collect::<Vec<_>>();
+            relocs.sort();
+            use goblin::elf::reloc::{R_X86_64_GOTPCREL, R_X86_64_PLT32};
+            assert_eq!(
+                relocs,
+                vec![(1, R_X86_64_PLT32, "f"), (8, R_X86_64_GOTPCREL, "f")]
+            );
+        }
+        _ => panic!("emitted as ELF but did not parse as ELF"),
+    }
+
+    let mach = obj.emit_as(BinaryFormat::Macho).unwrap();
+    match Object::parse(&mach).unwrap() {
+        Object::Mach(Mach::Binary(mach)) => {
+            use goblin::mach::relocation::{X86_64_RELOC_BRANCH, X86_64_RELOC_GOT_LOAD};
+            let symbols = mach
+                .symbols
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|sym| sym.unwrap().0)
+                .collect::<Vec<_>>();
+            let mut relocs = mach
+                .relocations()
+                .unwrap()
+                .into_iter()
+                .flat_map(|(_, relocs, _)| relocs)
+                .map(|reloc| {
+                    let reloc = reloc.unwrap();
+                    (
+                        reloc.r_address,
+                        reloc.r_type(),
+                        symbols[reloc.r_symbolnum()],
+                    )
+                })
+                .collect::<Vec<_>>();
+            relocs.sort();
+            assert_eq!(
+                relocs,
+                vec![
+                    (1, X86_64_RELOC_BRANCH, "_f"),
+                    (8, X86_64_RELOC_GOT_LOAD, "_f")
+                ]
+            );
+        }
+        _ => panic!("emitted as MACHO but did not parse as MACHO"),
+    }
+}
+
+#[test]
+fn validate_reports_dangling_links() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("a", Decl::function().global())
+        .expect("can declare a");
+    obj.declare_with("b", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define b");
+    obj.declare("c", Decl::data_import())
+        .expect("can declare c");
+    obj.link(Link {
+        from: "b",
+        to: "a",
+        at: 1,
+    })
+    .expect("can link from b to a");
+    assert!(obj
+        .link(Link {
+            from: "b",
+            to: "nope",
+            at: 1,
+        })
+        .is_err());
+
+    // `a` is linked to, but will never be defined
+    let errors = obj.validate().expect_err("a is dangling");
+    assert_eq!(errors.len(), 1);
+    match errors[0] {
+        ArtifactError::Undefined(ref name) => assert_eq!(name, "a"),
+        ref e => panic!("unexpected error {}", e),
+    }
+    assert!(obj.emit().is_err());
+
+    obj.define("a", vec![0xc3]).expect("can define a");
+    obj.validate().expect("artifact is valid");
+    obj.emit().expect("can emit");
+}
+
+#[test]
+fn imports_report_their_kind() {
+    let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    obj.declare("f", Decl::function_import())
+        .expect("can declare f");
+    obj.declare("d", Decl::data_import())
+        .expect("can declare d");
+    let mut imports = obj.imports().collect::<Vec<_>>();
+    imports.sort();
+    assert_eq!(
+        imports,
+        vec![("d", ImportKind::Data), ("f", ImportKind::Function)]
+    );
+}
+
+#[test]
+fn clear_and_reuse() {
+    let target = triple!("x86_64-unknown-unknown-unknown-elf");
+    let fill = |obj: &mut Artifact, name: &str| {
+        obj.declare(name, Decl::function_import())
+            .expect("can declare import");
+        obj.declare_with("main", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+            .expect("can declare and define main");
+        obj.link(Link {
+            from: "main",
+            to: name,
+            at: 1,
+        })
+        .expect("can link");
+    };
+
+    let mut obj = Artifact::new(target.clone(), "t.o".into());
+    fill(&mut obj, "f");
+    obj.clear();
+    assert_eq!(obj.imports().count(), 0);
+    assert!(obj.undefined_symbols().is_empty());
+    let empty = Artifact::new(target.clone(), "t.o".into());
+    assert_eq!(obj.emit().unwrap(), empty.emit().unwrap());
+
+    // refill with a different import; nothing from before the clear remains
+    fill(&mut obj, "g");
+    let mut fresh = Artifact::new(target, "t.o".into());
+    fill(&mut fresh, "g");
+    assert_eq!(
+        obj.imports().collect::<Vec<_>>(),
+        vec![("g", ImportKind::Function)]
+    );
+    assert_eq!(obj.emit().unwrap(), fresh.emit().unwrap());
+}
+
+#[test]
+fn write_to_path_replaces_atomically() {
+    use std::fs;
+    use std::io::Read;
+
+    let dir = std::env::temp_dir().join(format!("faerie-write-to-path-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("t.o");
+    fs::write(&path, b"stale").unwrap();
+    let mut stale = fs::File::open(&path).unwrap();
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.write_to_path(&path).expect("can write to path");
+    assert_eq!(fs::read(&path).unwrap(), obj.emit().unwrap());
+
+    // the old file was renamed over rather than truncated, so open handles still see it
+    let mut contents = Vec::new();
+    stale.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, b"stale");
+    // and no temporary file is left behind
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    // threads writing the same path each write a whole object of their own
+    fn object(i: usize) -> Artifact {
+        let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+        obj.declare_with("f", Decl::function().global(), vec![0x90; 4096 * (i + 1)])
+            .expect("can declare and define f");
+        obj
+    }
+    let objects = (0..8)
+        .map(|i| object(i).emit().unwrap())
+        .collect::<Vec<_>>();
+    let threads = (0..8)
+        .map(|i| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let obj = object(i);
+                for _ in 0..16 {
+                    obj.write_to_path(&path).expect("can write to path");
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert!(objects.contains(&fs::read(&path).unwrap()));
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn section_kind_of() {
+    use std::collections::BTreeMap;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("f", Decl::function()).unwrap();
+    let mut symbols = BTreeMap::new();
+    symbols.insert("f_entry".to_string(), 1);
+    obj.define_with_symbols("f", Data::Blob(vec![0x90, 0xc3]), symbols)
+        .unwrap();
+    obj.declare_with("d", Decl::data().global(), vec![1; 8])
+        .unwrap();
+    obj.declare_with("s", Decl::cstring(), b"hi\0".to_vec())
+        .unwrap();
+    obj.declare("z", Decl::data().writable()).unwrap();
+    obj.define_zero_init("z", 16).unwrap();
+    obj.declare("c", Decl::data().writable().common()).unwrap();
+    obj.define_zero_init("c", 16).unwrap();
+    obj.declare_with(".debug_info", Decl::section(SectionKind::Debug), vec![0; 4])
+        .unwrap();
+    obj.declare_with("custom", Decl::section(SectionKind::Text), vec![0xc3])
+        .unwrap();
+    obj.declare("undefined", Decl::data()).unwrap();
+    obj.import("imported", ImportKind::Function).unwrap();
+
+    assert_eq!(obj.section_kind_of("f"), Some(SymbolSection::Text));
+    assert_eq!(obj.section_kind_of("f_entry"), Some(SymbolSection::Text));
+    assert_eq!(obj.section_kind_of("d"), Some(SymbolSection::Data));
+    assert_eq!(obj.section_kind_of("s"), Some(SymbolSection::CString));
+    assert_eq!(obj.section_kind_of("z"), Some(SymbolSection::Bss));
+    assert_eq!(obj.section_kind_of("c"), Some(SymbolSection::Common));
+    assert_eq!(
+        obj.section_kind_of(".debug_info"),
+        Some(SymbolSection::Custom(SectionKind::Debug))
+    );
+    assert_eq!(
+        obj.section_kind_of("custom"),
+        Some(SymbolSection::Custom(SectionKind::Text))
+    );
+    assert_eq!(obj.section_kind_of("undefined"), None);
+    assert_eq!(obj.section_kind_of("imported"), None);
+    assert_eq!(obj.section_kind_of("missing"), None);
+}
+
+#[test]
+fn remove() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.import("imported", ImportKind::Function).unwrap();
+    obj.declare_with("kept", Decl::function().global(), vec![0xc3])
+        .unwrap();
+    obj.declare_with(
+        "dead",
+        Decl::function().global(),
+        vec![0xe8, 0, 0, 0, 0, 0xde, 0xad, 0xbe, 0xef],
+    )
+    .unwrap();
+    obj.declare_with("table", Decl::data(), vec![0; 8]).unwrap();
+    for &(from, to, at) in &[("dead", "imported", 1), ("table", "dead", 0)] {
+        obj.link(Link { from, to, at }).unwrap();
+    }
+
+    // still referenced by the table
+    assert!(obj.remove("dead").is_err());
+    obj.remove("table").unwrap();
+    obj.remove("dead").unwrap();
+    assert!(obj.remove("dead").is_err());
+    assert!(obj.remove("missing").is_err());
+
+    let bytes = obj.emit().unwrap();
+    let elf = goblin::elf::Elf::parse(&bytes).unwrap();
+    for name in &["dead", "table"] {
+        assert!(elf.syms.iter().all(|sym| &elf.strtab[sym.st_name] != *name));
+    }
+    assert!(elf
+        .syms
+        .iter()
+        .any(|sym| &elf.strtab[sym.st_name] == "kept"));
+    assert!(elf
+        .section_headers
+        .iter()
+        .all(|shdr| !elf.shdr_strtab[shdr.sh_name].contains("dead")));
+    assert!(bytes.windows(4).all(|w| w != [0xde, 0xad, 0xbe, 0xef]));
+    // the links from the removed function are gone too
+    assert!(elf.shdr_relocs.iter().all(|(_, relocs)| relocs.len() == 0));
+
+    // the name can be declared afresh
+    obj.declare("dead", Decl::data_import()).unwrap();
+}
+
+#[test]
+fn symbol_addresses() {
+    use goblin::mach::Mach;
+    use goblin::Object;
+
+    const BASE: u64 = 0x4000;
+
+    for target in &["x86_64-unknown-unknown-unknown-elf", "x86_64-apple-darwin"] {
+        let mut obj = Artifact::new(
+            target_lexicon::Triple::from_str(target).unwrap(),
+            "t.o".into(),
+        );
+        obj.declare_with("f", Decl::function().global(), vec![0x55, 0x90, 0xc3])
+            .unwrap();
+        obj.declare_with("d", Decl::data().global(), vec![0xd1, 0xd2])
+            .unwrap();
+        obj.declare("z", Decl::data().writable().with_align(Some(64)))
+            .unwrap();
+        obj.define_zero_init("z", 16).unwrap();
+        obj.import("imported", ImportKind::Function).unwrap();
+
+        let bytes = obj.emit().unwrap();
+        let addresses = obj.symbol_addresses(BASE).unwrap();
+        assert_eq!(
+            addresses.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["d", "f", "z"],
+            "{}",
+            target
+        );
+        assert_eq!(addresses["z"] % 64, 0, "{}", target);
+        // the file offset of each symbol's contents, given its offset from BASE
+        let file_offset: Box<dyn Fn(u64) -> usize> = match Object::parse(&bytes).unwrap() {
+            Object::Elf(_) => {
+                // the zero-fill data follows the end of the file
+                assert!(addresses["z"] >= BASE + bytes.len() as u64);
+                Box::new(|offset| offset as usize)
+            }
+            Object::Mach(Mach::Binary(mach)) => {
+                // the addresses are the symbols' values, relative to BASE
+                for (name, nlist) in mach.symbols().map(|symbol| symbol.unwrap()) {
+                    if let Some(&address) = addresses.get(&name[1..]) {
+                        assert_eq!(address, BASE + nlist.n_value, "{}", name);
+                    }
+                }
+                let sections = mach
+                    .segments
+                    .sections()
+                    .flatten()
+                    .map(|section| section.unwrap().0)
+                    .collect::<Vec<_>>();
+                Box::new(move |offset| {
+                    let section = sections
+                        .iter()
+                        .find(|section| {
+                            section.addr <= offset && offset < section.addr + section.size
+                        })
+                        .expect("a section contains the symbol");
+                    (u64::from(section.offset) + offset - section.addr) as usize
+                })
+            }
+            _ => panic!("unexpected object file"),
+        };
+        let contents = |name: &str, len: usize| {
+            let offset = file_offset(addresses[name] - BASE);
+            &bytes[offset..offset + len]
+        };
+        assert_eq!(contents("f", 3), &[0x55, 0x90, 0xc3], "{}", target);
+        assert_eq!(contents("d", 2), &[0xd1, 0xd2], "{}", target);
+    }
+}
+
+#[test]
+fn invalid_alignment() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    for &align in &[3, 0, 24] {
+        let err = obj
+            .declare("d", Decl::data().with_align(Some(align)))
+            .expect_err("alignment must be a power of two");
+        match err {
+            ArtifactError::InvalidAlignment {
+                ref name,
+                align: invalid,
+            } => {
+                assert_eq!(name, "d");
+                assert_eq!(invalid, align);
+            }
+            ref err => panic!("unexpected error {}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Alignment of d must be a nonzero power of two, got {}",
+                align
+            )
+        );
+    }
+    // nothing was declared
+    assert!(obj.undefined_symbols().is_empty());
+
+    obj.declare_with("d", Decl::data().with_align(Some(16)), vec![1; 4])
+        .unwrap();
+    obj.emit().unwrap();
+}
+
+#[test]
+fn relocations_must_match_architecture() {
+    // R_X86_64_PC32
+    const X86_64_PC32: u32 = 2;
+
+    let object = |target: &str, reloc: Reloc| {
+        let mut obj = Artifact::new(
+            target_lexicon::Triple::from_str(target).unwrap(),
+            "t.o".into(),
+        );
+        obj.declare_with("f", Decl::function().global(), vec![0; 8])
+            .unwrap();
+        obj.declare_with("d", Decl::data().global(), vec![0; 8])
+            .unwrap();
+        obj.link_with(
+            Link {
+                from: "f",
+                to: "d",
+                at: 4,
+            },
+            reloc,
+        )
+        .unwrap();
+        obj
+    };
+    let raw = Reloc::Raw {
+        reloc: X86_64_PC32,
+        addend: -4,
+    };
+
+    let err = object("aarch64-unknown-linux-gnu", raw).emit().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Unsupported relocation from f to d: 2 is not a valid aarch64 relocation"
+    );
+    assert!(object("aarch64-unknown-linux-gnu", Reloc::Auto)
+        .emit()
+        .is_err());
+    assert!(object("aarch64-apple-darwin", Reloc::Auto).emit().is_err());
+    assert!(object("aarch64-apple-darwin", Reloc::Call).emit().is_err());
+    // R_AARCH64_ADR_PREL_PG_HI21
+    let adrp = Reloc::Raw {
+        reloc: 275,
+        addend: 0,
+    };
+    object("aarch64-unknown-linux-gnu", adrp).emit().unwrap();
+
+    object("x86_64-unknown-linux-gnu", raw).emit().unwrap();
+    object("x86_64-unknown-linux-gnu", Reloc::Auto)
+        .emit()
+        .unwrap();
+    object("x86_64-apple-darwin", Reloc::Auto).emit().unwrap();
+}
+
+#[test]
+fn estimated_size() {
+    for target in &["x86_64-unknown-unknown-unknown-elf", "x86_64-apple-darwin"] {
+        let mut obj = Artifact::new(
+            target_lexicon::Triple::from_str(target).unwrap(),
+            "t.o".into(),
+        );
+        assert_eq!(
+            obj.estimated_size().unwrap(),
+            obj.emit().unwrap().len() as u64,
+            "{}",
+            target
+        );
+
+        obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0, 0xc3])
+            .unwrap();
+        obj.declare_with("d", Decl::data().writable(), vec![0; 8])
+            .unwrap();
+        obj.declare_with("s", Decl::cstring(), b"str\0".to_vec())
+            .unwrap();
+        obj.declare("z", Decl::data().writable()).unwrap();
+        obj.define_zero_init("z", 16).unwrap();
+        obj.import("imported", ImportKind::Function).unwrap();
+        obj.link(Link {
+            from: "f",
+            to: "imported",
+            at: 1,
+        })
+        .unwrap();
+        obj.link(Link {
+            from: "d",
+            to: "f",
+            at: 0,
+        })
+        .unwrap();
+        assert_eq!(
+            obj.estimated_size().unwrap(),
+            obj.emit().unwrap().len() as u64,
+            "{}",
+            target
+        );
+
+        obj.mach_function_starts = true;
+        obj.declare_with(".debug_str", Decl::section(SectionKind::Debug), vec![1; 5])
+            .unwrap();
+        assert_eq!(
+            obj.estimated_size().unwrap(),
+            obj.emit().unwrap().len() as u64,
+            "{}",
+            target
+        );
+    }
+}
+
+#[test]
+fn emit_in_target_format() {
+    use target_lexicon::BinaryFormat;
+
+    let emit = |target| {
+        let mut obj = Artifact::new(target, "t.o".into());
+        obj.declare_with("f", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define f");
+        obj.emit()
+    };
+
+    let elf = emit(triple!("x86_64-unknown-linux-gnu")).expect("can emit ELF");
+    assert_eq!(&elf[..4], b"\x7fELF");
+    let mach = emit(triple!("x86_64-apple-darwin")).expect("can emit Mach-O");
+    assert_eq!(&mach[..4], &0xfeed_facf_u32.to_le_bytes());
+
+    // COFF is not supported yet
+    let error = emit(triple!("x86_64-pc-windows-msvc")).expect_err("cannot emit COFF");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::UnsupportedFormat(BinaryFormat::Coff)) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+}
+
+#[test]
+fn tentative_definitions_merge() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("x", Decl::data().global().writable())
+        .expect("can declare x");
+    obj.define_zero_init("x", 8).expect("can define x");
+    obj.define_zero_init("x", 16)
+        .expect("can define x again with a larger size");
+    obj.define_zero_init("x", 4)
+        .expect("can define x again with a smaller size");
+    // merging checks the custom symbols as a first definition does
+    let mut symbols = std::collections::BTreeMap::new();
+    symbols.insert(String::new(), 0);
+    match obj.define_with_symbols("x", Data::ZeroInit(8), symbols) {
+        Err(ArtifactError::EmptyName) => {}
+        result => panic!("expected an empty name error, got {:?}", result),
+    }
+
+    // a definition with contents is not tentative
+    match obj.define("x", vec![1, 2, 3, 4]) {
+        Err(ArtifactError::DuplicateDefinition(name)) => assert_eq!(name, "x"),
+        result => panic!("expected a duplicate definition error, got {:?}", result),
+    }
+    obj.declare_with("y", Decl::data().global().writable(), vec![1, 2, 3, 4])
+        .expect("can declare and define y");
+    match obj.define_zero_init("y", 4) {
+        Err(ArtifactError::DuplicateDefinition(name)) => assert_eq!(name, "y"),
+        result => panic!("expected a duplicate definition error, got {:?}", result),
+    }
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let x = elf
+        .syms
+        .iter()
+        .find(|sym| &elf.strtab[sym.st_name] == "x")
+        .expect("x symbol exists");
+    assert_eq!(x.st_size, 16);
+}
+
+#[test]
+fn structured_errors() {
+    use faerie::{Link, Reloc};
+    use target_lexicon::Architecture;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    match obj.define("f", vec![0xc3]) {
+        Err(ArtifactError::Undeclared(name)) => assert_eq!(name, "f"),
+        result => panic!("expected an undeclared error, got {:?}", result),
+    }
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    match obj.define("f", vec![0xc3]) {
+        Err(ArtifactError::DuplicateDefinition(name)) => assert_eq!(name, "f"),
+        result => panic!("expected a duplicate definition error, got {:?}", result),
+    }
+
+    // problems found before emitting are reported together
+    obj.declare("g", Decl::function()).expect("can declare g");
+    obj.link(Link {
+        from: "f",
+        to: "g",
+        at: 1,
+    })
+    .expect("can link f to g");
+    let error = obj
+        .emit()
+        .expect_err("g is undefined and the link is out of bounds");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::Invalid(errors)) => {
+            assert!(errors.iter().any(|error| match error {
+                ArtifactError::Undefined(name) => name == "g",
+                _ => false,
+            }));
+            assert!(errors.iter().any(|error| match error {
+                ArtifactError::LinkOutOfBounds {
+                    from,
+                    at: 1,
+                    size: 1,
+                } => from == "f",
+                _ => false,
+            }));
+        }
+        _ => panic!("unexpected error {}", error),
+    }
+
+    let error = Artifact::new(triple!("wasm32-unknown-unknown-unknown-elf"), "t.o".into())
+        .emit()
+        .expect_err("wasm32 does not exist in ELF");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::UnsupportedArchitecture(Architecture::Wasm32)) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function(), vec![0; 8])
+        .expect("can declare and define f");
+    obj.declare_with("g", Decl::function(), vec![0xc3])
+        .expect("can declare and define g");
+    obj.link_with(
+        Link {
+            from: "f",
+            to: "g",
+            at: 0,
+        },
+        Reloc::Scattered {
+            reloc: 0,
+            addend: 0,
+        },
+    )
+    .expect("can link f to g");
+    let error = obj.emit().expect_err("ELF has no scattered relocations");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::UnsupportedRelocation { from, to, .. }) => {
+            assert_eq!(from, "f");
+            assert_eq!(to, "g");
+        }
+        _ => panic!("unexpected error {}", error),
+    }
+
+    // scattered relocations only have 24 bits of address
+    let mut obj = Artifact::new(triple!("i686-apple-darwin"), "t.o".into());
+    obj.declare("f", Decl::data()).expect("can declare f");
+    obj.define("f", vec![0; (1 << 24) + 4])
+        .expect("can define f");
+    obj.declare_with("g", Decl::data(), vec![0; 4])
+        .expect("can declare and define g");
+    obj.link_with(
+        Link {
+            from: "f",
+            to: "g",
+            at: 1 << 24,
+        },
+        Reloc::Scattered {
+            // GENERIC_RELOC_VANILLA
+            reloc: 0,
+            addend: 0,
+        },
+    )
+    .expect("can link f to g");
+    let error = obj.emit().expect_err("the relocation is out of range");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::RelocationOverflow { value, .. }) => assert!(*value >= 1 << 24),
+        _ => panic!("unexpected error {}", error),
+    }
+}
+
+#[test]
+fn write_hashed() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("x", Decl::data().global(), vec![1, 2, 3, 4])
+        .expect("can declare and define x");
+
+    let write = |obj: &Artifact| {
+        let mut bytes = Vec::new();
+        let digest = obj
+            .write_hashed(&mut bytes, &mut DefaultHasher::new())
+            .expect("can write object");
+        (bytes, digest)
+    };
+    let (bytes, digest) = write(&obj);
+    assert_eq!(bytes, obj.emit().expect("can emit object"));
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&bytes);
+    assert_eq!(digest, hasher.finish());
+    assert_eq!(write(&obj).1, digest);
+
+    obj.remove("x").expect("can remove x");
+    obj.declare_with("x", Decl::data().global(), vec![1, 2, 3, 5])
+        .expect("can declare and define x");
+    assert_ne!(write(&obj).1, digest);
+}
+
+#[test]
+fn write_hashed_mach() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    // streamed as it is laid out, or emitted first when the UUID is a hash of the object
+    for uuid in &[MachUuid::Fixed([7; 16]), MachUuid::Hashed] {
+        let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+            .name("t.o".into())
+            .mach_uuid(*uuid)
+            .finish();
+        obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0, 0xc3])
+            .expect("can declare and define f");
+        obj.declare_with("x", Decl::data().global(), vec![0; 8])
+            .expect("can declare and define x");
+        obj.declare("g", Decl::function_import())
+            .expect("can declare g");
+        obj.link(Link {
+            from: "f",
+            to: "g",
+            at: 1,
+        })
+        .expect("can link f to g");
+        obj.link(Link {
+            from: "x",
+            to: "f",
+            at: 0,
+        })
+        .expect("can link x to f");
+
+        let mut bytes = Vec::new();
+        let digest = obj
+            .write_hashed(&mut bytes, &mut DefaultHasher::new())
+            .expect("can write object");
+        assert_eq!(bytes, obj.emit().expect("can emit object"));
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&bytes);
+        assert_eq!(digest, hasher.finish());
+    }
+}
+
+#[test]
+fn empty_artifact() {
+    use goblin::{mach::Mach, Object};
+
+    for target in &[
+        "x86_64-unknown-linux-gnu",
+        "i686-unknown-linux-gnu",
+        "aarch64-unknown-linux-gnu",
+        "x86_64-apple-darwin",
+        "i686-apple-darwin",
+        "aarch64-apple-darwin",
+    ] {
+        let obj = Artifact::new(triple!(target), "empty.o".into());
+        let bytes = obj.emit().expect("can emit an empty artifact");
+        assert_eq!(
+            obj.estimated_size().expect("can estimate the size"),
+            bytes.len() as u64
+        );
+        match Object::parse(&bytes).expect("can parse an empty artifact") {
+            Object::Elf(elf) => {
+                // only the null and file symbols
+                assert_eq!(elf.syms.len(), 2);
+                assert!(elf.shdr_relocs.is_empty());
+                assert!(elf
+                    .section_headers
+                    .iter()
+                    .all(|shdr| shdr.sh_offset as usize <= bytes.len()));
+            }
+            Object::Mach(Mach::Binary(mach)) => {
+                assert_eq!(mach.symbols().count(), 0);
+                for section in mach.segments.sections().flatten() {
+                    let (section, data) = section.expect("can parse section");
+                    assert_eq!(section.size, 0);
+                    assert_eq!(section.nreloc, 0);
+                    assert!(data.is_empty());
+                }
+            }
+            _ => panic!("{} artifact did not parse as ELF or Mach-O", target),
+        }
+    }
+}
+
+#[test]
+fn patch_relocation() {
+    // the address the object is loaded at
+    const BASE: u64 = 0x10_0000;
+
+    for target in &["x86_64-unknown-linux-gnu", "x86_64-apple-darwin"] {
+        let mut obj = Artifact::new(triple!(target), "t.o".into());
+        // leaq table(%rip), %rax; retq
+        obj.declare_with(
+            "f",
+            Decl::function().global(),
+            vec![0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3],
+        )
+        .expect("can declare and define f");
+        obj.declare_with("table", Decl::data().global().writable(), vec![0; 16])
+            .expect("can declare and define table");
+        obj.link(Link {
+            from: "table",
+            to: "f",
+            at: 8,
+        })
+        .expect("can link table to f");
+        obj.link(Link {
+            from: "f",
+            to: "table",
+            at: 3,
+        })
+        .expect("can link f to table");
+
+        let mut bytes = obj.emit().expect("can emit object file");
+        obj.patch_relocation(&mut bytes, BASE, "table", "f", 8, 0x1234_5678_9abc)
+            .expect("can patch the relocation from table");
+        obj.patch_relocation(&mut bytes, BASE, "f", "table", 3, 0x2000)
+            .expect("can patch the relocation from f");
+        let layout = obj.layout().expect("can lay out object file");
+        let start = |name: &str| {
+            let symbol = layout
+                .symbols
+                .iter()
+                .find(|symbol| symbol.name == name)
+                .expect("symbol is laid out");
+            let section = layout
+                .sections
+                .iter()
+                .find(|section| section.name == symbol.section)
+                .expect("symbol's section is laid out");
+            (section.offset + symbol.offset) as usize
+        };
+        let table = start("table");
+        assert_eq!(&bytes[table..table + 8], &[0; 8]);
+        assert_eq!(
+            &bytes[table + 8..table + 16],
+            &0x1234_5678_9abc_u64.to_le_bytes()
+        );
+        // the displacement is from the end of the instruction, where the field ends
+        let f = start("f");
+        let end = BASE + f as u64 + 7;
+        assert_eq!(
+            &bytes[f + 3..f + 7],
+            &((0x2000 - end as i64) as i32).to_le_bytes()
+        );
+
+        // references through the GOT cannot be patched, and only existing links can
+        obj.declare_with(
+            "g",
+            Decl::function(),
+            vec![0x48, 0x8b, 0x05, 0, 0, 0, 0, 0xc3],
+        )
+        .expect("can declare and define g");
+        obj.link_with(
+            Link {
+                from: "g",
+                to: "table",
+                at: 3,
+            },
+            Reloc::GotLoad,
+        )
+        .expect("can link g to table");
+        let mut bytes = obj.emit().expect("can emit object file");
+        let error = obj
+            .patch_relocation(&mut bytes, BASE, "g", "table", 3, 0x2000)
+            .expect_err("GOT loads cannot be patched");
+        match error.downcast_ref::<ArtifactError>() {
+            Some(ArtifactError::UnsupportedRelocation { .. }) => {}
+            _ => panic!("unexpected error {}", error),
+        }
+        let error = obj
+            .patch_relocation(&mut bytes, BASE, "table", "f", 0, 0x1000)
+            .expect_err("there is no link at 0");
+        match error.downcast_ref::<ArtifactError>() {
+            Some(ArtifactError::NoSuchLink { at: 0, .. }) => {}
+            _ => panic!("unexpected error {}", error),
+        }
+    }
+}
+
+#[test]
+fn patch_relocation_overflow() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("table", Decl::data().global(), vec![0; 4])
+        .expect("can declare and define table");
+    obj.link_with(
+        Link {
+            from: "table",
+            to: "f",
+            at: 0,
+        },
+        Reloc::Absolute { size: 4, addend: 0 },
+    )
+    .expect("can link table to f");
+
+    let mut bytes = obj.emit().expect("can emit object file");
+    // fits neither an unsigned nor a sign-extended 4-byte field
+    let error = obj
+        .patch_relocation(&mut bytes, 0, "table", "f", 0, 0x1_0000_0000)
+        .expect_err("the value does not fit in 4 bytes");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::RelocationOverflow {
+            value: 0x1_0000_0000,
+            ..
+        }) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+    // but sign-extended negative values do
+    obj.patch_relocation(&mut bytes, 0, "table", "f", 0, -16i64 as u64)
+        .expect("can patch a sign-extended value");
+    obj.patch_relocation(&mut bytes, 0, "table", "f", 0, 0xffff_fff0)
+        .expect("can patch an unsigned value");
+}
+
+#[test]
+fn declared_size() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "t.o".into());
+    obj.declare("f", Decl::function().global().with_size(Some(16)))
+        .expect("can declare f");
+    match obj.define("f", vec![0x90; 20]) {
+        Err(ArtifactError::SizeMismatch {
+            ref name,
+            declared,
+            defined,
+        }) => {
+            assert_eq!(name, "f");
+            assert_eq!(declared, 16);
+            assert_eq!(defined, 20);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+    obj.define("f", vec![0x90; 16])
+        .expect("can define f with its declared size");
+
+    // zero-initialized data has the size it occupies in memory
+    obj.declare("buffer", Decl::data().writable().with_size(Some(64)))
+        .expect("can declare buffer");
+    assert!(obj.define_zero_init("buffer", 32).is_err());
+    obj.define_zero_init("buffer", 64)
+        .expect("can define buffer with its declared size");
+
+    // without a declared size, a definition can have any size
+    obj.declare_with("g", Decl::function(), vec![0xc3; 20])
+        .expect("can declare and define g");
+    obj.emit().expect("can emit object file");
+}
+
+#[test]
+fn debug_names_relocations() {
+    // a minimal DWARF 5 name index of one compilation unit with one name, `main`
+    let mut debug_names = Vec::new();
+    debug_names.extend_from_slice(&57u32.to_le_bytes()); // unit_length
+    debug_names.extend_from_slice(&5u16.to_le_bytes()); // version
+    debug_names.extend_from_slice(&0u16.to_le_bytes()); // padding
+    debug_names.extend_from_slice(&1u32.to_le_bytes()); // comp_unit_count
+    debug_names.extend_from_slice(&0u32.to_le_bytes()); // local_type_unit_count
+    debug_names.extend_from_slice(&0u32.to_le_bytes()); // foreign_type_unit_count
+    debug_names.extend_from_slice(&0u32.to_le_bytes()); // bucket_count
+    debug_names.extend_from_slice(&1u32.to_le_bytes()); // name_count
+    debug_names.extend_from_slice(&7u32.to_le_bytes()); // abbrev_table_size
+    debug_names.extend_from_slice(&0u32.to_le_bytes()); // augmentation_string_size
+                                                        // the offset of the compilation unit in .debug_info, at 36
+    debug_names.extend_from_slice(&0u32.to_le_bytes());
+    // the offset of the name in .debug_str, at 40
+    debug_names.extend_from_slice(&0u32.to_le_bytes());
+    // the offset of the name's entry in the entry pool
+    debug_names.extend_from_slice(&0u32.to_le_bytes());
+    // DW_TAG_subprogram, with a DW_IDX_die_offset of DW_FORM_ref4
+    debug_names.extend_from_slice(&[1, 0x2e, 3, 0x13, 0, 0, 0]);
+    // the entry, with the DIE's offset in its compilation unit
+    debug_names.push(1);
+    debug_names.extend_from_slice(&12u32.to_le_bytes());
+    debug_names.push(0);
+    assert_eq!(debug_names.len(), 61);
+
+    for &(target, names, info, string) in &[
+        (
+            "x86_64-unknown-linux-gnu",
+            ".debug_names",
+            ".debug_info",
+            ".debug_str",
+        ),
+        (
+            "x86_64-apple-darwin",
+            "__debug_names",
+            "__debug_info",
+            "__debug_str",
+        ),
+    ] {
+        let mut obj = Artifact::new(triple!(target), "t.o".into());
+        obj.declare_with(
+            ".debug_info",
+            Decl::section(SectionKind::Debug),
+            vec![0; 16],
+        )
+        .expect("can declare and define .debug_info");
+        obj.declare_with(
+            ".debug_str",
+            Decl::section(SectionKind::Debug),
+            b"main\0".to_vec(),
+        )
+        .expect("can declare and define .debug_str");
+        obj.declare_with(
+            ".debug_names",
+            Decl::section(SectionKind::Debug),
+            debug_names.clone(),
+        )
+        .expect("can declare and define .debug_names");
+        for &(to, at) in &[(".debug_info", 36), (".debug_str", 40)] {
+            obj.link_with(
+                Link {
+                    from: ".debug_names",
+                    to,
+                    at,
+                },
+                Reloc::Debug { size: 4, addend: 0 },
+            )
+            .expect("can link from .debug_names");
+        }
+
+        let (_, _, relocations) = obj.emit_with_relocations().expect("can emit");
+        let mut relocations = relocations
+            .iter()
+            .map(|reloc| (&*reloc.section, reloc.offset, &*reloc.symbol))
+            .collect::<Vec<_>>();
+        relocations.sort();
+        assert_eq!(relocations, [(names, 36, info), (names, 40, string)]);
+    }
+}
+
+#[test]
+fn object_facade() {
+    for target in &["x86_64-unknown-linux-gnu", "x86_64-apple-darwin"] {
+        let mut obj = Object::new(triple!(target), "t.o".into());
+        let callee = obj
+            .add_function("callee", vec![0xc3])
+            .expect("can add callee");
+        let caller = obj
+            .add_function("caller", vec![0xe8, 0, 0, 0, 0, 0xc3])
+            .expect("can add caller");
+        let puts = obj
+            .import("puts", ImportKind::Function)
+            .expect("can import puts");
+        obj.call(caller, callee, 1).expect("can call callee");
+        obj.call(callee, puts, 0).expect("can call puts");
+        assert_eq!(obj.name(caller).unwrap(), "caller");
+
+        let mut artifact = Artifact::new(triple!(target), "t.o".into());
+        artifact
+            .declare_with("callee", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define callee");
+        artifact
+            .declare_with(
+                "caller",
+                Decl::function().global(),
+                vec![0xe8, 0, 0, 0, 0, 0xc3],
+            )
+            .expect("can declare and define caller");
+        artifact
+            .import("puts", ImportKind::Function)
+            .expect("can import puts");
+        for &(from, to, at) in &[("caller", "callee", 1), ("callee", "puts", 0)] {
+            artifact
+                .link_with(Link { from, to, at }, Reloc::Call)
+                .expect("can link");
+        }
+
+        let (bytes, _, relocations) = obj
+            .artifact()
+            .emit_with_relocations()
+            .expect("can emit the object");
+        let (expected_bytes, _, expected_relocations) = artifact
+            .emit_with_relocations()
+            .expect("can emit the artifact");
+        assert_eq!(relocations, expected_relocations);
+        assert_eq!(bytes, expected_bytes);
+
+        // handles are only valid in the object which returned them
+        let mut other = Object::new(triple!(target), "u.o".into());
+        let f = other.add_function("f", vec![0xc3]).expect("can add f");
+        assert!(other.call(f, puts, 0).is_err());
+
+        // a symbol which cannot be defined is not left declared, so it can be added again
+        assert!(other
+            .add("g", Decl::function().with_size(Some(2)), vec![0xc3])
+            .is_err());
+        assert!(other.artifact().undefined_symbols().is_empty());
+        let g = other.add_function("g", vec![0xc3]).expect("can add g");
+        assert_eq!(other.name(g).unwrap(), "g");
+        assert!(other.call(g, f, 0).is_ok());
+    }
+}
+
+#[test]
+fn relocation_model() {
+    use faerie::{ArtifactBuilder, RelocationModel};
+    use goblin::{mach::Mach, Object};
+    use target_lexicon::BinaryFormat;
+
+    let emit = |model, format| {
+        let mut obj = ArtifactBuilder::new(triple!("x86_64"))
+            .relocation_model(model)
+            .finish();
+        obj.import("x", ImportKind::Data)
+            .expect("can import x as data");
+        obj.declare_with("d", Decl::data().writable(), vec![0; 8])
+            .expect("can declare and define d");
+        obj.declare_with("main", Decl::function().global(), vec![0; 14])
+            .expect("can declare and define main");
+        obj.link(Link {
+            from: "main",
+            to: "x",
+            at: 3,
+        })
+        .expect("can link main to x");
+        obj.link(Link {
+            from: "main",
+            to: "d",
+            at: 10,
+        })
+        .expect("can link main to d");
+        obj.emit_as(format).expect("can emit")
+    };
+
+    let elf_relocs = |model| {
+        let bytes = emit(model, BinaryFormat::Elf);
+        match Object::parse(&bytes).unwrap() {
+            Object::Elf(elf) => {
+                let mut relocs = elf
+                    .shdr_relocs
+                    .iter()
+                    .flat_map(|(_, relocs)| relocs.iter())
+                    .map(|reloc| (reloc.r_offset, reloc.r_type, reloc.r_addend.unwrap()))
+                    .collect::<Vec<_>>();
+                relocs.sort();
+                relocs
+            }
+            _ => panic!("emitted as ELF but did not parse as ELF"),
+        }
+    };
+    {
+        use goblin::elf::reloc::{R_X86_64_32S, R_X86_64_GOTPCREL, R_X86_64_PC32};
+        assert_eq!(
+            elf_relocs(RelocationModel::Pic),
+            vec![(3, R_X86_64_GOTPCREL, -4), (10, R_X86_64_PC32, -4)]
+        );
+        assert_eq!(
+            elf_relocs(RelocationModel::Static),
+            vec![(3, R_X86_64_32S, 0), (10, R_X86_64_32S, 0)]
+        );
+    }
+
+    let mach_relocs = |model| {
+        let bytes = emit(model, BinaryFormat::Macho);
+        match Object::parse(&bytes).unwrap() {
+            Object::Mach(Mach::Binary(mach)) => {
+                let mut relocs = mach
+                    .relocations()
+                    .unwrap()
+                    .into_iter()
+                    .flat_map(|(_, relocs, _)| relocs)
+                    .map(|reloc| {
+                        let reloc = reloc.unwrap();
+                        (reloc.r_address, reloc.r_type())
+                    })
+                    .collect::<Vec<_>>();
+                relocs.sort();
+                relocs
+            }
+            _ => panic!("emitted as MACHO but did not parse as MACHO"),
+        }
+    };
+    {
+        use goblin::mach::relocation::{X86_64_RELOC_GOT_LOAD, X86_64_RELOC_SIGNED};
+        assert_eq!(
+            mach_relocs(RelocationModel::Pic),
+            vec![(3, X86_64_RELOC_GOT_LOAD), (10, X86_64_RELOC_SIGNED)]
+        );
+        // Mach-O has no 32-bit absolute relocations for x86_64, so the import is referred to
+        // directly instead
+        assert_eq!(
+            mach_relocs(RelocationModel::Static),
+            vec![(3, X86_64_RELOC_SIGNED), (10, X86_64_RELOC_SIGNED)]
+        );
+    }
+}
+
+#[test]
+fn section_contents() {
+    use target_lexicon::BinaryFormat;
+
+    let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    // push %rbp; pop %rbp; ret
+    obj.declare_with("f", Decl::function().global(), vec![0x55, 0x5d, 0xc3])
+        .expect("can declare and define f");
+    obj.declare_with(
+        "g",
+        Decl::function().global().with_align(Some(16)),
+        vec![0xc3],
+    )
+    .expect("can declare and define g");
+    obj.declare("z", Decl::data().writable())
+        .expect("can declare z");
+    obj.define_with_symbols("z", Data::ZeroInit(4), Default::default())
+        .expect("can define z");
+
+    let contents = |format, text: &str, bss: &str| {
+        let sections = obj.section_contents_as(format).expect("can get contents");
+        let section = |name: &str| {
+            sections
+                .iter()
+                .find(|(section, _)| section == name)
+                .map(|(_, contents)| contents.clone())
+                .expect("section exists")
+        };
+        (section(text), section(bss))
+    };
+
+    // the functions are padded to g's alignment with int3, and the last to __text's minimum
+    // alignment
+    let mut text = vec![0x55, 0x5d, 0xc3];
+    text.extend(&[0xcc; 13]);
+    text.push(0xc3);
+    text.extend(&[0xcc; 15]);
+    let (mach_text, mach_bss) = contents(BinaryFormat::Macho, "__text", "__bss");
+    assert_eq!(mach_text, text);
+    assert_eq!(mach_bss, vec![0; 4]);
+
+    let (elf_f, elf_bss) = contents(BinaryFormat::Elf, ".text.f", ".bss.z");
+    assert_eq!(elf_f, vec![0x55, 0x5d, 0xc3]);
+    assert_eq!(elf_bss, vec![0; 4]);
+
+    // the contents are those of the emitted object
+    let (bytes, sections, _) = obj
+        .emit_with_relocations_as(BinaryFormat::Macho)
+        .expect("can emit");
+    let resolved = sections
+        .iter()
+        .find(|section| section.name == "__text")
+        .expect("__text is resolved");
+    assert_eq!(
+        &bytes[resolved.offset as usize..][..resolved.size as usize],
+        &mach_text[..]
+    );
+}
+
+#[test]
+fn malformed_input_is_an_error() {
+    let triples = ["x86_64-apple-darwin", "x86_64-unknown-unknown-unknown-elf"];
+    let new = |triple: &str| {
+        Artifact::new(
+            target_lexicon::Triple::from_str(triple).unwrap(),
+            "t.o".into(),
+        )
+    };
+
+    for triple in &triples {
+        // empty names, of a declaration or of a custom symbol
+        let mut obj = new(triple);
+        assert!(obj.declare("", Decl::function()).is_err());
+        obj.declare("f", Decl::function()).unwrap();
+        let mut symbols = std::collections::BTreeMap::new();
+        symbols.insert(String::new(), 0);
+        assert!(obj.define_with_symbols("f", vec![0xc3], symbols).is_err());
+
+        // a custom symbol named like a declaration
+        let mut obj = new(triple);
+        obj.declare("f", Decl::function()).unwrap();
+        obj.declare("g", Decl::function()).unwrap();
+        obj.define("g", vec![0xc3]).unwrap();
+        let mut symbols = std::collections::BTreeMap::new();
+        symbols.insert("g".to_string(), 0);
+        obj.define_with_symbols("f", vec![0xc3], symbols).unwrap();
+        assert!(obj.emit().is_err());
+
+        // a link past the end of the definition it is from
+        let mut obj = new(triple);
+        obj.declare_with("f", Decl::function(), vec![0xc3]).unwrap();
+        obj.declare("g", Decl::function_import()).unwrap();
+        obj.link(Link {
+            from: "f",
+            to: "g",
+            at: 8,
+        })
+        .unwrap();
+        assert!(obj.emit().is_err());
+    }
+
+    // a custom section named like the section faerie puts a function in
+    let mut obj = new("x86_64-unknown-unknown-unknown-elf");
+    obj.declare_with(".text.f", Decl::section(SectionKind::Text), vec![0xc3])
+        .unwrap();
+    obj.declare_with("f", Decl::function(), vec![0xc3]).unwrap();
+    match obj.emit().unwrap_err().downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::SectionNameCollision(name)) => assert_eq!(name, ".text.f"),
+        error => panic!("unexpected error {:?}", error),
+    }
+    let mut obj = new("x86_64-apple-darwin");
+    obj.declare_with("__text", Decl::section(SectionKind::Data), vec![0])
+        .unwrap();
+    obj.declare_with("f", Decl::function(), vec![0xc3]).unwrap();
+    match obj.emit().unwrap_err().downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::SectionNameCollision(name)) => assert_eq!(name, "__text"),
+        error => panic!("unexpected error {:?}", error),
+    }
+
+    // a debug relocation from a definition rather than a debug section
+    let mut obj = new("x86_64-apple-darwin");
+    obj.declare_with("d", Decl::data(), vec![0; 8]).unwrap();
+    obj.declare_with(".debug_info", Decl::section(SectionKind::Debug), vec![0; 8])
+        .unwrap();
+    obj.link_with(
+        Link {
+            from: "d",
+            to: ".debug_info",
+            at: 0,
+        },
+        Reloc::Debug { size: 4, addend: 0 },
+    )
+    .unwrap();
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn alignment_of() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "t.o".into());
+    obj.declare("buffer", Decl::data().with_align(Some(16)))
+        .expect("can declare buffer");
+    assert_eq!(obj.alignment_of("buffer"), Some(16));
+    obj.define("buffer", vec![0; 32])
+        .expect("can define buffer");
+    assert_eq!(obj.alignment_of("buffer"), Some(16));
+
+    obj.declare("f", Decl::function()).expect("can declare f");
+    assert_eq!(obj.alignment_of("f"), None);
+    obj.import("puts", ImportKind::Function)
+        .expect("can import puts");
+    assert_eq!(obj.alignment_of("puts"), None);
+    assert_eq!(obj.alignment_of("undeclared"), None);
+}
+
+#[test]
+fn redefine() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "t.o".into());
+    obj.declare("f", Decl::function().global().with_size(Some(2)))
+        .expect("can declare f");
+    match obj.redefine("f", vec![0x90, 0xc3]) {
+        Err(ArtifactError::Undefined(name)) => assert_eq!(name, "f"),
+        result => panic!("expected an undefined error, got {:?}", result),
+    }
+    obj.define("f", vec![0xcc, 0xcc]).expect("can define f");
+    // defining twice is a mistake, unless the redefinition is explicit
+    match obj.define("f", vec![0x90, 0xc3]) {
+        Err(ArtifactError::DuplicateDefinition(name)) => assert_eq!(name, "f"),
+        result => panic!("expected a duplicate definition error, got {:?}", result),
+    }
+    obj.redefine("f", vec![0x90, 0xc3]).expect("can redefine f");
+    // an invalid redefinition keeps the previous definition
+    assert!(obj.redefine("f", vec![0xc3]).is_err());
+    match obj.redefine("g", vec![0xc3]) {
+        Err(ArtifactError::Undeclared(name)) => assert_eq!(name, "g"),
+        result => panic!("expected an undeclared error, got {:?}", result),
+    }
+
+    let contents = obj.section_contents().expect("can get section contents");
+    let (_, text) = contents
+        .iter()
+        .find(|(name, _)| name == ".text.f")
+        .expect("f has a section");
+    assert_eq!(text, &[0x90, 0xc3]);
+}
+
+#[test]
+fn referenced_imports() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "t.o".into());
+    obj.import("puts", ImportKind::Function)
+        .expect("can import puts");
+    obj.import("printf", ImportKind::Function)
+        .expect("can import printf");
+    obj.declare_with("f", Decl::function(), vec![0xe8, 0, 0, 0, 0, 0xc3])
+        .expect("can declare and define f");
+    obj.link(Link {
+        from: "f",
+        to: "puts",
+        at: 1,
+    })
+    .expect("can link f to puts");
+    assert_eq!(
+        obj.referenced_imports().collect::<Vec<_>>(),
+        vec![("puts", ImportKind::Function)]
+    );
+
+    // the unreferenced import can be dropped
+    obj.undeclare("printf").expect("can undeclare printf");
+    assert_eq!(
+        obj.imports().collect::<Vec<_>>(),
+        obj.referenced_imports().collect::<Vec<_>>()
+    );
+}
@@ -0,0 +1,18 @@
+# The reference object reference.macho.o, for the `matches_reference_object` test, assembled with
+#   llvm-mc -triple x86_64-apple-macosx10.12 -filetype=obj reference.macho.s -o reference.macho.o
+	.section	__TEXT,__text,regular,pure_instructions
+	.globl	_main
+	.p2align	4
+_main:
+	callq	_foo
+	movq	_x(%rip), %rax
+	retq
+	nop
+	nop
+	nop
+	.section	__DATA,__data
+	.globl	_x
+	.p2align	3
+_x:
+	.quad	42
+	.subsections_via_symbols
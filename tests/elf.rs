@@ -150,6 +150,24 @@ fn decl_attributes() {
             ensure!(!sect.is_writable(), "immutable");
             Ok(())
         }),
+        DeclTestCase::new(
+            "global_hidden_func",
+            Decl::function().global().hidden(),
+            |sym, _sect| {
+                ensure!(sym.st_bind() == sym::STB_GLOBAL, "symbol is global");
+                ensure!(sym.st_visibility() == sym::STV_HIDDEN, "symbol is hidden");
+                Ok(())
+            },
+        ),
+        DeclTestCase::new(
+            "weak_hidden_data",
+            Decl::data().weak().hidden(),
+            |sym, _sect| {
+                ensure!(sym.st_bind() == sym::STB_WEAK, "symbol is weak");
+                ensure!(sym.st_visibility() == sym::STV_HIDDEN, "symbol is hidden");
+                Ok(())
+            },
+        ),
         DeclTestCase::new(
             "protected_func",
             Decl::function().protected(),
@@ -322,3 +340,1762 @@ fn extended_symtab_issue_76() {
         }
     }
 }
+
+#[test]
+fn rename_linked_symbol() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define f");
+    obj.declare_with("g", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define g");
+    obj.link(Link {
+        from: "f",
+        to: "g",
+        at: 1,
+    })
+    .expect("can link from f to g");
+    obj.link(Link {
+        from: "g",
+        to: "f",
+        at: 1,
+    })
+    .expect("can link from g to f");
+
+    assert!(obj.rename("f", "g").is_err());
+    assert!(obj.rename("h", "i").is_err());
+    obj.rename("f", "renamed").expect("can rename f");
+    assert!(obj.define("renamed", vec![]).is_err());
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let names = elf
+        .syms
+        .iter()
+        .map(|sym| &elf.strtab[sym.st_name])
+        .collect::<Vec<_>>();
+    assert!(names.contains(&"renamed"));
+    assert!(!names.contains(&"f"));
+    let section_names = elf
+        .section_headers
+        .iter()
+        .map(|shdr| &elf.shdr_strtab[shdr.sh_name])
+        .collect::<Vec<_>>();
+    assert!(section_names.contains(&".text.renamed"));
+    assert!(section_names.contains(&".rela.text.renamed"));
+    assert!(!section_names.contains(&".text.f"));
+
+    // the relocation in g now targets the renamed function's section
+    let text_renamed = section_names
+        .iter()
+        .position(|&name| name == ".text.renamed")
+        .unwrap();
+    let targets_renamed = elf.shdr_relocs.iter().any(|(_, relocs)| {
+        relocs.iter().any(|reloc| {
+            let sym = elf.syms.get(reloc.r_sym).expect("reloc symbol exists");
+            sym.st_shndx == text_renamed
+        })
+    });
+    assert!(targets_renamed);
+}
+
+#[test]
+fn retained_section() {
+    use faerie::SectionKind;
+
+    const SHF_GNU_RETAIN: u64 = 0x20_0000;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with(
+        ".retained",
+        Decl::section(SectionKind::Data).retain(),
+        vec![1, 2, 3, 4],
+    )
+    .expect("can declare and define retained section");
+    obj.declare_with(".collectable", Decl::section(SectionKind::Data), vec![1])
+        .expect("can declare and define section");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let flags = |name| {
+        elf.section_headers
+            .iter()
+            .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == name)
+            .expect("section exists")
+            .sh_flags
+    };
+    assert_eq!(flags(".retained") & SHF_GNU_RETAIN, SHF_GNU_RETAIN);
+    assert_eq!(flags(".collectable") & SHF_GNU_RETAIN, 0);
+}
+
+#[test]
+fn emit_with_relocations_matches_object() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("imported", Decl::function_import())
+        .expect("can declare import");
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0],
+    )
+    .expect("can declare and define f");
+    obj.declare_with("g", Decl::function(), vec![0xc3])
+        .expect("can declare and define g");
+    obj.link(Link {
+        from: "f",
+        to: "imported",
+        at: 1,
+    })
+    .expect("can link from f to imported");
+    obj.link(Link {
+        from: "f",
+        to: "g",
+        at: 6,
+    })
+    .expect("can link from f to g");
+
+    let (bytes, sections, relocations) = obj.emit_with_relocations().expect("can emit");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+
+    assert_eq!(sections.len(), 2);
+    for section in sections.iter() {
+        let shdr = elf
+            .section_headers
+            .iter()
+            .find(|shdr| elf.shdr_strtab[shdr.sh_name] == section.name)
+            .expect("section exists");
+        assert_eq!(shdr.sh_offset, section.offset);
+        assert_eq!(shdr.sh_size, section.size);
+    }
+
+    let parsed = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|&(idx, ref relocs)| {
+            let applies_to = elf.section_headers[idx].sh_info as usize;
+            let section = &elf.shdr_strtab[elf.section_headers[applies_to].sh_name];
+            relocs.iter().map(move |reloc| (section, reloc))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(relocations.len(), parsed.len());
+    for (resolved, (section, reloc)) in relocations.iter().zip(parsed) {
+        assert_eq!(resolved.section, section);
+        assert_eq!(resolved.offset, reloc.r_offset);
+        assert_eq!(resolved.reloc, reloc.r_type);
+        // both links are calls, with 4-byte displacements
+        assert_eq!(resolved.size, Some(4));
+        assert_eq!(Some(resolved.addend), reloc.r_addend);
+        let sym = elf.syms.get(reloc.r_sym).expect("relocation symbol exists");
+        if sym.st_type() == sym::STT_SECTION {
+            // defined symbols are relocated against their section's symbol
+            let target = elf
+                .syms
+                .iter()
+                .find(|target| elf.strtab[target.st_name] == resolved.symbol)
+                .expect("target symbol exists");
+            assert_eq!(target.st_shndx, sym.st_shndx);
+        } else {
+            assert_eq!(elf.strtab[sym.st_name], resolved.symbol);
+        }
+    }
+}
+
+#[test]
+fn function_symbols_at_offsets() {
+    use faerie::Data;
+    use std::collections::BTreeMap;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("f", Decl::function()).expect("can declare f");
+    let mut symbols = BTreeMap::new();
+    symbols.insert("f_alt_entry".to_string(), 2);
+    obj.define_with_symbols("f", Data::Blob(vec![0x90, 0x90, 0xc3]), symbols)
+        .expect("can define f with symbols");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let find = |name| {
+        elf.syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .expect("symbol exists")
+    };
+    let (f, alt) = (find("f"), find("f_alt_entry"));
+    assert_eq!(f.st_value, 0);
+    assert_eq!(alt.st_value, 2);
+    assert_eq!(alt.st_shndx, f.st_shndx);
+    assert!(alt.is_function());
+    assert_eq!(alt.st_bind(), sym::STB_LOCAL);
+    // both local symbols are counted before the first global
+    let symtab = elf
+        .section_headers
+        .iter()
+        .find(|shdr| shdr.sh_type == section_header::SHT_SYMTAB)
+        .unwrap();
+    assert_eq!(symtab.sh_info as usize, elf.syms.len());
+}
+
+#[test]
+fn debug_ranges_relocations() {
+    use faerie::{Reloc, SectionKind};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0x90; 5])
+        .expect("can declare and define f");
+    obj.declare_with(
+        ".debug_ranges",
+        Decl::section(SectionKind::Debug),
+        vec![0; 32],
+    )
+    .expect("can declare and define .debug_ranges");
+    // [f, f + 5), followed by the end of list entry
+    for &(at, addend) in &[(0, 0), (8, 5)] {
+        obj.link_with(
+            Link {
+                from: ".debug_ranges",
+                to: "f",
+                at,
+            },
+            Reloc::Debug { size: 8, addend },
+        )
+        .expect("can link from .debug_ranges to f");
+    }
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let f = elf
+        .syms
+        .iter()
+        .find(|sym| &elf.strtab[sym.st_name] == "f")
+        .expect("f exists");
+    let debug_ranges = elf
+        .section_headers
+        .iter()
+        .position(|shdr| &elf.shdr_strtab[shdr.sh_name] == ".debug_ranges")
+        .expect("debug ranges section exists");
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|&&(idx, _)| elf.section_headers[idx].sh_info as usize == debug_ranges)
+        .expect("debug ranges relocations exist");
+    let relocs = relocs
+        .iter()
+        .map(|reloc| {
+            let sym = elf.syms.get(reloc.r_sym).unwrap();
+            // relocated against the section symbol of f's section
+            assert_eq!(sym.st_type(), sym::STT_SECTION);
+            assert_eq!(sym.st_shndx, f.st_shndx);
+            (reloc.r_offset, reloc.r_type, reloc.r_addend)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relocs,
+        vec![
+            (0, reloc::R_X86_64_64, Some(0)),
+            (8, reloc::R_X86_64_64, Some(5))
+        ]
+    );
+}
+
+#[test]
+fn zero_init_extent() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("zeroes", Decl::data().global().writable())
+        .expect("can declare zeroes");
+    obj.define_zero_init("zeroes", 1024)
+        .expect("can define zeroes");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let bss = elf
+        .section_headers
+        .iter()
+        .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == ".bss.zeroes")
+        .expect("bss section exists");
+    assert_eq!(bss.sh_type, section_header::SHT_NOBITS);
+    assert_eq!(bss.sh_size, 1024);
+    let sym = elf
+        .syms
+        .iter()
+        .find(|sym| &elf.strtab[sym.st_name] == "zeroes")
+        .expect("symbol exists");
+    assert_eq!(sym.st_size, 1024);
+}
+
+#[test]
+fn osabi() {
+    use faerie::SectionKind;
+    use goblin::elf::header::{EI_ABIVERSION, EI_OSABI, ELFOSABI_NONE};
+
+    const ELFOSABI_GNU: u8 = 3;
+    const ELFOSABI_FREEBSD: u8 = 9;
+
+    let ident = |obj: &Artifact| {
+        let bytes = obj.emit().expect("can emit elf file");
+        let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+        (
+            elf.header.e_ident[EI_OSABI],
+            elf.header.e_ident[EI_ABIVERSION],
+        )
+    };
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    assert_eq!(ident(&obj), (ELFOSABI_NONE, 0));
+
+    // retained sections are a GNU extension
+    obj.declare_with(
+        ".retained",
+        Decl::section(SectionKind::Data).retain(),
+        vec![1],
+    )
+    .expect("can declare and define retained section");
+    assert_eq!(ident(&obj), (ELFOSABI_GNU, 0));
+
+    let obj = ArtifactBuilder::new(triple!("x86_64-unknown-freebsd"))
+        .elf_osabi(ELFOSABI_FREEBSD)
+        .elf_abiversion(1)
+        .finish();
+    assert_eq!(ident(&obj), (ELFOSABI_FREEBSD, 1));
+}
+
+#[test]
+fn ifunc() {
+    use goblin::elf::header::EI_OSABI;
+    use goblin::elf::sym::STT_GNU_IFUNC;
+
+    const ELFOSABI_GNU: u8 = 3;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    // the resolver returns the address of the implementation
+    obj.declare_with("impl", Decl::function(), vec![0xc3])
+        .expect("can declare and define impl");
+    obj.declare_with("dispatch", Decl::ifunc().global(), vec![0xc3])
+        .expect("can declare and define dispatch");
+    obj.declare_with("caller", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define caller");
+    obj.link(Link {
+        from: "caller",
+        to: "dispatch",
+        at: 1,
+    })
+    .expect("can link from caller to dispatch");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    assert_eq!(elf.header.e_ident[EI_OSABI], ELFOSABI_GNU);
+    let (index, sym) = elf
+        .syms
+        .iter()
+        .enumerate()
+        .find(|(_, sym)| &elf.strtab[sym.st_name] == "dispatch")
+        .expect("dispatch symbol exists");
+    assert_eq!(sym.st_type(), STT_GNU_IFUNC);
+
+    // calls go through the PLT, to the ifunc symbol rather than its section
+    let relocs = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .collect::<Vec<_>>();
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].r_type, reloc::R_X86_64_PLT32);
+    assert_eq!(relocs[0].r_sym, index);
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "t.o".into());
+    obj.declare_with("dispatch", Decl::ifunc().global(), vec![0xc3])
+        .expect("can declare and define dispatch");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn explicit_symbol_kinds() {
+    use faerie::SymbolKind;
+    use goblin::elf::sym::{STT_FUNC, STT_GNU_IFUNC, STT_NOTYPE, STT_OBJECT};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("function", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define function");
+    obj.declare_with("object", Decl::data().global(), vec![1, 2, 3, 4])
+        .expect("can declare and define object");
+    obj.declare_with(
+        "stub",
+        Decl::data()
+            .global()
+            .with_symbol_kind(Some(SymbolKind::Function)),
+        vec![0xc3],
+    )
+    .expect("can declare and define stub");
+    obj.declare_with(
+        "resolver",
+        Decl::function()
+            .global()
+            .with_symbol_kind(Some(SymbolKind::IndirectFunction)),
+        vec![0xc3],
+    )
+    .expect("can declare and define resolver");
+    obj.declare_with(
+        "label",
+        Decl::function()
+            .global()
+            .with_symbol_kind(Some(SymbolKind::NoType)),
+        vec![0xc3],
+    )
+    .expect("can declare and define label");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let st_type = |name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .expect("symbol exists")
+            .st_type()
+    };
+    assert_eq!(st_type("function"), STT_FUNC);
+    assert_eq!(st_type("object"), STT_OBJECT);
+    assert_eq!(st_type("stub"), STT_FUNC);
+    assert_eq!(st_type("resolver"), STT_GNU_IFUNC);
+    assert_eq!(st_type("label"), STT_NOTYPE);
+
+    // indirect functions are not supported by Mach-O, however they are declared
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "t.o".into());
+    obj.declare_with(
+        "resolver",
+        Decl::function()
+            .global()
+            .with_symbol_kind(Some(SymbolKind::IndirectFunction)),
+        vec![0xc3],
+    )
+    .expect("can declare and define resolver");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn section_symbol_relocations() {
+    use faerie::{Reloc, SectionKind};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with(".debug_str", Decl::section(SectionKind::Debug), vec![0; 16])
+        .expect("can declare and define .debug_str");
+    obj.declare_with(
+        ".debug_info",
+        Decl::section(SectionKind::Debug),
+        vec![0; 16],
+    )
+    .expect("can declare and define .debug_info");
+    // an offset into .debug_str, and a self reference
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: ".debug_str",
+            at: 4,
+        },
+        Reloc::Debug { size: 4, addend: 7 },
+    )
+    .expect("can link from .debug_info to .debug_str");
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: ".debug_info",
+            at: 8,
+        },
+        Reloc::Raw {
+            reloc: reloc::R_X86_64_64,
+            addend: 3,
+        },
+    )
+    .expect("can link from .debug_info to itself");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let shndx = |name| {
+        elf.section_headers
+            .iter()
+            .position(|shdr| &elf.shdr_strtab[shdr.sh_name] == name)
+            .expect("section exists")
+    };
+    let (debug_str, debug_info) = (shndx(".debug_str"), shndx(".debug_info"));
+    // every section has a local section symbol
+    for &section in &[debug_str, debug_info] {
+        assert!(elf.syms.iter().any(|sym| sym.st_type() == sym::STT_SECTION
+            && sym.st_bind() == sym::STB_LOCAL
+            && sym.st_shndx == section));
+    }
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|&&(idx, _)| elf.section_headers[idx].sh_info as usize == debug_info)
+        .expect("debug info relocations exist");
+    let relocs = relocs
+        .iter()
+        .map(|reloc| {
+            let sym = elf.syms.get(reloc.r_sym).unwrap();
+            assert_eq!(sym.st_type(), sym::STT_SECTION);
+            (reloc.r_offset, reloc.r_type, sym.st_shndx, reloc.r_addend)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relocs,
+        vec![
+            (4, reloc::R_X86_64_32, debug_str, Some(7)),
+            (8, reloc::R_X86_64_64, debug_info, Some(3)),
+        ]
+    );
+}
+
+#[test]
+fn debug_relocations_per_architecture() {
+    use faerie::{Reloc, SectionKind};
+
+    let debug_info = |target| {
+        let mut obj = Artifact::new(target, "t.o".into());
+        obj.declare_with(".debug_str", Decl::section(SectionKind::Debug), vec![0; 16])
+            .expect("can declare and define .debug_str");
+        obj.declare_with(
+            ".debug_info",
+            Decl::section(SectionKind::Debug),
+            vec![0; 16],
+        )
+        .expect("can declare and define .debug_info");
+        for &(at, size) in &[(4, 4), (8, 8)] {
+            obj.link_with(
+                Link {
+                    from: ".debug_info",
+                    to: ".debug_str",
+                    at,
+                },
+                Reloc::Debug { size, addend: 1 },
+            )
+            .expect("can link from .debug_info to .debug_str");
+        }
+        obj
+    };
+
+    let bytes = debug_info(triple!("aarch64-unknown-linux-gnu"))
+        .emit()
+        .expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let (_, relocs) = &elf.shdr_relocs[0];
+    let relocs = relocs
+        .iter()
+        .map(|reloc| (reloc.r_offset, reloc.r_type, reloc.r_addend))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relocs,
+        vec![
+            (4, reloc::R_AARCH64_ABS32, Some(1)),
+            (8, reloc::R_AARCH64_ABS64, Some(1)),
+        ]
+    );
+
+    // the debug relocations of other architectures are not known
+    assert!(debug_info(triple!("riscv64gc-unknown-linux-gnu"))
+        .emit()
+        .is_err());
+}
+
+#[test]
+fn layout() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0x90; 3])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![0; 8])
+        .expect("can declare and define d");
+    obj.import("imported", faerie::ImportKind::Function)
+        .expect("can import");
+
+    let layout = obj.layout().expect("can compute layout");
+    // the contents of the first section directly follow the 64 byte header
+    assert_eq!(layout.sections[0].name, ".text.f");
+    assert_eq!(layout.sections[0].offset, 64);
+    // only defined symbols are laid out
+    let symbols = layout
+        .symbols
+        .iter()
+        .map(|symbol| (&*symbol.name, &*symbol.section, symbol.offset))
+        .collect::<Vec<_>>();
+    assert_eq!(symbols, vec![("f", ".text.f", 0), ("d", ".rodata.d", 0)]);
+
+    let (_, sections, relocations) = obj.emit_with_relocations().expect("can emit");
+    assert_eq!(layout.sections, sections);
+    assert_eq!(layout.relocations, relocations);
+}
+
+#[test]
+fn literal_sections() {
+    use faerie::DataType;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "a".into());
+    obj.declare_with(
+        "one",
+        Decl::data().with_datatype(DataType::Literal4),
+        1.0f32.to_le_bytes().to_vec(),
+    )
+    .expect("can declare and define one");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let shdr = elf
+        .section_headers
+        .iter()
+        .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == ".rodata.one")
+        .expect("section exists");
+    assert_eq!(
+        shdr.sh_flags & u64::from(section_header::SHF_MERGE),
+        u64::from(section_header::SHF_MERGE)
+    );
+    assert_eq!(shdr.sh_flags & u64::from(section_header::SHF_STRINGS), 0);
+    assert_eq!(shdr.sh_entsize, 4);
+    assert_eq!(shdr.sh_addralign, 4);
+}
+
+#[test]
+fn e_flags() {
+    // EF_RISCV_RVC | EF_RISCV_FLOAT_ABI_DOUBLE
+    const FLAGS: u32 = 0x1 | 0x4;
+
+    let mut obj = ArtifactBuilder::new(triple!("riscv64-unknown-unknown-elf"))
+        .name("a".into())
+        .elf_flags(FLAGS)
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0x82, 0x80])
+        .expect("can declare and define f");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    assert_eq!(elf.header.e_machine, header::EM_RISCV);
+    assert_eq!(elf.header.e_flags, FLAGS);
+}
+
+#[test]
+fn riscv_default_e_flags() {
+    const EF_RISCV_RVC: u32 = 0x1;
+    const EF_RISCV_FLOAT_ABI_DOUBLE: u32 = 0x4;
+
+    let e_flags = |obj: &Artifact| {
+        let bytes = obj.emit().expect("can emit elf file");
+        goblin::elf::Elf::parse(&bytes)
+            .expect("can parse elf file")
+            .header
+            .e_flags
+    };
+    let gc = Artifact::new(triple!("riscv64gc-unknown-linux-gnu"), "a".into());
+    assert_eq!(e_flags(&gc), EF_RISCV_RVC | EF_RISCV_FLOAT_ABI_DOUBLE);
+    let imac = Artifact::new(triple!("riscv32imac-unknown-none-elf"), "a".into());
+    assert_eq!(e_flags(&imac), EF_RISCV_RVC);
+    let i = Artifact::new(triple!("riscv32i-unknown-none-elf"), "a".into());
+    assert_eq!(e_flags(&i), 0);
+    let x86 = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a".into());
+    assert_eq!(e_flags(&x86), 0);
+
+    // an explicit value overrides the default
+    let soft = ArtifactBuilder::new(triple!("riscv64gc-unknown-linux-gnu"))
+        .elf_flags(EF_RISCV_RVC)
+        .finish();
+    assert_eq!(e_flags(&soft), EF_RISCV_RVC);
+}
+
+#[test]
+fn rela_relocations() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0x90; 8])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![0; 8])
+        .expect("can declare and define d");
+    obj.link(Link {
+        from: "d",
+        to: "f",
+        at: 0,
+    })
+    .expect("can link d to f");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let shdr = elf
+        .section_headers
+        .iter()
+        .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == ".rela.rodata.d")
+        .expect(".rela.rodata.d exists");
+    assert_eq!(shdr.sh_type, section_header::SHT_RELA);
+    assert_eq!(shdr.sh_entsize, 24);
+}
+
+#[test]
+fn rel_relocations() {
+    use faerie::Reloc;
+
+    let mut obj = Artifact::new(triple!("i386-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0x90; 8])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![0xaa; 8])
+        .expect("can declare and define d");
+    for &(at, addend) in &[(0, 0), (4, 5)] {
+        obj.link_with(
+            Link {
+                from: "d",
+                to: "f",
+                at,
+            },
+            Reloc::Raw {
+                reloc: reloc::R_386_32,
+                addend,
+            },
+        )
+        .expect("can link d to f");
+    }
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    assert!(elf
+        .section_headers
+        .iter()
+        .all(|shdr| shdr.sh_type != section_header::SHT_RELA));
+    let (idx, shdr) = elf
+        .section_headers
+        .iter()
+        .enumerate()
+        .find(|(_, shdr)| &elf.shdr_strtab[shdr.sh_name] == ".rel.rodata.d")
+        .expect(".rel.rodata.d exists");
+    assert_eq!(shdr.sh_type, section_header::SHT_REL);
+    assert_eq!(shdr.sh_entsize, 8);
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|&&(reloc_idx, _)| reloc_idx == idx)
+        .expect(".rel.rodata.d is parsed");
+    assert_eq!(
+        relocs
+            .iter()
+            .map(|reloc| (reloc.r_offset, reloc.r_type, reloc.r_addend))
+            .collect::<Vec<_>>(),
+        vec![(0, reloc::R_386_32, None), (4, reloc::R_386_32, None)]
+    );
+
+    // the addends are implicit in the section contents, and a zero addend leaves them unchanged
+    let d = &elf.section_headers[shdr.sh_info as usize];
+    let contents = &bytes[d.sh_offset as usize..][..8];
+    assert_eq!(contents, &[0xaa, 0xaa, 0xaa, 0xaa, 5, 0, 0, 0]);
+}
+
+#[test]
+fn section_per_definition() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    for name in &["f", "g", "h"] {
+        obj.declare_with(
+            name,
+            Decl::function().global(),
+            vec![0xe8, 0, 0, 0, 0, 0xc3],
+        )
+        .expect("can declare and define function");
+    }
+    obj.declare_with("d", Decl::data().writable(), vec![1; 8])
+        .expect("can declare and define d");
+    obj.declare("z", Decl::data().writable())
+        .expect("can declare z");
+    obj.define_zero_init("z", 8).expect("can define z");
+    obj.link(Link {
+        from: "f",
+        to: "g",
+        at: 1,
+    })
+    .expect("can link f to g");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let names = elf
+        .section_headers
+        .iter()
+        .map(|shdr| &elf.shdr_strtab[shdr.sh_name])
+        .collect::<Vec<_>>();
+    let text_sections = names
+        .iter()
+        .filter(|name| name.starts_with(".text."))
+        .collect::<Vec<_>>();
+    assert_eq!(text_sections, [&".text.f", &".text.g", &".text.h"]);
+    assert!(names.contains(&".data.d"));
+    assert!(names.contains(&".bss.z"));
+
+    // the call from f to g is relocated against g's section
+    let text_g = names
+        .iter()
+        .position(|&name| name == ".text.g")
+        .expect(".text.g exists");
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|&&(idx, _)| names[idx] == ".rela.text.f")
+        .expect(".rela.text.f exists");
+    let sym = elf.syms.get(relocs.iter().next().unwrap().r_sym).unwrap();
+    assert_eq!(sym.st_type(), sym::STT_SECTION);
+    assert_eq!(sym.st_shndx, text_g);
+}
+
+#[test]
+fn weak_import() {
+    use faerie::ImportKind;
+    use goblin::elf::sym::{STB_GLOBAL, STB_WEAK};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.import_weak("new_api", ImportKind::Function)
+        .expect("can import new_api");
+    obj.import("old_api", ImportKind::Function)
+        .expect("can import old_api");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let bind = |name| {
+        let sym = elf
+            .syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .expect("import symbol exists");
+        assert_eq!(sym.st_shndx, section_header::SHN_UNDEF as usize);
+        sym.st_bind()
+    };
+    assert_eq!(bind("new_api"), STB_WEAK);
+    assert_eq!(bind("old_api"), STB_GLOBAL);
+}
+
+#[test]
+fn relaxable_got_loads() {
+    use faerie::{ImportKind, Reloc};
+    use goblin::elf::reloc::{R_X86_64_GOTPCREL, R_X86_64_GOTPCRELX, R_X86_64_REX_GOTPCRELX};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    // movq errno@GOTPCREL(%rip), %rax; movq stdout@GOTPCREL(%rip), %rax;
+    // movl environ@GOTPCREL(%rip), %eax
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![
+            0x48, 0x8b, 0x05, 0, 0, 0, 0, 0x48, 0x8b, 0x05, 0, 0, 0, 0, 0x8b, 0x05, 0, 0, 0, 0,
+        ],
+    )
+    .expect("can declare and define f");
+    for import in &["errno", "stdout", "environ"] {
+        obj.import(*import, ImportKind::Data)
+            .expect("can import data");
+    }
+    obj.link(Link {
+        from: "f",
+        to: "errno",
+        at: 3,
+    })
+    .expect("can link f to errno");
+    obj.link_with(
+        Link {
+            from: "f",
+            to: "stdout",
+            at: 10,
+        },
+        Reloc::RelaxableGotLoad { rex: true },
+    )
+    .expect("can link f to stdout");
+    obj.link_with(
+        Link {
+            from: "f",
+            to: "environ",
+            at: 16,
+        },
+        Reloc::RelaxableGotLoad { rex: false },
+    )
+    .expect("can link f to environ");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let mut relocs = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .map(|reloc| {
+            let sym = elf.syms.get(reloc.r_sym).expect("relocation has a symbol");
+            (
+                reloc.r_offset,
+                reloc.r_type,
+                &elf.strtab[sym.st_name],
+                reloc.r_addend,
+            )
+        })
+        .collect::<Vec<_>>();
+    relocs.sort();
+    assert_eq!(
+        relocs,
+        vec![
+            (3, R_X86_64_GOTPCREL, "errno", Some(-4)),
+            (10, R_X86_64_REX_GOTPCRELX, "stdout", Some(-4)),
+            (16, R_X86_64_GOTPCRELX, "environ", Some(-4)),
+        ]
+    );
+}
+
+#[test]
+fn global_offset_table() {
+    use goblin::elf::reloc::R_X86_64_GOTPC32;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    // leaq _GLOBAL_OFFSET_TABLE_(%rip), %r15; retq
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0x4c, 0x8d, 0x3d, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define f");
+    // only ELF's linker defines the GOT base, and not Mach-O's pseudo-symbols
+    assert!(obj
+        .link(Link {
+            from: "f",
+            to: "_mh_execute_header",
+            at: 3,
+        })
+        .is_err());
+    obj.link(Link {
+        from: "f",
+        to: "_GLOBAL_OFFSET_TABLE_",
+        at: 3,
+    })
+    .expect("can link to the GOT base without declaring it");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let relocs = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .collect::<Vec<_>>();
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].r_offset, 3);
+    assert_eq!(relocs[0].r_type, R_X86_64_GOTPC32);
+    assert_eq!(relocs[0].r_addend, Some(-4));
+    let sym = elf
+        .syms
+        .get(relocs[0].r_sym)
+        .expect("relocation has a symbol");
+    assert_eq!(&elf.strtab[sym.st_name], "_GLOBAL_OFFSET_TABLE_");
+    assert!(sym.is_import());
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    assert!(obj
+        .link(Link {
+            from: "f",
+            to: "_GLOBAL_OFFSET_TABLE_",
+            at: 0,
+        })
+        .is_err());
+}
+
+#[test]
+fn thread_locals() {
+    use faerie::Reloc;
+    use goblin::elf::reloc::R_X86_64_TPOFF32;
+    use goblin::elf::section_header::{SHF_ALLOC, SHF_TLS, SHF_WRITE, SHT_NOBITS, SHT_PROGBITS};
+    use goblin::elf::sym::STT_TLS;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("counter", Decl::data().global().tls())
+        .expect("can declare counter");
+    let mut symbols = std::collections::BTreeMap::new();
+    symbols.insert("counter_hi".to_string(), 4);
+    obj.define_with_symbols("counter", vec![1, 0, 0, 0, 2, 0, 0, 0], symbols)
+        .expect("can define counter");
+    obj.declare("scratch", Decl::data().tls())
+        .expect("can declare scratch");
+    obj.define_zero_init("scratch", 64)
+        .expect("can define scratch");
+    // movl %fs:counter@TPOFF, %eax
+    obj.declare_with(
+        "get",
+        Decl::function().global(),
+        vec![0x64, 0x8b, 0x04, 0x25, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define get");
+    obj.link_with(
+        Link {
+            from: "get",
+            to: "counter",
+            at: 4,
+        },
+        Reloc::Raw {
+            reloc: R_X86_64_TPOFF32,
+            addend: 0,
+        },
+    )
+    .expect("can link get to counter");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let section = |name| {
+        elf.section_headers
+            .iter()
+            .position(|shdr| &elf.shdr_strtab[shdr.sh_name] == name)
+            .expect("section exists")
+    };
+    let tls_flags = u64::from(SHF_ALLOC | SHF_WRITE | SHF_TLS);
+    let tdata = &elf.section_headers[section(".tdata.counter")];
+    assert_eq!(tdata.sh_type, SHT_PROGBITS);
+    assert_eq!(tdata.sh_flags, tls_flags);
+    let tbss = &elf.section_headers[section(".tbss.scratch")];
+    assert_eq!(tbss.sh_type, SHT_NOBITS);
+    assert_eq!(tbss.sh_flags, tls_flags);
+    assert_eq!(tbss.sh_size, 64);
+
+    let symbol = |name| {
+        elf.syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .expect("symbol exists")
+    };
+    // symbol values are offsets in their sections, which the linker lays out in the TLS segment
+    for &(name, shndx, value) in &[
+        ("counter", section(".tdata.counter"), 0),
+        ("counter_hi", section(".tdata.counter"), 4),
+        ("scratch", section(".tbss.scratch"), 0),
+    ] {
+        let sym = symbol(name);
+        assert_eq!(sym.st_type(), STT_TLS);
+        assert_eq!(sym.st_shndx, shndx);
+        assert_eq!(sym.st_value, value);
+    }
+
+    // TLS relocations refer to the thread-local symbol, not its section
+    let relocs = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .map(|reloc| {
+            let sym = elf.syms.get(reloc.r_sym).expect("relocation has a symbol");
+            (reloc.r_offset, reloc.r_type, &elf.strtab[sym.st_name])
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(relocs, vec![(4, R_X86_64_TPOFF32, "counter")]);
+
+    // the relocation depends on the TLS access model, so there is no automatic relocation
+    obj.link(Link {
+        from: "get",
+        to: "counter",
+        at: 4,
+    })
+    .expect("can link get to counter");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn shared_library() {
+    use goblin::elf::dynamic::DT_HASH;
+    use goblin::elf::header::ET_DYN;
+    use goblin::elf::program_header::{PT_DYNAMIC, PT_LOAD};
+    use scroll::Pread;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "libt.so".into());
+    // movl $42, %eax; retq
+    obj.declare_with(
+        "answer",
+        Decl::function().global(),
+        vec![0xb8, 42, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define answer");
+    obj.declare_with("value", Decl::data().global().writable(), vec![7, 0, 0, 0])
+        .expect("can declare and define value");
+    obj.declare_with("helper", Decl::function(), vec![0xc3])
+        .expect("can declare and define helper");
+
+    let bytes = obj.emit_shared_library().expect("can emit shared library");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    assert_eq!(elf.header.e_type, ET_DYN);
+    assert!(elf
+        .program_headers
+        .iter()
+        .any(|phdr| phdr.p_type == PT_LOAD && phdr.is_executable()));
+    let dynamic_phdr = elf
+        .program_headers
+        .iter()
+        .find(|phdr| phdr.p_type == PT_DYNAMIC)
+        .expect("has a PT_DYNAMIC segment");
+    let dynamic = elf.dynamic.as_ref().expect("has dynamic entries");
+
+    let shdr = |name| {
+        elf.section_headers
+            .iter()
+            .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == name)
+            .expect("section exists")
+    };
+    assert_eq!(shdr(".dynamic").sh_addr, dynamic_phdr.p_vaddr);
+    let hash = shdr(".hash");
+    assert!(dynamic
+        .dyns
+        .iter()
+        .any(|d| d.d_tag == DT_HASH && d.d_val == hash.sh_addr));
+
+    // only global symbols are exported
+    let names = elf
+        .dynsyms
+        .iter()
+        .skip(1)
+        .map(|sym| &elf.dynstrtab[sym.st_name])
+        .collect::<Vec<_>>();
+    assert_eq!(names.len(), 2);
+    assert!(names.contains(&"answer") && names.contains(&"value"));
+
+    // every exported symbol can be found through .hash
+    let hash_table = &bytes[hash.sh_offset as usize..][..hash.sh_size as usize];
+    let word = |index: usize| hash_table.pread_with::<u32>(index * 4, scroll::LE).unwrap() as usize;
+    let (nbucket, nchain) = (word(0), word(1));
+    assert_eq!(nchain, elf.dynsyms.len());
+    let lookup = |name: &str| {
+        let mut h = 0u32;
+        for &byte in name.as_bytes() {
+            h = (h << 4).wrapping_add(u32::from(byte));
+            let g = h & 0xf000_0000;
+            h ^= g >> 24;
+            h &= !g;
+        }
+        let mut index = word(2 + h as usize % nbucket);
+        while index != 0 {
+            if &elf.dynstrtab[elf.dynsyms.get(index).unwrap().st_name] == name {
+                return Some(elf.dynsyms.get(index).unwrap());
+            }
+            index = word(2 + nbucket + index);
+        }
+        None
+    };
+    let answer = lookup("answer").expect("answer is in .hash");
+    let text = shdr(".text");
+    assert_eq!(
+        answer.st_shndx,
+        elf.section_headers.iter().position(|s| s == text).unwrap()
+    );
+    assert_eq!(
+        &bytes[answer.st_value as usize..][..6],
+        &[0xb8, 42, 0, 0, 0, 0xc3]
+    );
+    assert!(lookup("value").is_some());
+    assert!(lookup("helper").is_none());
+}
+
+#[test]
+fn shared_library_links() {
+    use faerie::{ArtifactError, Reloc};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "libt.so".into());
+    // callq helper; leaq value(%rip), %rax; retq
+    let code = vec![0xe8, 0, 0, 0, 0, 0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3];
+    obj.declare_with("f", Decl::function().global(), code)
+        .expect("can declare and define f");
+    obj.declare_with("helper", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define helper");
+    obj.declare_with("value", Decl::data().global().writable(), vec![7, 0, 0, 0])
+        .expect("can declare and define value");
+    obj.link_with(
+        Link {
+            from: "f",
+            to: "helper",
+            at: 1,
+        },
+        Reloc::Call,
+    )
+    .expect("can link f to helper");
+    obj.link(Link {
+        from: "f",
+        to: "value",
+        at: 8,
+    })
+    .expect("can link f to value");
+
+    let bytes = obj.emit_shared_library().expect("can emit shared library");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let address = |name: &str| {
+        elf.dynsyms
+            .iter()
+            .find(|sym| &elf.dynstrtab[sym.st_name] == name)
+            .map(|sym| sym.st_value)
+            .expect("symbol is exported")
+    };
+    let (f, helper, value) = (address("f"), address("helper"), address("value"));
+    let field = |at: u64| {
+        let offset = (f + at) as usize;
+        i32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]) as i64
+    };
+    assert_eq!(f as i64 + 5 + field(1), helper as i64);
+    assert_eq!(f as i64 + 12 + field(8), value as i64);
+
+    // an absolute address needs a dynamic relocation
+    obj.declare_with("table", Decl::data().global(), vec![0; 8])
+        .expect("can declare and define table");
+    obj.link(Link {
+        from: "table",
+        to: "f",
+        at: 0,
+    })
+    .expect("can link table to f");
+    let error = obj
+        .emit_shared_library()
+        .expect_err("absolute links are not supported");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::UnsupportedRelocation { from, .. }) if from == "table" => {}
+        _ => panic!("unexpected error {}", error),
+    }
+}
+
+#[test]
+fn verbatim_section() {
+    use faerie::SectionKind;
+    use goblin::elf::section_header::{SHF_ALLOC, SHF_EXECINSTR, SHT_PROGBITS, SHT_SYMTAB};
+    use goblin::elf::sym::{STB_GLOBAL, STT_NOTYPE};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    // a precompiled routine, with an entry point one byte in
+    let blob = vec![0x90, 0xb8, 42, 0, 0, 0, 0xc3];
+    obj.declare(
+        ".text.vendor",
+        Decl::section(SectionKind::Text)
+            .with_elf_flags(u64::from(SHF_ALLOC | SHF_EXECINSTR))
+            .with_align(Some(32)),
+    )
+    .expect("can declare .text.vendor");
+    let mut symbols = std::collections::BTreeMap::new();
+    symbols.insert("vendor_entry".to_string(), 1);
+    obj.define_with_symbols(".text.vendor", blob.clone(), symbols)
+        .expect("can define .text.vendor");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let (shndx, section) = elf
+        .section_headers
+        .iter()
+        .enumerate()
+        .find(|(_, shdr)| &elf.shdr_strtab[shdr.sh_name] == ".text.vendor")
+        .expect(".text.vendor exists");
+    assert_eq!(section.sh_type, SHT_PROGBITS);
+    assert_eq!(section.sh_flags, u64::from(SHF_ALLOC | SHF_EXECINSTR));
+    assert_eq!(section.sh_addralign, 32);
+    let offset = section.sh_offset as usize;
+    assert_eq!(&bytes[offset..offset + blob.len()], &blob[..]);
+
+    // the custom symbol is global, so it follows every local symbol
+    let symtab = elf
+        .section_headers
+        .iter()
+        .find(|shdr| shdr.sh_type == SHT_SYMTAB)
+        .expect("symtab exists");
+    let (index, sym) = elf
+        .syms
+        .iter()
+        .enumerate()
+        .find(|(_, sym)| &elf.strtab[sym.st_name] == "vendor_entry")
+        .expect("vendor_entry exists");
+    assert!(index >= symtab.sh_info as usize);
+    assert_eq!(sym.st_bind(), STB_GLOBAL);
+    assert_eq!(sym.st_type(), STT_NOTYPE);
+    assert_eq!(sym.st_shndx, shndx);
+    assert_eq!(sym.st_value, 1);
+}
+
+#[test]
+fn sparse_data() {
+    use faerie::{ArtifactError, Data};
+    use std::collections::BTreeMap;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("table", Decl::data().global().writable())
+        .expect("can declare table");
+    obj.define_with_symbols(
+        "table",
+        Data::Sparse {
+            size: 64,
+            fill: 0xff,
+            chunks: vec![(0, vec![1, 2, 3, 4]), (48, vec![5; 16])],
+        },
+        BTreeMap::new(),
+    )
+    .expect("can define table");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let section = elf
+        .section_headers
+        .iter()
+        .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == ".data.table")
+        .expect(".data.table exists");
+    assert_eq!(section.sh_size, 64);
+    let offset = section.sh_offset as usize;
+    let contents = &bytes[offset..offset + 64];
+    assert_eq!(&contents[..4], &[1, 2, 3, 4]);
+    assert!(contents[4..48].iter().all(|&byte| byte == 0xff));
+    assert_eq!(&contents[48..], &[5; 16][..]);
+
+    // chunks must be in order, and within the data
+    for chunks in [
+        vec![(8, vec![1]), (0, vec![2])],
+        vec![(0, vec![1, 2]), (1, vec![3])],
+        vec![(60, vec![0; 8])],
+    ]
+    .iter()
+    .cloned()
+    {
+        obj.declare("bad", Decl::data()).expect("can declare bad");
+        match obj.define_with_symbols(
+            "bad",
+            Data::Sparse {
+                size: 64,
+                fill: 0,
+                chunks,
+            },
+            BTreeMap::new(),
+        ) {
+            Err(ArtifactError::InvalidSparseData(ref name)) => assert_eq!(name, "bad"),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn source_file_symbol() {
+    use goblin::elf::sym::{STB_LOCAL, STT_FILE};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    assert_eq!(obj.source_file(), None);
+    obj.set_source_file("src/main.rs");
+    assert_eq!(obj.source_file(), Some("src/main.rs"));
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    // the file symbol is the first after the null symbol
+    let file = elf.syms.get(1).expect("has a file symbol");
+    assert_eq!(file.st_type(), STT_FILE);
+    assert_eq!(file.st_bind(), STB_LOCAL);
+    assert_eq!(file.st_shndx, goblin::elf::section_header::SHN_ABS as usize);
+    assert_eq!(&elf.strtab[file.st_name], "src/main.rs");
+    assert!(elf.syms.iter().all(|sym| &elf.strtab[sym.st_name] != "t.o"));
+}
+
+#[test]
+fn section_boundary_import() {
+    use faerie::{ImportKind, Link};
+    use goblin::elf::reloc::R_X86_64_PC32;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.import("__start_mysection", ImportKind::SectionBoundary)
+        .expect("can import section start");
+    obj.import("__stop_mysection", ImportKind::SectionBoundary)
+        .expect("can import section stop");
+    // only the ELF convention, with a C identifier, names a section boundary
+    assert!(obj
+        .import("__start_.mysection", ImportKind::SectionBoundary)
+        .is_err());
+    assert!(obj
+        .import(
+            "section$start$__DATA$__mysection",
+            ImportKind::SectionBoundary
+        )
+        .is_err());
+    // leaq __start_mysection(%rip), %rax
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define f");
+    obj.link(Link {
+        from: "f",
+        to: "__start_mysection",
+        at: 3,
+    })
+    .expect("can link to section start");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    for name in &["__start_mysection", "__stop_mysection"] {
+        let sym = elf
+            .syms
+            .iter()
+            .find(|sym| &&elf.strtab[sym.st_name] == name)
+            .expect("section boundary symbol exists");
+        assert_eq!(sym.st_shndx, section_header::SHN_UNDEF as usize);
+        assert_eq!(sym.st_bind(), sym::STB_GLOBAL);
+    }
+
+    // the linker defines the boundary in the image, so it is not referred to through the GOT
+    let (_, relocs) = &elf.shdr_relocs[0];
+    let reloc = relocs.iter().next().expect("has a relocation");
+    assert_eq!(reloc.r_type, R_X86_64_PC32);
+    assert_eq!(reloc.r_addend, Some(-4));
+    let sym = elf.syms.get(reloc.r_sym).unwrap();
+    assert_eq!(&elf.strtab[sym.st_name], "__start_mysection");
+}
+
+#[test]
+fn debug_only() {
+    use faerie::{ArtifactError, Reloc, SectionKind};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("g", Decl::function(), vec![0xc3])
+        .expect("can declare and define g");
+    obj.declare_with("x", Decl::data().global(), vec![1, 2, 3, 4])
+        .expect("can declare and define x");
+    obj.declare_with(".debug_str", Decl::section(SectionKind::Debug), vec![0; 16])
+        .expect("can declare and define .debug_str");
+    obj.declare_with(
+        ".debug_info",
+        Decl::section(SectionKind::Debug),
+        vec![0; 16],
+    )
+    .expect("can declare and define .debug_info");
+    for &(to, at, size) in &[(".debug_str", 0, 4), ("f", 4, 8)] {
+        obj.link_with(
+            Link {
+                from: ".debug_info",
+                to,
+                at,
+            },
+            Reloc::Debug { size, addend: 0 },
+        )
+        .expect("can link from .debug_info");
+    }
+
+    let bytes = obj.emit_debug_only().expect("can emit debug only elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let names = elf
+        .section_headers
+        .iter()
+        .map(|shdr| &elf.shdr_strtab[shdr.sh_name])
+        .collect::<Vec<_>>();
+    assert!(names.contains(&".debug_info"));
+    assert!(names.contains(&".debug_str"));
+    assert!(names.contains(&".rela.debug_info"));
+    assert!(!names
+        .iter()
+        .any(|name| name.starts_with(".text") || name.starts_with(".data")));
+
+    // the only named symbol is the function the debug info refers to, which is undefined
+    let named = elf
+        .syms
+        .iter()
+        .filter(|sym| sym.st_type() != sym::STT_FILE && sym.st_name != 0)
+        .collect::<Vec<_>>();
+    assert_eq!(named.len(), 1);
+    assert_eq!(&elf.strtab[named[0].st_name], "f");
+    assert_eq!(named[0].st_shndx, section_header::SHN_UNDEF as usize);
+    let (_, relocs) = &elf.shdr_relocs[0];
+    let symbols = relocs.iter().map(|reloc| reloc.r_sym).collect::<Vec<_>>();
+    assert!(symbols
+        .iter()
+        .any(|&sym| elf.syms.get(sym).unwrap().st_name == named[0].st_name));
+
+    // a debug section cannot refer to a section which is left out
+    obj.declare_with(".text.custom", Decl::section(SectionKind::Text), vec![0xc3])
+        .expect("can declare and define .text.custom");
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: ".text.custom",
+            at: 12,
+        },
+        Reloc::Debug { size: 4, addend: 0 },
+    )
+    .expect("can link .debug_info to .text.custom");
+    let error = obj
+        .emit_debug_only()
+        .expect_err(".text.custom is not a debug section");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::UnsupportedRelocation { from, to, .. }) => {
+            assert_eq!(from, ".debug_info");
+            assert_eq!(to, ".text.custom");
+        }
+        _ => panic!("unexpected error {}", error),
+    }
+}
+
+#[test]
+fn linkonce_section() {
+    use faerie::SectionKind;
+    use std::collections::BTreeMap;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare(".gnu.linkonce.t.inline", Decl::section(SectionKind::Text))
+        .expect("can declare linkonce text section");
+    let mut symbols = BTreeMap::new();
+    symbols.insert("inline".to_string(), 0);
+    obj.define_with_symbols(".gnu.linkonce.t.inline", vec![0xc3], symbols)
+        .expect("can define linkonce text section");
+    obj.declare_with(
+        ".gnu.linkonce.r.table",
+        Decl::section(SectionKind::Data),
+        vec![1, 2, 3, 4],
+    )
+    .expect("can declare and define linkonce read-only section");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let section = |name| {
+        elf.section_headers
+            .iter()
+            .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == name)
+            .expect("section exists")
+    };
+    let text = section(".gnu.linkonce.t.inline");
+    assert_eq!(text.sh_type, section_header::SHT_PROGBITS);
+    assert_eq!(
+        text.sh_flags,
+        u64::from(section_header::SHF_ALLOC | section_header::SHF_EXECINSTR)
+    );
+    let table = section(".gnu.linkonce.r.table");
+    assert_eq!(table.sh_type, section_header::SHT_PROGBITS);
+    assert_eq!(table.sh_flags, u64::from(section_header::SHF_ALLOC));
+    // the linker keeps the copy of the section the global symbol is defined in
+    let inline = elf
+        .syms
+        .iter()
+        .find(|sym| &elf.strtab[sym.st_name] == "inline")
+        .expect("inline symbol exists");
+    assert_eq!(inline.st_bind(), sym::STB_GLOBAL);
+}
+
+#[test]
+fn negative_addends() {
+    use faerie::Reloc;
+
+    // explicit addends are signed
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![0; 8])
+        .expect("can declare and define d");
+    obj.link_with(
+        Link {
+            from: "d",
+            to: "f",
+            at: 0,
+        },
+        Reloc::Absolute {
+            size: 8,
+            addend: -4,
+        },
+    )
+    .expect("can link d to f");
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let relocs = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .map(|reloc| (reloc.r_type, reloc.r_addend))
+        .collect::<Vec<_>>();
+    assert_eq!(relocs, vec![(reloc::R_X86_64_64, Some(-4))]);
+
+    // implicit addends are written in two's complement
+    let mut obj = Artifact::new(triple!("i386-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![0; 4])
+        .expect("can declare and define d");
+    obj.link_with(
+        Link {
+            from: "d",
+            to: "f",
+            at: 0,
+        },
+        Reloc::Raw {
+            reloc: reloc::R_386_32,
+            addend: -4,
+        },
+    )
+    .expect("can link d to f");
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let d = elf
+        .section_headers
+        .iter()
+        .find(|shdr| &elf.shdr_strtab[shdr.sh_name] == ".rodata.d")
+        .expect(".rodata.d exists");
+    assert_eq!(
+        &bytes[d.sh_offset as usize..][..4],
+        &[0xfc, 0xff, 0xff, 0xff]
+    );
+}
+
+#[test]
+fn describe_malformed_object() {
+    use faerie::{describe, ABSOLUTE, COMMON};
+    use goblin::elf::section_header::{SHN_ABS, SHN_COMMON, SHT_PROGBITS, SHT_RELA, SHT_SYMTAB};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![1; 8])
+        .expect("can declare and define d");
+    obj.link(Link {
+        from: "f",
+        to: "d",
+        at: 1,
+    })
+    .expect("can link f to d");
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = Elf::parse(&bytes).expect("can parse elf file");
+
+    // the offsets of the symbol table entry of `d`, and of the section headers
+    let shdr = |sh_type| {
+        let index = elf
+            .section_headers
+            .iter()
+            .position(|shdr| shdr.sh_type == sh_type)
+            .expect("section exists");
+        elf.header.e_shoff as usize + index * 64
+    };
+    let index = elf
+        .syms
+        .iter()
+        .position(|sym| &elf.strtab[sym.st_name] == "d")
+        .expect("d exists");
+    let symtab = elf
+        .section_headers
+        .iter()
+        .find(|shdr| shdr.sh_type == SHT_SYMTAB)
+        .expect("symbol table exists");
+    let sym = symtab.sh_offset as usize + index * 24;
+    let progbits = shdr(SHT_PROGBITS);
+    let rela = shdr(SHT_RELA);
+
+    // absolute and common symbols are not undefined
+    let section_of_d = |bytes: &[u8]| {
+        describe(bytes)
+            .expect("can describe object")
+            .symbols
+            .into_iter()
+            .find(|sym| sym.name == "d")
+            .expect("d is described")
+            .section
+    };
+    let mut absolute = bytes.clone();
+    absolute[sym + 6..sym + 8].copy_from_slice(&(SHN_ABS as u16).to_le_bytes());
+    assert_eq!(section_of_d(&absolute), Some(ABSOLUTE.to_string()));
+    let mut common = bytes.clone();
+    common[sym + 6..sym + 8].copy_from_slice(&(SHN_COMMON as u16).to_le_bytes());
+    assert_eq!(section_of_d(&common), Some(COMMON.to_string()));
+
+    // a reserved section index
+    let mut malformed = bytes.clone();
+    malformed[sym + 6..sym + 8].copy_from_slice(&0xff05u16.to_le_bytes());
+    assert!(describe(&malformed).is_err());
+
+    // a symbol name outside of the string table
+    let mut malformed = bytes.clone();
+    malformed[sym..sym + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(describe(&malformed).is_err());
+
+    // a section name outside of the section header string table
+    let mut malformed = bytes.clone();
+    malformed[progbits..progbits + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(describe(&malformed).is_err());
+
+    // relocations applied to a section which does not exist
+    let mut malformed = bytes.clone();
+    malformed[rela + 44..rela + 48].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(describe(&malformed).is_err());
+}
+
+#[test]
+fn common_symbols() {
+    use faerie::{describe, ArtifactError, Data, Scope, COMMON};
+    use goblin::elf::section_header::SHN_COMMON;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-unknown-unknown-elf"), "t.o".into());
+    obj.declare("c", Decl::data().writable().common().with_align(Some(16)))
+        .expect("can declare c");
+    obj.define_zero_init("c", 24).expect("can define c");
+    // a larger tentative definition replaces the first
+    obj.define_zero_init("c", 32).expect("can define c again");
+    obj.declare_with("l", Decl::data().local(), vec![1; 8])
+        .expect("can declare and define l");
+    obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define f");
+    obj.link(Link {
+        from: "f",
+        to: "c",
+        at: 1,
+    })
+    .expect("can link f to c");
+
+    // only zero-initialized data can be common, and it has no links of its own
+    obj.declare("b", Decl::data().common())
+        .expect("can declare b");
+    match obj.define("b", vec![1; 8]) {
+        Err(ArtifactError::InvalidCommon(ref name)) => assert_eq!(name, "b"),
+        result => panic!("unexpected result {:?}", result),
+    }
+    let error = obj
+        .link(Link {
+            from: "b",
+            to: "f",
+            at: 0,
+        })
+        .expect_err("cannot link from a common symbol");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::InvalidCommon(name)) if name == "b" => {}
+        _ => panic!("unexpected error {}", error),
+    }
+    obj.declare("g", Decl::function().with_scope(Scope::Common))
+        .expect("can declare g");
+    match obj.define("g", vec![0xc3]) {
+        Err(ArtifactError::InvalidCommon(ref name)) => assert_eq!(name, "g"),
+        result => panic!("unexpected result {:?}", result),
+    }
+    obj.undeclare("b").expect("can undeclare b");
+    obj.undeclare("g").expect("can undeclare g");
+    obj.define_with_symbols("c", Data::ZeroInit(32), Default::default())
+        .expect("can define c with no custom symbols");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = Elf::parse(&bytes).expect("can parse elf file");
+    let (index, sym) = elf
+        .syms
+        .iter()
+        .enumerate()
+        .find(|(_, sym)| &elf.strtab[sym.st_name] == "c")
+        .expect("c exists");
+    assert_eq!(sym.st_shndx, SHN_COMMON as usize);
+    // the value of a common symbol is its alignment
+    assert_eq!(sym.st_value, 16);
+    assert_eq!(sym.st_size, 32);
+    assert_eq!(sym.st_bind(), sym::STB_GLOBAL);
+    assert_eq!(sym.st_type(), sym::STT_OBJECT);
+    assert!(!sym.is_import());
+    let symtab = elf
+        .section_headers
+        .iter()
+        .find(|shdr| shdr.sh_type == section_header::SHT_SYMTAB)
+        .expect("symbol table exists");
+    assert!(index >= symtab.sh_info as usize, "c follows the locals");
+
+    // the linker places c, so the relocation refers to its symbol
+    let relocs = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .collect::<Vec<_>>();
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].r_sym, index);
+
+    let description = describe(&bytes).expect("can describe object");
+    let c = description
+        .symbols
+        .iter()
+        .find(|sym| sym.name == "c")
+        .expect("c is described");
+    assert_eq!(c.section, Some(COMMON.to_string()));
+    let addresses = obj.symbol_addresses(0).expect("can compute addresses");
+    assert!(!addresses.contains_key("c"));
+    assert!(addresses.contains_key("f"));
+}
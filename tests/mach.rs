@@ -0,0 +1,3085 @@
+extern crate faerie;
+extern crate goblin;
+extern crate scroll;
+#[macro_use]
+extern crate target_lexicon;
+
+use faerie::{Artifact, Decl};
+use goblin::container::{Container, Ctx, Endian};
+use goblin::mach::relocation::RelocationInfo;
+use goblin::mach::symbols::{Nlist, N_EXT, N_PEXT, N_WEAK_DEF};
+use goblin::mach::{Mach, MachO};
+use std::str::FromStr;
+
+fn parse(bytes: &[u8]) -> MachO<'_> {
+    match goblin::Object::parse(bytes).expect("can parse mach file") {
+        goblin::Object::Mach(Mach::Binary(mach)) => mach,
+        _ => panic!("Mach file not parsed as mach file"),
+    }
+}
+
+fn symbol(mach: &MachO, name: &str) -> Nlist {
+    mach.symbols()
+        .map(|sym| sym.expect("can parse symbol"))
+        .find(|&(sym_name, _)| sym_name == format!("_{}", name))
+        .map(|(_, nlist)| nlist)
+        .expect("symbol should exist")
+}
+
+/// The relocations of the section named `sectname`
+fn relocations(bytes: &[u8], sectname: &str) -> Vec<RelocationInfo> {
+    let mach = parse(bytes);
+    let container = if mach.is_64 {
+        Container::Big
+    } else {
+        Container::Little
+    };
+    let endian = if mach.little_endian {
+        Endian::Little
+    } else {
+        Endian::Big
+    };
+    let (section, _) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == sectname)
+        .expect("section exists");
+    section
+        .iter_relocations(bytes, Ctx::new(container, endian))
+        .map(|reloc| reloc.expect("can parse relocation"))
+        .collect()
+}
+
+#[test]
+fn decl_linkage_and_visibility() {
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    let decls: Vec<(&str, Decl)> = vec![
+        ("local_func", Decl::function().into()),
+        ("global_func", Decl::function().global().into()),
+        ("weak_func", Decl::function().weak().into()),
+        ("hidden_func", Decl::function().global().hidden().into()),
+        (
+            "protected_func",
+            Decl::function().global().protected().into(),
+        ),
+        ("local_hidden_data", Decl::data().hidden().into()),
+        ("weak_hidden_data", Decl::data().weak().hidden().into()),
+    ];
+    for (name, decl) in decls {
+        obj.declare_with(name, decl, vec![1, 2, 3, 4])
+            .expect("can declare and define");
+    }
+    obj.import("imported", faerie::ImportKind::Function)
+        .expect("can import");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+
+    let sym = symbol(&mach, "local_func");
+    assert_eq!(sym.n_type & (N_EXT | N_PEXT), 0);
+    assert_eq!(sym.n_desc & N_WEAK_DEF, 0);
+
+    let sym = symbol(&mach, "global_func");
+    assert_eq!(sym.n_type & (N_EXT | N_PEXT), N_EXT);
+    assert_eq!(sym.n_desc & N_WEAK_DEF, 0);
+
+    let sym = symbol(&mach, "weak_func");
+    assert_eq!(sym.n_type & (N_EXT | N_PEXT), N_EXT);
+    assert_eq!(sym.n_desc & N_WEAK_DEF, N_WEAK_DEF);
+
+    let sym = symbol(&mach, "hidden_func");
+    assert_eq!(sym.n_type & (N_EXT | N_PEXT), N_EXT | N_PEXT);
+
+    let sym = symbol(&mach, "protected_func");
+    assert_eq!(sym.n_type & (N_EXT | N_PEXT), N_EXT);
+
+    let sym = symbol(&mach, "local_hidden_data");
+    assert_eq!(sym.n_type & (N_EXT | N_PEXT), 0);
+
+    let sym = symbol(&mach, "weak_hidden_data");
+    assert_eq!(sym.n_type & (N_EXT | N_PEXT), N_EXT | N_PEXT);
+    assert_eq!(sym.n_desc & N_WEAK_DEF, N_WEAK_DEF);
+
+    let sym = symbol(&mach, "imported");
+    assert!(sym.is_undefined());
+    assert!(sym.is_global());
+}
+
+#[test]
+fn bundle_filetype() {
+    use faerie::{ArtifactBuilder, ArtifactError, MachFileType};
+    use goblin::mach::header::{MH_BUNDLE, MH_DYLIB, MH_OBJECT};
+    use goblin::mach::load_command::{LC_DYLD_INFO_ONLY, LC_ID_DYLIB};
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .name("a.bundle".to_string())
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define");
+    let bytes = obj.emit().expect("can emit object");
+    let mach = parse(&bytes);
+    assert_eq!(mach.header.filetype, MH_OBJECT);
+
+    // neither linked image can be emitted without the load commands it requires
+    obj.mach_filetype = MachFileType::Bundle;
+    let error = obj.emit().expect_err("cannot emit a bundle");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::MissingLoadCommand {
+            filetype: MachFileType::Bundle,
+            command: "LC_DYLD_INFO",
+        }) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+
+    let dyld_info = vec![0; 40];
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_filetype(MachFileType::Bundle)
+        .mach_load_command(LC_DYLD_INFO_ONLY, dyld_info.clone())
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define");
+    let bytes = obj.emit().expect("can emit bundle");
+    let mach = parse(&bytes);
+    assert_eq!(mach.header.filetype, MH_BUNDLE);
+
+    let dylib = || {
+        ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+            .mach_filetype(MachFileType::Dylib)
+            .mach_load_command(LC_DYLD_INFO_ONLY, dyld_info.clone())
+    };
+    let error = dylib().finish().emit().expect_err("cannot emit a dylib");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::MissingLoadCommand {
+            filetype: MachFileType::Dylib,
+            command: "LC_ID_DYLIB",
+        }) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+
+    // the dylib's name follows the command header and its dylib struct
+    let mut id_dylib = 24u32.to_le_bytes().to_vec();
+    id_dylib.extend_from_slice(&[0; 12]);
+    id_dylib.extend_from_slice(b"libt.dylib\0");
+    let obj = dylib().mach_load_command(LC_ID_DYLIB, id_dylib).finish();
+    let bytes = obj.emit().expect("can emit dylib");
+    let mach = parse(&bytes);
+    assert_eq!(mach.header.filetype, MH_DYLIB);
+    assert!(mach
+        .load_commands
+        .iter()
+        .any(|command| command.command.cmd() == LC_ID_DYLIB));
+}
+
+#[test]
+fn emit_with_relocations_matches_object() {
+    use faerie::Link;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare("imported", Decl::function_import())
+        .expect("can declare import");
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0],
+    )
+    .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().writable(), vec![0; 8])
+        .expect("can declare and define d");
+    obj.link(Link {
+        from: "f",
+        to: "imported",
+        at: 1,
+    })
+    .expect("can link from f to imported");
+    obj.link(Link {
+        from: "d",
+        to: "f",
+        at: 0,
+    })
+    .expect("can link from d to f");
+
+    let (bytes, sections, resolved_relocations) = obj.emit_with_relocations().expect("can emit");
+    let mach = parse(&bytes);
+
+    let parsed_sections = mach.segments.sections().flatten().map(|section| {
+        let (section, _data) = section.expect("can parse section");
+        section
+    });
+    let mut parsed_sections = parsed_sections.collect::<Vec<_>>();
+    assert_eq!(sections.len(), parsed_sections.len());
+    for (resolved, section) in sections.iter().zip(parsed_sections.iter()) {
+        assert_eq!(resolved.name, section.name().unwrap());
+        assert_eq!(resolved.size, section.size);
+        assert_eq!(resolved.offset, u64::from(section.offset));
+    }
+
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").0)
+        .collect::<Vec<_>>();
+    let mut parsed = Vec::new();
+    for section in parsed_sections.drain(..) {
+        let sectname = section.name().unwrap();
+        for reloc in relocations(&bytes, sectname) {
+            parsed.push((sectname.to_string(), reloc));
+        }
+    }
+    assert_eq!(resolved_relocations.len(), 2);
+    assert_eq!(resolved_relocations.len(), parsed.len());
+    for (resolved, (section, reloc)) in resolved_relocations.iter().zip(parsed) {
+        assert_eq!(resolved.section, section);
+        assert_eq!(resolved.offset, reloc.r_address as u64);
+        assert_eq!(resolved.reloc, u32::from(reloc.r_type()));
+        assert_eq!(
+            format!("_{}", resolved.symbol),
+            symbols[reloc.r_symbolnum()]
+        );
+    }
+}
+
+#[test]
+fn data_symbols_at_offsets() {
+    use faerie::Data;
+    use std::collections::BTreeMap;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("before", Decl::data().global(), vec![0; 8])
+        .expect("can declare and define before");
+    obj.declare("table", Decl::data().global())
+        .expect("can declare table");
+    let mut symbols = BTreeMap::new();
+    symbols.insert("table_start".to_string(), 0);
+    symbols.insert("table_middle".to_string(), 16);
+    obj.define_with_symbols("table", Data::Blob(vec![0; 32]), symbols)
+        .expect("can define table with symbols");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let table = symbol(&mach, "table");
+    let start = symbol(&mach, "table_start");
+    let middle = symbol(&mach, "table_middle");
+    assert_eq!(table.n_value, 8);
+    assert_eq!(start.n_value, table.n_value);
+    assert_eq!(middle.n_value, table.n_value + 16);
+    assert_eq!(middle.n_sect, table.n_sect);
+    assert!(middle.is_global());
+}
+
+#[test]
+fn debug_ranges_relocations() {
+    use faerie::{Link, Reloc, SectionKind};
+    use goblin::mach::relocation::X86_64_RELOC_UNSIGNED;
+    use scroll::Pread;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function().global(), vec![0x90; 5])
+        .expect("can declare and define f");
+    obj.declare_with(
+        ".debug_ranges",
+        Decl::section(SectionKind::Debug),
+        vec![0; 32],
+    )
+    .expect("can declare and define .debug_ranges");
+    // [f, f + 5), followed by the end of list entry
+    for &(at, addend) in &[(0, 0), (8, 5)] {
+        obj.link_with(
+            Link {
+                from: ".debug_ranges",
+                to: "f",
+                at,
+            },
+            Reloc::Debug { size: 8, addend },
+        )
+        .expect("can link from .debug_ranges to f");
+    }
+
+    let (bytes, sections, resolved_relocations) = obj.emit_with_relocations().expect("can emit");
+    let mach = parse(&bytes);
+    let f = symbol(&mach, "f");
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").1)
+        .collect::<Vec<_>>();
+
+    let section = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__debug_ranges")
+        .expect("debug ranges section exists");
+    let resolved = sections
+        .iter()
+        .find(|section| section.name == "__debug_ranges")
+        .expect("debug ranges section is resolved");
+    assert_eq!(u64::from(section.offset), resolved.offset);
+    assert_eq!(
+        resolved_relocations
+            .iter()
+            .map(|reloc| (&*reloc.section, reloc.offset, &*reloc.symbol))
+            .collect::<Vec<_>>(),
+        vec![("__debug_ranges", 8, "f"), ("__debug_ranges", 0, "f")]
+    );
+
+    for reloc in relocations(&bytes, "__debug_ranges") {
+        assert_eq!(reloc.r_type(), X86_64_RELOC_UNSIGNED);
+        assert_eq!(reloc.r_length(), 3);
+        assert_eq!(reloc.r_pcrel(), 0);
+        assert_eq!(reloc.r_extern(), 1);
+        assert_eq!(symbols[reloc.r_symbolnum()].n_value, f.n_value);
+        assert_eq!(symbols[reloc.r_symbolnum()].n_strx, f.n_strx);
+    }
+
+    // the addends are implicit in the section contents
+    let contents = &bytes[section.offset as usize..][..32];
+    assert_eq!(contents.pread_with::<u64>(0, scroll::LE).unwrap(), 0);
+    assert_eq!(contents.pread_with::<u64>(8, scroll::LE).unwrap(), 5);
+}
+
+#[test]
+fn two_level_namespace_imports() {
+    use faerie::ImportKind;
+
+    let libsystem = "/usr/lib/libSystem.B.dylib";
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import("flat", ImportKind::Function)
+        .expect("can import flat");
+    obj.import_from("malloc", ImportKind::Function, libsystem)
+        .expect("can import malloc from libSystem");
+    obj.import_from("errno", ImportKind::Data, libsystem)
+        .expect("can import errno from libSystem");
+    obj.import_from(
+        "objc_msgSend",
+        ImportKind::Function,
+        "/usr/lib/libobjc.A.dylib",
+    )
+    .expect("can import objc_msgSend from libobjc");
+    assert_eq!(obj.import_library("malloc"), Some(libsystem));
+    assert_eq!(obj.import_library("flat"), None);
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    assert_eq!(mach.libs[1..], [libsystem, "/usr/lib/libobjc.A.dylib"][..]);
+
+    // the library ordinal is the high byte of n_desc, indexing the LC_LOAD_DYLIB commands from 1
+    let ordinal = |name| symbol(&mach, name).n_desc >> 8;
+    assert_eq!(ordinal("flat"), 0);
+    assert_eq!(ordinal("malloc"), 1);
+    assert_eq!(ordinal("errno"), 1);
+    assert_eq!(ordinal("objc_msgSend"), 2);
+    assert!(symbol(&mach, "malloc").is_undefined());
+}
+
+#[test]
+fn page_aligned_sections() {
+    use faerie::SectionKind;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3; 3])
+        .expect("can declare and define f");
+    obj.declare_with(
+        "page",
+        Decl::data().global().with_align(Some(0x1000)),
+        vec![1; 5],
+    )
+    .expect("can declare and define page");
+    obj.declare_with(
+        "__aligned",
+        Decl::section(SectionKind::Data).with_align(Some(0x1000)),
+        vec![2; 7],
+    )
+    .expect("can declare and define __aligned");
+
+    let (bytes, resolved, _) = obj.emit_with_relocations().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    for &(name, fill, size) in &[("__data", 1, 5), ("__aligned", 2, 7)] {
+        let (section, data) = sections
+            .iter()
+            .find(|(section, _)| section.name().unwrap() == name)
+            .expect("section exists");
+        assert_eq!(section.offset % 0x1000, 0);
+        assert_eq!(section.addr % 0x1000, 0);
+        assert_eq!(section.align, 12);
+        assert_eq!(&data[..size], &vec![fill; size][..]);
+        let resolved = resolved
+            .iter()
+            .find(|section| section.name == name)
+            .expect("section is resolved");
+        assert_eq!(resolved.offset, u64::from(section.offset));
+    }
+    let data = &sections[1].0;
+    assert_eq!(symbol(&mach, "page").n_value, data.addr);
+}
+
+#[test]
+fn function_starts() {
+    use faerie::ArtifactBuilder;
+    use goblin::mach::load_command::CommandVariant;
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_function_starts(true)
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0x90; 3])
+        .expect("can declare and define f");
+    obj.declare_with("g", Decl::function(), vec![0x90; 200])
+        .expect("can declare and define g");
+    obj.declare_with("h", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define h");
+    obj.declare_with("d", Decl::data(), vec![0; 8])
+        .expect("can declare and define d");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let function_starts = mach
+        .load_commands
+        .iter()
+        .filter_map(|lc| match lc.command {
+            CommandVariant::FunctionStarts(command) => Some(command),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(function_starts.len(), 1);
+    let command = function_starts[0];
+    assert_eq!(command.dataoff % 8, 0);
+    assert_eq!(command.datasize % 8, 0);
+    let table = &bytes[command.dataoff as usize..][..command.datasize as usize];
+
+    // decode the ULEB128 deltas, up to the terminating zero
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    let mut bytes = table.iter();
+    loop {
+        let mut delta = 0u64;
+        let mut shift = 0;
+        for &byte in &mut bytes {
+            delta |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        if delta == 0 {
+            break;
+        }
+        offset += delta;
+        offsets.push(offset);
+    }
+
+    let text = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__text")
+        .expect("text section exists");
+    // local definitions are laid out before global ones
+    let expected = ["g", "f", "h"]
+        .iter()
+        .map(|name| u64::from(text.offset) + symbol(&mach, name).n_value - text.addr)
+        .collect::<Vec<_>>();
+    assert_eq!(offsets, expected);
+
+    // the table is only emitted when asked for
+    obj.mach_function_starts = false;
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    assert!(mach.load_commands.iter().all(|lc| match lc.command {
+        CommandVariant::FunctionStarts(_) => false,
+        _ => true,
+    }));
+}
+
+#[test]
+fn zero_init_extent() {
+    use faerie::SectionKind;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("d", Decl::data().global(), vec![1; 8])
+        .expect("can declare and define d");
+    obj.declare("zeroes", Decl::data().global().writable())
+        .expect("can declare zeroes");
+    obj.define_zero_init("zeroes", 1024)
+        .expect("can define zeroes");
+    obj.declare("more_zeroes", Decl::data().global().writable())
+        .expect("can declare more_zeroes");
+    obj.define_zero_init("more_zeroes", 16)
+        .expect("can define more_zeroes");
+    obj.declare_with("__custom", Decl::section(SectionKind::Data), vec![2; 4])
+        .expect("can declare and define __custom");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let segment = &mach.segments[0];
+    let sections = segment
+        .sections()
+        .expect("can parse sections")
+        .into_iter()
+        .map(|(section, _)| section)
+        .collect::<Vec<_>>();
+    let bss = sections
+        .iter()
+        .find(|section| section.name().unwrap() == "__bss")
+        .expect("bss section exists");
+    assert_eq!(bss.size, 1024 + 16);
+    assert_eq!(bss.offset, 0);
+    // the zero-initialized data is in memory after everything in the file
+    for section in sections.iter().filter(|section| section.size > 0) {
+        assert!(section.addr + section.size <= bss.addr + bss.size);
+    }
+    assert_eq!(segment.vmsize, bss.addr + bss.size);
+    assert!(segment.filesize <= bss.addr);
+
+    let zeroes = symbol(&mach, "zeroes").n_value;
+    let more_zeroes = symbol(&mach, "more_zeroes").n_value;
+    let (first, second) = (zeroes.min(more_zeroes), zeroes.max(more_zeroes));
+    assert_eq!(first, bss.addr);
+    assert_eq!(second - first, if first == zeroes { 1024 } else { 16 });
+
+    // the custom section's contents follow the data in the file
+    let custom = sections
+        .iter()
+        .find(|section| section.name().unwrap() == "__custom")
+        .expect("custom section exists");
+    assert_eq!(&bytes[custom.offset as usize..][..4], &[2; 4]);
+}
+
+#[test]
+fn computed_protection() {
+    use faerie::{ArtifactBuilder, MachProtection};
+    use goblin::mach::constants::{VM_PROT_EXECUTE, VM_PROT_READ, VM_PROT_WRITE};
+
+    let protection = |obj: &Artifact| {
+        let bytes = obj.emit().expect("can emit mach file");
+        let segment = &parse(&bytes).segments[0];
+        assert_eq!(segment.initprot, segment.maxprot);
+        segment.initprot
+    };
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_protection(MachProtection::Computed)
+        .finish();
+    obj.declare_with("d", Decl::data(), vec![1; 8])
+        .expect("can declare and define d");
+    assert_eq!(protection(&obj), VM_PROT_READ);
+
+    obj.declare_with("w", Decl::data().writable(), vec![1; 8])
+        .expect("can declare and define w");
+    assert_eq!(protection(&obj), VM_PROT_READ | VM_PROT_WRITE);
+
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    assert_eq!(
+        protection(&obj),
+        VM_PROT_READ | VM_PROT_WRITE | VM_PROT_EXECUTE
+    );
+
+    // everything is allowed by default
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("d", Decl::data(), vec![1; 8])
+        .expect("can declare and define d");
+    assert_eq!(
+        protection(&obj),
+        VM_PROT_READ | VM_PROT_WRITE | VM_PROT_EXECUTE
+    );
+}
+
+#[test]
+fn function_pointer_table() {
+    use faerie::Link;
+    use goblin::mach::relocation::X86_64_RELOC_UNSIGNED;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("g", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define g");
+    obj.declare_with("table", Decl::data().global(), vec![0; 16])
+        .expect("can declare and define table");
+    for &(to, at) in &[("f", 0), ("g", 8)] {
+        obj.link(Link {
+            from: "table",
+            to,
+            at,
+        })
+        .expect("can link from table to function");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").0)
+        .collect::<Vec<_>>();
+    let relocations = relocations(&bytes, "__data");
+    assert_eq!(relocations.len(), 2);
+    let mut targets = Vec::new();
+    for reloc in relocations {
+        // an absolute, 8 byte pointer to the function symbol
+        assert_eq!(reloc.r_type(), X86_64_RELOC_UNSIGNED);
+        assert_eq!(reloc.r_length(), 3);
+        assert_eq!(reloc.r_pcrel(), 0);
+        assert_eq!(reloc.r_extern(), 1);
+        targets.push((reloc.r_address, symbols[reloc.r_symbolnum()]));
+    }
+    targets.sort();
+    assert_eq!(targets, vec![(0, "_f"), (8, "_g")]);
+}
+
+#[test]
+fn custom_section_flags() {
+    use faerie::SectionKind;
+    use goblin::mach::constants::{SECTION_TYPE, S_ATTR_DEBUG, S_ATTR_NO_TOC, S_COALESCED};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with(
+        "__coalesced",
+        Decl::section(SectionKind::Data).with_mach_flags(S_COALESCED | S_ATTR_NO_TOC),
+        vec![1; 4],
+    )
+    .expect("can declare and define __coalesced");
+    obj.declare_with(
+        ".debug_info",
+        Decl::section(SectionKind::Debug).with_mach_flags(S_ATTR_NO_TOC),
+        vec![2; 4],
+    )
+    .expect("can declare and define .debug_info");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let flags = |name| {
+        mach.segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section").0)
+            .find(|section| section.name().unwrap() == name)
+            .expect("section exists")
+            .flags
+    };
+    assert_eq!(flags("__coalesced") & SECTION_TYPE, S_COALESCED);
+    assert_eq!(flags("__coalesced") & S_ATTR_NO_TOC, S_ATTR_NO_TOC);
+    // the flags are added to those implied by the section kind
+    assert_eq!(flags("__debug_info"), S_ATTR_DEBUG | S_ATTR_NO_TOC);
+}
+
+#[test]
+fn layout() {
+    use faerie::{Link, ResolvedSymbol};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function().global(), vec![0x90; 3])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![0; 8])
+        .expect("can declare and define d");
+    obj.declare_with("e", Decl::data().global(), vec![0; 8])
+        .expect("can declare and define e");
+    obj.link(Link {
+        from: "e",
+        to: "f",
+        at: 0,
+    })
+    .expect("can link from e to f");
+
+    let layout = obj.layout().expect("can compute layout");
+    // the header, then a segment command with 2 sections, a symtab command and a dysymtab command
+    let text = &layout.sections[0];
+    assert_eq!(text.name, "__text");
+    assert_eq!(text.offset, 32 + (72 + 2 * 80) + 24 + 80);
+    let symbol = |name: &str| {
+        layout
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .expect("symbol is laid out")
+            .clone()
+    };
+    assert_eq!(
+        symbol("f"),
+        ResolvedSymbol {
+            name: "f".to_string(),
+            section: "__text".to_string(),
+            offset: 0,
+        }
+    );
+    assert_eq!(symbol("d").section, "__data");
+    assert_eq!(symbol("e").offset, symbol("d").offset + 8);
+    assert_eq!(layout.relocations.len(), 1);
+
+    // the layout is the one the object is emitted with
+    let (bytes, sections, relocations) = obj.emit_with_relocations().expect("can emit");
+    assert_eq!(layout.sections, sections);
+    assert_eq!(layout.relocations, relocations);
+    let mach = parse(&bytes);
+    let data = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__data")
+        .expect("data section exists");
+    assert_eq!(
+        data.addr + symbol("e").offset,
+        crate::symbol(&mach, "e").n_value
+    );
+}
+
+#[test]
+fn section_minimum_alignment() {
+    use faerie::ArtifactBuilder;
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .name("a".into())
+        .mach_section_align("__data", 64)
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("a", Decl::data().global(), vec![1; 3])
+        .expect("can declare and define a");
+    obj.declare_with("b", Decl::data().global(), vec![2; 8])
+        .expect("can declare and define b");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let (data, contents) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__data")
+        .expect("__data exists");
+    assert_eq!(data.align, 6);
+    assert_eq!(data.addr % 64, 0);
+    assert_eq!(data.offset % 64, 0);
+    // the members are still only padded to 8 bytes
+    assert_eq!(data.size, 16);
+    assert_eq!(symbol(&mach, "b").n_value - symbol(&mach, "a").n_value, 8);
+    assert_eq!(&contents[..3], &[1; 3]);
+    assert_eq!(&contents[8..16], &[2; 8]);
+}
+
+#[test]
+fn literal_sections() {
+    use faerie::{DataType, Link, SectionKind};
+    use goblin::mach::constants::{SECTION_TYPE, S_8BYTE_LITERALS};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with(
+        "pi",
+        Decl::data().with_datatype(DataType::Literal8),
+        std::f64::consts::PI.to_le_bytes().to_vec(),
+    )
+    .expect("can declare and define pi");
+    obj.declare_with("__custom", Decl::section(SectionKind::Data), vec![1, 2, 3])
+        .expect("can declare and define __custom");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    let names = sections
+        .iter()
+        .map(|(section, _)| section.name().unwrap())
+        .collect::<Vec<_>>();
+    // only the sections in use are emitted, and the literal sections before the custom sections
+    assert_eq!(names, ["__literal8", "__custom"]);
+    let (literal8, data) = &sections[0];
+    assert_eq!(literal8.segname().unwrap(), "__TEXT");
+    assert_eq!(literal8.flags & SECTION_TYPE, S_8BYTE_LITERALS);
+    assert_eq!(literal8.align, 3);
+    assert_eq!(literal8.addr % 8, 0);
+    assert_eq!(literal8.size, 8);
+    assert_eq!(&data[..], &std::f64::consts::PI.to_le_bytes()[..]);
+    let pi = symbol(&mach, "pi");
+    assert_eq!(pi.n_sect, 1);
+    assert_eq!(pi.n_value, literal8.addr);
+    assert_eq!(&sections[1].1[..], &[1, 2, 3]);
+
+    // each literal is exactly one constant
+    obj.declare_with(
+        "e",
+        Decl::data().with_datatype(DataType::Literal4),
+        vec![0; 8],
+    )
+    .expect("can declare and define e");
+    assert!(obj.emit().is_err());
+    obj.remove("e").expect("can remove e");
+
+    // the literal sections are read-only
+    obj.declare_with(
+        "e",
+        Decl::data().with_datatype(DataType::Literal4).writable(),
+        vec![1; 4],
+    )
+    .expect("can declare and define e");
+    assert!(obj.emit().is_err());
+    obj.remove("e").expect("can remove e");
+    obj.emit().expect("can emit mach file");
+
+    // and the linker cannot relocate them
+    obj.declare("f", Decl::function_import())
+        .expect("can declare f");
+    obj.link(Link {
+        from: "pi",
+        to: "f",
+        at: 0,
+    })
+    .expect("can link pi to f");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn no_symbols() {
+    use faerie::SectionKind;
+    use goblin::mach::load_command::CommandVariant;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("__blob", Decl::section(SectionKind::Data), vec![7; 5])
+        .expect("can declare and define __blob");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    assert_eq!(mach.symbols().count(), 0);
+    let symtab = mach
+        .load_commands
+        .iter()
+        .find_map(|lc| match lc.command {
+            CommandVariant::Symtab(symtab) => Some(symtab),
+            _ => None,
+        })
+        .expect("has a symtab command");
+    assert_eq!(symtab.nsyms, 0);
+    assert_eq!(symtab.strsize, 1);
+    assert_eq!(symtab.symoff, symtab.stroff);
+    assert!(symtab.stroff as usize + symtab.strsize as usize <= bytes.len());
+    assert_eq!(bytes[symtab.stroff as usize], 0);
+    let (_, data) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__blob")
+        .expect("__blob exists");
+    assert_eq!(data, &[7; 5]);
+}
+
+#[test]
+fn default_section_alignment() {
+    use faerie::ArtifactBuilder;
+
+    let align_of = |mut obj: Artifact, name: &str| {
+        obj.declare_with("f", Decl::function(), vec![0xc3])
+            .expect("can declare and define f");
+        obj.declare_with("d", Decl::data().writable(), vec![0])
+            .expect("can declare and define d");
+        let bytes = obj.emit().expect("can emit mach file");
+        let mach = parse(&bytes);
+        let section = mach
+            .segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section").0)
+            .find(|section| section.name().unwrap() == name)
+            .expect("section exists");
+        section.align
+    };
+
+    // the default sections are as aligned as their minimum, even when their members are not
+    let obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    assert_eq!(align_of(obj, "__text"), 4);
+    let obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    assert_eq!(align_of(obj, "__data"), 3);
+
+    let obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_section_align("__data", 32)
+        .finish();
+    assert_eq!(align_of(obj, "__data"), 5);
+}
+
+#[test]
+fn omitted_default_sections() {
+    use faerie::Data;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("d", Decl::data().global().writable(), vec![1, 2, 3, 4])
+        .expect("can declare and define d");
+    obj.declare("z", Decl::data().global().writable())
+        .expect("can declare z");
+    obj.define_with_symbols("z", Data::ZeroInit(16), Default::default())
+        .expect("can define z");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .collect::<Vec<_>>();
+    let names = sections
+        .iter()
+        .map(|section| section.name().unwrap())
+        .collect::<Vec<_>>();
+    // only the sections with definitions are emitted, and numbered in the order they are
+    assert_eq!(names, ["__data", "__bss"]);
+    assert_eq!(symbol(&mach, "d").n_sect, 1);
+    assert_eq!(symbol(&mach, "z").n_sect, 2);
+    assert_eq!(symbol(&mach, "z").n_value, sections[1].addr);
+}
+
+#[test]
+fn raw_section() {
+    use faerie::{ArtifactError, SectionKind};
+    use goblin::mach::constants::S_ATTR_NO_DEAD_STRIP;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    let manifest = b"faerie!".to_vec();
+    obj.declare_with(
+        "__manifest",
+        Decl::section(SectionKind::Data)
+            .raw()
+            .with_align(Some(64))
+            .with_mach_flags(S_ATTR_NO_DEAD_STRIP),
+        manifest.clone(),
+    )
+    .expect("can declare and define __manifest");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let (section, data) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__manifest")
+        .expect("__manifest exists");
+    assert_eq!(section.align, 0);
+    assert_eq!(section.flags, S_ATTR_NO_DEAD_STRIP);
+    assert_eq!(section.nreloc, 0);
+    assert_eq!(data, &manifest[..]);
+    let names = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").0)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["_f"]);
+
+    // raw sections cannot be relocated
+    obj.link(faerie::Link {
+        from: "__manifest",
+        to: "f",
+        at: 0,
+    })
+    .expect("can link");
+    match obj.validate() {
+        Err(ref errors) => match errors[..] {
+            [ArtifactError::RawSection(ref name)] => assert_eq!(name, "__manifest"),
+            _ => panic!("unexpected errors {:?}", errors),
+        },
+        Ok(()) => panic!("raw section links are invalid"),
+    }
+}
+
+#[test]
+fn header_flags() {
+    use faerie::ArtifactBuilder;
+    use goblin::mach::header::{MH_NOUNDEFS, MH_PIE, MH_SUBSECTIONS_VIA_SYMBOLS};
+
+    let obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    let bytes = obj.emit().expect("can emit mach file");
+    assert_eq!(parse(&bytes).header.flags, MH_SUBSECTIONS_VIA_SYMBOLS);
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_header_flags(MH_NOUNDEFS)
+        .finish();
+    let bytes = obj.emit().expect("can emit mach file");
+    assert_eq!(parse(&bytes).header.flags, MH_NOUNDEFS);
+
+    obj.mach_header_flags = Some(MH_SUBSECTIONS_VIA_SYMBOLS | MH_PIE);
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn eh_frame() {
+    use faerie::{ImportKind, Link, SectionKind};
+    use goblin::mach::constants::{
+        S_ATTR_LIVE_SUPPORT, S_ATTR_NO_TOC, S_ATTR_STRIP_STATIC_SYMS, S_COALESCED,
+    };
+    use goblin::mach::relocation::X86_64_RELOC_GOT;
+
+    // a CIE with a personality routine, and an FDE for f, both with pc-relative pointers
+    let mut cfi = vec![];
+    cfi.extend_from_slice(&28u32.to_le_bytes()); // length
+    cfi.extend_from_slice(&0u32.to_le_bytes()); // CIE id
+    cfi.push(1); // version
+    cfi.extend_from_slice(b"zPR\0"); // augmentation
+    cfi.extend_from_slice(&[1, 0x78, 0x10]); // code align, data align, return address register
+    cfi.push(6); // augmentation data length
+    cfi.push(0x9b); // personality: indirect, pc-relative, sdata4
+    const PERSONALITY: u64 = 18;
+    cfi.extend_from_slice(&[0; 4]);
+    cfi.push(0x1b); // FDE pointers: pc-relative, sdata4
+    cfi.extend_from_slice(&[0x0c, 0x07, 0x08, 0x90, 0x01, 0, 0, 0, 0]); // instructions, padding
+    assert_eq!(cfi.len(), 32);
+    cfi.extend_from_slice(&16u32.to_le_bytes()); // length
+    cfi.extend_from_slice(&36u32.to_le_bytes()); // CIE pointer
+    const PC_BEGIN: u64 = 40;
+    cfi.extend_from_slice(&[0; 4]);
+    cfi.extend_from_slice(&1u32.to_le_bytes()); // pc range
+    cfi.extend_from_slice(&[0, 0, 0, 0]); // augmentation data length, padding
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.import("personality", ImportKind::Function)
+        .expect("can import personality");
+    obj.declare_with(".eh_frame", Decl::section(SectionKind::Text), cfi.clone())
+        .expect("can declare and define .eh_frame");
+    for &(to, at) in &[("personality", PERSONALITY), ("f", PC_BEGIN)] {
+        obj.link(Link {
+            from: ".eh_frame",
+            to,
+            at,
+        })
+        .expect("can link");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let (section, contents) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__eh_frame")
+        .expect("__eh_frame exists");
+    assert_eq!(section.segname().unwrap(), "__TEXT");
+    assert_eq!(
+        section.flags,
+        S_COALESCED | S_ATTR_NO_TOC | S_ATTR_STRIP_STATIC_SYMS | S_ATTR_LIVE_SUPPORT
+    );
+
+    // the FDE's reference to f is resolved
+    let mut pc_begin = [0; 4];
+    pc_begin.copy_from_slice(&contents[PC_BEGIN as usize..][..4]);
+    let f = symbol(&mach, "f");
+    assert_eq!(
+        i64::from(i32::from_le_bytes(pc_begin)),
+        f.n_value as i64 - (section.addr + PC_BEGIN) as i64
+    );
+    assert_eq!(&contents[..PC_BEGIN as usize], &cfi[..PC_BEGIN as usize]);
+
+    // the personality routine is found through the GOT
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol"))
+        .collect::<Vec<_>>();
+    let relocations = relocations(&bytes, "__eh_frame");
+    assert_eq!(relocations.len(), 1);
+    let reloc = relocations[0];
+    assert_eq!(reloc.r_address as u64, PERSONALITY);
+    assert_eq!(reloc.r_type(), X86_64_RELOC_GOT);
+    assert_eq!(reloc.r_length(), 2);
+    assert_eq!(reloc.r_pcrel(), 1);
+    assert_eq!(reloc.r_extern(), 1);
+    assert_eq!(symbols[reloc.r_symbolnum()].0, "_personality");
+}
+
+#[test]
+fn thread_locals() {
+    use faerie::Link;
+    use goblin::mach::constants::{
+        SECTION_TYPE, S_THREAD_LOCAL_REGULAR, S_THREAD_LOCAL_VARIABLES, S_THREAD_LOCAL_ZEROFILL,
+    };
+    use goblin::mach::relocation::{X86_64_RELOC_TLV, X86_64_RELOC_UNSIGNED};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("counter", Decl::data().global().tls(), vec![1, 2, 3, 4])
+        .expect("can declare and define counter");
+    obj.declare("zeroed", Decl::data().tls())
+        .expect("can declare zeroed");
+    obj.define_zero_init("zeroed", 8)
+        .expect("can define zeroed");
+    obj.declare_with("f", Decl::function(), vec![0; 8])
+        .expect("can declare and define f");
+    obj.link(Link {
+        from: "f",
+        to: "counter",
+        at: 3,
+    })
+    .expect("can link f to counter");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    let section = |name| {
+        sections
+            .iter()
+            .position(|(section, _)| section.name().unwrap() == name)
+            .expect("section exists")
+    };
+    let (thread_vars, _) = &sections[section("__thread_vars")];
+    let (thread_data, data) = &sections[section("__thread_data")];
+    let (thread_bss, _) = &sections[section("__thread_bss")];
+    assert_eq!(thread_vars.flags & SECTION_TYPE, S_THREAD_LOCAL_VARIABLES);
+    assert_eq!(thread_data.flags & SECTION_TYPE, S_THREAD_LOCAL_REGULAR);
+    assert_eq!(thread_bss.flags & SECTION_TYPE, S_THREAD_LOCAL_ZEROFILL);
+    // one three pointer descriptor per variable
+    assert_eq!(thread_vars.size, 2 * 24);
+    assert_eq!(&data[..], &[1, 2, 3, 4]);
+    assert_eq!(thread_bss.size, 8);
+
+    // the variables are their descriptors, initialized from their `$tlv$init` symbols
+    let counter = symbol(&mach, "counter");
+    assert_eq!(counter.n_sect, section("__thread_vars") + 1);
+    assert!(counter.is_global());
+    let zeroed = symbol(&mach, "zeroed");
+    assert_eq!(zeroed.n_sect, section("__thread_vars") + 1);
+    let counter_descriptor = counter.n_value - thread_vars.addr;
+    let zeroed_descriptor = zeroed.n_value - thread_vars.addr;
+    let mut descriptors = [counter_descriptor, zeroed_descriptor];
+    descriptors.sort();
+    assert_eq!(descriptors, [0, 24]);
+    let counter_init = symbol(&mach, "counter$tlv$init");
+    assert_eq!(counter_init.n_sect, section("__thread_data") + 1);
+    assert!(!counter_init.is_global());
+    let zeroed_init = symbol(&mach, "zeroed$tlv$init");
+    assert_eq!(zeroed_init.n_sect, section("__thread_bss") + 1);
+    assert!(symbol(&mach, "_tlv_bootstrap").is_undefined());
+
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol"))
+        .collect::<Vec<_>>();
+    let mut relocs = relocations(&bytes, "__thread_vars")
+        .into_iter()
+        .map(|reloc| {
+            assert_eq!(reloc.r_type(), X86_64_RELOC_UNSIGNED);
+            assert_eq!(reloc.r_length(), 3);
+            (reloc.r_address as u64, symbols[reloc.r_symbolnum()].0)
+        })
+        .collect::<Vec<_>>();
+    relocs.sort();
+    let mut expected = vec![
+        (counter_descriptor, "__tlv_bootstrap"),
+        (counter_descriptor + 16, "_counter$tlv$init"),
+        (zeroed_descriptor, "__tlv_bootstrap"),
+        (zeroed_descriptor + 16, "_zeroed$tlv$init"),
+    ];
+    expected.sort();
+    assert_eq!(relocs, expected);
+
+    // code accesses a variable through its descriptor
+    let reloc = relocations(&bytes, "__text")
+        .into_iter()
+        .next()
+        .expect("f has a relocation");
+    assert_eq!(reloc.r_type(), X86_64_RELOC_TLV);
+    assert_eq!(symbols[reloc.r_symbolnum()].0, "_counter");
+}
+
+#[test]
+fn lazy_function() {
+    use faerie::Data;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("a", Decl::function(), vec![0x90; 16])
+        .expect("can declare and define a");
+    obj.declare("f", Decl::function().global())
+        .expect("can declare f");
+    let produced = Arc::new(AtomicUsize::new(0));
+    let counter = produced.clone();
+    obj.define_with_symbols(
+        "f",
+        Data::lazy(4, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            vec![0x55, 0x48, 0x89, 0xe5]
+        }),
+        BTreeMap::new(),
+    )
+    .expect("can define f");
+
+    // the layout only needs the size
+    obj.layout().expect("can lay out mach file");
+    assert_eq!(produced.load(Ordering::SeqCst), 0);
+
+    let bytes = obj.emit().expect("can emit mach file");
+    assert_eq!(produced.load(Ordering::SeqCst), 1);
+    let mach = parse(&bytes);
+    let (text, contents) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__text")
+        .expect("__text exists");
+    let f = symbol(&mach, "f");
+    let offset = (f.n_value - text.addr) as usize;
+    assert_eq!(&contents[offset..offset + 4], &[0x55, 0x48, 0x89, 0xe5]);
+
+    // the produced bytes must be exactly as many as promised
+    obj.declare("g", Decl::function()).expect("can declare g");
+    obj.define_with_symbols("g", Data::lazy(2, || vec![0xc3]), BTreeMap::new())
+        .expect("can define g");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn relocations_in_descending_address_order() {
+    use faerie::{ImportKind, Link};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function(), vec![0; 0x30])
+        .expect("can declare and define f");
+    obj.import("g", ImportKind::Function).expect("can import g");
+    for &at in &[0x10, 0x0, 0x20] {
+        obj.link(Link {
+            from: "f",
+            to: "g",
+            at,
+        })
+        .expect("can link f to g");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let addresses = relocations(&bytes, "__text")
+        .iter()
+        .map(|reloc| reloc.r_address)
+        .collect::<Vec<_>>();
+    assert_eq!(addresses, [0x20, 0x10, 0x0]);
+}
+
+#[test]
+fn relocation_into_embedded_constant() {
+    use faerie::Link;
+    use goblin::mach::relocation::X86_64_RELOC_SIGNED;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function(), vec![0x90; 5])
+        .expect("can declare and define f");
+    // code, then a 4-byte constant holding the offset of d, then more code
+    let mut g = vec![0x90; 8];
+    g.extend(&[0; 4]);
+    g.push(0xc3);
+    obj.declare_with("g", Decl::function(), g)
+        .expect("can declare and define g");
+    obj.declare_with("d", Decl::data(), vec![1, 2, 3, 4])
+        .expect("can declare and define d");
+    obj.link(Link {
+        from: "g",
+        to: "d",
+        at: 8,
+    })
+    .expect("can link g to d");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let (text, _) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__text")
+        .expect("__text exists");
+    // g follows f's alignment padding, which doesn't shift the relocation off the constant
+    let g = symbol(&mach, "g");
+    assert_eq!(g.n_value - text.addr, 16);
+    let relocations = relocations(&bytes, "__text");
+    assert_eq!(relocations.len(), 1);
+    assert_eq!(relocations[0].r_type(), X86_64_RELOC_SIGNED);
+    assert_eq!(relocations[0].r_address as u64, g.n_value - text.addr + 8);
+}
+
+#[test]
+fn scattered_relocation() {
+    use faerie::{Link, Reloc};
+    use goblin::mach::relocation::GENERIC_RELOC_VANILLA;
+    use scroll::Pread;
+
+    let mut obj = Artifact::new(triple!("i686-apple-darwin"), "a".into());
+    obj.declare_with("table", Decl::data(), vec![0; 8])
+        .expect("can declare and define table");
+    obj.declare_with("values", Decl::data(), vec![0; 16])
+        .expect("can declare and define values");
+    // a reference into the middle of values
+    obj.link_with(
+        Link {
+            from: "table",
+            to: "values",
+            at: 4,
+        },
+        Reloc::Scattered {
+            reloc: u32::from(GENERIC_RELOC_VANILLA),
+            addend: 8,
+        },
+    )
+    .expect("can link table to values");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let (data, contents) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__data")
+        .expect("__data exists");
+    let table = symbol(&mach, "table");
+    let values = symbol(&mach, "values");
+    let relocations = relocations(&bytes, "__data");
+    assert_eq!(relocations.len(), 1);
+    let first = relocations[0].r_address as u32;
+    // r_scattered
+    assert_eq!(first >> 31, 1);
+    // r_pcrel
+    assert_eq!((first >> 30) & 1, 0);
+    // r_length, of 4 bytes
+    assert_eq!((first >> 28) & 3, 2);
+    // r_type
+    assert_eq!((first >> 24) & 0xf, u32::from(GENERIC_RELOC_VANILLA));
+    // r_address
+    assert_eq!(
+        u64::from(first & 0x00ff_ffff),
+        table.n_value - data.addr + 4
+    );
+    // r_value, the address of the target, while the contents are the address referred to
+    assert_eq!(u64::from(relocations[0].r_info), values.n_value);
+    let offset = (table.n_value - data.addr + 4) as usize;
+    assert_eq!(
+        u64::from(contents.pread_with::<u32>(offset, scroll::LE).unwrap()),
+        values.n_value + 8
+    );
+
+    // 64-bit Mach-O has no scattered relocations
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("table", Decl::data(), vec![0; 8])
+        .expect("can declare and define table");
+    obj.declare_with("values", Decl::data(), vec![0; 16])
+        .expect("can declare and define values");
+    obj.link_with(
+        Link {
+            from: "table",
+            to: "values",
+            at: 0,
+        },
+        Reloc::Scattered {
+            reloc: u32::from(GENERIC_RELOC_VANILLA),
+            addend: 8,
+        },
+    )
+    .expect("can link table to values");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn many_custom_sections() {
+    use faerie::SectionKind;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    let names = (0..10)
+        .map(|i| format!("__custom{}", i))
+        .collect::<Vec<_>>();
+    for (i, name) in names.iter().enumerate() {
+        obj.declare_with(name, Decl::section(SectionKind::Data), vec![i as u8; i + 1])
+            .expect("can declare and define custom section");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    assert_eq!(mach.segments.len(), 1);
+    assert_eq!(mach.segments[0].nsects, 11);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    assert_eq!(sections.len(), 11);
+    for (i, name) in names.iter().enumerate() {
+        let (section, contents) = sections
+            .iter()
+            .find(|(section, _)| section.name().unwrap() == name)
+            .expect("custom section exists");
+        assert_eq!(section.size as usize, i + 1);
+        assert_eq!(&contents[..], &vec![i as u8; i + 1][..]);
+    }
+    // the symbol table follows the segment's contents
+    let end_of_sections = sections
+        .iter()
+        .map(|(section, _)| u64::from(section.offset) + section.size)
+        .max()
+        .unwrap();
+    let symtab = mach
+        .load_commands
+        .iter()
+        .find_map(|cmd| match cmd.command {
+            goblin::mach::load_command::CommandVariant::Symtab(symtab) => Some(symtab),
+            _ => None,
+        })
+        .expect("symtab command exists");
+    assert!(u64::from(symtab.symoff) >= end_of_sections);
+    assert_eq!(symbol(&mach, "f").n_value, sections[0].0.addr);
+}
+
+#[test]
+fn coalesced_function() {
+    use faerie::Link;
+    use goblin::mach::constants::{SECTION_TYPE, S_ATTR_PURE_INSTRUCTIONS, S_COALESCED};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with(
+        "main",
+        Decl::function().global(),
+        vec![0xe8, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define main");
+    obj.declare_with(
+        "inline",
+        Decl::function().coalesced(),
+        vec![0xe8, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define inline");
+    obj.declare("abort", Decl::function_import())
+        .expect("can declare abort");
+    obj.link(Link {
+        from: "main",
+        to: "inline",
+        at: 1,
+    })
+    .expect("can link main to inline");
+    obj.link(Link {
+        from: "inline",
+        to: "abort",
+        at: 1,
+    })
+    .expect("can link inline to abort");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    let (textcoal, data) = sections
+        .iter()
+        .find(|(section, _)| section.name().unwrap() == "__textcoal_nt")
+        .expect("coalesced functions have their own section");
+    assert_eq!(textcoal.segname().unwrap(), "__TEXT");
+    assert_eq!(textcoal.flags & SECTION_TYPE, S_COALESCED);
+    assert_ne!(textcoal.flags & S_ATTR_PURE_INSTRUCTIONS, 0);
+    // padded to the next function alignment, like __text
+    assert_eq!(&data[..6], &[0xe8, 0, 0, 0, 0, 0xc3]);
+    assert_eq!(textcoal.nreloc, 1);
+    assert_eq!(sections[0].0.nreloc, 1);
+
+    let inline = symbol(&mach, "inline");
+    assert_eq!(inline.n_value, textcoal.addr);
+    assert_eq!(inline.n_desc & N_WEAK_DEF, N_WEAK_DEF);
+    assert!(inline.is_global());
+    let main = symbol(&mach, "main");
+    assert_eq!(main.n_sect, 1);
+    assert_eq!(main.n_desc & N_WEAK_DEF, 0);
+}
+
+#[test]
+fn symbol_order() {
+    use faerie::{ArtifactBuilder, ImportKind, Link, MachSymbolOrder};
+
+    let names = |order| {
+        let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+            .mach_symbol_order(order)
+            .finish();
+        obj.declare_with("zeta", Decl::function(), vec![0xc3])
+            .expect("can declare and define zeta");
+        obj.declare_with("beta", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+            .expect("can declare and define beta");
+        obj.declare_with("alpha", Decl::data().global(), vec![1, 2, 3, 4])
+            .expect("can declare and define alpha");
+        obj.declare_with("delta", Decl::data(), vec![5, 6, 7, 8])
+            .expect("can declare and define delta");
+        obj.import("omega", ImportKind::Function)
+            .expect("can import omega");
+        obj.import("gamma", ImportKind::Function)
+            .expect("can import gamma");
+        obj.link(Link {
+            from: "beta",
+            to: "gamma",
+            at: 1,
+        })
+        .expect("can link beta to gamma");
+
+        let bytes = obj.emit().expect("can emit mach file");
+        let mach = parse(&bytes);
+        let names = mach
+            .symbols()
+            .map(|sym| sym.expect("can parse symbol").0.to_string())
+            .collect::<Vec<_>>();
+        // the relocation refers to gamma wherever it is in the symbol table
+        let relocs = relocations(&bytes, "__text");
+        assert_eq!(relocs.len(), 1);
+        assert_eq!(names[relocs[0].r_symbolnum()], "_gamma");
+        names
+    };
+
+    // the symbols are laid out section by section, then the imports
+    let insertion = names(MachSymbolOrder::InsertionOrder);
+    assert_eq!(insertion.last().unwrap(), "_gamma");
+    let mut sorted = insertion.clone();
+    sorted.sort();
+    assert_ne!(insertion, sorted);
+
+    // each order arranges the locals, then the defined externals, then the undefined externals
+    assert_eq!(
+        names(MachSymbolOrder::Alphabetical),
+        ["_delta", "_zeta", "_alpha", "_beta", "_gamma", "_omega"]
+    );
+    assert_eq!(
+        names(MachSymbolOrder::LocalsThenGlobals),
+        ["_delta", "_zeta", "_alpha", "_beta", "_gamma", "_omega"]
+    );
+    // the local definitions are laid out before the global ones in each section, and the
+    // imports are sorted rather than in the order they were imported
+    assert_eq!(
+        names(MachSymbolOrder::Address),
+        ["_zeta", "_delta", "_beta", "_alpha", "_gamma", "_omega"]
+    );
+}
+
+#[test]
+fn weak_import() {
+    use faerie::ImportKind;
+    use goblin::mach::symbols::N_WEAK_REF;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import_weak("new_api", ImportKind::Function)
+        .expect("can import new_api");
+    obj.import("old_api", ImportKind::Function)
+        .expect("can import old_api");
+    assert!(obj.is_weak_import("new_api"));
+    assert!(!obj.is_weak_import("old_api"));
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let new_api = symbol(&mach, "new_api");
+    assert!(new_api.is_undefined());
+    assert_eq!(new_api.n_desc & N_WEAK_REF, N_WEAK_REF);
+    assert_eq!(symbol(&mach, "old_api").n_desc & N_WEAK_REF, 0);
+}
+
+#[test]
+fn pc_relative_with_trailing_immediate() {
+    use faerie::{Link, Reloc};
+    use goblin::mach::relocation::X86_64_RELOC_SIGNED_4;
+
+    let store = |trailing| {
+        let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+        obj.declare_with("x", Decl::data().writable(), vec![0; 4])
+            .expect("can declare and define x");
+        // movl $0x2a, x(%rip)
+        obj.declare_with(
+            "f",
+            Decl::function(),
+            vec![0xc7, 0x05, 0, 0, 0, 0, 0x2a, 0, 0, 0],
+        )
+        .expect("can declare and define f");
+        obj.link_with(
+            Link {
+                from: "f",
+                to: "x",
+                at: 2,
+            },
+            Reloc::PcRelative { trailing },
+        )
+        .expect("can link f to x");
+        obj.emit()
+    };
+
+    let bytes = store(4).expect("can emit mach file");
+    let relocs = relocations(&bytes, "__text");
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].r_address, 2);
+    assert_eq!(relocs[0].r_type(), X86_64_RELOC_SIGNED_4);
+    assert_eq!(relocs[0].r_pcrel(), 1);
+    assert_eq!(relocs[0].r_length(), 2);
+
+    // the other sizes cannot be encoded
+    assert!(store(3).is_err());
+}
+
+#[test]
+fn strings_with_embedded_nuls() {
+    use faerie::SymbolSection;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("hello", Decl::cstring(), b"hello\0".to_vec())
+        .expect("can declare and define hello");
+    obj.declare_with("pair", Decl::cstring(), b"a\0b\0".to_vec())
+        .expect("can declare and define pair");
+    obj.declare_with("unterminated", Decl::cstring(), b"abc".to_vec())
+        .expect("can declare and define unterminated");
+    assert_eq!(obj.section_kind_of("hello"), Some(SymbolSection::CString));
+    assert_eq!(obj.section_kind_of("pair"), Some(SymbolSection::Data));
+    assert_eq!(
+        obj.section_kind_of("unterminated"),
+        Some(SymbolSection::Data)
+    );
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    let sectname = |name| {
+        let n_sect = symbol(&mach, name).n_sect;
+        sections[n_sect - 1].0.name().unwrap().to_string()
+    };
+    assert_eq!(sectname("hello"), "__cstring");
+    assert_eq!(sectname("pair"), "__data");
+    assert_eq!(sectname("unterminated"), "__data");
+    let (cstring, data) = &sections[1];
+    assert_eq!(cstring.name().unwrap(), "__cstring");
+    assert_eq!(&data[..], b"hello\0");
+}
+
+#[test]
+fn anonymous_data() {
+    use faerie::{DataType, Link};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    // the generated names skip names which are already declared
+    obj.declare_with("anon.0", Decl::data(), vec![0; 4])
+        .expect("can declare and define anon.0");
+    let constant = obj
+        .declare_anonymous(
+            Decl::data().global().with_datatype(DataType::Literal8),
+            std::f64::consts::E.to_le_bytes().to_vec(),
+        )
+        .expect("can declare and define an anonymous constant");
+    assert_eq!(constant, "anon.1");
+    // movsd constant(%rip), %xmm0
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0xf2, 0x0f, 0x10, 0x05, 0, 0, 0, 0],
+    )
+    .expect("can declare and define f");
+    obj.link(Link {
+        from: "f",
+        to: &constant,
+        at: 4,
+    })
+    .expect("can link f to the constant");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sym = symbol(&mach, &constant);
+    assert_eq!(sym.n_type & N_EXT, 0);
+    let externals = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol"))
+        .filter(|(_, nlist)| nlist.n_type & N_EXT != 0)
+        .map(|(name, _)| name.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(externals, ["_f"]);
+
+    let relocs = relocations(&bytes, "__text");
+    assert_eq!(relocs.len(), 1);
+    let (name, _) = mach
+        .symbols()
+        .nth(relocs[0].r_symbolnum())
+        .expect("relocation symbol exists")
+        .expect("can parse symbol");
+    assert_eq!(name, "_anon.1");
+}
+
+#[test]
+fn matches_reference_object() {
+    use faerie::{describe, ArtifactBuilder, ImportKind, Link, MachSymbolOrder};
+
+    // the same object as tests/data/reference.macho.s
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_symbol_order(MachSymbolOrder::LocalsThenGlobals)
+        .finish();
+    // callq _foo; movq _x(%rip), %rax; retq; and nops up to the function alignment
+    obj.declare_with(
+        "main",
+        Decl::function().global(),
+        vec![
+            0xe8, 0, 0, 0, 0, 0x48, 0x8b, 0x05, 0, 0, 0, 0, 0xc3, 0x90, 0x90, 0x90,
+        ],
+    )
+    .expect("can declare and define main");
+    obj.declare_with(
+        "x",
+        Decl::data().global().writable(),
+        vec![42, 0, 0, 0, 0, 0, 0, 0],
+    )
+    .expect("can declare and define x");
+    obj.import("foo", ImportKind::Function)
+        .expect("can import foo");
+    obj.link(Link {
+        from: "main",
+        to: "foo",
+        at: 1,
+    })
+    .expect("can link main to foo");
+    obj.link(Link {
+        from: "main",
+        to: "x",
+        at: 8,
+    })
+    .expect("can link main to x");
+
+    let faerie =
+        describe(&obj.emit().expect("can emit mach file")).expect("can describe faerie's object");
+    let reference = describe(include_bytes!("data/reference.macho.o"))
+        .expect("can describe the reference object");
+    assert_eq!(faerie, reference);
+}
+
+#[test]
+fn describe_malformed_object() {
+    use faerie::{describe, ABSOLUTE};
+    use goblin::mach::load_command::CommandVariant;
+    use goblin::mach::symbols::N_ABS;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "t.o".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![1; 8])
+        .expect("can declare and define d");
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+
+    // the offsets of the first section header, and of the nlist of `_d`
+    let (segment, symtab) = mach
+        .load_commands
+        .iter()
+        .fold((0, 0), |found, command| match command.command {
+            CommandVariant::Segment64(_) => (command.offset, found.1),
+            CommandVariant::Symtab(symtab) => (found.0, symtab.symoff as usize),
+            _ => found,
+        });
+    let section = segment + 72;
+    let index = mach
+        .symbols()
+        .position(|sym| sym.expect("can parse symbol").0 == "_d")
+        .expect("_d exists");
+    let nlist = symtab + index * 16;
+    assert!(symbol(&mach, "d").n_value > 0);
+
+    // an absolute symbol has no section
+    let mut absolute = bytes.clone();
+    absolute[nlist + 4] = N_ABS | N_EXT;
+    absolute[nlist + 5] = 0;
+    let description = describe(&absolute).expect("can describe an absolute symbol");
+    let d = description
+        .symbols
+        .iter()
+        .find(|sym| sym.name == "_d")
+        .expect("_d is described");
+    assert_eq!(d.section.as_deref(), Some(ABSOLUTE));
+
+    // a symbol before the start of its section
+    let mut malformed = bytes.clone();
+    malformed[nlist + 8..nlist + 16].copy_from_slice(&0u64.to_le_bytes());
+    assert!(describe(&malformed).is_err());
+
+    // an alignment too large to represent
+    let mut malformed = bytes.clone();
+    malformed[section + 52..section + 56].copy_from_slice(&64u32.to_le_bytes());
+    assert!(describe(&malformed).is_err());
+}
+
+#[test]
+fn arm64_pointer_table() {
+    use faerie::Link;
+    use goblin::mach::relocation::ARM64_RELOC_UNSIGNED;
+
+    let mut obj = Artifact::new(triple!("aarch64-apple-darwin"), "t.o".into());
+    // ret
+    obj.declare_with("f", Decl::function().global(), vec![0xc0, 0x03, 0x5f, 0xd6])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global(), vec![1; 8])
+        .expect("can declare and define d");
+    obj.declare_with("table", Decl::data().global(), vec![0; 16])
+        .expect("can declare and define table");
+    for &(to, at) in &[("f", 0), ("d", 8)] {
+        obj.link(Link {
+            from: "table",
+            to,
+            at,
+        })
+        .expect("can link table");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mut relocs = relocations(&bytes, "__data")
+        .into_iter()
+        .map(|reloc| {
+            assert_eq!(reloc.r_type(), ARM64_RELOC_UNSIGNED);
+            assert_eq!(reloc.r_pcrel(), 0);
+            assert_eq!(reloc.r_length(), 3);
+            assert_eq!(reloc.r_extern(), 1);
+            reloc.r_address
+        })
+        .collect::<Vec<_>>();
+    relocs.sort();
+    // the relocations are at the pointers, relative to the section
+    let mach = parse(&bytes);
+    let data = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__data")
+        .expect("data section exists");
+    let table = (symbol(&mach, "table").n_value - data.addr) as i32;
+    assert_eq!(relocs, [table, table + 8]);
+
+    // code still needs the x86_64 relocations
+    obj.link(Link {
+        from: "f",
+        to: "d",
+        at: 0,
+    })
+    .expect("can link f to d");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn reexport() {
+    use faerie::{ArtifactError, ImportKind};
+    use goblin::mach::symbols::N_INDR;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "t.o".into());
+    obj.reexport("foo", "bar", ImportKind::Function)
+        .expect("can reexport bar as foo");
+    assert_eq!(obj.reexported_import("foo"), Some("bar"));
+    assert_eq!(obj.reexported_import("bar"), None);
+    assert!(obj.reexport("baz", "foo", ImportKind::Function).is_err());
+    assert!(obj.undeclare("bar").is_err());
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let foo = symbol(&mach, "foo");
+    assert_eq!(foo.n_type, N_INDR | N_EXT);
+    assert_eq!(foo.n_sect, 0);
+    // the value of an indirect symbol is the string table offset of its target's name
+    let bar = symbol(&mach, "bar");
+    assert_eq!(foo.n_value, bar.n_strx as u64);
+
+    let error = obj
+        .emit_as(target_lexicon::BinaryFormat::Elf)
+        .expect_err("ELF has no re-exports");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::UnsupportedSymbol { name, .. }) => assert_eq!(name, "foo"),
+        _ => panic!("unexpected error {}", error),
+    }
+}
+
+#[test]
+fn sized_absolute_relocations() {
+    use faerie::{Link, Reloc};
+    use goblin::mach::relocation::X86_64_RELOC_UNSIGNED;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    // .long _f; .long _f + 1; .quad _f
+    obj.declare_with("table", Decl::data(), vec![0; 16])
+        .expect("can declare and define table");
+    for &(at, size, addend) in &[(0, 4, 0), (4, 4, 1), (8, 8, 0)] {
+        obj.link_with(
+            Link {
+                from: "table",
+                to: "f",
+                at,
+            },
+            Reloc::Absolute { size, addend },
+        )
+        .expect("can link table to f");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let data = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__data")
+        .expect("data section exists");
+    let mut relocations = relocations(&bytes, "__data")
+        .into_iter()
+        .map(|reloc| {
+            assert_eq!(reloc.r_type(), X86_64_RELOC_UNSIGNED);
+            assert_eq!(reloc.r_pcrel(), 0);
+            (reloc.r_address, reloc.r_length())
+        })
+        .collect::<Vec<_>>();
+    relocations.sort();
+    // r_length is the log2 of the size
+    assert_eq!(relocations, vec![(0, 2), (4, 2), (8, 3)]);
+    let contents = &bytes[data.offset as usize..][..16];
+    assert_eq!(&contents[4..8], &[1, 0, 0, 0]);
+}
+
+#[test]
+fn debug_section_relocations() {
+    use faerie::{Link, Reloc, SectionKind};
+    use goblin::mach::relocation::X86_64_RELOC_UNSIGNED;
+    use scroll::Pread;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with(
+        ".debug_info",
+        Decl::section(SectionKind::Debug),
+        vec![0; 16],
+    )
+    .expect("can declare and define .debug_info");
+    obj.declare_with(
+        ".debug_line",
+        Decl::section(SectionKind::Debug),
+        vec![0; 16],
+    )
+    .expect("can declare and define .debug_line");
+    // DW_AT_stmt_list, the offset of the compilation unit's line program
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: ".debug_line",
+            at: 4,
+        },
+        Reloc::Debug { size: 4, addend: 8 },
+    )
+    .expect("can link from .debug_info to .debug_line");
+
+    let (bytes, _, resolved) = obj.emit_with_relocations().expect("can emit");
+    assert_eq!(
+        resolved
+            .iter()
+            .map(|reloc| (&*reloc.section, reloc.offset, &*reloc.symbol))
+            .collect::<Vec<_>>(),
+        vec![("__debug_info", 4, "__debug_line")]
+    );
+
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .collect::<Vec<_>>();
+    let section = |name| {
+        sections
+            .iter()
+            .position(|section| section.name().unwrap() == name)
+            .expect("section exists")
+    };
+    let (info, line) = (section("__debug_info"), section("__debug_line"));
+    let relocs = relocations(&bytes, "__debug_info");
+    assert_eq!(relocs.len(), 1);
+    let reloc = relocs[0];
+    assert_eq!(reloc.r_address, 4);
+    assert_eq!(reloc.r_type(), X86_64_RELOC_UNSIGNED);
+    assert_eq!(reloc.r_length(), 2);
+    assert_eq!(reloc.r_pcrel(), 0);
+    // the relocation refers to the section by its ordinal
+    assert_eq!(reloc.r_extern(), 0);
+    assert_eq!(reloc.r_symbolnum(), line + 1);
+
+    // and the relocated contents are the address of the offset in it
+    let contents = &bytes[sections[info].offset as usize..][..16];
+    assert_eq!(
+        u64::from(contents.pread_with::<u32>(4, scroll::LE).unwrap()),
+        sections[line].addr + 8
+    );
+}
+
+#[test]
+fn compact_unwind() {
+    use goblin::mach::constants::S_ATTR_DEBUG;
+    use goblin::mach::relocation::X86_64_RELOC_UNSIGNED;
+    use scroll::Pread;
+
+    // UNWIND_X86_64_MODE_RBP_FRAME, with no saved registers
+    const ENCODING: u32 = 0x0100_0000;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    // push %rbp; mov %rsp, %rbp; pop %rbp; ret
+    obj.declare_with(
+        "g",
+        Decl::function()
+            .global()
+            .with_compact_unwind(Some(ENCODING)),
+        vec![0x55, 0x48, 0x89, 0xe5, 0x5d, 0xc3],
+    )
+    .expect("can declare and define g");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let section = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__compact_unwind")
+        .expect("compact unwind section exists");
+    assert_eq!(section.segname().unwrap(), "__LD");
+    assert_eq!(section.flags, S_ATTR_DEBUG);
+    assert_eq!(section.align, 3);
+    assert_eq!(section.size, 32);
+
+    let contents = &bytes[section.offset as usize..][..32];
+    assert_eq!(contents.pread_with::<u64>(0, scroll::LE).unwrap(), 0);
+    assert_eq!(contents.pread_with::<u32>(8, scroll::LE).unwrap(), 6);
+    assert_eq!(
+        contents.pread_with::<u32>(12, scroll::LE).unwrap(),
+        ENCODING
+    );
+    assert_eq!(contents.pread_with::<u64>(16, scroll::LE).unwrap(), 0);
+    assert_eq!(contents.pread_with::<u64>(24, scroll::LE).unwrap(), 0);
+
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").1)
+        .collect::<Vec<_>>();
+    let g = symbol(&mach, "g");
+    let relocs = relocations(&bytes, "__compact_unwind");
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].r_address, 0);
+    assert_eq!(relocs[0].r_type(), X86_64_RELOC_UNSIGNED);
+    assert_eq!(relocs[0].r_length(), 3);
+    assert_eq!(relocs[0].r_pcrel(), 0);
+    assert_eq!(relocs[0].r_extern(), 1);
+    assert_eq!(symbols[relocs[0].r_symbolnum()].n_strx, g.n_strx);
+}
+
+#[test]
+fn custom_section_segname() {
+    use faerie::{ArtifactError, SectionKind};
+    use goblin::mach::constants::S_ATTR_DEBUG;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with(
+        "__compact_unwind",
+        Decl::section(SectionKind::Data)
+            .with_mach_segname(Some("__LD"))
+            .expect("__LD is a valid segment name")
+            .with_mach_flags(S_ATTR_DEBUG),
+        vec![0; 32],
+    )
+    .expect("can declare and define __compact_unwind");
+    // segment names can be chosen at runtime
+    let vendor = format!("__{}", "MYSEG");
+    obj.declare_with(
+        "__vendor",
+        Decl::section(SectionKind::Data)
+            .with_mach_segname(Some(&vendor))
+            .expect("__MYSEG is a valid segment name"),
+        vec![1, 2, 3],
+    )
+    .expect("can declare and define __vendor");
+    obj.declare_with("__default", Decl::section(SectionKind::Data), vec![4])
+        .expect("can declare and define __default");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let segname = |name| {
+        mach.segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section").0)
+            .find(|section| section.name().unwrap() == name)
+            .expect("section exists")
+            .segname()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(segname("__compact_unwind"), "__LD");
+    assert_eq!(segname("__vendor"), "__MYSEG");
+    // without an override, the segment is the one the section's kind implies
+    assert_eq!(segname("__default"), "__DATA");
+
+    for invalid in &["__SEVENTEEN_BYTES", "", "__A\0B"] {
+        match Decl::section(SectionKind::Data).with_mach_segname(Some(invalid)) {
+            Err(ArtifactError::InvalidSegmentName(name)) => assert_eq!(&name, invalid),
+            _ => panic!("{:?} is not a valid segment name", invalid),
+        }
+    }
+}
+
+#[test]
+fn section_order() {
+    use faerie::{ArtifactBuilder, SectionKind};
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_section_order(vec!["__custom".to_string(), "__data".to_string()])
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("d", Decl::data().global().writable(), vec![1; 8])
+        .expect("can declare and define d");
+    obj.declare_with("s", Decl::cstring().global(), b"s\0".to_vec())
+        .expect("can declare and define s");
+    obj.declare_with("__custom", Decl::section(SectionKind::Data), vec![2; 3])
+        .expect("can declare and define __custom");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    let names = sections
+        .iter()
+        .map(|(section, _)| section.name().unwrap())
+        .collect::<Vec<_>>();
+    // the unlisted sections follow in the default order
+    assert_eq!(names, ["__custom", "__data", "__text", "__cstring"]);
+    // and are laid out in that order
+    for pair in sections.windows(2) {
+        assert!(pair[0].0.addr + pair[0].0.size <= pair[1].0.addr);
+        assert!(pair[0].0.offset as u64 + pair[0].0.size <= pair[1].0.offset as u64);
+    }
+    assert_eq!(sections[0].1, &[2; 3]);
+    assert_eq!(sections[1].1, &[1; 8]);
+    assert_eq!(&sections[2].1[..1], &[0xc3]);
+    assert_eq!(sections[3].1, b"s\0");
+    // the symbols are numbered and placed by the section order
+    let d = symbol(&mach, "d");
+    assert_eq!(d.n_sect, 2);
+    assert_eq!(d.n_value, sections[1].0.addr);
+    let f = symbol(&mach, "f");
+    assert_eq!(f.n_sect, 3);
+    assert_eq!(f.n_value, sections[2].0.addr);
+}
+
+#[test]
+fn segment_name() {
+    use faerie::ArtifactBuilder;
+
+    let emit = |obj: &mut Artifact| {
+        obj.declare_with("f", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define f");
+        obj.emit()
+    };
+
+    // the segment of an object file is unnamed
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    let bytes = emit(&mut obj).expect("can emit mach file");
+    let mach = parse(&bytes);
+    assert_eq!(mach.segments.len(), 1);
+    assert_eq!(mach.segments[0].segname, [0; 16]);
+    assert_eq!(mach.segments[0].name().unwrap(), "");
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_segment_name("__TEXT".to_string())
+        .finish();
+    let bytes = emit(&mut obj).expect("can emit mach file");
+    let mach = parse(&bytes);
+    assert_eq!(mach.segments[0].name().unwrap(), "__TEXT");
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_segment_name("__SEVENTEEN_BYTES".to_string())
+        .finish();
+    assert!(emit(&mut obj).is_err());
+}
+
+#[test]
+fn fat_object() {
+    use faerie::{to_fat_bytes, ArtifactError};
+    use target_lexicon::{Aarch64Architecture, Architecture};
+
+    let new = |triple: &str, code: Vec<u8>| {
+        let mut obj = Artifact::new(triple!(triple), "a".into());
+        obj.declare_with("f", Decl::function().global(), code)
+            .unwrap();
+        obj
+    };
+    let x86_64 = new("x86_64-apple-darwin", vec![0xc3]);
+    let arm64 = new("aarch64-apple-darwin", vec![0xc0, 0x03, 0x5f, 0xd6]);
+    let bytes = to_fat_bytes(&[
+        (Architecture::X86_64, &x86_64),
+        (Architecture::Aarch64(Aarch64Architecture::Aarch64), &arm64),
+    ])
+    .expect("can emit fat object");
+
+    let multi = match goblin::Object::parse(&bytes).expect("can parse fat file") {
+        goblin::Object::Mach(Mach::Fat(multi)) => multi,
+        _ => panic!("fat file not parsed as fat mach file"),
+    };
+    let arches = multi.arches().expect("can parse fat arches");
+    assert_eq!(arches.len(), 2);
+    assert_eq!(arches[0].cputype, goblin::mach::cputype::CPU_TYPE_X86_64);
+    assert_eq!(arches[1].cputype, goblin::mach::cputype::CPU_TYPE_ARM64);
+    // each slice is aligned to its architecture's page size
+    assert_eq!((arches[0].offset, arches[0].align), (1 << 12, 12));
+    assert_eq!(arches[1].offset % (1 << 14), 0);
+    assert_eq!(arches[1].align, 14);
+
+    // each slice is the object emitted for its artifact
+    for (index, (artifact, code)) in [
+        (&x86_64, vec![0xc3]),
+        (&arm64, vec![0xc0, 0x03, 0x5f, 0xd6]),
+    ]
+    .iter()
+    .enumerate()
+    {
+        assert_eq!(arches[index].slice(&bytes), &artifact.emit().unwrap()[..]);
+        let mach = multi.get(index).expect("can parse slice");
+        let f = symbol(&mach, "f");
+        let (_, text) = mach
+            .segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section"))
+            .find(|(section, _)| section.name().unwrap() == "__text")
+            .expect("text section exists");
+        assert_eq!(&text[f.n_value as usize..][..code.len()], &code[..]);
+    }
+
+    // artifacts must target the architecture they are paired with, once each
+    let error = to_fat_bytes(&[]).expect_err("a fat object needs an artifact");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::EmptyFatObject) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+    let error =
+        to_fat_bytes(&[(Architecture::X86_64, &arm64)]).expect_err("the artifact targets arm64");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::ArchitectureMismatch {
+            target: Architecture::Aarch64(_),
+            architecture: Architecture::X86_64,
+            ..
+        }) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+    let error = to_fat_bytes(&[
+        (Architecture::X86_64, &x86_64),
+        (Architecture::X86_64, &x86_64),
+    ])
+    .expect_err("x86_64 is in the fat object twice");
+    match error.downcast_ref::<ArtifactError>() {
+        Some(ArtifactError::DuplicateArchitecture(Architecture::X86_64)) => {}
+        _ => panic!("unexpected error {}", error),
+    }
+}
+
+#[test]
+fn section_boundary_import() {
+    use faerie::{ImportKind, Link};
+    use goblin::mach::relocation::X86_64_RELOC_SIGNED;
+
+    const START: &str = "section$start$__DATA$__mysection";
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import(START, ImportKind::SectionBoundary)
+        .expect("can import section start");
+    // only the Mach-O convention names a section boundary
+    assert!(obj
+        .import("__start_mysection", ImportKind::SectionBoundary)
+        .is_err());
+    assert!(obj
+        .import("section$start$__DATA", ImportKind::SectionBoundary)
+        .is_err());
+    assert!(obj
+        .import(
+            "section$end$__DATA$__seventeen_bytes",
+            ImportKind::SectionBoundary
+        )
+        .is_err());
+    // leaq section$start$__DATA$__mysection(%rip), %rax
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define f");
+    obj.link(Link {
+        from: "f",
+        to: START,
+        at: 3,
+    })
+    .expect("can link to section start");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    // the linker's name for the boundary has no underscore prefix
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol"))
+        .collect::<Vec<_>>();
+    let (_, start) = symbols
+        .iter()
+        .find(|&&(name, _)| name == START)
+        .expect("section start symbol exists");
+    assert!(start.is_undefined());
+    assert!(start.is_global());
+    assert!(symbols.iter().any(|&(name, _)| name == "_f"));
+
+    let relocations = relocations(&bytes, "__text");
+    assert_eq!(relocations.len(), 1);
+    assert_eq!(relocations[0].r_type(), X86_64_RELOC_SIGNED);
+    assert_eq!(symbols[relocations[0].r_symbolnum()].0, START);
+}
+
+#[test]
+fn import_with_stub() {
+    use faerie::Link;
+    use goblin::mach::constants::{SECTION_TYPE, S_LAZY_SYMBOL_POINTERS, S_SYMBOL_STUBS};
+    use goblin::mach::load_command::Section64;
+    use goblin::mach::relocation::X86_64_RELOC_BRANCH;
+    use scroll::{Pread, LE};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import_with_stub("puts").expect("can import puts");
+    assert!(obj.is_stubbed_import("puts"));
+    // call puts; ret
+    obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0, 0xc3])
+        .expect("can declare and define f");
+    obj.link(Link {
+        from: "f",
+        to: "puts",
+        at: 1,
+    })
+    .expect("can link to puts");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    // goblin does not parse the reserved fields, so read the section headers themselves
+    let header = |sectname: &str| {
+        let mut name = [0; 16];
+        name[..sectname.len()].copy_from_slice(sectname.as_bytes());
+        let at = bytes
+            .windows(16)
+            .position(|window| window == name)
+            .expect("section header exists");
+        bytes
+            .pread_with::<Section64>(at, LE)
+            .expect("can read section header")
+    };
+    let stubs = header("__stubs");
+    assert_eq!(stubs.flags & SECTION_TYPE, S_SYMBOL_STUBS);
+    assert_eq!(stubs.size, 6);
+    assert_eq!(stubs.reserved1, 0);
+    assert_eq!(stubs.reserved2, 6);
+    let lazy_pointers = header("__la_symbol_ptr");
+    assert_eq!(&lazy_pointers.segname[..6], b"__DATA");
+    assert_eq!(lazy_pointers.flags & SECTION_TYPE, S_LAZY_SYMBOL_POINTERS);
+    assert_eq!(lazy_pointers.size, 8);
+    // the lazy pointers' indirect symbols follow the stubs'
+    assert_eq!(lazy_pointers.reserved1, 1);
+    // jmp *ptr(%rip), relative to the end of the stub
+    let contents = &bytes[stubs.offset as usize..][..6];
+    assert_eq!(&contents[..2], &[0xff, 0x25]);
+    let displacement = contents.pread_with::<i32>(2, LE).unwrap();
+    assert_eq!(
+        stubs.addr as i64 + 6 + i64::from(displacement),
+        lazy_pointers.addr as i64
+    );
+
+    // the call goes through the stub
+    let relocs = relocations(&bytes, "__text");
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].r_type(), X86_64_RELOC_BRANCH);
+    assert!(!relocs[0].is_extern());
+}
+
+#[test]
+fn stub_call_from_section() {
+    use faerie::{Link, Reloc, SectionKind};
+    use goblin::mach::relocation::X86_64_RELOC_BRANCH;
+    use scroll::{Pread, LE};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import_with_stub("g").expect("can import g");
+    // call g; ret
+    obj.declare_with(
+        ".text.a",
+        Decl::section(SectionKind::Text),
+        vec![0xe8, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define .text.a");
+    obj.link_with(
+        Link {
+            from: ".text.a",
+            to: "g",
+            at: 1,
+        },
+        Reloc::Call,
+    )
+    .expect("can link .text.a to g");
+
+    let relocations = obj.relocations().expect("can get relocations");
+    let (sectname, relocs) = relocations
+        .iter()
+        .find(|(_, relocs)| relocs[0].reloc == u32::from(X86_64_RELOC_BRANCH))
+        .expect("the call is relocated");
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].offset, 1);
+    assert_eq!(relocs[0].symbol, "__stubs");
+
+    // the call's implicit addend is the stub's displacement from the end of the call
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let section = |name: &str| {
+        mach.segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section").0)
+            .find(|section| section.name().unwrap() == name)
+            .expect("section exists")
+    };
+    let (custom, stubs) = (section(sectname), section("__stubs"));
+    let displacement = bytes
+        .pread_with::<i32>(custom.offset as usize + 1, LE)
+        .unwrap();
+    assert_eq!(
+        custom.addr as i64 + 5 + i64::from(displacement),
+        stubs.addr as i64
+    );
+}
+
+#[test]
+fn section_reserved_fields() {
+    use faerie::Link;
+    use goblin::mach::header::SIZEOF_HEADER_64;
+    use goblin::mach::load_command::{Section64, SIZEOF_SECTION_64, SIZEOF_SEGMENT_COMMAND_64};
+    use scroll::{Pread, LE};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import_with_stub("puts").expect("can import puts");
+    obj.import_with_stub("exit").expect("can import exit");
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define f");
+    obj.declare_with("x", Decl::data().global(), vec![1, 2, 3, 4])
+        .expect("can declare and define x");
+    for &(to, at) in &[("puts", 1), ("exit", 6)] {
+        obj.link(Link { from: "f", to, at })
+            .expect("can link to stubbed import");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    // the section headers follow the mach header and the segment load command
+    let first = SIZEOF_HEADER_64 + SIZEOF_SEGMENT_COMMAND_64;
+    let headers = (0..mach.segments[0].nsects as usize)
+        .map(|i| {
+            bytes
+                .pread_with::<Section64>(first + i * SIZEOF_SECTION_64, LE)
+                .expect("can read section header")
+        })
+        .collect::<Vec<_>>();
+    for header in &headers {
+        let sectname = std::str::from_utf8(&header.sectname)
+            .unwrap()
+            .trim_end_matches('\0');
+        let reserved = match sectname {
+            "__stubs" => (0, 6),
+            // after the two stubs' indirect symbols
+            "__la_symbol_ptr" => (2, 0),
+            _ => (0, 0),
+        };
+        assert_eq!(
+            (header.reserved1, header.reserved2),
+            reserved,
+            "reserved fields of {}",
+            sectname
+        );
+    }
+    assert!(headers
+        .iter()
+        .any(|header| header.sectname.starts_with(b"__stubs\0") && header.size == 12));
+}
+
+#[test]
+fn dysymtab() {
+    use faerie::{ImportKind, Link};
+    use goblin::mach::load_command::CommandVariant;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import("puts", ImportKind::Function)
+        .expect("can import puts");
+    obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0, 0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("g", Decl::function(), vec![0xc3])
+        .expect("can declare and define g");
+    obj.declare_with("x", Decl::data().global(), vec![1, 2, 3, 4])
+        .expect("can declare and define x");
+    obj.declare_with("y", Decl::data(), vec![5, 6, 7, 8])
+        .expect("can declare and define y");
+    obj.link(Link {
+        from: "f",
+        to: "puts",
+        at: 1,
+    })
+    .expect("can link to puts");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let dysymtab = mach
+        .load_commands
+        .iter()
+        .find_map(|command| match command.command {
+            CommandVariant::Dysymtab(dysymtab) => Some(dysymtab),
+            _ => None,
+        })
+        .expect("dysymtab exists");
+    assert_eq!((dysymtab.ilocalsym, dysymtab.nlocalsym), (0, 2));
+    assert_eq!((dysymtab.iextdefsym, dysymtab.nextdefsym), (2, 2));
+    assert_eq!((dysymtab.iundefsym, dysymtab.nundefsym), (4, 1));
+    assert_eq!(dysymtab.nindirectsyms, 0);
+
+    // each range has the symbols of its binding
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").1)
+        .collect::<Vec<_>>();
+    assert!(symbols[..2].iter().all(|sym| !sym.is_global()));
+    assert!(symbols[2..4]
+        .iter()
+        .all(|sym| sym.is_global() && !sym.is_undefined()));
+    assert!(symbols[4].is_undefined());
+}
+
+#[test]
+fn additional_load_commands() {
+    use faerie::ArtifactBuilder;
+    use goblin::mach::load_command::{LC_LINKER_OPTION, LC_SYMTAB};
+    use scroll::{Pread, LE};
+
+    // one string, NUL terminated
+    let mut payload = 1u32.to_le_bytes().to_vec();
+    payload.extend_from_slice(b"-lm\0");
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_load_command(LC_LINKER_OPTION, payload)
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    assert_eq!(mach.header.ncmds, mach.load_commands.len());
+    let command = mach
+        .load_commands
+        .iter()
+        .find(|command| command.command.cmd() == LC_LINKER_OPTION)
+        .expect("linker option exists");
+    // padded to a pointer size
+    assert_eq!(command.command.cmdsize(), 16);
+    let at = command.offset;
+    assert_eq!(bytes.pread_with::<u32>(at + 8, LE).unwrap(), 1);
+    assert_eq!(&bytes[at + 12..at + 16], b"-lm\0");
+    // the sections still follow the load commands
+    assert_eq!(symbol(&mach, "f").n_value, 0);
+    let (text, data) = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|s| s.expect("can parse section"))
+        .find(|(s, _)| s.name().unwrap() == "__text")
+        .expect("text section exists");
+    assert!(text.offset as usize >= at + 16);
+    assert_eq!(data[0], 0xc3);
+
+    let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+        .mach_load_command(LC_SYMTAB, vec![0; 16])
+        .finish();
+    obj.declare_with("f", Decl::function().global(), vec![0xc3])
+        .expect("can declare and define f");
+    assert!(obj.emit().is_err());
+}
+
+#[test]
+fn section_fill() {
+    use faerie::ArtifactBuilder;
+
+    let contents = |builder: ArtifactBuilder| {
+        let mut obj = builder.finish();
+        obj.declare_with("f", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define f");
+        obj.declare_with("g", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define g");
+        obj.declare_with("x", Decl::data().global(), vec![1])
+            .expect("can declare and define x");
+        obj.declare_with("y", Decl::data().global().with_align(Some(8)), vec![2])
+            .expect("can declare and define y");
+        obj.section_contents().expect("can get section contents")
+    };
+    let section = |sections: &[(String, Vec<u8>)], name: &str| {
+        sections
+            .iter()
+            .find(|(sectname, _)| sectname == name)
+            .expect("section exists")
+            .1
+            .clone()
+    };
+
+    // code is padded with int3, and data with 0xaa
+    let default = contents(ArtifactBuilder::new(triple!("x86_64-apple-darwin")));
+    let text = section(&default, "__text");
+    assert_eq!(text[0], 0xc3);
+    assert!(text[1..16].iter().all(|&byte| byte == 0xcc));
+    assert_eq!(
+        &section(&default, "__data")[..8],
+        &[1, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa]
+    );
+
+    let zeroed = contents(
+        ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+            .mach_section_fill("__text", 0)
+            .mach_section_fill("__data", 0),
+    );
+    let text = section(&zeroed, "__text");
+    assert_eq!(text[0], 0xc3);
+    assert!(text[1..16].iter().all(|&byte| byte == 0));
+    assert_eq!(text[16], 0xc3);
+    assert_eq!(
+        &section(&zeroed, "__data")[..9],
+        &[1, 0, 0, 0, 0, 0, 0, 0, 2]
+    );
+}
+
+#[test]
+fn uuid() {
+    use faerie::{ArtifactBuilder, MachUuid};
+    use goblin::mach::load_command::CommandVariant;
+
+    let uuid = |mach_uuid: Option<MachUuid>| {
+        let builder = ArtifactBuilder::new(triple!("x86_64-apple-darwin"));
+        let builder = match mach_uuid {
+            Some(mach_uuid) => builder.mach_uuid(mach_uuid),
+            None => builder,
+        };
+        let mut obj = builder.finish();
+        obj.declare_with("f", Decl::function().global(), vec![0xc3])
+            .expect("can declare and define f");
+        let bytes = obj.emit().expect("can emit mach file");
+        let mach = parse(&bytes);
+        assert_eq!(mach.header.ncmds, mach.load_commands.len());
+        assert_eq!(symbol(&mach, "f").n_value, 0);
+        mach.load_commands
+            .iter()
+            .find_map(|command| match command.command {
+                CommandVariant::Uuid(command) => Some(command.uuid),
+                _ => None,
+            })
+    };
+
+    // no UUID by default
+    assert_eq!(uuid(None), None);
+    let fixed = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32,
+        0x10,
+    ];
+    assert_eq!(uuid(Some(MachUuid::Fixed(fixed))), Some(fixed));
+    // a hashed UUID is the same every time, and is a version 3 UUID
+    let hashed = uuid(Some(MachUuid::Hashed)).expect("hashed uuid exists");
+    assert_eq!(uuid(Some(MachUuid::Hashed)), Some(hashed));
+    assert_ne!(hashed, [0; 16]);
+    assert_eq!(hashed[6] >> 4, 3);
+}
+
+#[test]
+fn negative_addends() {
+    use faerie::{Link, Reloc};
+    use scroll::{Pread, LE};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    // .long _f - 4; .quad _f - 4
+    obj.declare_with("table", Decl::data(), vec![0; 12])
+        .expect("can declare and define table");
+    for &(at, size) in &[(0, 4), (4, 8)] {
+        obj.link_with(
+            Link {
+                from: "table",
+                to: "f",
+                at,
+            },
+            Reloc::Absolute { size, addend: -4 },
+        )
+        .expect("can link table to f");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let data = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__data")
+        .expect("data section exists");
+    // the implicit addends are in two's complement, sign-extended to the field
+    let contents = &bytes[data.offset as usize..][..12];
+    assert_eq!(&contents[..4], &[0xfc, 0xff, 0xff, 0xff]);
+    assert_eq!(contents.pread_with::<i64>(4, LE).unwrap(), -4);
+}
+
+#[test]
+fn addends_over_streamed_data() {
+    use faerie::{Data, Link, Reloc};
+    use std::collections::BTreeMap;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare("table", Decl::data().writable())
+        .expect("can declare table");
+    // the first addend straddles the end of a chunk, and the second lies within the fill
+    obj.define_with_symbols(
+        "table",
+        Data::Sparse {
+            size: 24,
+            fill: 0xee,
+            chunks: vec![(0, vec![1, 2, 3, 4, 5, 6])],
+        },
+        BTreeMap::new(),
+    )
+    .expect("can define table");
+    for &(at, size, addend) in &[(12, 8, 0x0102), (4, 4, -1)] {
+        obj.link_with(
+            Link {
+                from: "table",
+                to: "f",
+                at,
+            },
+            Reloc::Absolute { size, addend },
+        )
+        .expect("can link table to f");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let data = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__data")
+        .expect("data section exists");
+    assert_eq!(
+        &bytes[data.offset as usize..][..24],
+        &[
+            1, 2, 3, 4, 0xff, 0xff, 0xff, 0xff, 0xee, 0xee, 0xee, 0xee, 2, 1, 0, 0, 0, 0, 0, 0,
+            0xee, 0xee, 0xee, 0xee
+        ][..]
+    );
+}
+
+#[test]
+fn linker_defined_symbols() {
+    use faerie::{Link, Reloc};
+    use goblin::mach::relocation::{X86_64_RELOC_GOT_LOAD, X86_64_RELOC_UNSIGNED};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    // movq __mh_execute_header@GOTPCREL(%rip), %rax
+    obj.declare_with(
+        "f",
+        Decl::function(),
+        vec![0x48, 0x8b, 0x05, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define f");
+    obj.declare_with("header", Decl::data(), vec![0; 8])
+        .expect("can declare and define header");
+    // an invalid link does not import the symbol it is to
+    assert!(obj
+        .link(Link {
+            from: "undeclared",
+            to: "__dso_handle",
+            at: 0,
+        })
+        .is_err());
+    assert_eq!(obj.imports().count(), 0);
+    obj.link_with(
+        Link {
+            from: "f",
+            to: "_mh_execute_header",
+            at: 3,
+        },
+        Reloc::GotLoad,
+    )
+    .expect("can link to the mach header without declaring it");
+    obj.link(Link {
+        from: "header",
+        to: "_mh_execute_header",
+        at: 0,
+    })
+    .expect("can link to the mach header again");
+    assert!(obj
+        .link(Link {
+            from: "f",
+            to: "_mh_header",
+            at: 3,
+        })
+        .is_err());
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let header = symbol(&mach, "_mh_execute_header");
+    assert!(header.is_undefined());
+    assert!(header.is_global());
+    let index = mach
+        .symbols()
+        .position(|sym| sym.expect("can parse symbol").0 == "__mh_execute_header")
+        .expect("mach header has a symbol");
+    let text = relocations(&bytes, "__text");
+    assert_eq!(text.len(), 1);
+    assert_eq!(text[0].r_type(), X86_64_RELOC_GOT_LOAD);
+    assert!(text[0].is_extern());
+    assert!(text[0].is_pic());
+    assert_eq!(text[0].r_symbolnum(), index);
+    let data = relocations(&bytes, "__data");
+    assert_eq!(data.len(), 1);
+    assert_eq!(data[0].r_type(), X86_64_RELOC_UNSIGNED);
+    assert!(data[0].is_extern());
+    assert_eq!(data[0].r_length(), 3);
+    assert_eq!(data[0].r_symbolnum(), index);
+}
+
+#[test]
+fn prune_imports() {
+    use faerie::{ArtifactBuilder, ImportKind, Link};
+
+    let symbols = |prune| {
+        let mut obj = ArtifactBuilder::new(triple!("x86_64-apple-darwin"))
+            .prune_imports(prune)
+            .finish();
+        obj.declare_with("f", Decl::function(), vec![0xe8, 0, 0, 0, 0, 0xc3])
+            .expect("can declare and define f");
+        obj.import("used", ImportKind::Function)
+            .expect("can import used");
+        obj.import("unused", ImportKind::Function)
+            .expect("can import unused");
+        obj.link(Link {
+            from: "f",
+            to: "used",
+            at: 1,
+        })
+        .expect("can link f to used");
+        let bytes = obj.emit().expect("can emit mach file");
+        parse(&bytes)
+            .symbols()
+            .map(|sym| sym.expect("can parse symbol").0.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(symbols(false), ["_f", "_used", "_unused"]);
+    assert_eq!(symbols(true), ["_f", "_used"]);
+}
+
+#[test]
+fn relocations_by_section() {
+    use faerie::{ImportKind, Link};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.import("imported", ImportKind::Function)
+        .expect("can import imported");
+    obj.declare_with(
+        "f",
+        Decl::function().global(),
+        vec![0xe8, 0, 0, 0, 0, 0xe8, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can declare and define f");
+    obj.declare_with("table", Decl::data(), vec![0; 16])
+        .expect("can declare and define table");
+    for &(from, to, at) in &[
+        ("f", "imported", 1),
+        ("f", "f", 6),
+        ("table", "f", 0),
+        ("table", "imported", 8),
+    ] {
+        obj.link(Link { from, to, at }).expect("can link");
+    }
+
+    let groups = obj.relocations().expect("can get relocations");
+    assert_eq!(
+        groups
+            .iter()
+            .map(|(section, relocations)| (section.as_str(), relocations.len()))
+            .collect::<Vec<_>>(),
+        [("__text", 2), ("__data", 2)]
+    );
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    let names = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").0.to_string())
+        .collect::<Vec<_>>();
+    for (sectname, resolved_relocations) in groups.iter() {
+        let parsed = relocations(&bytes, sectname);
+        assert_eq!(resolved_relocations.len(), parsed.len());
+        for resolved in resolved_relocations.iter() {
+            assert_eq!(&resolved.section, sectname);
+            let reloc = parsed
+                .iter()
+                .find(|reloc| reloc.r_address as u64 == resolved.offset)
+                .expect("relocation exists");
+            assert!(reloc.is_extern());
+            assert_eq!(names[reloc.r_symbolnum()], format!("_{}", resolved.symbol));
+            assert_eq!(resolved.reloc, u32::from(reloc.r_type()));
+            assert_eq!(resolved.size, Some(1 << reloc.r_length()));
+            assert_eq!(resolved.addend, 0);
+        }
+    }
+    let sizes = groups
+        .iter()
+        .flat_map(|(_, relocations)| relocations.iter().map(|reloc| reloc.size))
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [Some(4), Some(4), Some(8), Some(8)]);
+}
+
+#[test]
+fn relocations_in_their_definitions_section() {
+    use faerie::Link;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a".into());
+    obj.declare_with("f", Decl::function(), vec![0xc3])
+        .expect("can declare and define f");
+    obj.declare_with("table", Decl::data(), vec![0; 16])
+        .expect("can declare and define table");
+    obj.declare_with("s", Decl::cstring(), b"0123456789abcde\0".to_vec())
+        .expect("can declare and define s");
+    obj.declare_with("counter", Decl::data().tls(), vec![0; 16])
+        .expect("can declare and define counter");
+    for &from in &["table", "s", "counter"] {
+        obj.link(Link {
+            from,
+            to: "f",
+            at: 8,
+        })
+        .expect("can link to f");
+    }
+
+    // each relocation is in the section of the definition it is from, at its offset there
+    let layout = obj.layout().expect("can lay out mach file");
+    // the contents of a thread-local variable are its initial value
+    for &(from, sectname) in &[
+        ("table", "__data"),
+        ("s", "__cstring"),
+        ("counter$tlv$init", "__thread_data"),
+    ] {
+        let symbol = layout
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == from)
+            .expect("symbol is laid out");
+        assert_eq!(symbol.section, sectname);
+        assert!(layout
+            .relocations
+            .iter()
+            .any(|reloc| reloc.section == sectname
+                && reloc.offset == symbol.offset + 8
+                && reloc.symbol == "f"));
+    }
+}
+
+#[test]
+fn common_symbols() {
+    use faerie::{describe, COMMON};
+    use goblin::mach::symbols::{NO_SECT, N_TYPE, N_UNDF};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "t.o".into());
+    obj.declare("c", Decl::data().writable().common().with_align(Some(16)))
+        .expect("can declare c");
+    obj.define_zero_init("c", 32).expect("can define c");
+    obj.declare("h", Decl::data().writable().common().hidden())
+        .expect("can declare h");
+    obj.define_zero_init("h", 4).expect("can define h");
+    obj.declare_with("f", Decl::function().global(), vec![0xe8, 0, 0, 0, 0])
+        .expect("can declare and define f");
+    obj.link(faerie::Link {
+        from: "f",
+        to: "c",
+        at: 1,
+    })
+    .expect("can link f to c");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = parse(&bytes);
+    // common symbols are undefined external symbols whose value is their size
+    let c = symbol(&mach, "c");
+    assert_eq!(c.n_type & N_TYPE, N_UNDF);
+    assert_eq!(c.n_type & N_EXT, N_EXT);
+    assert_eq!(c.n_type & N_PEXT, 0);
+    assert_eq!(c.n_sect, NO_SECT as usize);
+    assert_eq!(c.n_value, 32);
+    // the alignment exponent is stored in the high byte of the description
+    assert_eq!((c.n_desc >> 8) & 0xf, 4);
+    let h = symbol(&mach, "h");
+    assert_eq!(h.n_type & N_TYPE, N_UNDF);
+    assert_eq!(h.n_type & (N_EXT | N_PEXT), N_EXT | N_PEXT);
+    assert_eq!(h.n_value, 4);
+    assert_eq!((h.n_desc >> 8) & 0xf, 3);
+    // no section holds them
+    assert!(mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .all(|section| section.name().unwrap() != "__bss"));
+
+    // the linker places c, so the relocation refers to its symbol
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").0)
+        .collect::<Vec<_>>();
+    let relocs = relocations(&bytes, "__text");
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].r_extern(), 1);
+    assert_eq!(symbols[relocs[0].r_symbolnum()], "_c");
+
+    let description = describe(&bytes).expect("can describe object");
+    let c = description
+        .symbols
+        .iter()
+        .find(|sym| sym.name == "_c")
+        .expect("c is described");
+    assert_eq!(c.section, Some(COMMON.to_string()));
+    let addresses = obj.symbol_addresses(0).expect("can compute addresses");
+    assert!(!addresses.contains_key("c"));
+    assert!(addresses.contains_key("f"));
+
+    // Mach-O can only record alignments of up to 2^15 bytes
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "t.o".into());
+    obj.declare("c", Decl::data().common().with_align(Some(1 << 16)))
+        .expect("can declare c");
+    obj.define_zero_init("c", 8).expect("can define c");
+    assert!(obj.emit().is_err());
+}